@@ -1,5 +1,20 @@
+use crate::optimizer::{jump_destinations, jump_destinations_mut};
 use crate::{operands, Node};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+mod debug_info;
+mod patch;
+mod relocation;
+mod verify;
+
+pub use debug_info::{assemble_with_debug_info, DebugInfo, InstructionInfo};
+pub use patch::assemble_patch;
+pub use relocation::{assemble_relocatable, Relocatable, Relocation, RelocationEncoding, Symbol};
+pub use verify::{assemble_verified, Mismatch, VerifyError};
 
 pub trait AssembleEnv {
     /// Converts a rust string into the correct string identifier for the destination context
@@ -7,21 +22,301 @@ pub trait AssembleEnv {
     fn get_variable_name_index(&mut self, name: &[u8]) -> Option<u32>;
     fn get_proc_index(&mut self, path: &str) -> Option<u32>;
     fn get_type(&mut self, path: &str) -> Option<(u8, u32)>;
+
+    // The batch versions are for envs where each lookup is expensive, such as a round-trip into the game process.
+    // `assemble_batch` hands them everything it needs up front.
+
+    fn get_string_indices(&mut self, strings: &[&[u8]]) -> Vec<Option<u32>> {
+        strings
+            .iter()
+            .map(|string| self.get_string_index(string))
+            .collect()
+    }
+
+    fn get_variable_name_indices(&mut self, names: &[&[u8]]) -> Vec<Option<u32>> {
+        names
+            .iter()
+            .map(|name| self.get_variable_name_index(name))
+            .collect()
+    }
+
+    fn get_proc_indices(&mut self, paths: &[&str]) -> Vec<Option<u32>> {
+        paths.iter().map(|path| self.get_proc_index(path)).collect()
+    }
+
+    fn get_types(&mut self, paths: &[&str]) -> Vec<Option<(u8, u32)>> {
+        paths.iter().map(|path| self.get_type(path)).collect()
+    }
+}
+
+impl<E: AssembleEnv + ?Sized> AssembleEnv for &mut E {
+    fn get_string_index(&mut self, string: &[u8]) -> Option<u32> {
+        (**self).get_string_index(string)
+    }
+
+    fn get_variable_name_index(&mut self, name: &[u8]) -> Option<u32> {
+        (**self).get_variable_name_index(name)
+    }
+
+    fn get_proc_index(&mut self, path: &str) -> Option<u32> {
+        (**self).get_proc_index(path)
+    }
+
+    fn get_type(&mut self, path: &str) -> Option<(u8, u32)> {
+        (**self).get_type(path)
+    }
+
+    fn get_string_indices(&mut self, strings: &[&[u8]]) -> Vec<Option<u32>> {
+        (**self).get_string_indices(strings)
+    }
+
+    fn get_variable_name_indices(&mut self, names: &[&[u8]]) -> Vec<Option<u32>> {
+        (**self).get_variable_name_indices(names)
+    }
+
+    fn get_proc_indices(&mut self, paths: &[&str]) -> Vec<Option<u32>> {
+        (**self).get_proc_indices(paths)
+    }
+
+    fn get_types(&mut self, paths: &[&str]) -> Vec<Option<(u8, u32)>> {
+        (**self).get_types(paths)
+    }
 }
 
 #[derive(Debug, PartialEq)]
-pub enum AssembleError {
+pub enum AssembleErrorKind {
     UnsupportedValue(operands::Value),
     ProcNotFound(String),
-    InvalidVariableName,
+    InvalidVariableName(String),
     TypeNotFound(String),
+    StringNotFound(String),
+
+    // An operand we can disassemble but don't know how to write back yet
+    UnsupportedOperand,
+
+    // A value too big for the bits the bytecode has for it
+    OperandTooLarge(u32),
+
+    // A jump to a label that isn't in the nodes
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+
+    // A label further into the proc than a jump operand can point
+    JumpOutOfRange(String),
+
+    // The instruction doesn't exist in the BYOND version being assembled for
+    UnsupportedByTarget { version: u32, target: u32 },
+
+    // An unchanged instruction that overlaps the one before it, or goes past the end of the original bytecode
+    BadPatchOffset(u32),
+
+    // Edited code that can neither fit between the unchanged instructions around it nor jump elsewhere
+    PatchDoesNotFit,
+}
+
+impl fmt::Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedValue(value) => write!(f, "unsupported value {:?}", value),
+            Self::ProcNotFound(path) => write!(f, "proc not found: {}", path),
+            Self::InvalidVariableName(name) => write!(f, "invalid variable name: {}", name),
+            Self::TypeNotFound(path) => write!(f, "type not found: {}", path),
+            Self::StringNotFound(string) => write!(f, "string couldn't be created: {:?}", string),
+            Self::UnsupportedOperand => write!(f, "operand can't be assembled"),
+            Self::OperandTooLarge(value) => write!(f, "operand too large: {:#X}", value),
+            Self::UndefinedLabel(label) => write!(f, "undefined label {}", label),
+            Self::DuplicateLabel(label) => write!(f, "label {} is defined more than once", label),
+            Self::JumpOutOfRange(label) => write!(f, "label {} is too far away to jump to", label),
+            Self::UnsupportedByTarget { version, target } => {
+                write!(
+                    f,
+                    "requires BYOND {} or later, but the target is {}",
+                    version, target
+                )
+            }
+            Self::BadPatchOffset(offset) => {
+                write!(f, "unchanged instruction can't be at offset {:#X}", offset)
+            }
+            Self::PatchDoesNotFit => write!(
+                f,
+                "edited code doesn't fit between the unchanged instructions"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub kind: AssembleErrorKind,
+
+    /// The index of the node that failed to assemble
+    pub node: Option<usize>,
+
+    /// The name of the instruction at that node
+    pub instruction: Option<String>,
+
+    /// The name of the instruction's operand that failed, as in the instruction table (`var`, `arg_count`...)
+    pub operand: Option<&'static str>,
+}
+
+impl AssembleError {
+    pub(crate) fn in_operand(mut self, operand: &'static str) -> Self {
+        // Operands can be made of other operands, but the outermost is the one in the instruction table
+        self.operand = Some(operand);
+        self
+    }
+
+    fn at_node(mut self, index: usize, instruction: Option<String>) -> Self {
+        self.node = self.node.or(Some(index));
+        self.instruction = self.instruction.or(instruction);
+        self
+    }
+}
+
+impl From<AssembleErrorKind> for AssembleError {
+    fn from(kind: AssembleErrorKind) -> Self {
+        Self {
+            kind,
+            node: None,
+            instruction: None,
+            operand: None,
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(node) = self.node {
+            write!(f, "node {}", node)?;
+
+            if let Some(instruction) = &self.instruction {
+                write!(f, " ({}", instruction)?;
+
+                if let Some(operand) = self.operand {
+                    write!(f, " {}", operand)?;
+                }
+
+                write!(f, ")")?;
+            }
+
+            write!(f, ": ")?;
+        }
+
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// What to do with the `DbgFile` and `DbgLine` instructions in the nodes
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum DebugNodes {
+    #[default]
+    Keep,
+
+    /// Leaves them out, making the bytecode smaller. Runtime errors then won't say where they came from.
+    Remove,
+
+    /// Keeps the lines but gives every `DbgFile` this name instead
+    RenameFile(String),
+}
+
+/// Everything that changes how nodes get assembled. Start from `AssembleOptions::new()` and chain the setters.
+#[derive(Clone, Debug, Default)]
+pub struct AssembleOptions {
+    /// The BYOND version (such as 514) the bytecode is for. Instructions it doesn't have are an error.
+    /// `None` allows everything.
+    pub target_version: Option<u32>,
+
+    /// Looked up through the env, in order, before anything in the nodes. Envs that add to a .dmb's tables
+    /// hand out ids in the order they're asked for them, so this pins them down no matter how the code changes.
+    /// `Relocatable::symbols` gives the order assembling would otherwise use.
+    pub resolve_first: Vec<Symbol>,
+
+    /// Runs `optimizer::peephole` over the nodes first. Node indices in errors are then into its output.
+    /// Patching ignores this, as it would move the unchanged instructions.
+    pub peephole: bool,
+
+    /// Like `peephole`, this changes which nodes get assembled and patching ignores it
+    pub debug_nodes: DebugNodes,
+}
+
+impl AssembleOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn target_version(mut self, version: u32) -> Self {
+        self.target_version = Some(version);
+        self
+    }
+
+    pub fn resolve_first(mut self, symbols: Vec<Symbol>) -> Self {
+        self.resolve_first = symbols;
+        self
+    }
+
+    pub fn peephole(mut self, peephole: bool) -> Self {
+        self.peephole = peephole;
+        self
+    }
+
+    pub fn debug_nodes(mut self, debug_nodes: DebugNodes) -> Self {
+        self.debug_nodes = debug_nodes;
+        self
+    }
+}
+
+// The nodes as the options say they should be assembled
+fn prepare<'n>(nodes: &'n [Node], options: &AssembleOptions) -> Cow<'n, [Node]> {
+    let mut nodes = Cow::Borrowed(nodes);
+
+    match &options.debug_nodes {
+        DebugNodes::Keep => {}
+
+        DebugNodes::Remove => {
+            nodes = Cow::Owned(crate::source_map::strip_debug_info(nodes.into_owned()));
+        }
+
+        DebugNodes::RenameFile(name) => {
+            for node in nodes.to_mut() {
+                if let Node::Instruction(crate::Instruction::DbgFile(file), _) = node {
+                    *file = operands::DMString(name.clone().into_bytes());
+                }
+            }
+        }
+    }
+
+    if options.peephole {
+        nodes = Cow::Owned(crate::optimizer::peephole(nodes.into_owned()));
+    }
+
+    nodes
 }
 
 pub struct Assembler<'a, E: AssembleEnv> {
     nodes: &'a [Node],
     bytecode: Vec<u32>,
-    jump_destinations: HashMap<String, u32>,
-    jump_sources: Vec<(usize, String)>,
+
+    // Where the next word goes. Only behind the end of the bytecode when patching, see `assemble_patch`.
+    offset: usize,
+
+    // Where each node started
+    node_offsets: Vec<usize>,
+    jump_destinations: HashMap<String, usize>,
+
+    // Where each label operand is, and the index of the node it came from
+    jump_sources: Vec<(usize, String, usize)>,
+    current_node: usize,
+
+    // Only kept when assembling without a real env, see `assemble_relocatable`
+    relocations: Option<Vec<Relocation>>,
+
+    // Every string the env was asked about. Envs that add to the string table as they go might not give the same
+    // string the same id twice, and identical strings in a proc should be identical at runtime.
+    string_indices: HashMap<Vec<u8>, Option<u32>>,
+
+    // Where the words go instead of `bytecode`, see `assemble_to_writer`
+    writer: Option<&'a mut dyn io::Write>,
+    write_error: Option<io::Error>,
     pub env: &'a mut E,
 }
 
@@ -30,42 +325,856 @@ impl<'a, E: AssembleEnv> Assembler<'a, E> {
         Assembler {
             nodes,
             bytecode: vec![],
+            offset: 0,
+            node_offsets: vec![],
             jump_destinations: HashMap::new(),
             jump_sources: vec![],
+            current_node: 0,
+            relocations: None,
+            string_indices: HashMap::new(),
+            writer: None,
+            write_error: None,
             env,
         }
     }
 
+    fn assemble_node(
+        &mut self,
+        idx: usize,
+        options: &AssembleOptions,
+    ) -> Result<(), AssembleError> {
+        let nodes = self.nodes;
+        self.current_node = idx;
+        self.node_offsets.push(self.offset);
+
+        match &nodes[idx] {
+            // When writing straight out, the labels have already been placed
+            Node::Label(_) if self.writer.is_some() => (),
+
+            Node::Label(identifier) => {
+                if self
+                    .jump_destinations
+                    .insert(identifier.clone(), self.offset)
+                    .is_some()
+                {
+                    return Err(AssembleError::from(AssembleErrorKind::DuplicateLabel(
+                        identifier.clone(),
+                    ))
+                    .at_node(idx, None));
+                }
+            }
+
+            Node::Comment(_) => (),
+
+            Node::Instruction(ins, _) => {
+                if let (Some(version), Some(target)) = (ins.min_version(), options.target_version) {
+                    if target < version {
+                        return Err(AssembleError::from(AssembleErrorKind::UnsupportedByTarget {
+                            version,
+                            target,
+                        })
+                        .at_node(idx, Some(ins.op_name())));
+                    }
+                }
+
+                ins.assemble(self)
+                    .map_err(|err| err.at_node(idx, Some(ins.op_name())))?
+            }
+
+            Node::RawData(words, _) => {
+                for word in words {
+                    self.emit(*word);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_labels(&mut self) -> Result<(), AssembleError> {
+        for (offset, label, idx) in std::mem::take(&mut self.jump_sources) {
+            // Jump operands already take up a whole word, so there's no longer encoding to fall back on
+            let destination = match self.jump_destinations.get(&label) {
+                Some(destination) => u32::try_from(*destination)
+                    .map_err(|_| AssembleErrorKind::JumpOutOfRange(label)),
+                None => Err(AssembleErrorKind::UndefinedLabel(label)),
+            };
+
+            self.bytecode[offset] = destination.map_err(|kind| {
+                let instruction = match &self.nodes[idx] {
+                    Node::Instruction(ins, _) => Some(ins.op_name()),
+                    _ => None,
+                };
+
+                AssembleError::from(kind).at_node(idx, instruction)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // Lookups happen right before their result is emitted, so the relocation starts at the current offset
+    fn relocate(&mut self, symbol: Symbol, encoding: RelocationEncoding) {
+        if let Some(relocations) = &mut self.relocations {
+            relocations.push(Relocation {
+                offset: self.offset,
+                node: self.current_node,
+                symbol,
+                encoding,
+            });
+        }
+    }
+
+    pub fn get_string_index(&mut self, string: &[u8], encoding: RelocationEncoding) -> Option<u32> {
+        self.relocate(Symbol::String(string.to_vec()), encoding);
+
+        let env = &mut self.env;
+        *self
+            .string_indices
+            .entry(string.to_vec())
+            .or_insert_with(|| env.get_string_index(string))
+    }
+
+    pub fn get_variable_name_index(&mut self, name: &[u8]) -> Option<u32> {
+        self.relocate(
+            Symbol::VariableName(name.to_vec()),
+            RelocationEncoding::Word,
+        );
+        self.env.get_variable_name_index(name)
+    }
+
+    pub fn get_proc_index(&mut self, path: &str) -> Option<u32> {
+        self.relocate(Symbol::Proc(path.to_owned()), RelocationEncoding::Word);
+        self.env.get_proc_index(path)
+    }
+
+    pub fn get_type(&mut self, path: &str) -> Option<(u8, u32)> {
+        self.relocate(Symbol::Type(path.to_owned()), RelocationEncoding::Value);
+        self.env.get_type(path)
+    }
+
     pub fn emit(&mut self, code: u32) {
-        self.bytecode.push(code);
+        if let Some(writer) = &mut self.writer {
+            if self.write_error.is_none() {
+                if let Err(err) = writer.write_all(&code.to_le_bytes()) {
+                    self.write_error = Some(err);
+                }
+            }
+        } else {
+            match self.bytecode.get_mut(self.offset) {
+                Some(existing) => *existing = code,
+                None => self.bytecode.push(code),
+            }
+        }
+
+        self.offset += 1;
     }
 
     pub fn emit_label_operand(&mut self, name: &String) {
-        self.jump_sources.push((self.bytecode.len(), name.clone()));
+        // Nothing can be filled in later when writing straight out, so anything left over is an error
+        if self.writer.is_some() {
+            if let Some(destination) = self
+                .jump_destinations
+                .get(name)
+                .and_then(|destination| u32::try_from(*destination).ok())
+            {
+                self.emit(destination);
+                return;
+            }
+        }
+
+        self.jump_sources
+            .push((self.offset, name.clone(), self.current_node));
         self.emit(0xC0C0C0C0);
     }
 }
 
+/// Parses the text `crate::format` produces (labels, comments and instructions) back into nodes,
+/// so a hand-edited listing can be assembled again. Errors are already formatted for display.
+///
+/// `.equ NAME value` constants and `.macro NAME params...` / `.endm` macros can be used too.
+pub fn parse(text: &str) -> Result<Vec<Node>, String> {
+    let expanded = crate::directives::expand(text)?;
+    crate::parser::parse(&expanded.text).map_err(|err| expanded.map_error(&err))
+}
+
+/// Assembling is deterministic: the same nodes and env always give the same bytecode, and the env is asked
+/// about each string, variable name, proc and type in the order they first appear in the nodes.
 pub fn assemble<E: AssembleEnv>(nodes: &[Node], env: &mut E) -> Result<Vec<u32>, AssembleError> {
-    let mut state = Assembler::new(nodes, env);
+    assemble_with_options(nodes, env, &AssembleOptions::default())
+}
+
+pub fn assemble_with_options<E: AssembleEnv>(
+    nodes: &[Node],
+    env: &mut E,
+    options: &AssembleOptions,
+) -> Result<Vec<u32>, AssembleError> {
+    let nodes = prepare(nodes, options);
 
-    for node in nodes {
+    let state = run(Assembler::new(&nodes, env), options)?;
+    Ok(state.bytecode)
+}
+
+/// Like `assemble_with_options`, but the bytecode replaces whatever was in `out` so its allocation gets reused.
+pub fn assemble_into<E: AssembleEnv>(
+    nodes: &[Node],
+    env: &mut E,
+    options: &AssembleOptions,
+    out: &mut Vec<u32>,
+) -> Result<(), AssembleError> {
+    let nodes = prepare(nodes, options);
+
+    let mut state = Assembler::new(&nodes, env);
+    out.clear();
+    state.bytecode = std::mem::take(out);
+
+    *out = run(state, options)?.bytecode;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    Assemble(AssembleError),
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assemble(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "couldn't write bytecode: {}", err),
+        }
+    }
+}
+
+impl From<AssembleError> for WriteError {
+    fn from(err: AssembleError) -> Self {
+        Self::Assemble(err)
+    }
+}
+
+/// Writes the bytecode out word by word (little-endian, as it is in memory) without building it up first,
+/// such as into a memory-mapped proc with `&mut [u8]` as the writer. Label offsets are worked out beforehand.
+/// Returns how many words were written. The writer can be left with some of the proc if assembling fails.
+pub fn assemble_to_writer<E: AssembleEnv, W: io::Write>(
+    nodes: &[Node],
+    env: &mut E,
+    options: &AssembleOptions,
+    writer: &mut W,
+) -> Result<usize, WriteError> {
+    let nodes = prepare(nodes, options);
+
+    let mut state = Assembler::new(&nodes, env);
+    state.jump_destinations = label_offsets(&nodes)?;
+    state.writer = Some(writer);
+
+    let mut state = run(state, options)?;
+
+    match state.write_error.take() {
+        Some(err) => Err(WriteError::Io(err)),
+        None => Ok(state.offset),
+    }
+}
+
+fn instruction_size(ins: &crate::Instruction) -> Result<usize, AssembleError> {
+    let mut env = relocation::Placeholders;
+    let mut asm = Assembler::new(&[], &mut env);
+    ins.assemble(&mut asm)?;
+    Ok(asm.bytecode.len())
+}
+
+// Where every label is going to end up, from the size of each instruction before it
+fn label_offsets(nodes: &[Node]) -> Result<HashMap<String, usize>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+
+    for (idx, node) in nodes.iter().enumerate() {
         match node {
-            Node::Label(identifier) => {
-                state
-                    .jump_destinations
-                    .insert(identifier.clone(), state.bytecode.len() as u32);
+            Node::Label(name) => {
+                if labels.insert(name.clone(), offset).is_some() {
+                    return Err(AssembleError::from(AssembleErrorKind::DuplicateLabel(
+                        name.clone(),
+                    ))
+                    .at_node(idx, None));
+                }
             }
 
             Node::Comment(_) => (),
 
-            Node::Instruction(ins, _) => ins.assemble(&mut state)?,
+            Node::Instruction(ins, _) => {
+                offset +=
+                    instruction_size(ins).map_err(|err| err.at_node(idx, Some(ins.op_name())))?;
+            }
+
+            Node::RawData(words, _) => offset += words.len(),
         }
     }
 
-    for src in state.jump_sources {
-        state.bytecode[src.0] = state.jump_destinations[&src.1];
+    Ok(labels)
+}
+
+fn run<'a, E: AssembleEnv>(
+    mut state: Assembler<'a, E>,
+    options: &AssembleOptions,
+) -> Result<Assembler<'a, E>, AssembleError> {
+    for symbol in &options.resolve_first {
+        relocation::resolve_symbol(symbol, state.env)?;
     }
 
-    Ok(state.bytecode)
+    for idx in 0..state.nodes.len() {
+        state.assemble_node(idx, options)?;
+    }
+
+    state.resolve_labels()?;
+    Ok(state)
+}
+
+/// Assembles several procs against the same env. Every string, variable name, proc and type is only
+/// looked up through the env once for the whole batch, so envs that add new entries to a .dmb's tables
+/// don't create duplicates. A proc failing doesn't stop the rest: the results are in the same order as `procs`.
+///
+/// Everything is looked up before assembling starts, through the env's batch methods.
+pub fn assemble_batch<E: AssembleEnv>(
+    procs: &[&[Node]],
+    env: &mut E,
+) -> Vec<Result<Vec<u32>, AssembleError>> {
+    let mut shared = crate::CachedEnv::new(env);
+
+    // Procs that don't assemble have their errors reported below
+    let mut symbols: Vec<Symbol> = vec![];
+    let mut seen = HashSet::new();
+    for nodes in procs {
+        if let Ok(relocatable) = assemble_relocatable(nodes, &AssembleOptions::default()) {
+            for symbol in relocatable.symbols() {
+                if seen.insert(symbol.clone()) {
+                    symbols.push(symbol);
+                }
+            }
+        }
+    }
+
+    shared.prefetch(&symbols);
+
+    procs
+        .iter()
+        .map(|nodes| assemble(nodes, &mut shared))
+        .collect()
+}
+
+/// Labels nothing jumps to. These assemble fine, but are usually a sign of a typo in hand-written code.
+pub fn unused_labels<D>(nodes: &[Node<D>]) -> Vec<&str> {
+    let referenced: HashSet<&str> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Instruction(ins, _) => Some(jump_destinations(ins)),
+            _ => None,
+        })
+        .flatten()
+        .map(|label| label.0.as_str())
+        .collect();
+
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Label(name) if !referenced.contains(name.as_str()) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Joins separately compiled fragments into one proc. Each fragment's labels are renamed to start with `F<index>_`,
+/// so the `LAB_XXXX` labels fragments tend to share don't collide. Fragments can only jump to their own labels.
+pub fn link_fragments<D: Clone>(fragments: &[&[Node<D>]]) -> Result<Vec<Node<D>>, AssembleError> {
+    let mut linked = vec![];
+    let mut defined = HashSet::new();
+
+    for (fragment_idx, fragment) in fragments.iter().enumerate() {
+        let start = linked.len();
+        let rename = |label: &str| format!("F{}_{}", fragment_idx, label);
+
+        let labels: HashSet<&str> = fragment
+            .iter()
+            .filter_map(|node| match node {
+                Node::Label(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        for (idx, node) in fragment.iter().enumerate() {
+            let node = match node {
+                Node::Label(name) => {
+                    let name = rename(name);
+
+                    if !defined.insert(name.clone()) {
+                        return Err(AssembleError::from(AssembleErrorKind::DuplicateLabel(name))
+                            .at_node(start + idx, None));
+                    }
+
+                    Node::Label(name)
+                }
+
+                Node::Instruction(ins, data) => {
+                    let mut ins = ins.clone();
+
+                    for label in jump_destinations_mut(&mut ins) {
+                        if !labels.contains(label.0.as_str()) {
+                            return Err(AssembleError::from(AssembleErrorKind::UndefinedLabel(
+                                label.0.clone(),
+                            ))
+                            .at_node(start + idx, Some(ins.op_name())));
+                        }
+
+                        label.0 = rename(&label.0);
+                    }
+
+                    Node::Instruction(ins, data.clone())
+                }
+
+                Node::Comment(comment) => Node::Comment(comment.clone()),
+                Node::RawData(words, data) => Node::RawData(words.clone(), data.clone()),
+            };
+
+            linked.push(node);
+        }
+    }
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+fn jmp(label: &str) -> Node {
+    Node::Instruction(
+        crate::Instruction::Jmp(operands::Label(label.to_owned())),
+        (),
+    )
+}
+
+#[test]
+fn label_errors() {
+    let nodes = vec![Node::Comment("".to_owned()), jmp("missing")];
+    assert_eq!(
+        assemble(&nodes, &mut crate::TestAssembleEnv),
+        Err(AssembleError {
+            kind: AssembleErrorKind::UndefinedLabel("missing".to_owned()),
+            node: Some(1),
+            instruction: Some("Jmp".to_owned()),
+
+            // Labels are resolved after the instructions are written, so the operand isn't known
+            operand: None,
+        })
+    );
+
+    let nodes = vec![
+        Node::Label("twice".to_owned()),
+        jmp("twice"),
+        Node::Label("twice".to_owned()),
+    ];
+    assert_eq!(
+        assemble(&nodes, &mut crate::TestAssembleEnv)
+            .unwrap_err()
+            .kind,
+        AssembleErrorKind::DuplicateLabel("twice".to_owned())
+    );
+
+    let nodes = vec![
+        jmp("used"),
+        Node::Label("unused".to_owned()),
+        Node::Label("used".to_owned()),
+    ];
+    assert_eq!(
+        assemble(&nodes, &mut crate::TestAssembleEnv),
+        Ok(vec![0x0F, 2])
+    );
+    assert_eq!(unused_labels(&nodes), vec!["unused"]);
+}
+
+#[test]
+fn linking() {
+    let first = vec![jmp("LAB_0002"), Node::Label("LAB_0002".to_owned())];
+    let second = vec![
+        Node::Comment("second".to_owned()),
+        jmp("LAB_0002"),
+        Node::Label("LAB_0002".to_owned()),
+    ];
+
+    let linked = link_fragments(&[&first, &second]).unwrap();
+    assert_eq!(
+        linked,
+        vec![
+            jmp("F0_LAB_0002"),
+            Node::Label("F0_LAB_0002".to_owned()),
+            Node::Comment("second".to_owned()),
+            jmp("F1_LAB_0002"),
+            Node::Label("F1_LAB_0002".to_owned()),
+        ]
+    );
+    assert_eq!(
+        assemble(&linked, &mut crate::TestAssembleEnv),
+        Ok(vec![0x0F, 2, 0x0F, 4])
+    );
+
+    // Jumping into another fragment
+    let err = link_fragments(&[&first, &[jmp("elsewhere")][..]]).unwrap_err();
+    assert_eq!(
+        err.kind,
+        AssembleErrorKind::UndefinedLabel("elsewhere".to_owned())
+    );
+    assert_eq!(err.node, Some(2));
+
+    let twice = vec![
+        Node::Label("again".to_owned()),
+        Node::Label("again".to_owned()),
+    ];
+    assert_eq!(
+        link_fragments(&[&first, &twice]).unwrap_err().kind,
+        AssembleErrorKind::DuplicateLabel("F1_again".to_owned())
+    );
+}
+
+#[test]
+fn operand_errors() {
+    use crate::operands::{DMString, Value, Variable};
+    use crate::Instruction;
+
+    struct NoStrings;
+
+    impl AssembleEnv for NoStrings {
+        fn get_string_index(&mut self, _data: &[u8]) -> Option<u32> {
+            None
+        }
+
+        fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+            Some(1)
+        }
+
+        fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+            Some(1)
+        }
+
+        fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+            Some((0x09, 0x01))
+        }
+    }
+
+    let nodes = vec![
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(
+            Instruction::Call(Variable::DynamicProc(DMString(b"foo".to_vec())), 0),
+            (),
+        ),
+    ];
+
+    let err = assemble(&nodes, &mut NoStrings).unwrap_err();
+    assert_eq!(
+        err.kind,
+        AssembleErrorKind::StringNotFound("foo".to_owned())
+    );
+    assert_eq!(
+        err.to_string(),
+        "node 1 (Call proc): string couldn't be created: \"foo\""
+    );
+
+    let nodes = vec![Node::Instruction(
+        Instruction::PushVal(
+            Value::Raw {
+                tag: 0x29,
+                data: 0x1000000,
+            }
+            .into(),
+        ),
+        (),
+    )];
+
+    let err = assemble(&nodes, &mut NoStrings).unwrap_err();
+    assert_eq!(err.kind, AssembleErrorKind::OperandTooLarge(0x1000000));
+    assert_eq!(err.operand, Some("value"));
+}
+
+#[test]
+fn batch() {
+    use crate::operands::{DMString, Value};
+    use crate::Instruction;
+
+    // Hands out a new index for every string it's asked about, like an env adding to the string table would.
+    // Also counts how many batches of strings it was asked about.
+    struct CountingEnv(u32, u32);
+
+    impl AssembleEnv for CountingEnv {
+        fn get_string_index(&mut self, _data: &[u8]) -> Option<u32> {
+            self.0 += 1;
+            Some(self.0)
+        }
+
+        fn get_string_indices(&mut self, strings: &[&[u8]]) -> Vec<Option<u32>> {
+            self.1 += 1;
+            strings
+                .iter()
+                .map(|string| self.get_string_index(string))
+                .collect()
+        }
+
+        fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+            None
+        }
+
+        fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+            None
+        }
+
+        fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+            None
+        }
+    }
+
+    fn push_string(string: &str) -> Node {
+        Node::Instruction(
+            Instruction::PushVal(Value::DMString(DMString(string.as_bytes().to_vec())).into()),
+            (),
+        )
+    }
+
+    let first = vec![push_string("foo"), push_string("bar")];
+    let second = vec![push_string("bar"), push_string("foo")];
+    let third = vec![Node::Instruction(
+        Instruction::PushVal(Value::Path("/datum".to_owned()).into()),
+        (),
+    )];
+
+    let mut env = CountingEnv(0, 0);
+    let results = assemble_batch(&[&first, &second, &third], &mut env);
+
+    assert_eq!(env.0, 2);
+    assert_eq!(env.1, 1);
+    assert_eq!(results[0], Ok(vec![0x60, 0x06, 1, 0x60, 0x06, 2]));
+    assert_eq!(results[1], Ok(vec![0x60, 0x06, 2, 0x60, 0x06, 1]));
+    assert_eq!(
+        results[2].as_ref().unwrap_err().kind,
+        AssembleErrorKind::TypeNotFound("/datum".to_owned())
+    );
+
+    // The same string twice in one proc is only looked up once, even outside of a batch
+    let mut env = CountingEnv(0, 0);
+    assert_eq!(
+        assemble(&[push_string("foo"), push_string("foo")], &mut env),
+        Ok(vec![0x60, 0x06, 1, 0x60, 0x06, 1])
+    );
+    assert_eq!(env.0, 1);
+}
+
+#[test]
+fn target_version() {
+    use crate::Instruction;
+
+    // Nothing newer than 514 is confirmed yet, so everything assembles for it
+    let nodes = vec![Node::Instruction(Instruction::Rgb2Num, ())];
+    assert_eq!(
+        assemble_with_options(
+            &nodes,
+            &mut crate::TestAssembleEnv,
+            &AssembleOptions::new().target_version(514)
+        ),
+        Ok(vec![0x162])
+    );
+}
+
+#[test]
+fn resolve_order() {
+    use crate::operands::{DMString, Value};
+    use crate::Instruction;
+
+    struct InterningEnv(Vec<Vec<u8>>);
+
+    impl AssembleEnv for InterningEnv {
+        fn get_string_index(&mut self, data: &[u8]) -> Option<u32> {
+            let idx = match self.0.iter().position(|string| string == data) {
+                Some(idx) => idx,
+                None => {
+                    self.0.push(data.to_vec());
+                    self.0.len() - 1
+                }
+            };
+
+            Some(idx as u32)
+        }
+
+        fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+            None
+        }
+
+        fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+            None
+        }
+
+        fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+            None
+        }
+    }
+
+    let nodes: Vec<Node> = ["foo", "bar", "foo"]
+        .iter()
+        .map(|string| {
+            Node::Instruction(
+                Instruction::PushVal(Value::DMString(DMString(string.as_bytes().to_vec())).into()),
+                (),
+            )
+        })
+        .collect();
+
+    let symbols = assemble_relocatable(&nodes, &AssembleOptions::new())
+        .unwrap()
+        .symbols();
+    assert_eq!(
+        symbols,
+        vec![
+            Symbol::String(b"foo".to_vec()),
+            Symbol::String(b"bar".to_vec())
+        ]
+    );
+
+    let mut env = InterningEnv(vec![]);
+    assemble(&nodes, &mut env).unwrap();
+    assert_eq!(env.0, vec![b"foo".to_vec(), b"bar".to_vec()]);
+
+    let options = AssembleOptions::new().resolve_first(symbols.into_iter().rev().collect());
+    let mut env = InterningEnv(vec![]);
+    assert_eq!(
+        assemble_with_options(&nodes, &mut env, &options),
+        Ok(vec![0x60, 0x06, 1, 0x60, 0x06, 0, 0x60, 0x06, 1])
+    );
+    assert_eq!(env.0, vec![b"bar".to_vec(), b"foo".to_vec()]);
+}
+
+#[test]
+fn streaming() {
+    use crate::Instruction;
+
+    let nodes = vec![
+        Node::Label("top".to_owned()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Jz(operands::Label("end".to_owned())), ()),
+        jmp("top"),
+        Node::Label("end".to_owned()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    let expected = assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut out = Vec::with_capacity(64);
+    assemble_into(
+        &nodes,
+        &mut crate::TestAssembleEnv,
+        &AssembleOptions::new(),
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(out, expected);
+    assert_eq!(out.capacity(), 64);
+
+    let mut bytes = vec![];
+    let written = assemble_to_writer(
+        &nodes,
+        &mut crate::TestAssembleEnv,
+        &AssembleOptions::new(),
+        &mut bytes,
+    )
+    .unwrap();
+    assert_eq!(written, expected.len());
+    assert_eq!(
+        bytes,
+        expected
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect::<Vec<u8>>()
+    );
+
+    // Too small a buffer
+    let mut buffer = [0u8; 8];
+    match assemble_to_writer(
+        &nodes,
+        &mut crate::TestAssembleEnv,
+        &AssembleOptions::new(),
+        &mut &mut buffer[..],
+    ) {
+        Err(WriteError::Io(_)) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let nodes = vec![jmp("missing")];
+    match assemble_to_writer(
+        &nodes,
+        &mut crate::TestAssembleEnv,
+        &AssembleOptions::new(),
+        &mut vec![],
+    ) {
+        Err(WriteError::Assemble(err)) => assert_eq!(
+            err.kind,
+            AssembleErrorKind::UndefinedLabel("missing".to_owned())
+        ),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn debug_nodes() {
+    use crate::operands::DMString;
+    use crate::Instruction;
+
+    let nodes = vec![
+        Node::Instruction(Instruction::DbgFile(DMString(b"secret.dm".to_vec())), ()),
+        Node::Instruction(Instruction::DbgLine(12), ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let options = AssembleOptions::new().debug_nodes(DebugNodes::Remove);
+    assert_eq!(
+        assemble_with_options(&nodes, &mut crate::TestAssembleEnv, &options),
+        assemble(&nodes[2..], &mut crate::TestAssembleEnv)
+    );
+
+    let renamed = vec![
+        Node::Instruction(Instruction::DbgFile(DMString(b"injected".to_vec())), ()),
+        Node::Instruction(Instruction::DbgLine(12), ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let options = AssembleOptions::new().debug_nodes(DebugNodes::RenameFile("injected".to_owned()));
+    let relocatable = assemble_relocatable(&nodes, &options).unwrap();
+    assert_eq!(
+        relocatable.symbols(),
+        vec![Symbol::String(b"injected".to_vec())]
+    );
+    assert_eq!(
+        relocatable.resolve(&mut crate::TestAssembleEnv),
+        assemble(&renamed, &mut crate::TestAssembleEnv)
+    );
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn jump_out_of_range() {
+    // Nothing this big can be assembled for real, so the label's offset is made up
+    let nodes = vec![jmp("far")];
+    let mut env = crate::TestAssembleEnv;
+    let mut state = Assembler::new(&nodes, &mut env);
+    state.assemble_node(0, &AssembleOptions::new()).unwrap();
+    state.jump_destinations.insert("far".to_owned(), 1 << 32);
+
+    let err = state.resolve_labels().unwrap_err();
+    assert_eq!(err.kind, AssembleErrorKind::JumpOutOfRange("far".to_owned()));
+    assert_eq!(err.to_string(), "node 0 (Jmp): label far is too far away to jump to");
+}
+
+#[test]
+fn type_filters() {
+    use crate::list_operands::TypeFilter;
+
+    let nodes = crate::parser::parse("IterLoad 5 (mob | obj | )\nIterPop\n").unwrap();
+    let bytecode = assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    assert_eq!(bytecode, vec![0x52, 5, 0x03, 0x55]);
+    assert_eq!(
+        nodes[0],
+        Node::Instruction(crate::Instruction::IterLoad(5, TypeFilter::MOB | TypeFilter::OBJ), ())
+    );
 }
@@ -0,0 +1,136 @@
+use super::{prepare, run, AssembleEnv, AssembleError, AssembleOptions, Assembler};
+use crate::{Instruction, Node};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionInfo {
+    /// Where the instruction starts in the bytecode
+    pub offset: usize,
+
+    /// The index of its node
+    pub node: usize,
+
+    /// The last `DbgFile` and `DbgLine` before it
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// What a debugger needs to get from a position in assembled bytecode back to the nodes it came from
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DebugInfo {
+    /// Every instruction, in order of offset
+    pub instructions: Vec<InstructionInfo>,
+
+    /// Every label and the offset it points to, in the order they were in the nodes
+    pub labels: Vec<(String, usize)>,
+}
+
+impl DebugInfo {
+    /// The instruction a program counter is in. Program counters in the middle of an instruction's operands count too.
+    pub fn lookup(&self, offset: usize) -> Option<&InstructionInfo> {
+        let idx = match self
+            .instructions
+            .binary_search_by_key(&offset, |info| info.offset)
+        {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+
+        self.instructions.get(idx)
+    }
+
+    /// The labels pointing at an offset
+    pub fn labels_at(&self, offset: usize) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |(_, label_offset)| *label_offset == offset)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Assembles the nodes along with a map from offsets in the bytecode back to them
+pub fn assemble_with_debug_info<E: AssembleEnv>(
+    nodes: &[Node],
+    env: &mut E,
+    options: &AssembleOptions,
+) -> Result<(Vec<u32>, DebugInfo), AssembleError> {
+    let nodes = prepare(nodes, options);
+
+    let state = run(Assembler::new(&nodes, env), options)?;
+
+    let mut info = DebugInfo::default();
+    let mut file = None;
+    let mut line = None;
+
+    for (idx, (node, offset)) in nodes.iter().zip(state.node_offsets).enumerate() {
+        match node {
+            Node::Label(name) => info.labels.push((name.clone(), offset)),
+            Node::Comment(_) => {}
+            Node::RawData(..) => info.instructions.push(InstructionInfo {
+                offset,
+                node: idx,
+                file: file.clone(),
+                line,
+            }),
+            Node::Instruction(ins, _) => {
+                match ins {
+                    Instruction::DbgFile(name) => {
+                        file = Some(String::from_utf8_lossy(&name.0).into_owned())
+                    }
+                    Instruction::DbgLine(number) => line = Some(*number),
+                    _ => {}
+                }
+
+                info.instructions.push(InstructionInfo {
+                    offset,
+                    node: idx,
+                    file: file.clone(),
+                    line,
+                });
+            }
+        }
+    }
+
+    Ok((state.bytecode, info))
+}
+
+#[test]
+fn offsets_to_nodes() {
+    use crate::operands::DMString;
+
+    let nodes = vec![
+        Node::Instruction(Instruction::DbgFile(DMString(b"code.dm".to_vec())), ()),
+        Node::Instruction(Instruction::DbgLine(3), ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Comment("then".to_owned()),
+        Node::Label("next".to_owned()),
+        Node::Instruction(Instruction::DbgLine(4), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let (bytecode, info) =
+        assemble_with_debug_info(&nodes, &mut crate::TestAssembleEnv, &AssembleOptions::new())
+            .unwrap();
+
+    assert_eq!(
+        bytecode,
+        super::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap()
+    );
+
+    // PushInt's operand
+    assert_eq!(
+        info.lookup(5),
+        Some(&InstructionInfo {
+            offset: 4,
+            node: 2,
+            file: Some("code.dm".to_owned()),
+            line: Some(3),
+        })
+    );
+
+    assert_eq!(
+        info.lookup(8).map(|info| (info.node, info.line)),
+        Some((6, Some(4)))
+    );
+    assert_eq!(info.labels_at(6).collect::<Vec<_>>(), vec!["next"]);
+}
@@ -0,0 +1,341 @@
+use super::{
+    instruction_size, relocation, AssembleEnv, AssembleError, AssembleErrorKind, AssembleOptions,
+    Assembler,
+};
+use crate::operands::Label;
+use crate::{Instruction, Node};
+
+// Lays the patch's nodes out over the original bytecode before any of them get assembled
+struct Patcher<'a> {
+    nodes: &'a [Node<Option<u32>>],
+
+    placed: Vec<Node>,
+
+    // Where to move to before assembling each placed node, if it doesn't follow on from the one before it
+    positions: Vec<Option<usize>>,
+
+    // The index in `nodes` each placed node came from
+    sources: Vec<usize>,
+
+    // Edited code that didn't fit where it was, to go after everything else
+    moved: Vec<(Node, usize)>,
+    next_label: u32,
+}
+
+impl<'a> Patcher<'a> {
+    fn place(&mut self, node: Node, position: Option<usize>, source: usize) {
+        self.placed.push(node);
+        self.positions.push(position);
+        self.sources.push(source);
+    }
+
+    fn place_all(&mut self, region: &[usize], position: usize) {
+        for (i, idx) in region.iter().enumerate() {
+            let position = if i == 0 { Some(position) } else { None };
+            self.place(self.nodes[*idx].clone().strip_debug_data(), position, *idx);
+        }
+    }
+
+    fn new_label(&mut self) -> String {
+        let label = format!("PATCH_{:0>4X}", self.next_label);
+        self.next_label += 1;
+        label
+    }
+
+    fn region_size(&self, region: &[usize]) -> Result<usize, AssembleError> {
+        let mut size = 0;
+
+        for idx in region {
+            match &self.nodes[*idx] {
+                Node::Instruction(ins, _) => {
+                    size += instruction_size(ins)
+                        .map_err(|err| err.at_node(*idx, Some(ins.op_name())))?;
+                }
+                Node::RawData(words, _) => size += words.len(),
+                _ => {}
+            }
+        }
+
+        Ok(size)
+    }
+
+    // Puts edited nodes into the space between `start` and the unchanged instruction at `end` (at node `anchor`).
+    // Anything left over is jumped over, and anything too big is moved to the end with a jump there and back.
+    fn place_region(
+        &mut self,
+        region: &[usize],
+        start: usize,
+        end: usize,
+        anchor: usize,
+        target: &mut Option<String>,
+    ) -> Result<(), AssembleError> {
+        let size = self.region_size(region)?;
+        let available = end - start;
+        let source = region.first().copied().unwrap_or(anchor);
+
+        if size == available {
+            self.place_all(region, start);
+            return Ok(());
+        }
+
+        let target = target.get_or_insert_with(|| self.new_label()).clone();
+        let jmp = |label: String| Node::Instruction(Instruction::Jmp(Label(label)), ());
+
+        // Jmp takes up 2 words
+        if size + 2 <= available {
+            self.place_all(region, start);
+            let position = if region.is_empty() { Some(start) } else { None };
+            self.place(jmp(target), position, source);
+            return Ok(());
+        }
+
+        if available < 2 {
+            return Err(
+                AssembleError::from(AssembleErrorKind::PatchDoesNotFit).at_node(source, None)
+            );
+        }
+
+        let label = self.new_label();
+        self.place(jmp(label.clone()), Some(start), source);
+
+        self.moved.push((Node::Label(label), source));
+        for idx in region {
+            self.moved
+                .push((self.nodes[*idx].clone().strip_debug_data(), *idx));
+        }
+        self.moved.push((jmp(target), source));
+
+        Ok(())
+    }
+}
+
+/// Reassembles an edited proc over its original bytecode. Instructions that haven't changed are the ones with
+/// their original offset as their node data (such as `DebugData::offset` from disassembling), and they stay there.
+/// Edited code goes in the space between them, or is moved to the end of the bytecode with jumps there and back
+/// when it doesn't fit. These jumps use labels named like `PATCH_0000`, so the nodes shouldn't have any.
+pub fn assemble_patch<E: AssembleEnv>(
+    original: &[u32],
+    nodes: &[Node<Option<u32>>],
+    env: &mut E,
+    options: &AssembleOptions,
+) -> Result<Vec<u32>, AssembleError> {
+    let mut patcher = Patcher {
+        nodes,
+        placed: vec![],
+        positions: vec![],
+        sources: vec![],
+        moved: vec![],
+        next_label: 0,
+    };
+
+    // The end of the last unchanged instruction
+    let mut end = 0;
+
+    // The edited nodes since then
+    let mut region = vec![];
+
+    for (idx, node) in nodes.iter().enumerate() {
+        let (ins, offset) = match node {
+            Node::Instruction(ins, Some(offset)) => (ins, *offset),
+            _ => {
+                region.push(idx);
+                continue;
+            }
+        };
+
+        let start = offset as usize;
+        let size = instruction_size(ins).map_err(|err| err.at_node(idx, Some(ins.op_name())))?;
+
+        if start < end || start + size > original.len() {
+            return Err(
+                AssembleError::from(AssembleErrorKind::BadPatchOffset(offset))
+                    .at_node(idx, Some(ins.op_name())),
+            );
+        }
+
+        // Labels and comments right before an unchanged instruction stay with it
+        let split = region
+            .iter()
+            .rposition(|idx| matches!(nodes[*idx], Node::Instruction(..) | Node::RawData(..)))
+            .map_or(0, |last| last + 1);
+        let trailing = region.split_off(split);
+
+        let mut target = trailing.iter().find_map(|idx| match &nodes[*idx] {
+            Node::Label(name) => Some(name.clone()),
+            _ => None,
+        });
+        let had_target = target.is_some();
+
+        patcher.place_region(&region, end, start, idx, &mut target)?;
+
+        let mut position = Some(start);
+
+        for idx in &trailing {
+            patcher.place(
+                nodes[*idx].clone().strip_debug_data(),
+                position.take(),
+                *idx,
+            );
+        }
+
+        if let (false, Some(target)) = (had_target, target) {
+            patcher.place(Node::Label(target), position.take(), idx);
+        }
+
+        patcher.place(node.clone().strip_debug_data(), position, idx);
+
+        region.clear();
+        end = start + size;
+    }
+
+    // Whatever comes after the last unchanged instruction can take as much space as it likes
+    let size = patcher.region_size(&region)?;
+    patcher.place_all(&region, end);
+    end += size;
+
+    for (i, (node, source)) in std::mem::take(&mut patcher.moved).into_iter().enumerate() {
+        let position = if i == 0 { Some(end) } else { None };
+        patcher.place(node, position, source);
+    }
+
+    let sources = patcher.sources;
+    let from_source = |mut err: AssembleError| {
+        err.node = err.node.map(|idx| sources[idx]);
+        err
+    };
+
+    for symbol in &options.resolve_first {
+        relocation::resolve_symbol(symbol, env)?;
+    }
+
+    let mut state = Assembler::new(&patcher.placed, env);
+    state.bytecode = original[..end.min(original.len())].to_vec();
+
+    for (idx, position) in patcher.positions.iter().enumerate() {
+        if let Some(position) = position {
+            state.offset = *position;
+        }
+
+        state.assemble_node(idx, options).map_err(from_source)?;
+    }
+
+    state.resolve_labels().map_err(from_source)?;
+    Ok(state.bytecode)
+}
+
+#[test]
+fn patching() {
+    fn ins(instruction: Instruction, offset: Option<u32>) -> Node<Option<u32>> {
+        Node::Instruction(instruction, offset)
+    }
+
+    fn jmp(label: &str) -> Node {
+        Node::Instruction(Instruction::Jmp(Label(label.to_owned())), ())
+    }
+
+    let original = super::assemble(
+        &[
+            Node::Instruction(Instruction::PushInt(1), ()),
+            Node::Instruction(Instruction::Pop, ()),
+            Node::Instruction(Instruction::PushInt(2), ()),
+            Node::Instruction(Instruction::Pop, ()),
+            Node::Instruction(Instruction::End, ()),
+        ],
+        &mut crate::TestAssembleEnv,
+    )
+    .unwrap();
+
+    let patch = |nodes: &[Node<Option<u32>>]| {
+        assemble_patch(
+            &original,
+            nodes,
+            &mut crate::TestAssembleEnv,
+            &AssembleOptions::new(),
+        )
+    };
+
+    // Fits where the old code was
+    assert_eq!(
+        patch(&[
+            ins(Instruction::PushInt(5), None),
+            ins(Instruction::Pop, Some(2)),
+            ins(Instruction::PushInt(2), Some(3)),
+            ins(Instruction::Pop, Some(5)),
+            ins(Instruction::End, Some(6)),
+        ]),
+        super::assemble(
+            &[
+                Node::Instruction(Instruction::PushInt(5), ()),
+                Node::Instruction(Instruction::Pop, ()),
+                Node::Instruction(Instruction::PushInt(2), ()),
+                Node::Instruction(Instruction::Pop, ()),
+                Node::Instruction(Instruction::End, ()),
+            ],
+            &mut crate::TestAssembleEnv,
+        )
+    );
+
+    // Removed code gets jumped over
+    assert_eq!(
+        patch(&[
+            ins(Instruction::Pop, Some(2)),
+            ins(Instruction::PushInt(2), Some(3)),
+            ins(Instruction::Pop, Some(5)),
+            ins(Instruction::End, Some(6)),
+        ]),
+        super::assemble(
+            &[
+                jmp("PATCH_0000"),
+                Node::Label("PATCH_0000".to_owned()),
+                Node::Instruction(Instruction::Pop, ()),
+                Node::Instruction(Instruction::PushInt(2), ()),
+                Node::Instruction(Instruction::Pop, ()),
+                Node::Instruction(Instruction::End, ()),
+            ],
+            &mut crate::TestAssembleEnv,
+        )
+    );
+
+    // Too big to fit, so it moves to the end
+    assert_eq!(
+        patch(&[
+            ins(Instruction::PushInt(1), Some(0)),
+            ins(Instruction::Pop, Some(2)),
+            ins(Instruction::PushInt(2), None),
+            ins(Instruction::PushInt(3), None),
+            ins(Instruction::Add, None),
+            Node::Label("back".to_owned()),
+            ins(Instruction::Pop, Some(5)),
+            ins(Instruction::End, Some(6)),
+        ]),
+        super::assemble(
+            &[
+                Node::Instruction(Instruction::PushInt(1), ()),
+                Node::Instruction(Instruction::Pop, ()),
+                jmp("PATCH_0000"),
+                Node::Label("back".to_owned()),
+                Node::Instruction(Instruction::Pop, ()),
+                Node::Instruction(Instruction::End, ()),
+                Node::Label("PATCH_0000".to_owned()),
+                Node::Instruction(Instruction::PushInt(2), ()),
+                Node::Instruction(Instruction::PushInt(3), ()),
+                Node::Instruction(Instruction::Add, ()),
+                jmp("back"),
+            ],
+            &mut crate::TestAssembleEnv,
+        )
+    );
+
+    // There's no room for a jump over a single word
+    assert_eq!(
+        patch(&[
+            ins(Instruction::PushInt(1), Some(0)),
+            ins(Instruction::PushInt(2), Some(3)),
+            ins(Instruction::Pop, Some(5)),
+            ins(Instruction::End, Some(6)),
+        ])
+        .unwrap_err()
+        .kind,
+        AssembleErrorKind::PatchDoesNotFit
+    );
+}
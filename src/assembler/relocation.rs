@@ -0,0 +1,202 @@
+use super::{
+    prepare, run, AssembleEnv, AssembleError, AssembleErrorKind, AssembleOptions, Assembler,
+};
+use crate::Node;
+
+/// Something the env would normally have been asked for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    String(Vec<u8>),
+    VariableName(Vec<u8>),
+    Proc(String),
+    Type(String),
+}
+
+/// How a resolved symbol is written into the bytecode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationEncoding {
+    // One word holding the id
+    Word,
+
+    // Two words holding a value: the tag and the top 8 bits of the data, then the bottom 16 bits.
+    // Strings get the string tag, types get whatever tag the env gives them.
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// Where the symbol goes in the bytecode
+    pub offset: usize,
+
+    /// The index of the node the symbol came from
+    pub node: usize,
+
+    pub symbol: Symbol,
+    pub encoding: RelocationEncoding,
+}
+
+/// Bytecode with zeroes where the env's ids go. Labels only depend on the proc itself, so they're already resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocatable {
+    pub bytecode: Vec<u32>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Relocatable {
+    /// Every symbol in the order the env would be asked about it, without duplicates
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = vec![];
+
+        for relocation in &self.relocations {
+            if !symbols.contains(&relocation.symbol) {
+                symbols.push(relocation.symbol.clone());
+            }
+        }
+
+        symbols
+    }
+
+    /// Fills in every relocation through the env, giving the same bytecode `assemble` would have
+    pub fn resolve<E: AssembleEnv>(mut self, env: &mut E) -> Result<Vec<u32>, AssembleError> {
+        for relocation in &self.relocations {
+            let (tag, data) = resolve_symbol(&relocation.symbol, env)
+                .map_err(|err| err.at_node(relocation.node, None))?;
+
+            match relocation.encoding {
+                RelocationEncoding::Word => self.bytecode[relocation.offset] = data,
+                RelocationEncoding::Value => {
+                    if data > 0xFFFFFF {
+                        return Err(
+                            AssembleError::from(AssembleErrorKind::OperandTooLarge(data))
+                                .at_node(relocation.node, None),
+                        );
+                    }
+
+                    self.bytecode[relocation.offset] = (tag as u32) | ((data & 0xFF0000) >> 8);
+                    self.bytecode[relocation.offset + 1] = data & 0xFFFF;
+                }
+            }
+        }
+
+        Ok(self.bytecode)
+    }
+}
+
+pub(super) fn resolve_symbol<E: AssembleEnv>(
+    symbol: &Symbol,
+    env: &mut E,
+) -> Result<(u8, u32), AssembleError> {
+    let resolved = match symbol {
+        Symbol::String(string) => env
+            .get_string_index(string)
+            .map(|id| (0x06, id))
+            .ok_or_else(|| {
+                AssembleErrorKind::StringNotFound(String::from_utf8_lossy(string).into_owned())
+            }),
+
+        Symbol::VariableName(name) => env
+            .get_variable_name_index(name)
+            .map(|id| (0x00, id))
+            .ok_or_else(|| {
+                AssembleErrorKind::InvalidVariableName(String::from_utf8_lossy(name).into_owned())
+            }),
+
+        Symbol::Proc(path) => env
+            .get_proc_index(path)
+            .map(|id| (0x00, id))
+            .ok_or_else(|| AssembleErrorKind::ProcNotFound(path.clone())),
+
+        Symbol::Type(path) => env
+            .get_type(path)
+            .ok_or_else(|| AssembleErrorKind::TypeNotFound(path.clone())),
+    };
+
+    resolved.map_err(AssembleError::from)
+}
+
+// Resolves everything to zero, the assembler keeps track of where the real ids go
+pub(super) struct Placeholders;
+
+impl AssembleEnv for Placeholders {
+    fn get_string_index(&mut self, _string: &[u8]) -> Option<u32> {
+        Some(0)
+    }
+
+    fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+        Some(0)
+    }
+
+    fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+        Some(0)
+    }
+
+    fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+        Some((0x00, 0))
+    }
+}
+
+/// Assembles without an env, recording where each string, variable name, proc and type id goes instead.
+/// `Relocatable::resolve` finishes the job later, such as inside the game process where the ids are known.
+pub fn assemble_relocatable(
+    nodes: &[Node],
+    options: &AssembleOptions,
+) -> Result<Relocatable, AssembleError> {
+    let nodes = prepare(nodes, options);
+
+    let mut env = Placeholders;
+    let mut state = Assembler::new(&nodes, &mut env);
+    state.relocations = Some(vec![]);
+
+    let state = run(state, options)?;
+
+    Ok(Relocatable {
+        bytecode: state.bytecode,
+        relocations: state.relocations.unwrap_or_default(),
+    })
+}
+
+#[test]
+fn resolves_like_assemble() {
+    use crate::operands::{DMString, Proc, Value, Variable};
+    use crate::Instruction;
+
+    let nodes = vec![
+        Node::Instruction(
+            Instruction::PushVal(Value::DMString(DMString(b"foo".to_vec())).into()),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::PushVal(Value::Path("/datum".to_owned()).into()),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::GetVar(Variable::Global(DMString(b"bar".to_vec()))),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::CallGlob(0, Proc::from_path("/proc/baz".to_owned())),
+            (),
+        ),
+    ];
+
+    let relocatable = assemble_relocatable(&nodes, &AssembleOptions::new()).unwrap();
+
+    assert_eq!(
+        relocatable
+            .relocations
+            .iter()
+            .map(|relocation| &relocation.symbol)
+            .collect::<Vec<_>>(),
+        vec![
+            &Symbol::String(b"foo".to_vec()),
+            &Symbol::Type("/datum".to_owned()),
+            &Symbol::VariableName(b"bar".to_vec()),
+            &Symbol::Proc("/proc/baz".to_owned()),
+        ]
+    );
+
+    assert_eq!(
+        relocatable.resolve(&mut crate::TestAssembleEnv),
+        super::assemble(&nodes, &mut crate::TestAssembleEnv)
+    );
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{prepare, run, AssembleEnv, AssembleError, AssembleOptions, Assembler};
+use crate::disassembler::{disassemble, DisassembleEnv, DisassembleError};
+use crate::optimizer::jump_destinations_mut;
+use crate::Node;
+
+/// An instruction that came out of the disassembler differently to how it went in to the assembler
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// The index of the node that went in, or `None` if the disassembler found more instructions than there were
+    pub node: Option<usize>,
+
+    /// Where the instruction that came out starts, or `None` if the disassembler found fewer instructions
+    pub offset: Option<u32>,
+
+    pub expected: Option<String>,
+    pub found: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.node {
+            Some(node) => write!(f, "node {}", node)?,
+            None => write!(f, "after the last node")?,
+        }
+
+        if let Some(offset) = self.offset {
+            write!(f, " (offset {:#X})", offset)?;
+        }
+
+        write!(
+            f,
+            ": expected `{}`, found `{}`",
+            self.expected.as_deref().unwrap_or("nothing"),
+            self.found.as_deref().unwrap_or("nothing")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    Assemble(AssembleError),
+    Disassemble(DisassembleError),
+    Mismatch(Vec<Mismatch>),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assemble(err) => write!(f, "{}", err),
+            Self::Disassemble(err) => write!(f, "assembled code doesn't disassemble: {:?}", err),
+            Self::Mismatch(mismatches) => {
+                write!(
+                    f,
+                    "assembled code doesn't disassemble to the same instructions"
+                )?;
+
+                for mismatch in mismatches {
+                    write!(f, "\n{}", mismatch)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<AssembleError> for VerifyError {
+    fn from(err: AssembleError) -> Self {
+        Self::Assemble(err)
+    }
+}
+
+// Remembers everything the env resolved, so it can be looked up the other way around
+struct Mirror<'a, E: AssembleEnv> {
+    env: &'a mut E,
+    strings: HashMap<u32, Vec<u8>>,
+    variable_names: HashMap<u32, Vec<u8>>,
+    procs: HashMap<u32, String>,
+    types: HashMap<(u32, u32), String>,
+}
+
+impl<'a, E: AssembleEnv> AssembleEnv for Mirror<'a, E> {
+    fn get_string_index(&mut self, string: &[u8]) -> Option<u32> {
+        let id = self.env.get_string_index(string)?;
+        self.strings.insert(id, string.to_vec());
+        Some(id)
+    }
+
+    fn get_variable_name_index(&mut self, name: &[u8]) -> Option<u32> {
+        let id = self.env.get_variable_name_index(name)?;
+        self.variable_names.insert(id, name.to_vec());
+        Some(id)
+    }
+
+    fn get_proc_index(&mut self, path: &str) -> Option<u32> {
+        let id = self.env.get_proc_index(path)?;
+        self.procs.insert(id, path.to_owned());
+        Some(id)
+    }
+
+    fn get_type(&mut self, path: &str) -> Option<(u8, u32)> {
+        let (tag, data) = self.env.get_type(path)?;
+        self.types.insert((tag as u32, data), path.to_owned());
+        Some((tag, data))
+    }
+}
+
+impl<'a, E: AssembleEnv> DisassembleEnv for Mirror<'a, E> {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        self.strings.get(&index).cloned()
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        self.variable_names.get(&index).cloned()
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        self.procs.get(&index).cloned()
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        self.types
+            .get(&(tag, data))
+            .map(|path| path.clone().into_bytes())
+    }
+}
+
+/// Assembles the nodes, then disassembles the result (using what the env was asked for) and checks the same
+/// instructions come back out. Labels are compared by where they point, as the disassembler names its own.
+pub fn assemble_verified<E: AssembleEnv>(
+    nodes: &[Node],
+    env: &mut E,
+    options: &AssembleOptions,
+) -> Result<Vec<u32>, VerifyError> {
+    let nodes = prepare(nodes, options);
+
+    let mut mirror = Mirror {
+        env,
+        strings: HashMap::new(),
+        variable_names: HashMap::new(),
+        procs: HashMap::new(),
+        types: HashMap::new(),
+    };
+
+    let state = run(Assembler::new(&nodes, &mut mirror), options)?;
+    let labels = state.jump_destinations;
+    let bytecode = state.bytecode;
+
+    let expected: Vec<(usize, String)> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| match node {
+            Node::Instruction(ins, _) => {
+                let mut ins = ins.clone();
+
+                for label in jump_destinations_mut(&mut ins) {
+                    if let Some(offset) = labels.get(&label.0) {
+                        label.0 = format!("LAB_{:0>4X}", offset);
+                    }
+                }
+
+                Some((idx, ins.to_string()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let (disassembled, err) = disassemble(&bytecode, &mut mirror);
+
+    if let Some(err) = err {
+        return Err(VerifyError::Disassemble(err));
+    }
+
+    let found: Vec<(u32, String)> = disassembled
+        .iter()
+        .filter_map(|node| match node {
+            Node::Instruction(ins, debug) => Some((debug.offset, ins.to_string())),
+            _ => None,
+        })
+        .collect();
+
+    let mut mismatches = vec![];
+
+    for i in 0..expected.len().max(found.len()) {
+        let expected = expected.get(i);
+        let found = found.get(i);
+
+        if expected.map(|(_, ins)| ins) != found.map(|(_, ins)| ins) {
+            mismatches.push(Mismatch {
+                node: expected.map(|(idx, _)| *idx),
+                offset: found.map(|(offset, _)| *offset),
+                expected: expected.map(|(_, ins)| ins.clone()),
+                found: found.map(|(_, ins)| ins.clone()),
+            });
+        }
+    }
+
+    if !mismatches.is_empty() {
+        return Err(VerifyError::Mismatch(mismatches));
+    }
+
+    Ok(bytecode)
+}
+
+#[test]
+fn round_trip() {
+    use crate::operands::{DMString, Label, Proc, Value, Variable};
+    use crate::Instruction;
+
+    // Gives everything its own id
+    struct Counter(u32);
+
+    impl Counter {
+        fn next(&mut self) -> u32 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    impl AssembleEnv for Counter {
+        fn get_string_index(&mut self, _data: &[u8]) -> Option<u32> {
+            Some(self.next())
+        }
+
+        fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+            Some(self.next())
+        }
+
+        fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+            Some(self.next())
+        }
+
+        fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+            Some((0x20, self.next()))
+        }
+    }
+
+    let mut nodes = vec![
+        Node::Comment("test".to_owned()),
+        Node::Instruction(
+            Instruction::GetVar(Variable::Global(DMString(b"x".to_vec()))),
+            (),
+        ),
+        Node::Instruction(Instruction::Jz(Label("skip".to_owned())), ()),
+        Node::Instruction(
+            Instruction::PushVal(Value::DMString(DMString(b"foo".to_vec())).into()),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::PushVal(Value::Path("/datum".to_owned()).into()),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::CallGlob(2, Proc::from_path("/proc/bar".to_owned())),
+            (),
+        ),
+        Node::Instruction(Instruction::Pop, ()),
+        Node::Label("skip".to_owned()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    assert!(assemble_verified(&nodes, &mut Counter(0), &AssembleOptions::new()).is_ok());
+
+    // The assembler turns underscores in proc names into spaces
+    nodes.insert(
+        6,
+        Node::Instruction(
+            Instruction::Call(Variable::DynamicProc(DMString(b"do_thing".to_vec())), 0),
+            (),
+        ),
+    );
+
+    match assemble_verified(&nodes, &mut Counter(0), &AssembleOptions::new()) {
+        Err(VerifyError::Mismatch(mismatches)) => {
+            assert_eq!(mismatches.len(), 1);
+            assert_eq!(mismatches[0].node, Some(6));
+        }
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::assembler::{AssembleEnv, Symbol};
+use crate::disassembler::DisassembleEnv;
+
+/// Remembers every lookup made through an env, for as long as the `CachedEnv` is kept around.
+/// Most procs share most of their strings, so reusing one across assemble and disassemble calls saves a lot of them.
+///
+/// Failed lookups are remembered too. `clear` forgets everything, for when the env's tables change.
+pub struct CachedEnv<E> {
+    env: E,
+
+    // Assembling
+    string_indices: HashMap<Vec<u8>, Option<u32>>,
+    variable_name_indices: HashMap<Vec<u8>, Option<u32>>,
+    proc_indices: HashMap<String, Option<u32>>,
+    types: HashMap<String, Option<(u8, u32)>>,
+
+    // Disassembling
+    strings: HashMap<u32, Option<Vec<u8>>>,
+    variable_names: HashMap<u32, Option<Vec<u8>>>,
+    proc_names: HashMap<u32, Option<String>>,
+    values: HashMap<(u32, u32), Option<Vec<u8>>>,
+}
+
+impl<E> CachedEnv<E> {
+    pub fn new(env: E) -> Self {
+        Self {
+            env,
+            string_indices: HashMap::new(),
+            variable_name_indices: HashMap::new(),
+            proc_indices: HashMap::new(),
+            types: HashMap::new(),
+            strings: HashMap::new(),
+            variable_names: HashMap::new(),
+            proc_names: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &E {
+        &self.env
+    }
+
+    pub fn into_inner(self) -> E {
+        self.env
+    }
+
+    pub fn clear(&mut self) {
+        self.string_indices.clear();
+        self.variable_name_indices.clear();
+        self.proc_indices.clear();
+        self.types.clear();
+        self.strings.clear();
+        self.variable_names.clear();
+        self.proc_names.clear();
+        self.values.clear();
+    }
+}
+
+// Looks up whichever keys aren't cached yet in one batch, then answers everything from the cache
+fn batch<K, Q, V, F>(cache: &mut HashMap<K, Option<V>>, keys: &[&Q], lookup: F) -> Vec<Option<V>>
+where
+    K: std::hash::Hash + Eq + std::borrow::Borrow<Q>,
+    Q: std::hash::Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    V: Clone,
+    F: FnOnce(&[&Q]) -> Vec<Option<V>>,
+{
+    let mut missing: Vec<&Q> = vec![];
+
+    for key in keys {
+        if !cache.contains_key(*key) && !missing.contains(key) {
+            missing.push(key);
+        }
+    }
+
+    if !missing.is_empty() {
+        let found = lookup(&missing);
+        cache.extend(missing.iter().map(|key| (*key).to_owned()).zip(found));
+    }
+
+    keys.iter().map(|key| cache[*key].clone()).collect()
+}
+
+impl<E: AssembleEnv> CachedEnv<E> {
+    /// Looks up everything that isn't already known, one batch per kind of symbol
+    pub fn prefetch(&mut self, symbols: &[Symbol]) {
+        let mut strings: Vec<&[u8]> = vec![];
+        let mut variable_names: Vec<&[u8]> = vec![];
+        let mut procs: Vec<&str> = vec![];
+        let mut types: Vec<&str> = vec![];
+
+        for symbol in symbols {
+            match symbol {
+                Symbol::String(string) => strings.push(string),
+                Symbol::VariableName(name) => variable_names.push(name),
+                Symbol::Proc(path) => procs.push(path),
+                Symbol::Type(path) => types.push(path),
+            }
+        }
+
+        self.get_string_indices(&strings);
+        self.get_variable_name_indices(&variable_names);
+        self.get_proc_indices(&procs);
+        self.get_types(&types);
+    }
+}
+
+impl<E: AssembleEnv> AssembleEnv for CachedEnv<E> {
+    fn get_string_index(&mut self, string: &[u8]) -> Option<u32> {
+        let env = &mut self.env;
+        *self
+            .string_indices
+            .entry(string.to_vec())
+            .or_insert_with(|| env.get_string_index(string))
+    }
+
+    fn get_variable_name_index(&mut self, name: &[u8]) -> Option<u32> {
+        let env = &mut self.env;
+        *self
+            .variable_name_indices
+            .entry(name.to_vec())
+            .or_insert_with(|| env.get_variable_name_index(name))
+    }
+
+    fn get_proc_index(&mut self, path: &str) -> Option<u32> {
+        let env = &mut self.env;
+        *self
+            .proc_indices
+            .entry(path.to_owned())
+            .or_insert_with(|| env.get_proc_index(path))
+    }
+
+    fn get_type(&mut self, path: &str) -> Option<(u8, u32)> {
+        let env = &mut self.env;
+        *self
+            .types
+            .entry(path.to_owned())
+            .or_insert_with(|| env.get_type(path))
+    }
+
+    fn get_string_indices(&mut self, strings: &[&[u8]]) -> Vec<Option<u32>> {
+        let env = &mut self.env;
+        batch(&mut self.string_indices, strings, |missing| {
+            env.get_string_indices(missing)
+        })
+    }
+
+    fn get_variable_name_indices(&mut self, names: &[&[u8]]) -> Vec<Option<u32>> {
+        let env = &mut self.env;
+        batch(&mut self.variable_name_indices, names, |missing| {
+            env.get_variable_name_indices(missing)
+        })
+    }
+
+    fn get_proc_indices(&mut self, paths: &[&str]) -> Vec<Option<u32>> {
+        let env = &mut self.env;
+        batch(&mut self.proc_indices, paths, |missing| {
+            env.get_proc_indices(missing)
+        })
+    }
+
+    fn get_types(&mut self, paths: &[&str]) -> Vec<Option<(u8, u32)>> {
+        let env = &mut self.env;
+        batch(&mut self.types, paths, |missing| env.get_types(missing))
+    }
+}
+
+impl<E: DisassembleEnv> DisassembleEnv for CachedEnv<E> {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        let env = &mut self.env;
+        self.strings
+            .entry(index)
+            .or_insert_with(|| env.get_string_data(index))
+            .clone()
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        let env = &mut self.env;
+        self.variable_names
+            .entry(index)
+            .or_insert_with(|| env.get_variable_name(index))
+            .clone()
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        let env = &mut self.env;
+        self.proc_names
+            .entry(index)
+            .or_insert_with(|| env.get_proc_name(index))
+            .clone()
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        let env = &mut self.env;
+        self.values
+            .entry((tag, data))
+            .or_insert_with(|| env.value_to_string_data(tag, data))
+            .clone()
+    }
+
+    // Local names differ from proc to proc, so they aren't cached
+    fn get_local_name(&mut self, slot: u32) -> Option<Vec<u8>> {
+        self.env.get_local_name(slot)
+    }
+}
+
+#[test]
+fn caches_lookups() {
+    use crate::assembler::assemble;
+    use crate::disassembler::disassemble;
+    use crate::operands::{DMString, Value};
+    use crate::{Instruction, Node};
+
+    #[derive(Default)]
+    struct Counting {
+        lookups: u32,
+    }
+
+    impl AssembleEnv for Counting {
+        fn get_string_index(&mut self, _string: &[u8]) -> Option<u32> {
+            self.lookups += 1;
+            Some(7)
+        }
+
+        fn get_variable_name_index(&mut self, _name: &[u8]) -> Option<u32> {
+            None
+        }
+
+        fn get_proc_index(&mut self, _path: &str) -> Option<u32> {
+            None
+        }
+
+        fn get_type(&mut self, _path: &str) -> Option<(u8, u32)> {
+            None
+        }
+    }
+
+    impl DisassembleEnv for Counting {
+        fn get_string_data(&mut self, _index: u32) -> Option<Vec<u8>> {
+            self.lookups += 1;
+            Some(b"foo".to_vec())
+        }
+
+        fn get_variable_name(&mut self, _index: u32) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn get_proc_name(&mut self, _index: u32) -> Option<String> {
+            None
+        }
+
+        fn value_to_string_data(&mut self, _tag: u32, _data: u32) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    let nodes = vec![
+        Node::Instruction(
+            Instruction::PushVal(Value::DMString(DMString(b"foo".to_vec())).into()),
+            (),
+        ),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let mut env = CachedEnv::new(Counting::default());
+    let bytecode = assemble(&nodes, &mut env).unwrap();
+    assert_eq!(assemble(&nodes, &mut env), Ok(bytecode.clone()));
+    assert_eq!(env.get_ref().lookups, 1);
+
+    disassemble(&bytecode, &mut env);
+    disassemble(&bytecode, &mut env);
+    assert_eq!(env.get_ref().lookups, 2);
+
+    env.clear();
+    assemble(&nodes, &mut env).unwrap();
+    assert_eq!(env.into_inner().lookups, 3);
+}
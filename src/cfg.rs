@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::optimizer::jump_destinations;
+use crate::{Instruction, Node};
+
+#[derive(Debug, PartialEq)]
+pub enum CfgError {
+    UnknownLabel(String),
+}
+
+impl fmt::Display for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownLabel(label) => write!(f, "jump to unknown label {}", label),
+        }
+    }
+}
+
+/// A run of nodes that can only be entered at the start and only left at the end
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    /// Indices into the nodes, including any labels at the start
+    pub nodes: Range<usize>,
+
+    /// The blocks that can run next, by index
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+/// A natural loop: the header and everything that can get back to it without going through it again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    /// The one block every way into the loop goes through
+    pub header: usize,
+
+    /// The blocks that jump back to the header
+    pub latches: Vec<usize>,
+
+    /// Every block in the loop, including the header, in order
+    pub blocks: Vec<usize>,
+
+    /// Blocks outside the loop that it can go on to, in order
+    pub exits: Vec<usize>,
+}
+
+/// The control flow graph of a proc. The first block is the entry point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+// Whether execution can continue on to the next node
+pub(crate) fn falls_through(ins: &Instruction) -> bool {
+    !matches!(
+        ins,
+        Instruction::Jmp(_)
+            | Instruction::JmpLoop(_)
+            | Instruction::Ret
+            | Instruction::End
+            | Instruction::Throw
+            | Instruction::Crash
+            | Instruction::PickProb(_)
+            | Instruction::Switch(_)
+            | Instruction::PickSwitch(_)
+            | Instruction::SwitchRange(_)
+    )
+}
+
+impl Cfg {
+    pub fn new<D>(nodes: &[Node<D>]) -> Result<Self, CfgError> {
+        let mut starts = vec![0];
+
+        for (idx, node) in nodes.iter().enumerate() {
+            match node {
+                Node::Label(_) if idx > 0 && !matches!(nodes[idx - 1], Node::Label(_)) => {
+                    starts.push(idx);
+                }
+
+                Node::Instruction(ins, _)
+                    if !falls_through(ins) || !jump_destinations(ins).is_empty() =>
+                {
+                    starts.push(idx + 1);
+                }
+
+                _ => {}
+            }
+        }
+
+        starts.dedup();
+        starts.retain(|start| *start < nodes.len());
+
+        let mut blocks: Vec<BasicBlock> = starts
+            .iter()
+            .enumerate()
+            .map(|(idx, start)| BasicBlock {
+                nodes: *start..starts.get(idx + 1).copied().unwrap_or(nodes.len()),
+                successors: vec![],
+                predecessors: vec![],
+            })
+            .collect();
+
+        let mut labels = HashMap::new();
+        for (block_idx, block) in blocks.iter().enumerate() {
+            for node in &nodes[block.nodes.clone()] {
+                if let Node::Label(name) = node {
+                    labels.insert(name.as_str(), block_idx);
+                }
+            }
+        }
+
+        for block_idx in 0..blocks.len() {
+            let mut successors = vec![];
+            let mut falls_through_to_next = true;
+
+            let last = nodes[blocks[block_idx].nodes.clone()]
+                .iter()
+                .rev()
+                .find_map(|node| match node {
+                    Node::Instruction(ins, _) => Some(ins),
+                    _ => None,
+                });
+
+            if let Some(ins) = last {
+                for destination in jump_destinations(ins) {
+                    match labels.get(destination.0.as_str()) {
+                        Some(target) => successors.push(*target),
+                        None => return Err(CfgError::UnknownLabel(destination.0.clone())),
+                    }
+                }
+
+                falls_through_to_next = falls_through(ins);
+            }
+
+            if falls_through_to_next && block_idx + 1 < blocks.len() {
+                successors.push(block_idx + 1);
+            }
+
+            successors.sort_unstable();
+            successors.dedup();
+
+            for successor in &successors {
+                blocks[*successor].predecessors.push(block_idx);
+            }
+
+            blocks[block_idx].successors = successors;
+        }
+
+        Ok(Self { blocks })
+    }
+
+    /// The block a node is in
+    pub fn block_of(&self, node: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| block.nodes.contains(&node))
+    }
+
+    /// Edges from a block to one that's still being explored when walking the graph depth first from the entry
+    /// point. These are what make loops.
+    pub fn back_edges(&self) -> Vec<(usize, usize)> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Unvisited,
+            Exploring,
+            Done,
+        }
+
+        let mut state = vec![State::Unvisited; self.blocks.len()];
+        let mut back_edges = vec![];
+
+        if self.blocks.is_empty() {
+            return back_edges;
+        }
+
+        // Each block along with how many of its successors have been looked at
+        let mut stack = vec![(0, 0)];
+        state[0] = State::Exploring;
+
+        while let Some((block, next)) = stack.last_mut() {
+            let block = *block;
+
+            match self.blocks[block].successors.get(*next) {
+                Some(successor) => {
+                    *next += 1;
+
+                    match state[*successor] {
+                        State::Unvisited => {
+                            state[*successor] = State::Exploring;
+                            stack.push((*successor, 0));
+                        }
+                        State::Exploring => back_edges.push((block, *successor)),
+                        State::Done => {}
+                    }
+                }
+
+                None => {
+                    state[block] = State::Done;
+                    stack.pop();
+                }
+            }
+        }
+
+        back_edges
+    }
+
+    /// Whether each block can be reached from the entry point
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+
+        while let Some(block) = stack.pop() {
+            if block >= self.blocks.len() || reachable[block] {
+                continue;
+            }
+
+            reachable[block] = true;
+            stack.extend(&self.blocks[block].successors);
+        }
+
+        reachable
+    }
+
+    /// Runs of nodes that nothing can get to, such as padding, hidden data or what's left of a bad patch.
+    /// Neighbouring blocks are joined into one range.
+    pub fn unreachable_nodes(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = vec![];
+
+        for (block, reachable) in self.blocks.iter().zip(self.reachable()) {
+            if reachable {
+                continue;
+            }
+
+            match ranges.last_mut() {
+                Some(last) if last.end == block.nodes.start => last.end = block.nodes.end,
+                _ => ranges.push(block.nodes.clone()),
+            }
+        }
+
+        ranges
+    }
+
+    // Whether every way from the entry point to `block` goes through `through`
+    fn dominates(&self, through: usize, block: usize) -> bool {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+
+        while let Some(next) = stack.pop() {
+            if next == through || seen[next] {
+                continue;
+            }
+
+            if next == block {
+                return false;
+            }
+
+            seen[next] = true;
+            stack.extend(&self.blocks[next].successors);
+        }
+
+        true
+    }
+
+    /// The natural loops, one per header, in the order of their headers. Back edges to a block that can be
+    /// reached without going through it (from jumping into the middle of a loop) don't make a natural loop, so
+    /// they're left out.
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = vec![];
+
+        for (latch, header) in self.back_edges() {
+            if !self.dominates(header, latch) {
+                continue;
+            }
+
+            let idx = match loops.iter().position(|found| found.header == header) {
+                Some(idx) => idx,
+                None => {
+                    loops.push(Loop {
+                        header,
+                        latches: vec![],
+                        blocks: vec![header],
+                        exits: vec![],
+                    });
+                    loops.len() - 1
+                }
+            };
+
+            let found = &mut loops[idx];
+            found.latches.push(latch);
+
+            // Everything that leads to the latch without passing the header
+            let mut stack = vec![latch];
+            while let Some(block) = stack.pop() {
+                if found.blocks.contains(&block) {
+                    continue;
+                }
+
+                found.blocks.push(block);
+                stack.extend(&self.blocks[block].predecessors);
+            }
+        }
+
+        for found in &mut loops {
+            found.latches.sort_unstable();
+            found.blocks.sort_unstable();
+
+            let mut exits: Vec<usize> = found
+                .blocks
+                .iter()
+                .flat_map(|block| self.blocks[*block].successors.iter().copied())
+                .filter(|successor| !found.blocks.contains(successor))
+                .collect();
+            exits.sort_unstable();
+            exits.dedup();
+            found.exits = exits;
+        }
+
+        loops.sort_by_key(|found| found.header);
+        loops
+    }
+}
+
+#[test]
+fn blocks() {
+    use crate::operands::Label;
+
+    let nodes = crate::parser::parse(
+        r#"
+PushInt 0
+SetVar local(0)
+LAB_0000:
+GetVar local(0)
+PushInt 10
+Tl
+Jz LAB_0001
+Inc local(0)
+Jmp LAB_0000
+LAB_0001:
+End
+"#,
+    )
+    .unwrap();
+
+    let cfg = Cfg::new(&nodes).unwrap();
+
+    assert_eq!(
+        cfg.blocks
+            .iter()
+            .map(|block| (block.nodes.clone(), block.successors.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (0..2, vec![1]),
+            (2..7, vec![2, 3]),
+            (7..9, vec![1]),
+            (9..11, vec![]),
+        ]
+    );
+
+    assert_eq!(cfg.blocks[1].predecessors, vec![0, 2]);
+    assert_eq!(cfg.block_of(8), Some(2));
+    assert_eq!(cfg.back_edges(), vec![(2, 1)]);
+    assert_eq!(
+        cfg.loops(),
+        vec![Loop {
+            header: 1,
+            latches: vec![2],
+            blocks: vec![1, 2],
+            exits: vec![3],
+        }]
+    );
+
+    let nodes = vec![Node::Instruction(
+        Instruction::Jmp(Label("nowhere".to_owned())),
+        (),
+    )];
+    assert_eq!(
+        Cfg::new(&nodes),
+        Err(CfgError::UnknownLabel("nowhere".to_owned()))
+    );
+}
+
+#[test]
+fn unreachable() {
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Jz LAB_0000
+Ret
+PushInt 1
+Pop
+LAB_0001:
+End
+LAB_0000:
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    let cfg = Cfg::new(&nodes).unwrap();
+    assert_eq!(cfg.reachable(), vec![true, true, false, false, true]);
+    assert_eq!(cfg.unreachable_nodes(), vec![3..7]);
+}
@@ -0,0 +1,64 @@
+//! The same text as `format`, with ANSI colors for reading in a terminal.
+
+use std::fmt::Write;
+
+use crate::highlight::{highlight, Class};
+use crate::Node;
+
+const RESET: &str = "\x1b[0m";
+
+fn color(class: Class) -> Option<&'static str> {
+    match class {
+        Class::Plain => None,
+        Class::Mnemonic => Some("\x1b[1;34m"),
+        Class::Label | Class::Jump => Some("\x1b[33m"),
+        Class::String => Some("\x1b[32m"),
+        Class::Number => Some("\x1b[36m"),
+        Class::Comment => Some("\x1b[90m"),
+    }
+}
+
+/// `format`, colored. Mnemonics, labels, strings, numbers and comments each get their own color.
+pub fn format_colored<D>(nodes: &[Node<D>]) -> String {
+    let mut out = String::new();
+
+    for node in nodes {
+        for (class, text) in highlight(node) {
+            match color(class) {
+                Some(color) => write!(&mut out, "{}{}{}", color, text, RESET).unwrap(),
+                None => out.push_str(&text),
+            }
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+#[test]
+fn colors() {
+    let nodes = crate::parser::parse(
+        r#"
+; check
+LAB_0000:
+PushVal "a \"b\" 1"
+GetVar arg(2)
+Jz LAB_0000
+PushInt -3
+"#,
+    )
+    .unwrap();
+
+    let expected = [
+        "\x1b[90m; check\x1b[0m",
+        "\x1b[33mLAB_0000\x1b[0m:",
+        "\x1b[1;34mPushVal\x1b[0m \x1b[32m\"a \\\"b\\\" 1\"\x1b[0m",
+        "\x1b[1;34mGetVar\x1b[0m arg(\x1b[36m2\x1b[0m)",
+        "\x1b[1;34mJz\x1b[0m \x1b[33mLAB_0000\x1b[0m",
+        "\x1b[1;34mPushInt\x1b[0m \x1b[36m-3\x1b[0m",
+        "",
+    ];
+
+    assert_eq!(format_colored(&nodes), expected.join("\n"));
+}
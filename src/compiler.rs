@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::Arc;
 
 use dreammaker::ast::Follow;
+use dreammaker::ast::FormatTreePath;
 use dreammaker::ast::PropertyAccessKind;
 use dreammaker::ast::{AssignOp, BinaryOp, UnaryOp};
-use dreammaker::{ast::Expression, Severity};
+use dreammaker::objtree::ObjectTree;
+use dreammaker::{ast::Expression, Location, Severity};
 
 use crate::operands::{self, DMString, Label, Value, Variable};
 use crate::Instruction;
@@ -14,14 +18,35 @@ mod assignment;
 mod args;
 mod binary_ops;
 mod builtin_procs;
+mod cache;
 mod chain_builder;
+mod fold;
 mod follow;
+mod metadata;
+mod new_expr;
+mod purity;
+mod stack_depth;
+mod statement;
 mod strings;
+mod template;
 mod term;
 mod ternary;
+mod type_check;
 mod unary;
 
 use chain_builder::ChainBuilder;
+use new_expr::NewExprs;
+
+pub use cache::Cache;
+pub use fold::const_eval;
+pub use metadata::{collect_metadata, Metadata};
+pub use purity::{purity, Purity};
+pub use statement::static_guard_name;
+pub use stack_depth::{max_stack_depth, stack_depths, StackDepthError};
+pub(crate) use builtin_procs::builtin_proc_name;
+pub(crate) use stack_depth::stack_effect;
+pub use type_check::StaticType;
+pub use template::{compile_template, Template, TemplateArg, TemplateError};
 
 // TODO: Think
 fn is_writable(var: &Variable) -> bool {
@@ -35,14 +60,16 @@ fn is_writable(var: &Variable) -> bool {
         // TODO: These can be constant too.
         | Variable::Arg { .. }
         | Variable::Local { .. }
-        // TODO: Stuff like global.vars are constant and should return false here.
-        | Variable::Global { .. } => true,
+        | Variable::NamedLocal { .. } => true,
+
+        // `global.vars` is the list of every global and can't be replaced, though its entries can be
+        Variable::Global(name) => name.0 != b"vars",
         _ => false,
     }
 }
 
 #[derive(Debug)]
-pub enum CompileError {
+pub enum CompileErrorKind {
     ParseError(dreammaker::DMError),
     StringError(strings::StringError),
 
@@ -55,6 +82,7 @@ pub enum CompileError {
     UnexpectedRange,
     UnexpectedGlobal,
     UnexpectedArgList,
+    UnexpectedNothing,
     UnexpectedProbability,
     UnexpectedNamedArguments,
 
@@ -63,80 +91,185 @@ pub enum CompileError {
     UnsupportedImplicitNew,
     UnsupportedRelativeCall,
     UnsupportedImplicitLocate,
+    UnsupportedImplicitAsType,
     UnsupportedStringInterpolation,
     UnsupportedInput,
+    UnsupportedStatement,
 
     AmbiguousListConstructor,
     InvalidLocateArgs,
 
+    // `x.field` where the type of `x` is known and doesn't have that var
+    UnknownField { type_path: String, field: String },
+
+    // A call to a proc that isn't in the object tree (or `CompilerOptions::global_procs`)
+    UnknownProc(String),
+
+    // A call the sandbox policy doesn't allow
+    ForbiddenCall(String),
+
+    // A `$` in a template that isn't followed by a number from 1 up
+    InvalidPlaceholder(String),
+
+    // Only in strict identifier mode, see `CompilerOptions::strict_identifiers`
+    UnknownIdentifier(String),
+
+    // A warning raised in strict mode
+    Warning(CompileWarningKind),
+
     // TODO: Merge these
     IncorrectArgCount(String),
     MissingArgument { proc: String, index: u32 },
     TooManyArguments { proc: String, expected: u32 },
 }
 
+/// A `CompileErrorKind` along with where it happened in the source
+#[derive(Debug)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+
+    /// The location of the innermost expression or statement being compiled, if it's known
+    pub location: Option<Location>,
+}
+
+impl CompileError {
+    fn or_location(mut self, location: Location) -> Self {
+        if self.location.is_none() {
+            self.location = Some(location);
+        }
+
+        self
+    }
+
+    // Maps a location in the source handed to dreammaker back to the caller's code, which was
+    // wrapped with `lines` extra lines above and indented by `columns`
+    fn unwrap_location(mut self, lines: u32, columns: u16) -> Self {
+        if let Some(location) = &mut self.location {
+            location.line = location.line.saturating_sub(lines);
+            location.column = location.column.saturating_sub(columns);
+        }
+
+        self
+    }
+}
+
+impl From<CompileErrorKind> for CompileError {
+    fn from(kind: CompileErrorKind) -> Self {
+        Self {
+            kind,
+            location: None,
+        }
+    }
+}
+
 impl From<strings::StringError> for CompileError {
     fn from(err: strings::StringError) -> Self {
-        Self::StringError(err)
+        CompileErrorKind::StringError(err).into()
     }
 }
 
 impl From<dreammaker::DMError> for CompileError {
     fn from(err: dreammaker::DMError) -> Self {
-        Self::ParseError(err)
+        Self {
+            location: Some(err.location()),
+            kind: CompileErrorKind::ParseError(err),
+        }
+    }
+}
+
+impl From<CompileWarning> for CompileError {
+    fn from(warning: CompileWarning) -> Self {
+        Self {
+            kind: CompileErrorKind::Warning(warning.kind),
+            location: warning.location,
+        }
     }
 }
 
 impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            // dreammaker's errors already say where they are
+            Some(location) if !matches!(self.kind, CompileErrorKind::ParseError(_)) => {
+                write!(f, "{}:{}: {}", location.line, location.column, self.kind)
+            }
+
+            _ => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl fmt::Display for CompileErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            CompileError::ParseError(err) => {
+            CompileErrorKind::ParseError(err) => {
                 write!(f, "parser error: {}", err)
             }
 
-            CompileError::StringError(err) => {
+            CompileErrorKind::StringError(err) => {
                 write!(f, "string error: {}", err)
             }
 
-            CompileError::ExpectedLValue => write!(f, "expected l-value"),
-            CompileError::ExpectedFieldReference => write!(f, "expected field reference"),
-            CompileError::ExpectedEnd => {
+            CompileErrorKind::ExpectedLValue => write!(f, "expected l-value"),
+            CompileErrorKind::ExpectedFieldReference => write!(f, "expected field reference"),
+            CompileErrorKind::ExpectedEnd => {
                 write!(f, "expected end (received more code than expected)")
             }
-            CompileError::UnexpectedRange => write!(f, "unexpected range"),
-            CompileError::UnexpectedGlobal => write!(f, "unexpected global"),
-            CompileError::UnexpectedArgList => write!(f, "unexpected arglist"),
-            CompileError::UnexpectedProbability => write!(f, "unexpected prob()"),
-            CompileError::UnexpectedNamedArguments => write!(f, "unexpected named arguments"),
-            CompileError::UnsupportedPrefabWithVars => {
+            CompileErrorKind::UnexpectedRange => write!(f, "unexpected range"),
+            CompileErrorKind::UnexpectedGlobal => write!(f, "unexpected global"),
+            CompileErrorKind::UnexpectedArgList => write!(f, "unexpected arglist"),
+            CompileErrorKind::UnexpectedNothing => write!(f, "expression has no value"),
+            CompileErrorKind::UnexpectedProbability => write!(f, "unexpected prob()"),
+            CompileErrorKind::UnexpectedNamedArguments => write!(f, "unexpected named arguments"),
+            CompileErrorKind::UnsupportedPrefabWithVars => {
                 write!(f, "prefabs with variable overrides are not supported")
             }
-            CompileError::UnsupportedBuiltin { proc } => {
+            CompileErrorKind::UnsupportedBuiltin { proc } => {
                 write!(f, "unsupported built-in proc: {}", proc)
             }
-            CompileError::UnsupportedImplicitNew => {
+            CompileErrorKind::UnsupportedImplicitNew => {
                 write!(f, "implicit new() calls are not supported")
             }
-            CompileError::UnsupportedRelativeCall => write!(f, "relative calls are not supported"),
-            CompileError::UnsupportedImplicitLocate => {
+            CompileErrorKind::UnsupportedRelativeCall => {
+                write!(f, "relative calls are not supported")
+            }
+            CompileErrorKind::UnsupportedImplicitLocate => {
                 write!(f, "implicit locate() calls are not supported")
             }
-            CompileError::UnsupportedStringInterpolation => {
+            CompileErrorKind::UnsupportedImplicitAsType => {
+                write!(f, "implicit astype() calls are not supported")
+            }
+            CompileErrorKind::UnsupportedStringInterpolation => {
                 write!(f, "interpolated strings are not supported")
             }
-            CompileError::UnsupportedInput => write!(f, "unsupported built-in proc: input"),
-            CompileError::AmbiguousListConstructor => write!(
+            CompileErrorKind::UnsupportedInput => write!(f, "unsupported built-in proc: input"),
+            CompileErrorKind::UnsupportedStatement => write!(f, "unsupported statement"),
+            CompileErrorKind::AmbiguousListConstructor => write!(
                 f,
                 "provided list constructor (or named parameters) are ambiguous"
             ),
-            CompileError::InvalidLocateArgs => write!(f, "invalid arguments for locate()"),
-            CompileError::IncorrectArgCount(proc) => {
+            CompileErrorKind::InvalidLocateArgs => write!(f, "invalid arguments for locate()"),
+            CompileErrorKind::UnknownField { type_path, field } => {
+                write!(f, "undefined var {} on {}", field, type_path)
+            }
+            CompileErrorKind::UnknownProc(path) => write!(f, "undefined proc: {}", path),
+            CompileErrorKind::InvalidPlaceholder(placeholder) => {
+                write!(f, "invalid template placeholder: {}", placeholder)
+            }
+            CompileErrorKind::ForbiddenCall(proc) => {
+                write!(f, "calling {}() is not allowed by the sandbox", proc)
+            }
+            CompileErrorKind::UnknownIdentifier(ident) => {
+                write!(f, "unknown identifier: {}", ident)
+            }
+            CompileErrorKind::Warning(kind) => write!(f, "{}", kind),
+            CompileErrorKind::IncorrectArgCount(proc) => {
                 write!(f, "incorrect amount of arguments for: {}", proc)
             }
-            CompileError::MissingArgument { proc, index: _ } => {
+            CompileErrorKind::MissingArgument { proc, index: _ } => {
                 write!(f, "missing argument(s) for: {}", proc)
             }
-            CompileError::TooManyArguments { proc, expected } => write!(
+            CompileErrorKind::TooManyArguments { proc, expected } => write!(
                 f,
                 "too many argument(s) for: {} (expected {})",
                 proc, expected
@@ -145,238 +278,2843 @@ impl fmt::Display for CompileError {
     }
 }
 
-pub fn compile_expr(code: &str, params: &[&str]) -> Result<Vec<Node>, CompileError> {
-    let mut compiler = Compiler {
-        params,
-        nodes: vec![Node::Instruction(
-            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
-            (),
-        )],
-        label_count: 0,
-        short_circuit_labels: vec![],
-    };
-
-    // Expression begin
-    let ctx = dreammaker::Context::default();
+/// Code that compiles fine but probably doesn't do what was intended
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileWarningKind {
+    // A plain `=` used as a condition. Wrapping it in (another set of) parentheses says it's on purpose.
+    AssignmentInCondition,
 
-    let mut lexer = dreammaker::lexer::Lexer::new(&ctx, Default::default(), code.as_bytes());
-    let mut indents = dreammaker::indents::IndentProcessor::new(&ctx, &mut lexer);
-    let expr = dreammaker::parser::parse_expression(&ctx, Default::default(), &mut indents)?;
+    // `x == null` is only true for null itself, not for 0 or "" like `!x` or `isnull(x)`
+    NullComparison,
 
-    if !lexer.remaining().is_empty() {
-        return Err(CompileError::ExpectedEnd);
-    }
+    // An identifier that isn't a local, param, built-in or known global, so it's looked up as a global var anyway
+    ImplicitGlobal(String),
 
-    // TODO: Make sure we've consumed the whole buffer
+    // A whole expression that's a `<<` or `>>` on something that isn't known to be an object or a number.
+    // It's compiled as a shift, but was probably meant as output or input.
+    AmbiguousShift(&'static str),
+}
 
-    for err in ctx.errors().iter() {
-        if err.severity() >= Severity::Error {
-            return Err(err.clone().into());
+impl fmt::Display for CompileWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileWarningKind::AssignmentInCondition => {
+                write!(
+                    f,
+                    "assignment used as a condition (wrap it in parentheses if intended)"
+                )
+            }
+            CompileWarningKind::NullComparison => {
+                write!(
+                    f,
+                    "comparison against null with == or != (consider isnull())"
+                )
+            }
+            CompileWarningKind::ImplicitGlobal(name) => {
+                write!(f, "unknown identifier treated as a global var: {}", name)
+            }
+            CompileWarningKind::AmbiguousShift(op) => {
+                write!(f, "{} compiled as a shift (write it as a statement for output or input)", op)
+            }
         }
     }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileWarning {
+    pub kind: CompileWarningKind,
+    pub location: Option<Location>,
+}
 
-    let kind = compiler.emit_expr(expr)?;
-    compiler.emit_move_to_stack(kind)?;
+impl CompileWarning {
+    // See `CompileError::unwrap_location`
+    fn unwrap_location(mut self, lines: u32, columns: u16) -> Self {
+        if let Some(location) = &mut self.location {
+            location.line = location.line.saturating_sub(lines);
+            location.column = location.column.saturating_sub(columns);
+        }
 
-    let mut arg_id = 0;
-    for _ in params {
-        compiler.emit_ins(Instruction::GetVar(Variable::Arg(arg_id)));
-        arg_id += 1;
+        self
     }
+}
 
-    compiler.emit_ins(Instruction::NewList(params.len() as u32 + 1));
-    compiler.emit_ins(Instruction::Ret);
-    Ok(compiler.nodes)
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{}:{}: {}", location.line, location.column, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-enum EvalKind {
-    // The result of the expression will be on the top of the stack
-    Stack,
+/// How much work the compiler does to shrink the emitted code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptimizationLevel {
+    /// Emit code exactly as written
+    #[default]
+    None,
 
-    // The result of the expression is a list entry L[K] where the top of the stack is the index and the 2nd top of the stack is the list
-    ListRef,
+    /// Evaluate constant arithmetic, string concatenation and ternaries at compile time
+    ConstantFolding,
 
-    // The result of the expression is 2 values on the Stack due to the `To` operator
-    Range,
+    /// Constant folding, plus running the emitted code through `optimizer::optimize`
+    Peephole,
+}
 
-    // The result of the expression is the `global` pseudo-object
-    Global,
+/// What a compiled expression returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReturnConvention {
+    /// `list(result, args...)`, so the caller can see anything the expression did to its args
+    #[default]
+    ResultAndArgs,
 
-    // The result of the expression is an arglist (which is on the top of the stack)
-    ArgList,
+    /// Just the result
+    Result,
 
-    // The result of the expression can be accessed using a Variable operand
-    Var(Variable),
+    /// The result is left in `.` and the proc ends without an explicit return
+    Dot,
+}
 
-    // Similar to Var, but more state
-    Field(ChainBuilder, String),
-    // TODO: Eval?
+/// Which procs compiled code may call, for running code from people who shouldn't have full control (like admin eval).
+/// Procs are matched by name alone, whether they're global procs, built-ins or called on an object (`world.Export()` is `Export`).
+/// `call()()` can't be checked any further than its name.
+#[derive(Debug, Clone)]
+pub enum SandboxPolicy {
+    /// Anything except these procs
+    Deny(Vec<String>),
+
+    /// Only these procs
+    Allow(Vec<String>),
 }
 
-impl fmt::Display for EvalKind {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl SandboxPolicy {
+    /// Denies procs that touch the host's files, run commands, reach the network or call arbitrary code.
+    /// This is a starting point rather than a guarantee: anything the game itself exposes is still callable.
+    pub fn deny_dangerous() -> Self {
+        let procs = [
+            "shell", "file", "fdel", "fcopy", "file2text", "text2file", "ftp", "call", "call_ext",
+            "Export", "Import", "Reboot", "del", "Del",
+        ];
+
+        Self::Deny(procs.iter().map(|&name| name.to_owned()).collect())
+    }
+
+    pub fn allows(&self, proc: &str) -> bool {
         match self {
-            EvalKind::Stack => write!(f, "stack value"),
-            EvalKind::ListRef => write!(f, "list access"),
-            EvalKind::Range => write!(f, "range"),
-            EvalKind::Global => write!(f, "global"),
-            EvalKind::ArgList => write!(f, "arglist"),
-            EvalKind::Var(_) => write!(f, "variable"),
-            EvalKind::Field(_, _) => write!(f, "field access"),
+            Self::Deny(procs) => !procs.iter().any(|name| name == proc),
+            Self::Allow(procs) => procs.iter().any(|name| name == proc),
         }
     }
 }
 
+/// Everything that changes how code gets compiled. Start from `CompilerOptions::new()` and chain the setters:
+///
+/// ```ignore
+/// let options = CompilerOptions::new()
+///     .optimization_level(OptimizationLevel::Peephole)
+///     .target_version(514)
+///     .define("MAX_HEALTH", "100");
+/// ```
 #[derive(Clone)]
-struct Compiler<'a> {
-    params: &'a [&'a str],
-    nodes: Vec<Node>,
-    label_count: u32,
-    short_circuit_labels: Vec<(String, bool)>,
+pub struct CompilerOptions {
+    pub optimization_level: OptimizationLevel,
+
+    /// The oldest BYOND version (such as 514) the code has to run on. Nothing depends on it yet: the instructions
+    /// newer versions added are only used once their opcodes are confirmed, and then only when the target has them.
+    pub target_version: Option<u32>,
+
+    /// Turns every warning into an error
+    pub strict: bool,
+
+    /// Makes identifiers that aren't a local, param, built-in or one of `globals` an error,
+    /// instead of quietly reading a global var of that name
+    pub strict_identifiers: bool,
+
+    /// Global vars the code is allowed to use by name
+    pub globals: Vec<String>,
+
+    /// The name of a param holding an associative list. Identifiers that would otherwise be global vars
+    /// are looked up in it by name instead, so the host can hand values to the code without making globals.
+    pub environment: Option<String>,
+
+    /// Whether to emit DbgFile, so runtimes and profilers know where the code came from
+    pub debug_info: bool,
+
+    /// Handed to the preprocessor. Each define is a `(name, value)` pair as it would appear after `#define`,
+    /// so `("MAX(a, b)", "max(a, b)")` works too.
+    pub defines: Vec<(String, String)>,
+
+    /// Given the name of a var being assigned to, returns its type path (like `/obj/item`).
+    /// This is what `new`, `locate()` and `astype(x)` without a type create or look for.
+    /// Vars declared with a type in the code itself don't need it.
+    pub implied_type: Option<fn(&str) -> Option<String>>,
+
+    /// Maps identifiers to any variable the host likes (a cached datum, an auxtools slot...).
+    /// It's asked about everything that isn't a local, so it can shadow params and globals too.
+    pub resolver: Option<fn(&str) -> Option<Variable>>,
+
+    /// The code the compiled code will run against. With it, `.` field accesses and proc calls on values
+    /// of a known type are checked, and calls to procs that don't exist are errors.
+    /// The procs it finds are called by their path (like `/datum/proc/foo`) instead of by name.
+    pub object_tree: Option<Arc<ObjectTree>>,
+
+    /// Names of global procs that exist, for when there's no object tree. Calls to any other proc are errors.
+    pub global_procs: Vec<String>,
+
+    /// The type of `src` (like `/mob/living`), if it's known
+    pub src_type: Option<String>,
+
+    /// Calls the sandbox doesn't allow are errors
+    pub sandbox: Option<SandboxPolicy>,
+
+    /// Code to put at the start of a compiled expression, after the DbgFile (which `debug_info` can turn off).
+    /// Labels in it mustn't look like the compiler's own (`LAB_0000` and friends).
+    pub prologue: Vec<Node>,
+
+    /// Code to put right before a compiled expression returns. Whatever it returns is on top of the stack,
+    /// except with `ReturnConvention::Dot` where it's already in `.`.
+    pub epilogue: Vec<Node>,
+
+    /// What a compiled expression hands back to whatever runs it
+    pub return_convention: ReturnConvention,
 }
 
-impl<'a> Compiler<'a> {
-    fn emit_ins(&mut self, ins: Instruction) {
-        self.nodes.push(Node::Instruction(ins, ()));
+// ObjectTree isn't Debug
+impl fmt::Debug for CompilerOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompilerOptions")
+            .field("optimization_level", &self.optimization_level)
+            .field("target_version", &self.target_version)
+            .field("strict", &self.strict)
+            .field("strict_identifiers", &self.strict_identifiers)
+            .field("globals", &self.globals)
+            .field("environment", &self.environment)
+            .field("debug_info", &self.debug_info)
+            .field("defines", &self.defines)
+            .field("implied_type", &self.implied_type)
+            .field("resolver", &self.resolver)
+            .field("object_tree", &self.object_tree.is_some())
+            .field("global_procs", &self.global_procs)
+            .field("src_type", &self.src_type)
+            .field("sandbox", &self.sandbox)
+            .field("prologue", &self.prologue)
+            .field("epilogue", &self.epilogue)
+            .field("return_convention", &self.return_convention)
+            .finish()
+    }
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self {
+            optimization_level: OptimizationLevel::None,
+            target_version: None,
+            strict: false,
+            strict_identifiers: false,
+            globals: vec![],
+            environment: None,
+            debug_info: true,
+            defines: vec![],
+            implied_type: None,
+            resolver: None,
+            object_tree: None,
+            global_procs: vec![],
+            src_type: None,
+            sandbox: None,
+            prologue: vec![],
+            epilogue: vec![],
+            return_convention: ReturnConvention::ResultAndArgs,
+        }
     }
+}
 
-    fn emit_label(&mut self, label: String) {
-        self.nodes.push(Node::Label(label));
+impl CompilerOptions {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn emit_find_var(&mut self, ident: dreammaker::ast::Ident) -> EvalKind {
-        if let Some(index) = self.params.iter().rposition(|x| *x == ident) {
-            return EvalKind::Var(Variable::Arg(index as u32));
-        }
+    pub fn optimization_level(mut self, level: OptimizationLevel) -> Self {
+        self.optimization_level = level;
+        self
+    }
 
-        match ident.as_str() {
-            "." => EvalKind::Var(Variable::Dot),
-            "usr" => EvalKind::Var(Variable::Usr),
-            "src" => EvalKind::Var(Variable::Src),
-            "args" => EvalKind::Var(Variable::Args),
-            "world" => EvalKind::Var(Variable::World),
-            "global" => EvalKind::Global,
+    pub fn target_version(mut self, version: u32) -> Self {
+        self.target_version = Some(version);
+        self
+    }
 
-            // Anything else is treated as a global var
-            _ => EvalKind::Var(Variable::Global(DMString(ident.into()))),
-        }
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
-    fn emit_move_to_stack(&mut self, kind: EvalKind) -> Result<EvalKind, CompileError> {
-        match kind {
-            EvalKind::Stack => {}
+    pub fn strict_identifiers(mut self, strict_identifiers: bool) -> Self {
+        self.strict_identifiers = strict_identifiers;
+        self
+    }
 
-            EvalKind::ListRef => {
-                self.emit_ins(Instruction::ListGet);
-            }
+    pub fn global(mut self, name: &str) -> Self {
+        self.globals.push(name.to_owned());
+        self
+    }
 
-            EvalKind::Range => return Err(CompileError::UnexpectedRange),
-            EvalKind::Global => return Err(CompileError::UnexpectedGlobal),
-            EvalKind::ArgList => return Err(CompileError::UnexpectedArgList),
+    pub fn environment(mut self, param: &str) -> Self {
+        self.environment = Some(param.to_owned());
+        self
+    }
 
-            EvalKind::Var(var) => {
-                self.emit_ins(Instruction::GetVar(var));
-            }
+    pub fn debug_info(mut self, debug_info: bool) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
 
-            EvalKind::Field(builder, field) => {
-                let var = builder.get_field(DMString(field.into()));
-                self.emit_ins(Instruction::GetVar(var));
-            }
-        }
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.push((name.to_owned(), value.to_owned()));
+        self
+    }
 
-        Ok(EvalKind::Stack)
+    pub fn implied_type(mut self, hook: fn(&str) -> Option<String>) -> Self {
+        self.implied_type = Some(hook);
+        self
     }
 
-    // TODO: lots of copied code from emit_move_to_stack
-    fn emit_move_to_chain_builder(&mut self, kind: EvalKind) -> Result<ChainBuilder, CompileError> {
-        match kind {
-            EvalKind::Stack => {
-                self.emit_ins(Instruction::SetVar(Variable::Cache));
-                Ok(ChainBuilder::begin(Variable::Cache))
-            }
+    pub fn resolver(mut self, hook: fn(&str) -> Option<Variable>) -> Self {
+        self.resolver = Some(hook);
+        self
+    }
 
-            EvalKind::ListRef => {
-                self.emit_ins(Instruction::ListGet);
-                self.emit_ins(Instruction::SetVar(Variable::Cache));
-                Ok(ChainBuilder::begin(Variable::Cache))
-            }
+    pub fn object_tree(mut self, tree: Arc<ObjectTree>) -> Self {
+        self.object_tree = Some(tree);
+        self
+    }
 
-            EvalKind::Range => Err(CompileError::UnexpectedRange),
-            EvalKind::Global => Err(CompileError::UnexpectedGlobal),
-            EvalKind::ArgList => return Err(CompileError::UnexpectedArgList),
+    pub fn global_proc(mut self, name: &str) -> Self {
+        self.global_procs.push(name.to_owned());
+        self
+    }
 
-            EvalKind::Field(mut builder, field) => {
-                builder.append(DMString(field.into()));
-                Ok(builder)
-            }
+    pub fn src_type(mut self, path: &str) -> Self {
+        self.src_type = Some(path.to_owned());
+        self
+    }
 
-            EvalKind::Var(var) => Ok(ChainBuilder::begin(var)),
-        }
+    pub fn sandbox(mut self, policy: SandboxPolicy) -> Self {
+        self.sandbox = Some(policy);
+        self
     }
 
-    fn short_circuit(&mut self) -> String {
-        let label = self.short_circuit_labels.last_mut().unwrap();
-        label.1 = true;
-        label.0.to_owned()
+    pub fn prologue(mut self, nodes: Vec<Node>) -> Self {
+        self.prologue = nodes;
+        self
     }
 
-    fn emit_inner_expr(&mut self, expr: Expression) -> Result<EvalKind, CompileError> {
-        match expr {
-            Expression::TernaryOp { cond, if_, else_ } => ternary::emit(self, *cond, *if_, *else_),
-            Expression::BinaryOp { op, lhs, rhs } => binary_ops::emit(self, op, *lhs, *rhs),
-            Expression::AssignOp { op, lhs, rhs } => assignment::emit(self, op, *lhs, *rhs),
+    pub fn epilogue(mut self, nodes: Vec<Node>) -> Self {
+        self.epilogue = nodes;
+        self
+    }
 
-            Expression::Base {
-                unary,
-                term,
-                follow,
-            } => {
-                let unspanned_follows: Vec<Follow> = follow.into_iter().map(|f| f.elem).collect();
-                let kind = term::emit(self, term.elem)?;
-                let kind = follow::emit(self, unspanned_follows, kind)?;
-                let kind = unary::emit(self, unary, kind)?;
-                Ok(kind)
-            }
-        }
+    pub fn return_convention(mut self, convention: ReturnConvention) -> Self {
+        self.return_convention = convention;
+        self
     }
+}
 
-    fn emit_expr(&mut self, expr: Expression) -> Result<EvalKind, CompileError> {
-        let label = format!("LAB_{:0>4X}", self.label_count);
-        self.label_count += 1;
-        self.short_circuit_labels.push((label, false));
+/// The output of `compile_expr_with_type`
+#[derive(Debug)]
+pub struct CompiledExpr {
+    pub nodes: Vec<Node>,
+    pub warnings: Vec<CompileWarning>,
+    pub result_type: StaticType,
+}
 
-        let kind = self.emit_inner_expr(expr)?;
+/// The output of `compile_proc`
+#[derive(Debug)]
+pub struct CompiledProc {
+    pub nodes: Vec<Node>,
 
-        let (label, used) = self.short_circuit_labels.pop().unwrap();
+    /// Names of the `var/global` (or `var/static`) declarations in the proc, plus `static_guard_name(name)` for each
+    /// one given a value, which remembers that it's been set. The host has to create all of them, with the guards
+    /// starting out null, and keep them between calls.
+    pub globals: Vec<String>,
 
-        // We only care if the label was actually used
-        // TODO: BYOND would put this jump destination before any unary ops (if this is Expression::Base), idk if that is sane.
-        if used {
-            self.emit_move_to_stack(kind)?;
-            self.emit_label(label);
-            return Ok(EvalKind::Stack);
-        }
+    /// Names of the proc's local variables, indexed by their slot
+    pub locals: Vec<String>,
 
-        Ok(kind)
+    pub warnings: Vec<CompileWarning>,
+
+    /// Globals and procs the code uses, see `collect_metadata`
+    pub metadata: Metadata,
+}
+
+fn check_parse_errors(ctx: &dreammaker::Context) -> Result<(), Vec<CompileError>> {
+    let errors: Vec<CompileError> = ctx
+        .errors()
+        .iter()
+        .filter(|err| err.severity() >= Severity::Error)
+        .map(|err| err.clone().into())
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
     }
+
+    Ok(())
 }
 
-#[test]
-fn compile_test() {
-    let context: dreammaker::Context = Default::default();
-    let lexer =
-        dreammaker::lexer::Lexer::new(&context, Default::default(), "pick(1;;2,3;4)".as_bytes());
-    let expr = dreammaker::parser::parse_expression(&context, Default::default(), lexer);
-    context.assert_success();
-    println!("{:#?}\n\n\n", expr);
+// Internally errors come in batches, but most entry points only report the first
+fn first_error(mut errors: Vec<CompileError>) -> CompileError {
+    errors.remove(0)
+}
 
-    let expr = compile_expr("a?.b++", &["a"]);
-    println!("{:#?}", expr);
+fn unwrap_locations(errors: Vec<CompileError>, lines: u32, columns: u16) -> Vec<CompileError> {
+    errors
+        .into_iter()
+        .map(|err| err.unwrap_location(lines, columns))
+        .collect()
+}
 
-    if let Ok(expr) = expr {
-        println!("{}", crate::format(&expr));
-        let code = crate::assembler::assemble(&expr, &mut crate::TestAssembleEnv);
-        println!("{:#x?}", code);
+pub fn compile_expr(code: &str, params: &[&str]) -> Result<Vec<Node>, CompileError> {
+    compile_expr_with_options(code, params, &CompilerOptions::default())
+}
+
+pub fn compile_expr_with_options(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<Vec<Node>, CompileError> {
+    compile_expr_with_type(code, params, options).map(|expr| expr.nodes)
+}
+
+/// Like `compile_expr_with_options`, but also returns the warnings raised along the way.
+pub fn compile_expr_with_warnings(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<(Vec<Node>, Vec<CompileWarning>), CompileError> {
+    compile_expr_with_type(code, params, options).map(|expr| (expr.nodes, expr.warnings))
+}
+
+/// Like `compile_expr_with_warnings`, but also works out the type of the result.
+/// This is far more useful when the options have an object tree to look up var types in.
+pub fn compile_expr_with_type(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<CompiledExpr, CompileError> {
+    compile_expr_body(code, params, options, false).map_err(first_error)
+}
+
+/// Like `compile_expr_with_type`, but reports every error. A part of the expression that fails to compile is
+/// skipped over so the parts next to it still get checked, and with `strict` each warning is an error of its own.
+pub fn compile_expr_collecting_errors(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<CompiledExpr, Vec<CompileError>> {
+    compile_expr_body(code, params, options, true)
+}
+
+fn compile_expr_body(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledExpr, Vec<CompileError>> {
+    if !options.defines.is_empty() {
+        return compile_preprocessed_expr(code, params, options, collect_errors);
     }
+
+    let (expr, new_exprs) = parse_expr(code).map_err(|err| vec![err])?;
+    compile_parsed_expr(expr, &new_exprs, params, options, collect_errors)
+}
+
+fn parse_expr(code: &str) -> Result<(Expression, NewExprs), CompileError> {
+    let ctx = dreammaker::Context::default();
+    let (code, new_exprs) = new_expr::rewrite(code);
+
+    let mut lexer = dreammaker::lexer::Lexer::new(&ctx, Default::default(), code.as_bytes());
+    let mut indents = dreammaker::indents::IndentProcessor::new(&ctx, &mut lexer);
+    let expr = dreammaker::parser::parse_expression(&ctx, Default::default(), &mut indents)?;
+
+    if !lexer.remaining().is_empty() {
+        return Err(CompileErrorKind::ExpectedEnd.into());
+    }
+
+    // TODO: Make sure we've consumed the whole buffer
+
+    check_parse_errors(&ctx).map_err(first_error)?;
+
+    Ok((expr, new_exprs))
+}
+
+// The preprocessor only feeds the object tree parser, so with defines the expression is parsed as a return statement
+fn compile_preprocessed_expr(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledExpr, Vec<CompileError>> {
+    let wrapper_lines = options.defines.len() as u32 + 1;
+    let wrapper_columns = 1 + RETURN_PREFIX.len() as u16;
+
+    let (block, new_exprs) = parse_proc_body(&format!("{}{}", RETURN_PREFIX, code), &options.defines)
+        .map_err(|errors| unwrap_locations(errors, wrapper_lines, wrapper_columns))?;
+    let mut block = block.into_vec();
+
+    if block.len() != 1 {
+        return Err(vec![CompileErrorKind::ExpectedEnd.into()]);
+    }
+
+    let expr = match block.pop().unwrap().elem {
+        dreammaker::ast::Statement::Return(Some(expr)) => expr,
+        _ => return Err(vec![CompileErrorKind::ExpectedEnd.into()]),
+    };
+
+    let mut compiled = compile_parsed_expr(expr, &new_exprs, params, options, collect_errors)
+        .map_err(|errors| unwrap_locations(errors, wrapper_lines, wrapper_columns))?;
+
+    compiled.warnings = compiled
+        .warnings
+        .into_iter()
+        .map(|warning| warning.unwrap_location(wrapper_lines, wrapper_columns))
+        .collect();
+
+    Ok(compiled)
+}
+
+/// Compiles an expression that's already been parsed by dreammaker, such as one taken from a larger file.
+/// Errors and warnings keep the locations from the AST.
+pub fn compile_expr_ast(expr: Expression, params: &[&str]) -> Result<Vec<Node>, CompileError> {
+    compile_expr_ast_with_options(expr, params, &CompilerOptions::default())
+}
+
+/// Like `compile_expr_ast`. The defines in the options are ignored, as preprocessing is already done.
+pub fn compile_expr_ast_with_options(
+    expr: Expression,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<Vec<Node>, CompileError> {
+    compile_parsed_expr(expr, &NewExprs::default(), params, options, false)
+        .map(|expr| expr.nodes)
+        .map_err(first_error)
+}
+
+const RETURN_PREFIX: &str = "return ";
+
+// `new_exprs` are the `new (expr)(args)` in the code the expression was parsed from, see `new_expr`
+fn compile_parsed_expr(
+    expr: Expression,
+    new_exprs: &NewExprs,
+    params: &[&str],
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledExpr, Vec<CompileError>> {
+    let mut compiler = Compiler::new(params, b"<dmasm expression>", options);
+    compiler.collect_errors = collect_errors;
+    compiler.new_exprs = new_exprs.clone();
+    compiler.nodes.extend(options.prologue.iter().cloned());
+
+    let mut result_type = type_check::infer_type(&compiler, &expr);
+
+    // A `<<` or `>>` making up the whole expression is output or input when the left side is known to be an object,
+    // like `world << "hi"`. Otherwise it stays a shift, with a warning unless the left side is known to be a number.
+    let kind = match compiler.optimize_expr(expr) {
+        Expression::BinaryOp { op, lhs, rhs } if op == BinaryOp::LShift || op == BinaryOp::RShift => {
+            match type_check::infer_type(&compiler, &lhs) {
+                StaticType::Object(_) => {
+                    result_type = StaticType::Null;
+                    binary_ops::emit_io(&mut compiler, op, *lhs, *rhs)
+                }
+
+                lhs_type => {
+                    if lhs_type != StaticType::Number {
+                        let op_str = if op == BinaryOp::LShift { "<<" } else { ">>" };
+                        compiler.warnings.push(CompileWarning {
+                            kind: CompileWarningKind::AmbiguousShift(op_str),
+                            location: Some(expr_location(&lhs)),
+                        });
+                    }
+
+                    compiler.emit_expr(Expression::BinaryOp { op, lhs, rhs })
+                }
+            }
+        }
+
+        expr => compiler.emit_expr(expr),
+    }
+    .map_err(|err| vec![err])?;
+    compiler
+        .emit_move_to_stack(kind)
+        .map_err(|err| vec![err])?;
+
+    match options.return_convention {
+        ReturnConvention::ResultAndArgs => {
+            let mut arg_id = 0;
+            for _ in params {
+                compiler.emit_ins(Instruction::GetVar(Variable::Arg(arg_id)));
+                arg_id += 1;
+            }
+
+            compiler.emit_ins(Instruction::NewList(params.len() as u32 + 1));
+            compiler.nodes.extend(options.epilogue.iter().cloned());
+            compiler.emit_ins(Instruction::Ret);
+        }
+
+        ReturnConvention::Result => {
+            compiler.nodes.extend(options.epilogue.iter().cloned());
+            compiler.emit_ins(Instruction::Ret);
+        }
+
+        ReturnConvention::Dot => {
+            compiler.emit_ins(Instruction::SetVar(Variable::Dot));
+            compiler.nodes.extend(options.epilogue.iter().cloned());
+            compiler.emit_ins(Instruction::End);
+        }
+    }
+
+    let mut errors = std::mem::take(&mut compiler.errors);
+
+    if options.strict {
+        errors.extend(compiler.warnings.drain(..).map(CompileError::from));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(CompiledExpr {
+        nodes: compiler.finish_nodes(),
+        warnings: compiler.warnings,
+        result_type,
+    })
+}
+
+// The source handed to the preprocessor, as if it were the .dme
+const PREPROCESSOR_ENV_FILE: &str = "<dmasm>.dme";
+
+#[cfg(test)]
+fn parse_object_tree(
+    source: &str,
+    defines: &[(String, String)],
+) -> Result<dreammaker::objtree::ObjectTree, Vec<CompileError>> {
+    parse_source(source, defines).map(|(tree, _)| tree)
+}
+
+fn parse_source(
+    source: &str,
+    defines: &[(String, String)],
+) -> Result<(dreammaker::objtree::ObjectTree, NewExprs), Vec<CompileError>> {
+    let ctx = dreammaker::Context::default();
+
+    // dreammaker has no way to hand the preprocessor a define table, so the defines are prepended as directives
+    let mut buffer = String::new();
+    for (name, value) in defines {
+        buffer.push_str(&format!("#define {} {}\n", name, value));
+    }
+    buffer.push_str(source);
+    let (buffer, new_exprs) = new_expr::rewrite(&buffer);
+
+    let preprocessor = dreammaker::preprocessor::Preprocessor::from_buffer(
+        &ctx,
+        PREPROCESSOR_ENV_FILE.into(),
+        buffer,
+    );
+    let indents = dreammaker::indents::IndentProcessor::new(&ctx, preprocessor);
+    let mut parser = dreammaker::parser::Parser::new(&ctx, indents);
+    parser.enable_procs();
+    let tree = parser.parse_object_tree();
+
+    check_parse_errors(&ctx)?;
+    Ok((tree, new_exprs))
+}
+
+// dreammaker only parses statements as part of a proc definition, so the code gets wrapped in one
+const PROC_WRAPPER_NAME: &str = "__dmasm_proc";
+
+fn parse_proc_body(
+    code: &str,
+    defines: &[(String, String)],
+) -> Result<(dreammaker::ast::Block, NewExprs), Vec<CompileError>> {
+    let mut source = format!("/proc/{}()\n", PROC_WRAPPER_NAME);
+
+    for line in code.lines() {
+        source.push('\t');
+        source.push_str(line);
+        source.push('\n');
+    }
+
+    let (tree, new_exprs) = parse_source(&source, defines)?;
+
+    // An empty body doesn't get any code
+    let block = tree
+        .root()
+        .get_proc(PROC_WRAPPER_NAME)
+        .and_then(|proc| proc.get().code.clone())
+        .unwrap_or_default();
+
+    Ok((block, new_exprs))
+}
+
+// With `collect_errors`, compilation carries on with the next statement after an error.
+// `line_offset` is how many lines of wrapping the code was given before parsing, so DbgLine can point at the original line.
+fn compile_block(
+    block: dreammaker::ast::Block,
+    new_exprs: &NewExprs,
+    params: &[&str],
+    file: &[u8],
+    options: &CompilerOptions,
+    collect_errors: bool,
+    line_offset: u32,
+) -> Result<CompiledProc, Vec<CompileError>> {
+    let mut compiler = Compiler::new(params, file, options);
+    compiler.collect_errors = collect_errors;
+    compiler.line_offset = line_offset;
+    compiler.new_exprs = new_exprs.clone();
+
+    let mut errors = vec![];
+
+    for statement in block.into_vec() {
+        let location = statement.location;
+        compiler.location = Some(location);
+        compiler.emit_line(location);
+
+        let result = statement::emit(&mut compiler, statement.elem);
+
+        // Errors in the statement's expressions that compilation carried on past
+        errors.append(&mut compiler.errors);
+
+        if let Err(err) = result {
+            errors.push(err.or_location(location));
+
+            if !collect_errors {
+                break;
+            }
+        }
+    }
+
+    if options.strict {
+        errors.extend(compiler.warnings.drain(..).map(CompileError::from));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // No need for another `End` when the proc already finishes with a `return`
+    let returns = matches!(
+        compiler.nodes.last(),
+        Some(Node::Instruction(ins, ())) if !crate::cfg::falls_through(ins)
+    );
+    if !returns {
+        compiler.emit_ins(Instruction::End);
+    }
+
+    let nodes = compiler.finish_nodes();
+
+    Ok(CompiledProc {
+        metadata: collect_metadata(&nodes),
+        nodes,
+        globals: compiler.globals,
+        locals: compiler.locals,
+        warnings: compiler.warnings,
+    })
+}
+
+/// Compiles a block of statements as the body of a proc.
+pub fn compile_proc(code: &str, params: &[&str]) -> Result<CompiledProc, CompileError> {
+    compile_proc_with_options(code, params, &CompilerOptions::default())
+}
+
+pub fn compile_proc_with_options(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<CompiledProc, CompileError> {
+    compile_proc_body(code, params, options, false).map_err(first_error)
+}
+
+/// Like `compile_proc_with_options`, but carries on past errors so all of them can be reported at once.
+/// Compilation resumes at the next statement, so only the first error in each statement is found.
+pub fn compile_proc_collecting_errors(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<CompiledProc, Vec<CompileError>> {
+    compile_proc_body(code, params, options, true)
+}
+
+/// Compiles a block of statements that's already been parsed by dreammaker as the body of a proc,
+/// like the `code` of a proc in an `ObjectTree`.
+pub fn compile_proc_ast(
+    block: dreammaker::ast::Block,
+    params: &[&str],
+) -> Result<CompiledProc, CompileError> {
+    compile_proc_ast_with_options(block, params, &CompilerOptions::default())
+}
+
+/// Like `compile_proc_ast`. The defines in the options are ignored, as preprocessing is already done.
+pub fn compile_proc_ast_with_options(
+    block: dreammaker::ast::Block,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<CompiledProc, CompileError> {
+    compile_block(block, &NewExprs::default(), params, b"<dmasm proc>", options, false, 0).map_err(first_error)
+}
+
+fn compile_proc_body(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledProc, Vec<CompileError>> {
+    // Each line of the code was indented by a tab under the wrapper proc
+    let wrapper_lines = options.defines.len() as u32 + 1;
+
+    let mut compiled = parse_proc_body(code, &options.defines)
+        .and_then(|(block, new_exprs)| {
+            compile_block(block, &new_exprs, params, b"<dmasm proc>", options, collect_errors, wrapper_lines)
+        })
+        .map_err(|errors| unwrap_locations(errors, wrapper_lines, 1))?;
+
+    compiled.warnings = compiled
+        .warnings
+        .into_iter()
+        .map(|warning| warning.unwrap_location(wrapper_lines, 1))
+        .collect();
+
+    Ok(compiled)
+}
+
+/// A var declared by a type in a file passed to `compile_file`
+#[derive(Debug, PartialEq)]
+pub struct DeclaredVar {
+    pub name: String,
+    pub type_path: String,
+    pub is_static: bool,
+}
+
+/// The output of `compile_file`
+#[derive(Debug, Default)]
+pub struct CompiledFile {
+    /// Every proc with a body, keyed by path (such as `/datum/foo/proc/bar`)
+    pub procs: BTreeMap<String, CompiledProc>,
+
+    /// Every var declaration, keyed by the path of the type declaring it
+    pub vars: BTreeMap<String, Vec<DeclaredVar>>,
+}
+
+/// Compiles every proc defined in a whole DM file.
+/// Built-in types are part of the tree, but only code and vars from the file itself are returned.
+pub fn compile_file(source: &str) -> Result<CompiledFile, CompileError> {
+    compile_file_with_options(source, &CompilerOptions::default())
+}
+
+pub fn compile_file_with_options(
+    source: &str,
+    options: &CompilerOptions,
+) -> Result<CompiledFile, CompileError> {
+    compile_file_body(source, options, false).map_err(first_error)
+}
+
+/// Like `compile_file_with_options`, but carries on past errors so all of them can be reported at once.
+/// Every proc is compiled even if an earlier one failed (see `compile_proc_collecting_errors`).
+pub fn compile_file_collecting_errors(
+    source: &str,
+    options: &CompilerOptions,
+) -> Result<CompiledFile, Vec<CompileError>> {
+    compile_file_body(source, options, true)
+}
+
+fn compile_file_body(
+    source: &str,
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledFile, Vec<CompileError>> {
+    // The defines are prepended to the file
+    let define_lines = options.defines.len() as u32;
+
+    let mut file = parse_source(source, &options.defines)
+        .and_then(|(tree, new_exprs)| compile_tree(tree, &new_exprs, options, collect_errors))
+        .map_err(|errors| unwrap_locations(errors, define_lines, 0))?;
+
+    for proc in file.procs.values_mut() {
+        proc.warnings = std::mem::take(&mut proc.warnings)
+            .into_iter()
+            .map(|warning| warning.unwrap_location(define_lines, 0))
+            .collect();
+    }
+
+    Ok(file)
+}
+
+fn compile_tree(
+    tree: dreammaker::objtree::ObjectTree,
+    new_exprs: &NewExprs,
+    options: &CompilerOptions,
+    collect_errors: bool,
+) -> Result<CompiledFile, Vec<CompileError>> {
+    let mut file = CompiledFile::default();
+    let mut errors = vec![];
+
+    for ty in tree.iter_types() {
+        let ty = ty.get();
+
+        for (name, var) in ty.vars.iter() {
+            let declaration = match &var.declaration {
+                Some(declaration) if !declaration.location.is_builtins() => declaration,
+                _ => continue,
+            };
+
+            file.vars
+                .entry(ty.path.clone())
+                .or_default()
+                .push(DeclaredVar {
+                    name: name.clone(),
+                    type_path: format!("{}", FormatTreePath(&declaration.var_type.type_path)),
+                    is_static: declaration.var_type.flags.is_static(),
+                });
+        }
+
+        for (name, proc) in ty.procs.iter() {
+            // Only the most recent definition matters
+            let value = match proc.value.last() {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let code = match &value.code {
+                Some(code) => code.clone(),
+                None => continue,
+            };
+
+            let params: Vec<&str> = value.parameters.iter().map(|x| x.name.as_str()).collect();
+            let path = format!("{}/proc/{}", ty.path, name);
+            let define_lines = options.defines.len() as u32;
+            match compile_block(code, new_exprs, &params, b"<dmasm file>", options, collect_errors, define_lines) {
+                Ok(compiled) => {
+                    file.procs.insert(path, compiled);
+                }
+
+                Err(mut proc_errors) if collect_errors => errors.append(&mut proc_errors),
+                Err(proc_errors) => return Err(proc_errors),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(file)
+}
+
+// Where the left-most term of an expression starts
+fn expr_location(expr: &Expression) -> Location {
+    match expr {
+        Expression::Base { term, .. } => term.location,
+        Expression::BinaryOp { lhs, .. } => expr_location(lhs),
+        Expression::AssignOp { lhs, .. } => expr_location(lhs),
+        Expression::TernaryOp { cond, .. } => expr_location(cond),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum EvalKind {
+    // The result of the expression will be on the top of the stack
+    Stack,
+
+    // The result of the expression is a list entry L[K] where the top of the stack is the index and the 2nd top of the stack is the list
+    ListRef,
+
+    // The result of the expression is 2 values on the Stack due to the `To` operator
+    Range,
+
+    // The result of the expression is the `global` pseudo-object
+    Global,
+
+    // The result of the expression is an arglist (which is on the top of the stack)
+    ArgList,
+
+    // The expression doesn't have a result, like output with `<<`
+    Nothing,
+
+    // The result of the expression can be accessed using a Variable operand
+    Var(Variable),
+
+    // Similar to Var, but more state
+    Field(ChainBuilder, String),
+    // TODO: Eval?
+}
+
+impl fmt::Display for EvalKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalKind::Stack => write!(f, "stack value"),
+            EvalKind::ListRef => write!(f, "list access"),
+            EvalKind::Range => write!(f, "range"),
+            EvalKind::Global => write!(f, "global"),
+            EvalKind::ArgList => write!(f, "arglist"),
+            EvalKind::Nothing => write!(f, "nothing"),
+            EvalKind::Var(_) => write!(f, "variable"),
+            EvalKind::Field(_, _) => write!(f, "field access"),
+        }
+    }
+}
+
+struct Compiler<'a> {
+    params: &'a [&'a str],
+    options: &'a CompilerOptions,
+    nodes: Vec<Node>,
+    label_count: u32,
+    short_circuit_labels: Vec<(String, bool)>,
+
+    // Variables declared by statements
+    locals: Vec<String>,
+    globals: Vec<String>,
+
+    // The declared type path of each local, if it has one
+    local_types: Vec<Option<String>>,
+
+    // Where the innermost expression (or statement) being compiled is, for warnings
+    location: Option<Location>,
+    warnings: Vec<CompileWarning>,
+
+    // The type of the var the expression being compiled is assigned to, see `CompilerOptions::implied_type`
+    implied_type: Option<String>,
+
+    // With `collect_errors`, an expression that fails to compile has its error kept here and is replaced by null,
+    // so the expressions around it still get compiled
+    collect_errors: bool,
+    errors: Vec<CompileError>,
+
+    // How many lines of wrapping the code was given before parsing, and the last line a DbgLine was emitted for
+    line_offset: u32,
+    last_line: Option<u32>,
+
+    // Where the code had `new (expr)(args)`, which dreammaker parsed as an implicit `new` (see `new_expr`)
+    new_exprs: NewExprs,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(params: &'a [&'a str], file: &[u8], options: &'a CompilerOptions) -> Self {
+        let mut compiler = Self {
+            params,
+            options,
+            nodes: vec![],
+            label_count: 0,
+            short_circuit_labels: vec![],
+            locals: vec![],
+            globals: vec![],
+            local_types: vec![],
+            location: None,
+            warnings: vec![],
+            implied_type: None,
+            collect_errors: false,
+            errors: vec![],
+            line_offset: 0,
+            last_line: None,
+            new_exprs: NewExprs::default(),
+        };
+
+        if options.debug_info {
+            compiler.emit_ins(Instruction::DbgFile(DMString(file.to_vec())));
+        }
+
+        compiler
+    }
+
+    // Marks the code that follows as coming from the statement at `location`, when debug info is on
+    fn emit_line(&mut self, location: Location) {
+        let line = location.line.saturating_sub(self.line_offset);
+        if self.options.debug_info && self.last_line != Some(line) {
+            self.emit_ins(Instruction::DbgLine(line));
+            self.last_line = Some(line);
+        }
+    }
+
+    // Errors if the sandbox policy doesn't allow calling the proc
+    fn check_call_allowed(&self, proc: &str) -> Result<(), CompileError> {
+        match &self.options.sandbox {
+            Some(policy) if !policy.allows(proc) => {
+                Err(CompileErrorKind::ForbiddenCall(proc.to_owned()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Asks `CompilerOptions::resolver` about an identifier
+    fn resolve_ident(&self, ident: &str) -> Option<Variable> {
+        self.options.resolver.and_then(|hook| hook(ident))
+    }
+
+    // Looks up the implied type of a var through `CompilerOptions::implied_type`
+    fn lookup_implied_type(&self, var: &str) -> Option<String> {
+        self.options.implied_type.and_then(|hook| hook(var))
+    }
+
+    fn warn(&mut self, kind: CompileWarningKind) {
+        self.warnings.push(CompileWarning {
+            kind,
+            location: self.location,
+        });
+    }
+
+    // Checks an expression about to be used as a condition for things that look like mistakes
+    fn check_condition(&mut self, cond: &Expression) {
+        if let Expression::AssignOp {
+            op: AssignOp::Assign,
+            ..
+        } = cond
+        {
+            self.warn(CompileWarningKind::AssignmentInCondition);
+        }
+    }
+
+    fn emit_ins(&mut self, ins: Instruction) {
+        self.nodes.push(Node::Instruction(ins, ()));
+    }
+
+    // Runs the AST-level optimizations on an expression about to be emitted as a whole
+    fn optimize_expr(&self, expr: Expression) -> Expression {
+        if self.options.optimization_level >= OptimizationLevel::ConstantFolding {
+            return fold::fold(expr);
+        }
+
+        expr
+    }
+
+    // Hands over the emitted code, running the optimizer over it if enabled
+    fn finish_nodes(&mut self) -> Vec<Node> {
+        let nodes = std::mem::take(&mut self.nodes);
+
+        if self.options.optimization_level >= OptimizationLevel::Peephole {
+            return crate::optimizer::optimize(nodes);
+        }
+
+        nodes
+    }
+
+    fn emit_label(&mut self, label: String) {
+        self.nodes.push(Node::Label(label));
+    }
+
+    fn emit_find_var(&mut self, ident: dreammaker::ast::Ident) -> Result<EvalKind, CompileError> {
+        if let Some(index) = self.locals.iter().rposition(|x| *x == ident) {
+            return Ok(EvalKind::Var(Variable::Local(index as u32)));
+        }
+
+        // Template placeholders have to make it through untouched
+        if template::is_placeholder(&ident) {
+            return Ok(EvalKind::Var(Variable::Global(DMString(ident.into()))));
+        }
+
+        if let Some(var) = self.resolve_ident(&ident) {
+            return Ok(EvalKind::Var(var));
+        }
+
+        if let Some(index) = self.params.iter().rposition(|x| *x == ident) {
+            return Ok(EvalKind::Var(Variable::Arg(index as u32)));
+        }
+
+        let kind = match ident.as_str() {
+            "." => EvalKind::Var(Variable::Dot),
+            "usr" => EvalKind::Var(Variable::Usr),
+            "src" => EvalKind::Var(Variable::Src),
+            "args" => EvalKind::Var(Variable::Args),
+            "world" => EvalKind::Var(Variable::World),
+            "global" => EvalKind::Global,
+
+            // Globals declared by the code itself or that the caller told us about
+            _ if self.globals.contains(&ident) || self.options.globals.contains(&ident) => {
+                EvalKind::Var(Variable::Global(DMString(ident.into())))
+            }
+
+            // `x` is `env["x"]`
+            _ if self.options.environment.is_some() => {
+                let env = self.options.environment.as_ref().unwrap();
+
+                let index = match self.params.iter().rposition(|x| x == env) {
+                    Some(index) => index,
+                    None => return Err(CompileErrorKind::UnknownIdentifier(env.clone()).into()),
+                };
+
+                self.emit_ins(Instruction::GetVar(Variable::Arg(index as u32)));
+                self.emit_ins(Instruction::PushVal(Value::DMString(DMString(ident.into())).into()));
+                EvalKind::ListRef
+            }
+
+            _ if self.options.strict_identifiers => {
+                return Err(CompileErrorKind::UnknownIdentifier(ident).into())
+            }
+
+            // Anything else is treated as a global var
+            _ => {
+                self.warn(CompileWarningKind::ImplicitGlobal(ident.clone()));
+                EvalKind::Var(Variable::Global(DMString(ident.into())))
+            }
+        };
+
+        Ok(kind)
+    }
+
+    fn emit_move_to_stack(&mut self, kind: EvalKind) -> Result<EvalKind, CompileError> {
+        match kind {
+            EvalKind::Stack => {}
+
+            EvalKind::ListRef => {
+                self.emit_ins(Instruction::ListGet);
+            }
+
+            EvalKind::Range => return Err(CompileErrorKind::UnexpectedRange.into()),
+            EvalKind::Global => return Err(CompileErrorKind::UnexpectedGlobal.into()),
+            EvalKind::ArgList => return Err(CompileErrorKind::UnexpectedArgList.into()),
+
+            // Null stands in when something needs a value after all
+            EvalKind::Nothing => {
+                self.emit_ins(Instruction::PushVal(Value::Null.into()));
+            }
+
+            EvalKind::Var(var) => {
+                self.emit_ins(Instruction::GetVar(var));
+            }
+
+            EvalKind::Field(builder, field) => {
+                let var = builder.get_field(DMString(field.into()));
+                self.emit_ins(Instruction::GetVar(var));
+            }
+        }
+
+        Ok(EvalKind::Stack)
+    }
+
+    // TODO: lots of copied code from emit_move_to_stack
+    fn emit_move_to_chain_builder(&mut self, kind: EvalKind) -> Result<ChainBuilder, CompileError> {
+        match kind {
+            EvalKind::Stack => {
+                self.emit_ins(Instruction::SetVar(Variable::Cache));
+                Ok(ChainBuilder::begin(Variable::Cache))
+            }
+
+            EvalKind::ListRef => {
+                self.emit_ins(Instruction::ListGet);
+                self.emit_ins(Instruction::SetVar(Variable::Cache));
+                Ok(ChainBuilder::begin(Variable::Cache))
+            }
+
+            EvalKind::Range => Err(CompileErrorKind::UnexpectedRange.into()),
+            EvalKind::Global => Err(CompileErrorKind::UnexpectedGlobal.into()),
+            EvalKind::ArgList => return Err(CompileErrorKind::UnexpectedArgList.into()),
+            EvalKind::Nothing => Err(CompileErrorKind::UnexpectedNothing.into()),
+
+            EvalKind::Field(mut builder, field) => {
+                builder.append(DMString(field.into()));
+                Ok(builder)
+            }
+
+            EvalKind::Var(var) => Ok(ChainBuilder::begin(var)),
+        }
+    }
+
+    fn short_circuit(&mut self) -> String {
+        let label = self.short_circuit_labels.last_mut().unwrap();
+        label.1 = true;
+        label.0.to_owned()
+    }
+
+    fn emit_inner_expr(&mut self, expr: Expression) -> Result<EvalKind, CompileError> {
+        let location = expr_location(&expr);
+        let outer_location = self.location.replace(location);
+
+        // Only a bare `new`, `locate()` or `astype(x)` can make use of the implied type, not anything nested in it
+        let implied_type = self.implied_type.take();
+
+        let result = match expr {
+            Expression::TernaryOp { cond, if_, else_ } => ternary::emit(self, *cond, *if_, *else_),
+            Expression::BinaryOp { op, lhs, rhs } => binary_ops::emit(self, op, *lhs, *rhs),
+            Expression::AssignOp { op, lhs, rhs } => assignment::emit(self, op, *lhs, *rhs),
+
+            Expression::Base {
+                unary,
+                term,
+                follow,
+            } => {
+                let uses_implied_type = match &term.elem {
+                    dreammaker::ast::Term::New { .. } | dreammaker::ast::Term::Locate { .. } => true,
+                    dreammaker::ast::Term::Call(name, _) => name == "astype",
+                    _ => false,
+                };
+
+                if unary.is_empty() && follow.is_empty() && uses_implied_type {
+                    self.implied_type = implied_type;
+                }
+
+                self.emit_base(unary, *term, follow)
+            }
+        };
+
+        self.location = outer_location;
+        self.implied_type = None;
+
+        // Nested expressions get their say first
+        match result.map_err(|err| err.or_location(location)) {
+            Err(err) if self.collect_errors => {
+                self.errors.push(err);
+                self.emit_ins(Instruction::PushVal(Value::Null.into()));
+                Ok(EvalKind::Stack)
+            }
+
+            result => result,
+        }
+    }
+
+    fn emit_base(
+        &mut self,
+        unary: Vec<UnaryOp>,
+        term: dreammaker::ast::Spanned<dreammaker::ast::Term>,
+        follow: Vec<dreammaker::ast::Spanned<Follow>>,
+    ) -> Result<EvalKind, CompileError> {
+        let procs = type_check::check_follows(self, &term.elem, &follow)?;
+
+        let unspanned_follows: Vec<Follow> = follow.into_iter().map(|f| f.elem).collect();
+        let kind = match term.elem {
+            dreammaker::ast::Term::New {
+                type_: dreammaker::ast::NewType::Implicit,
+                args: Some(args),
+            } if !args.is_empty() && self.new_exprs.contains(term.location) => {
+                term::emit_new_expr(self, args)?
+            }
+
+            elem => term::emit(self, elem)?,
+        };
+        let kind = follow::emit(self, unspanned_follows, procs, kind)?;
+        let kind = unary::emit(self, unary, kind)?;
+        Ok(kind)
+    }
+
+    // Expressions that make up a whole statement. `<<` and `>>` are output and input here instead of shifts.
+    fn emit_statement_expr(&mut self, expr: Expression) -> Result<EvalKind, CompileError> {
+        match self.optimize_expr(expr) {
+            Expression::BinaryOp { op, lhs, rhs }
+                if op == BinaryOp::LShift || op == BinaryOp::RShift =>
+            {
+                binary_ops::emit_io(self, op, *lhs, *rhs)
+            }
+
+            expr => self.emit_expr(expr),
+        }
+    }
+
+    fn emit_expr(&mut self, expr: Expression) -> Result<EvalKind, CompileError> {
+        let label = format!("LAB_{:0>4X}", self.label_count);
+        self.label_count += 1;
+        self.short_circuit_labels.push((label, false));
+
+        let kind = self.emit_inner_expr(expr)?;
+
+        let (label, used) = self.short_circuit_labels.pop().unwrap();
+
+        // We only care if the label was actually used
+        // TODO: BYOND would put this jump destination before any unary ops (if this is Expression::Base), idk if that is sane.
+        if used {
+            self.emit_move_to_stack(kind)?;
+            self.emit_label(label);
+            return Ok(EvalKind::Stack);
+        }
+
+        Ok(kind)
+    }
+}
+
+#[test]
+fn compile_test() {
+    let context: dreammaker::Context = Default::default();
+    let lexer =
+        dreammaker::lexer::Lexer::new(&context, Default::default(), "pick(1;;2,3;4)".as_bytes());
+    let expr = dreammaker::parser::parse_expression(&context, Default::default(), lexer);
+    context.assert_success();
+    println!("{:#?}\n\n\n", expr);
+
+    let expr = compile_expr("a?.b++", &["a"]);
+    println!("{:#?}", expr);
+
+    if let Ok(expr) = expr {
+        println!("{}", crate::format(&expr));
+        let code = crate::assembler::assemble(&expr, &mut crate::TestAssembleEnv);
+        println!("{:#x?}", code);
+    }
+}
+
+#[cfg(test)]
+fn compile_instructions(code: &str, params: &[&str]) -> Vec<Instruction> {
+    compile_expr(code, params)
+        .unwrap()
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Instruction(ins, ()) => Some(ins),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn params_builtins() {
+    assert_eq!(
+        compile_instructions("params2list(href)", &["href"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::Params2List,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("list2params(href_list)", &["href_list"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::List2Params,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    assert!(compile_expr("params2list()", &[]).is_err());
+    assert!(compile_expr("list2params(a, b)", &["a", "b"]).is_err());
+}
+
+#[test]
+fn output_operator() {
+    let statements = |code: &str, params: &[&str]| -> Vec<Instruction> {
+        compile_proc(code, params)
+            .unwrap()
+            .nodes
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(Instruction::DbgFile(_), ()) => None,
+                Node::Instruction(Instruction::DbgLine(_), ()) => None,
+                Node::Instruction(ins, ()) => Some(ins),
+                _ => None,
+            })
+            .collect()
+    };
+
+    // Nothing is left on the stack to pop
+    assert_eq!(
+        statements("world << \"hello\"", &[]),
+        vec![
+            Instruction::GetVar(Variable::World),
+            Instruction::PushVal(Value::DMString(DMString(b"hello".to_vec())).into()),
+            Instruction::Output,
+            Instruction::End,
+        ]
+    );
+
+    // What's read is stored in the var on the right
+    assert_eq!(
+        statements("usr >> x", &["x"]),
+        vec![
+            Instruction::GetVar(Variable::Usr),
+            Instruction::Read,
+            Instruction::SetVar(Variable::Arg(0)),
+            Instruction::End,
+        ]
+    );
+
+    // An expression on its own does output too when the left side is an object, and gives back null
+    let options = CompilerOptions::default();
+    let expr = compile_expr_with_type("world << \"hello\"", &[], &options).unwrap();
+    assert_eq!(expr.result_type, StaticType::Null);
+    assert!(expr.warnings.is_empty());
+    assert_eq!(
+        expr.nodes,
+        vec![
+            Node::Instruction(Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())), ()),
+            Node::Instruction(Instruction::GetVar(Variable::World), ()),
+            Node::Instruction(
+                Instruction::PushVal(Value::DMString(DMString(b"hello".to_vec())).into()),
+                ()
+            ),
+            Node::Instruction(Instruction::Output, ()),
+            Node::Instruction(Instruction::PushVal(Value::Null.into()), ()),
+            Node::Instruction(Instruction::NewList(1), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+
+    // When the left side could be a number it's a shift, with a warning
+    let (nodes, warnings) = compile_expr_with_warnings("x << 2", &["x"], &options).unwrap();
+    assert_eq!(
+        nodes,
+        vec![
+            Node::Instruction(Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::PushInt(2), ()),
+            Node::Instruction(Instruction::LShift, ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::NewList(2), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, CompileWarningKind::AmbiguousShift("<<"));
+
+    // No warning when it can only be a number, or when the shift is part of something bigger
+    let (_, warnings) = compile_expr_with_warnings("-x >> 1", &["x"], &options).unwrap();
+    assert!(warnings.is_empty());
+    let (_, warnings) = compile_expr_with_warnings("(x << 2) + 1", &["x"], &options).unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn chained_comparison() {
+    let epilogue = vec![
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::GetVar(Variable::Arg(1)),
+        Instruction::GetVar(Variable::Arg(2)),
+        Instruction::NewList(4),
+        Instruction::Ret,
+    ];
+
+    // (a < b) < c
+    let mut expected = vec![
+        Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::GetVar(Variable::Arg(1)),
+        Instruction::Tl,
+        Instruction::GetVar(Variable::Arg(2)),
+        Instruction::Tl,
+    ];
+    expected.extend(epilogue.clone());
+    assert_eq!(
+        compile_instructions("a < b < c", &["a", "b", "c"]),
+        expected
+    );
+
+    // (a == b) == c
+    let mut expected = vec![
+        Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::GetVar(Variable::Arg(1)),
+        Instruction::Teq,
+        Instruction::Pop,
+        Instruction::GetFlag,
+        Instruction::GetVar(Variable::Arg(2)),
+        Instruction::Teq,
+        Instruction::Pop,
+        Instruction::GetFlag,
+    ];
+    expected.extend(epilogue);
+    assert_eq!(
+        compile_instructions("a == b == c", &["a", "b", "c"]),
+        expected
+    );
+}
+
+#[test]
+fn power_operator() {
+    assert_eq!(
+        compile_instructions("x ** 2", &["x"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::PushInt(2),
+            Instruction::Pow,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+}
+
+#[test]
+fn var_declarations() {
+    let proc = compile_proc("var/global/counter\ncounter = 5\nvar/x = counter\nreturn x", &[]).unwrap();
+
+    assert_eq!(proc.globals, vec!["counter".to_owned()]);
+    assert_eq!(proc.locals, vec!["x".to_owned()]);
+
+    let counter = Variable::Global(DMString(b"counter".to_vec()));
+
+    assert_eq!(
+        proc.nodes,
+        vec![
+            Node::Instruction(Instruction::DbgFile(DMString(b"<dmasm proc>".to_vec())), ()),
+            Node::Instruction(Instruction::DbgLine(1), ()),
+            Node::Instruction(Instruction::DbgLine(2), ()),
+            Node::Instruction(Instruction::PushInt(5), ()),
+            Node::Instruction(Instruction::SetVarExpr(counter.clone()), ()),
+            Node::Instruction(Instruction::Pop, ()),
+            Node::Instruction(Instruction::DbgLine(3), ()),
+            Node::Instruction(Instruction::GetVar(counter), ()),
+            Node::Instruction(Instruction::SetVar(Variable::Local(0)), ()),
+            Node::Instruction(Instruction::DbgLine(4), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Local(0)), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+
+    // A static is only initialized the first time through
+    let proc = compile_proc_with_options(
+        "var/static/counter = 5\nreturn counter",
+        &[],
+        &CompilerOptions::new().debug_info(false),
+    )
+    .unwrap();
+
+    assert_eq!(proc.globals, vec!["counter".to_owned(), static_guard_name("counter")]);
+
+    let counter = Variable::Global(DMString(b"counter".to_vec()));
+    let initialized = Variable::Global(DMString(static_guard_name("counter").into_bytes()));
+    let label = || Label("LAB_0000".to_owned());
+
+    assert_eq!(
+        proc.nodes,
+        vec![
+            Node::Instruction(Instruction::GetVar(initialized.clone()), ()),
+            Node::Instruction(Instruction::Test, ()),
+            Node::Instruction(Instruction::Jnz(label()), ()),
+            Node::Instruction(Instruction::PushInt(5), ()),
+            Node::Instruction(Instruction::SetVar(counter.clone()), ()),
+            Node::Instruction(Instruction::PushInt(1), ()),
+            Node::Instruction(Instruction::SetVar(initialized), ()),
+            Node::Label("LAB_0000".to_owned()),
+            Node::Instruction(Instruction::GetVar(counter), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+}
+
+#[test]
+fn static_initialized_once() {
+    let proc = compile_proc_with_options(
+        "var/static/counter = 5\nreturn counter",
+        &[],
+        &CompilerOptions::new().debug_info(false),
+    )
+    .unwrap();
+
+    // Just enough of the VM to run the proc, with globals that outlive each call
+    let run = |globals: &mut BTreeMap<Vec<u8>, i32>| {
+        let mut stack = vec![];
+        let mut test = false;
+        let mut pc = 0;
+
+        loop {
+            match &proc.nodes[pc] {
+                Node::Instruction(Instruction::GetVar(Variable::Global(DMString(name))), ()) => {
+                    stack.push(globals.get(name).copied().unwrap_or(0))
+                }
+                Node::Instruction(Instruction::SetVar(Variable::Global(DMString(name))), ()) => {
+                    globals.insert(name.clone(), stack.pop().unwrap());
+                }
+                Node::Instruction(Instruction::PushInt(value), ()) => stack.push(*value),
+                Node::Instruction(Instruction::Test, ()) => test = stack.pop().unwrap() != 0,
+                Node::Instruction(Instruction::Jnz(Label(label)), ()) if test => {
+                    pc = proc.nodes.iter().position(|node| *node == Node::Label(label.clone())).unwrap();
+                }
+                Node::Instruction(Instruction::Jnz(_), ()) | Node::Label(_) => {}
+                Node::Instruction(Instruction::Ret, ()) => return stack.pop().unwrap(),
+                node => panic!("unexpected {:?}", node),
+            }
+
+            pc += 1;
+        }
+    };
+
+    let mut globals = BTreeMap::new();
+    assert_eq!(run(&mut globals), 5);
+
+    // Anything done to it since sticks, as the initializer doesn't run again
+    globals.insert(b"counter".to_vec(), 7);
+    assert_eq!(run(&mut globals), 7);
+    assert_eq!(run(&mut globals), 7);
+
+    // A user's global can't share the guard's name
+    assert!(!static_guard_name("counter").chars().all(|c| c.is_alphanumeric() || c == '_'));
+}
+
+#[test]
+fn for_list() {
+    let options = CompilerOptions::new().debug_info(false);
+    let ins = |ins: Instruction| Node::Instruction(ins, ());
+    let label = |name: &str| Label(name.to_owned());
+
+    // A copy of the list (or the contents of anything else) is walked by index, skipping what isn't a mob
+    let proc = compile_proc_with_options("for (var/mob/M in view(5))\n\t. = M", &[], &options).unwrap();
+    assert_eq!(
+        proc.locals,
+        vec!["M".to_owned(), "<dmasm for list>".to_owned(), "<dmasm for index>".to_owned()]
+    );
+
+    let (list, index) = (Variable::Local(1), Variable::Local(2));
+    assert_eq!(
+        proc.nodes,
+        vec![
+            ins(Instruction::PushInt(5)),
+            ins(Instruction::PushVal(Value::Null.into())),
+            ins(Instruction::View),
+            ins(Instruction::SetVar(Variable::Cache)),
+            ins(Instruction::GetVar(Variable::Cache)),
+            ins(Instruction::Test),
+            ins(Instruction::Jz(label("LAB_0004"))),
+            ins(Instruction::GetVar(Variable::Cache)),
+            ins(Instruction::IsList),
+            ins(Instruction::Test),
+            ins(Instruction::Jnz(label("LAB_0002"))),
+            ins(Instruction::GetVar(Variable::Field(DMString(b"contents".to_vec())))),
+            ins(Instruction::SetVar(Variable::Cache)),
+            Node::Label("LAB_0002".to_owned()),
+            ins(Instruction::Call(Variable::DynamicProc(DMString(b"Copy".to_vec())), 0)),
+            ins(Instruction::SetVar(list.clone())),
+            ins(Instruction::PushInt(0)),
+            ins(Instruction::SetVar(index.clone())),
+            Node::Label("LAB_0003".to_owned()),
+            ins(Instruction::GetVar(index.clone())),
+            ins(Instruction::PushInt(1)),
+            ins(Instruction::Add),
+            ins(Instruction::SetVar(index.clone())),
+            ins(Instruction::GetVar(index.clone())),
+            ins(Instruction::GetVar(list.clone())),
+            ins(Instruction::Length),
+            ins(Instruction::Tle),
+            ins(Instruction::Test),
+            ins(Instruction::Jz(label("LAB_0004"))),
+            ins(Instruction::GetVar(list)),
+            ins(Instruction::GetVar(index)),
+            ins(Instruction::ListGet),
+            ins(Instruction::SetVar(Variable::Local(0))),
+            ins(Instruction::GetVar(Variable::Local(0))),
+            ins(Instruction::PushVal(Value::Path("/mob".to_owned()).into())),
+            ins(Instruction::IsType),
+            ins(Instruction::Test),
+            ins(Instruction::Jz(label("LAB_0003"))),
+            ins(Instruction::GetVar(Variable::Local(0))),
+            ins(Instruction::SetVarExpr(Variable::Dot)),
+            ins(Instruction::Pop),
+            ins(Instruction::Jmp(label("LAB_0003"))),
+            Node::Label("LAB_0004".to_owned()),
+            ins(Instruction::End),
+        ]
+    );
+
+    assert_eq!(max_stack_depth(&proc.nodes), Ok(2));
+    assert!(crate::assembler::assemble(&proc.nodes, &mut crate::TestAssembleEnv).is_ok());
+
+    // With no list it's the world
+    let proc = compile_proc_with_options("for (var/obj/item/I)\n\t. = I", &[], &options).unwrap();
+    assert_eq!(
+        proc.nodes[..2],
+        [ins(Instruction::GetVar(Variable::World)), ins(Instruction::SetVar(Variable::Cache))]
+    );
+
+    let item = Value::Path("/obj/item".to_owned());
+    assert!(proc.nodes.contains(&ins(Instruction::PushVal(item.into()))));
+
+    // A var that's already there takes anything
+    let proc = compile_proc_with_options("for (x in L)\n\t. = x", &["x", "L"], &options).unwrap();
+    assert_eq!(proc.locals, vec!["<dmasm for list>".to_owned(), "<dmasm for index>".to_owned()]);
+    assert_eq!(proc.nodes[0], ins(Instruction::GetVar(Variable::Arg(1))));
+    assert!(proc.nodes.contains(&ins(Instruction::SetVar(Variable::Arg(0)))));
+    assert!(!proc.nodes.contains(&ins(Instruction::IsType)));
+
+    let err = compile_proc("for (world in L)\n\t. = 1", &["L"]).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::ExpectedLValue));
+
+    // Each statement in the body gets its own line
+    let proc = compile_proc("for (var/x in L)\n\t. = x\n\t. = 2", &["L"]).unwrap();
+    let lines: Vec<u32> = proc
+        .nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Instruction(Instruction::DbgLine(line), ()) => Some(*line),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lines, vec![1, 2, 3]);
+
+    // And an error in one doesn't stop the rest of them being checked
+    let errors = compile_proc_collecting_errors(
+        "for (var/x in L)\n\tfor (world in L)\n\t\t. = 1\n\tfor (world in L)\n\t\t. = 2",
+        &["L"],
+        &options,
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|err| matches!(err.kind, CompileErrorKind::ExpectedLValue)));
+}
+
+#[test]
+fn preprocessor_defines() {
+    let options = CompilerOptions::new()
+        .define("FOO", "2")
+        .define("DOUBLE(a)", "(a * 2)");
+
+    let nodes = compile_expr_with_options("FOO + DOUBLE(x)", &["x"], &options).unwrap();
+
+    assert_eq!(
+        nodes,
+        vec![
+            Node::Instruction(
+                Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+                ()
+            ),
+            Node::Instruction(Instruction::PushInt(2), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::PushInt(2), ()),
+            Node::Instruction(Instruction::Mul, ()),
+            Node::Instruction(Instruction::Add, ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::NewList(2), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+
+    assert!(compile_expr_with_options("FOO 1", &[], &options).is_err());
+}
+
+#[test]
+fn constant_folding() {
+    let options = CompilerOptions::new().optimization_level(OptimizationLevel::ConstantFolding);
+
+    let compile = |code: &str| -> Vec<Instruction> {
+        compile_expr_with_options(code, &["x"], &options)
+            .unwrap()
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(ins, ()) => Some(ins),
+                _ => None,
+            })
+            .skip(1)
+            .collect()
+    };
+
+    let epilogue = vec![
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::NewList(2),
+        Instruction::Ret,
+    ];
+
+    let mut expected = vec![Instruction::PushInt(1200)];
+    expected.extend(epilogue.clone());
+    assert_eq!(compile("2 * 60 * 10"), expected);
+
+    let mut expected = vec![Instruction::PushVal(
+        Value::DMString(DMString(b"foobar".to_vec())).into(),
+    )];
+    expected.extend(epilogue.clone());
+    assert_eq!(compile("\"foo\" + (\"bar\")"), expected);
+
+    let mut expected = vec![Instruction::GetVar(Variable::Arg(0))];
+    expected.extend(epilogue.clone());
+    assert_eq!(compile("(1 - 1) ? 5 : x"), expected);
+
+    // Division by zero is left for the runtime to complain about
+    let mut expected = vec![
+        Instruction::PushInt(1),
+        Instruction::PushInt(0),
+        Instruction::Div,
+    ];
+    expected.extend(epilogue);
+    assert_eq!(compile("1 / 0"), expected);
+
+    // Folding is opt-in
+    assert_eq!(
+        compile_instructions("2 * 3", &[])[1..4],
+        [
+            Instruction::PushInt(2),
+            Instruction::PushInt(3),
+            Instruction::Mul
+        ]
+    );
+}
+
+#[test]
+fn error_locations() {
+    let err = compile_expr("x + locate()", &["x"]).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        CompileErrorKind::UnsupportedImplicitLocate
+    ));
+
+    let location = err.location.unwrap();
+    assert_eq!(location.line, 1);
+    assert!(location.column > 1);
+
+    // Lines are relative to the code passed in, not the proc it gets wrapped in
+    let err = compile_proc("var/x = 1\nreturn locate()", &[]).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        CompileErrorKind::UnsupportedImplicitLocate
+    ));
+    assert_eq!(err.location.unwrap().line, 2);
+}
+
+#[test]
+fn collecting_errors() {
+    let options = CompilerOptions::default();
+
+    let errors =
+        compile_proc_collecting_errors("var/x = locate()\nx = 1\nreturn locate()", &[], &options)
+            .unwrap_err();
+
+    let lines: Vec<u32> = errors
+        .iter()
+        .map(|err| err.location.unwrap().line)
+        .collect();
+    assert_eq!(lines, vec![1, 3]);
+
+    // Without collecting, only the first is reported
+    let err = compile_proc_with_options("var/x = locate()\nx = 1\nreturn locate()", &[], &options)
+        .unwrap_err();
+    assert_eq!(err.location.unwrap().line, 1);
+
+    // Both sides of an expression get compiled, even after the first fails
+    let errors = compile_expr_collecting_errors("locate() + astype(x)", &["x"], &options).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(errors[0].kind, CompileErrorKind::UnsupportedImplicitLocate));
+    assert!(matches!(errors[1].kind, CompileErrorKind::UnsupportedImplicitAsType));
+    assert!(errors[0].location.unwrap().column < errors[1].location.unwrap().column);
+
+    let err = compile_expr_with_options("locate() + astype(x)", &["x"], &options).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::UnsupportedImplicitLocate));
+
+    // As do a proc's
+    let errors = compile_proc_collecting_errors("return list(locate(), astype(1))", &[], &options).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|err| err.location.unwrap().line == 1));
+}
+
+#[test]
+fn warnings() {
+    let options = CompilerOptions::default();
+    let kinds = |warnings: Vec<CompileWarning>| -> Vec<CompileWarningKind> {
+        warnings.into_iter().map(|warning| warning.kind).collect()
+    };
+
+    let (_, warnings) =
+        compile_expr_with_warnings("(x = y) ? x == null : z", &["x", "y", "z"], &options).unwrap();
+    assert_eq!(
+        kinds(warnings),
+        vec![
+            CompileWarningKind::AssignmentInCondition,
+            CompileWarningKind::NullComparison
+        ]
+    );
+
+    let (_, warnings) =
+        compile_expr_with_warnings("((x = y)) ? 1 : 2", &["x", "y"], &options).unwrap();
+    assert!(warnings.is_empty());
+
+    let (_, warnings) = compile_expr_with_warnings("x + foo", &["x"], &options).unwrap();
+    assert_eq!(
+        kinds(warnings),
+        vec![CompileWarningKind::ImplicitGlobal("foo".to_owned())]
+    );
+
+    let proc = compile_proc("var/a = 1\nreturn b", &[]).unwrap();
+    assert_eq!(proc.warnings[0].location.unwrap().line, 2);
+}
+
+#[test]
+fn compiler_options() {
+    let compile =
+        |code: &str, options: &CompilerOptions| -> Result<Vec<Instruction>, CompileError> {
+            Ok(compile_expr_with_options(code, &["x"], options)?
+                .into_iter()
+                .filter_map(|node| match node {
+                    Node::Instruction(ins, ()) => Some(ins),
+                    _ => None,
+                })
+                .collect())
+        };
+
+    let options = CompilerOptions::new().debug_info(false);
+    assert_eq!(
+        compile("x", &options).unwrap(),
+        vec![
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    // The flags need an instruction that isn't confirmed yet, whatever the target
+    let options = CompilerOptions::new().debug_info(false);
+    for options in &[options.clone(), options.clone().target_version(515)] {
+        assert!(matches!(
+            compile("json_encode(x, 1)", options).unwrap_err().kind,
+            CompileErrorKind::UnsupportedBuiltin { .. }
+        ));
+    }
+    assert!(matches!(
+        compile("json_encode(x, 1, 2)", &options).unwrap_err().kind,
+        CompileErrorKind::TooManyArguments { expected: 2, .. }
+    ));
+    assert_eq!(
+        compile("json_encode(x)", &options).unwrap()[..2],
+        [Instruction::GetVar(Variable::Arg(0)), Instruction::JsonEncode]
+    );
+
+    let options = CompilerOptions::new().strict(true);
+    let err = compile("x + y", &options).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        CompileErrorKind::Warning(CompileWarningKind::ImplicitGlobal(_))
+    ));
+
+    // Every warning is reported when collecting, not just the first
+    let errors = compile_expr_collecting_errors("x + y", &[], &options).unwrap_err();
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn implied_types() {
+    let options = CompilerOptions::new().implied_type(|var| match var {
+        "x" => Some("/obj/item".to_owned()),
+        _ => None,
+    });
+
+    let nodes = compile_expr_with_options("x = new", &["x"], &options).unwrap();
+    assert!(nodes.contains(&Node::Instruction(
+        Instruction::PushVal(operands::Value::Path("/obj/item".to_owned()).into()),
+        ()
+    )));
+
+    // Only a bare `new` gets the type
+    assert!(compile_expr_with_options("x = list(new)", &["x"], &options).is_err());
+
+    let proc = compile_proc("var/mob/M = locate()\nreturn M", &[]).unwrap();
+    assert!(proc.nodes.contains(&Node::Instruction(
+        Instruction::PushVal(operands::Value::Path("/mob".to_owned()).into()),
+        ()
+    )));
+}
+
+#[test]
+fn strict_identifiers() {
+    let options = CompilerOptions::new()
+        .strict_identifiers(true)
+        .global("config");
+
+    let err = compile_expr_with_options("x + confg", &["x"], &options).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::UnknownIdentifier(ident) if ident == "confg"));
+
+    assert!(compile_expr_with_options("x + config", &["x"], &options).is_ok());
+
+    // Declaring a global in the proc counts too
+    assert!(compile_proc_with_options("var/global/y\nreturn y", &[], &options).is_ok());
+
+    // Whitelisted globals aren't worth a warning either
+    let (_, warnings) =
+        compile_expr_with_warnings("config", &[], &CompilerOptions::new().global("config"))
+            .unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn compile_ast() {
+    let context: dreammaker::Context = Default::default();
+    let lexer = dreammaker::lexer::Lexer::new(&context, Default::default(), "x * 2".as_bytes());
+    let expr = dreammaker::parser::parse_expression(&context, Default::default(), lexer).unwrap();
+    context.assert_success();
+
+    assert_eq!(
+        compile_expr_ast(expr, &["x"]).unwrap(),
+        compile_expr("x * 2", &["x"]).unwrap()
+    );
+
+    // The block's lines are where they were in the wrapper proc, so only the code is compared
+    let options = CompilerOptions::new().debug_info(false);
+    let (block, _) = parse_proc_body("var/y = 1\nreturn y", &[]).unwrap();
+    assert_eq!(
+        compile_proc_ast_with_options(block, &[], &options).unwrap().nodes,
+        compile_proc_with_options("var/y = 1\nreturn y", &[], &options).unwrap().nodes
+    );
+}
+
+#[test]
+fn field_checks() {
+    let tree = parse_object_tree("/mob/living\n\tvar/health = 100\n\tvar/obj/held\n", &[]).unwrap();
+    let options = CompilerOptions::new()
+        .object_tree(Arc::new(tree))
+        .src_type("/mob/living");
+
+    assert!(compile_expr_with_options("src.health + src.held.name", &[], &options).is_ok());
+
+    let err = compile_expr_with_options("src.helth", &[], &options).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        CompileErrorKind::UnknownField { type_path, field } if type_path == "/mob/living" && field == "helth"
+    ));
+
+    // `:` and values of an unknown type aren't checked
+    assert!(compile_expr_with_options("src:helth + x.helth", &["x"], &options).is_ok());
+
+    assert!(compile_proc_with_options("var/mob/living/M = src\nreturn M.helth", &[], &options).is_err());
+}
+
+#[test]
+fn proc_resolution() {
+    let tree = parse_object_tree(
+        "/proc/helper()\n/mob/living\n\tvar/obj/held\n/mob/proc/heal()\n/mob/living/heal()\n/obj/proc/use()\n\
+         /obj/verb/wield()\n",
+        &[],
+    )
+    .unwrap();
+
+    let options = CompilerOptions::new()
+        .object_tree(Arc::new(tree))
+        .src_type("/mob/living");
+
+    let calls = |code: &str| -> Vec<Instruction> {
+        compile_expr_with_options(code, &[], &options)
+            .unwrap()
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(ins @ Instruction::Call(..), ()) => Some(ins),
+                Node::Instruction(ins @ Instruction::CallGlob(..), ()) => Some(ins),
+                _ => None,
+            })
+            .collect()
+    };
+    let static_proc = |path: &str| Variable::StaticProc(operands::Proc::from_path(path.to_owned()));
+
+    // `heal()` belongs to src, so it's called on src instead of as a global proc. The override on /mob/living
+    // is still called by where the proc was declared, BYOND finds the override.
+    assert_eq!(calls("heal()"), vec![Instruction::Call(static_proc("/mob/proc/heal"), 0)]);
+    assert_eq!(calls("src.heal()"), vec![Instruction::Call(static_proc("/mob/proc/heal"), 0)]);
+
+    assert_eq!(
+        calls("helper() + global.helper()"),
+        vec![
+            Instruction::CallGlob(0, operands::Proc::from_path("/proc/helper".to_owned())),
+            Instruction::CallGlob(0, operands::Proc::from_path("/proc/helper".to_owned())),
+        ]
+    );
+
+    assert_eq!(
+        calls("src.held.use() + src.held?.wield()"),
+        vec![
+            Instruction::Call(static_proc("/obj/proc/use"), 0),
+            Instruction::Call(
+                Variable::StaticVerb(operands::Proc::from_path("/obj/verb/wield".to_owned())),
+                0
+            ),
+        ]
+    );
+
+    // `:` and values of an unknown type are still looked up by name at runtime
+    assert_eq!(
+        calls("src.held:use()"),
+        vec![Instruction::Call(Variable::DynamicProc(DMString(b"use".to_vec())), 0)]
+    );
+
+    for code in &["hepler()", "global.hepler()", "src.held.heal()"] {
+        let err = compile_expr_with_options(code, &[], &options).unwrap_err();
+        assert!(matches!(err.kind, CompileErrorKind::UnknownProc(_)));
+    }
+
+    // A proc table works without a tree
+    let options = CompilerOptions::new().global_proc("helper");
+    assert!(compile_expr_with_options("helper()", &[], &options).is_ok());
+    assert!(compile_expr_with_options("hepler()", &[], &options).is_err());
+}
+
+#[test]
+fn debug_lines() {
+    let lines = |proc: CompiledProc| -> Vec<u32> {
+        proc.nodes
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(Instruction::DbgLine(line), ()) => Some(line),
+                _ => None,
+            })
+            .collect()
+    };
+
+    // Lines are relative to the code passed in, whatever it gets wrapped in
+    let options = CompilerOptions::new().define("FOO", "1");
+    let proc = compile_proc_with_options("var/x = FOO\n\nreturn x", &[], &options).unwrap();
+    assert_eq!(lines(proc), vec![1, 3]);
+
+    let options = CompilerOptions::new().debug_info(false);
+    let proc = compile_proc_with_options("var/x = 1\nreturn x", &[], &options).unwrap();
+    assert!(lines(proc).is_empty());
+}
+
+#[test]
+fn resolver() {
+    let options = CompilerOptions::new().resolver(|ident| match ident {
+        "x" | "cached" => Some(Variable::Global(DMString(b"cached_datum".to_vec()))),
+        _ => None,
+    });
+
+    let cached = Variable::Global(DMString(b"cached_datum".to_vec()));
+
+    let nodes = compile_expr_with_options("cached + x", &["x"], &options).unwrap();
+    assert!(nodes.contains(&Node::Instruction(Instruction::GetVar(cached.clone()), ())));
+    // The params list at the end is the only place the real param is read
+    let arg = Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ());
+    assert_eq!(nodes.iter().filter(|node| **node == arg).count(), 1);
+
+    // Locals still come first
+    let proc = compile_proc_with_options("var/cached = 1\nreturn cached", &[], &options).unwrap();
+    assert!(!proc.nodes.contains(&Node::Instruction(Instruction::GetVar(cached), ())));
+}
+
+#[test]
+fn sandbox() {
+    let options = CompilerOptions::new().sandbox(SandboxPolicy::deny_dangerous());
+
+    for code in &[
+        "shell(\"rm -rf /\")",
+        "fdel(\"data/\")",
+        "world.Export(\"http://example.com\")",
+        "global.shell(\"ls\")",
+        "call(x, \"f\")()",
+        "del(x)",
+    ] {
+        let err = compile_expr_with_options(code, &["x"], &options).unwrap_err();
+        assert!(matches!(err.kind, CompileErrorKind::ForbiddenCall(_)));
+    }
+
+    // Writing and reading files with `<<` and `>>` needs file()
+    for code in &["file(\"data/x\") << x", "file(\"data/x\") >> x"] {
+        let err = compile_proc_with_options(code, &["x"], &options).unwrap_err();
+        assert!(matches!(err.kind, CompileErrorKind::ForbiddenCall(proc) if proc == "file"));
+    }
+    assert!(compile_proc_with_options("world << x", &["x"], &options).is_ok());
+
+    assert!(compile_expr_with_options("max(x, 1) + x.f()", &["x"], &options).is_ok());
+
+    let options = CompilerOptions::new().sandbox(SandboxPolicy::Allow(vec!["max".to_owned()]));
+    assert!(compile_expr_with_options("max(x, 1)", &["x"], &options).is_ok());
+    assert!(compile_expr_with_options("x.f()", &["x"], &options).is_err());
+}
+
+#[test]
+fn environment() {
+    let options = CompilerOptions::new().environment("env").global("config");
+
+    assert_eq!(
+        compile_expr_with_options("health + config", &["env"], &options)
+            .unwrap()
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(ins, ()) => Some(ins),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::PushVal(Value::DMString(DMString(b"health".to_vec())).into()),
+            Instruction::ListGet,
+            Instruction::GetVar(Variable::Global(DMString(b"config".to_vec()))),
+            Instruction::Add,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    // The environment has to be one of the params
+    assert!(compile_expr_with_options("health", &[], &options).is_err());
+}
+
+#[test]
+fn vars_lookup() {
+    assert_eq!(
+        compile_instructions("x.vars[\"health\"]", &["x"]),
+        compile_instructions("x.health", &["x"])
+    );
+
+    assert_eq!(
+        compile_instructions("x.y.vars[\"health\"] += 5", &["x"]),
+        compile_instructions("x.y.health += 5", &["x"])
+    );
+
+    assert_eq!(
+        compile_instructions("x?.vars[\"health\"]", &["x"]),
+        compile_instructions("x?.health", &["x"])
+    );
+
+    // Anything but a constant name still has to go through the list
+    assert!(compile_instructions("x.vars[y]", &["x", "y"]).contains(&Instruction::ListGet));
+}
+
+#[test]
+fn dot_lvalue() {
+    let has = |code: &str, ins: Instruction| compile_instructions(code, &["x"]).contains(&ins);
+
+    assert!(has(". = x", Instruction::SetVarExpr(Variable::Dot)));
+    assert!(has(". += x", Instruction::AugAdd(Variable::Dot)));
+    assert!(has(". |= x", Instruction::AugBor(Variable::Dot)));
+    assert!(has(". ||= x", Instruction::SetVarExpr(Variable::Dot)));
+    assert!(has(".++", Instruction::PostInc(Variable::Dot)));
+    assert!(has("--.", Instruction::PreDec(Variable::Dot)));
+
+    // A bare return hands back whatever was put in `.`
+    let proc = compile_proc(". = 1\n. *= 2\nreturn", &[]).unwrap();
+    assert_eq!(
+        proc.nodes
+            .into_iter()
+            .filter_map(|node| match node {
+                Node::Instruction(Instruction::DbgFile(_), ()) => None,
+                Node::Instruction(Instruction::DbgLine(_), ()) => None,
+                Node::Instruction(ins, ()) => Some(ins),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        vec![
+            Instruction::PushInt(1),
+            Instruction::SetVarExpr(Variable::Dot),
+            Instruction::Pop,
+            Instruction::PushInt(2),
+            Instruction::AugMul(Variable::Dot),
+            Instruction::PushEval,
+            Instruction::Pop,
+            Instruction::End,
+        ]
+    );
+}
+
+#[test]
+fn prologue_and_epilogue() {
+    let options = CompilerOptions::new()
+        .debug_info(false)
+        .prologue(vec![Node::Instruction(
+            Instruction::DbgFile(DMString(b"admin_eval.dm".to_vec())),
+            (),
+        )])
+        .epilogue(vec![Node::Instruction(Instruction::JsonEncode, ())]);
+
+    assert_eq!(
+        compile_expr_with_options("x", &["x"], &options).unwrap(),
+        vec![
+            Node::Instruction(Instruction::DbgFile(DMString(b"admin_eval.dm".to_vec())), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::NewList(2), ()),
+            Node::Instruction(Instruction::JsonEncode, ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+}
+
+#[test]
+fn return_conventions() {
+    let compile = |convention: ReturnConvention| -> Vec<Node> {
+        let options = CompilerOptions::new()
+            .debug_info(false)
+            .return_convention(convention);
+
+        compile_expr_with_options("x", &["x", "y"], &options).unwrap()
+    };
+
+    assert_eq!(
+        compile(ReturnConvention::Result),
+        vec![
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+
+    assert_eq!(
+        compile(ReturnConvention::Dot),
+        vec![
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::SetVar(Variable::Dot), ()),
+            Node::Instruction(Instruction::End, ()),
+        ]
+    );
+
+    assert_eq!(
+        compile(ReturnConvention::ResultAndArgs),
+        vec![
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Arg(1)), ()),
+            Node::Instruction(Instruction::NewList(3), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+}
+
+#[test]
+fn result_types() {
+    let result_type = |code: &str, options: &CompilerOptions| {
+        compile_expr_with_type(code, &["x"], options).unwrap().result_type
+    };
+
+    let options = CompilerOptions::new();
+    assert_eq!(result_type("1 + 2 * x", &options), StaticType::Number);
+    assert_eq!(result_type("\"apples\"", &options), StaticType::Text);
+    assert_eq!(result_type("\"!\" + x", &options), StaticType::Text);
+    assert_eq!(result_type("list(1, 2) + x", &options), StaticType::List);
+    assert_eq!(result_type("x ? 1 : \"one\"", &options), StaticType::Unknown);
+    assert_eq!(result_type("/mob", &options), StaticType::TypePath("/mob".to_owned()));
+    assert_eq!(result_type("new /obj", &options), StaticType::Object("/obj".to_owned()));
+    assert_eq!(result_type("x", &options), StaticType::Unknown);
+
+    let tree = parse_object_tree("/mob/living\n\tvar/health = 100\n\tvar/obj/held\n\tvar/list/items\n", &[]).unwrap();
+    let options = CompilerOptions::new()
+        .object_tree(Arc::new(tree))
+        .src_type("/mob/living");
+
+    assert_eq!(result_type("src", &options), StaticType::Object("/mob/living".to_owned()));
+    assert_eq!(result_type("src.held", &options), StaticType::Object("/obj".to_owned()));
+    assert_eq!(result_type("src.items", &options), StaticType::List);
+
+    // Untyped vars could hold anything
+    assert_eq!(result_type("src.health", &options), StaticType::Unknown);
+}
+
+#[test]
+fn const_evaluation() {
+    let eval = |code: &str| const_eval(&parse_expr(code).unwrap().0);
+
+    assert_eq!(eval("2 * 60 * 10"), Some(Value::Number(1200.0)));
+    assert_eq!(eval("-(-(3)) % 2"), Some(Value::Number(1.0)));
+    assert_eq!(
+        eval("(1 - 1) ? \"a\" : \"b\" + \"c\""),
+        Some(Value::DMString(DMString(b"bc".to_vec())))
+    );
+    assert_eq!(eval("1 ? null : x"), Some(Value::Null));
+
+    assert_eq!(eval("0 ? 1 : x"), None);
+    assert_eq!(eval("1 / 0"), None);
+    assert_eq!(eval("length(\"a\")"), None);
+}
+
+#[test]
+fn world_calls() {
+    assert_eq!(
+        compile_instructions("world.Export(addr)", &["addr"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::Call(
+                Variable::SetCache(
+                    Box::new(Variable::World),
+                    Box::new(Variable::DynamicProc(DMString(b"Export".to_vec())))
+                ),
+                1
+            ),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    // Arguments in a list are counted the same as for any other call
+    let export = |count: u32| {
+        Instruction::Call(
+            Variable::SetCache(
+                Box::new(Variable::World),
+                Box::new(Variable::DynamicProc(DMString(b"Export".to_vec()))),
+            ),
+            count,
+        )
+    };
+    assert!(compile_instructions("world.Export(arglist(addr))", &["addr"]).contains(&export(0xFFFF)));
+
+    let instructions = compile_instructions("world.Export(Addr = addr)", &["addr"]);
+    assert!(instructions.contains(&Instruction::NewAssocList(1)));
+    assert!(instructions.contains(&export(0xFFFF)));
+
+    let err = compile_expr("world.Exprot(addr)", &["addr"]).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::UnknownProc(proc) if proc == "/world/proc/Exprot"));
+
+    // Code bases can add their own procs to /world, which only the object tree knows about.
+    // The tree says where each proc is, so it's called by its path.
+    let tree = parse_object_tree("/world/proc/reload()\n", &[]).unwrap();
+    let options = CompilerOptions::new().object_tree(Arc::new(tree));
+    let nodes = compile_expr_with_options("world.reload()", &[], &options).unwrap();
+    assert!(nodes.contains(&Node::Instruction(
+        Instruction::Call(
+            Variable::SetCache(
+                Box::new(Variable::World),
+                Box::new(Variable::StaticProc(operands::Proc::from_path("/world/proc/reload".to_owned())))
+            ),
+            0
+        ),
+        ()
+    )));
+}
+
+#[test]
+fn global_vars() {
+    let global = |name: &str| Variable::Global(DMString(name.as_bytes().to_vec()));
+
+    assert!(compile_instructions("global.vars", &[]).contains(&Instruction::GetVar(global("vars"))));
+
+    // A constant name is just the global itself
+    assert!(compile_instructions("global.vars[\"config\"]", &[]).contains(&Instruction::GetVar(global("config"))));
+    assert!(compile_instructions("global.vars[\"config\"] = 1", &[]).contains(&Instruction::SetVarExpr(global("config"))));
+    assert!(compile_instructions("global.vars[\"config\"].name", &[]).contains(&Instruction::GetVar(Variable::SetCache(
+        Box::new(global("config")),
+        Box::new(Variable::Field(DMString(b"name".to_vec())))
+    ))));
+
+    // Anything else goes through the list
+    let instructions = compile_instructions("global.vars[name] = 1", &["name"]);
+    assert!(instructions.contains(&Instruction::GetVar(global("vars"))));
+    assert!(instructions.contains(&Instruction::SetVarExpr(Variable::CacheIndex)));
+
+    assert!(compile_instructions("global.vars[name] += 1", &["name"]).contains(&Instruction::AugAdd(Variable::CacheIndex)));
+
+    let err = compile_expr("global.vars = list()", &[]).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::ExpectedLValue));
+}
+
+#[test]
+fn movement_procs() {
+    assert_eq!(
+        compile_instructions("step(x, d)", &["x", "d", "s"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::Step,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::GetVar(Variable::Arg(2)),
+            Instruction::NewList(4),
+            Instruction::Ret,
+        ]
+    );
+
+    // The speed gets its own instruction
+    assert_eq!(
+        compile_instructions("step(x, d, s)", &["x", "d", "s"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::GetVar(Variable::Arg(2)),
+            Instruction::StepSpeed,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::GetVar(Variable::Arg(2)),
+            Instruction::NewList(4),
+            Instruction::Ret,
+        ]
+    );
+
+    let err = compile_expr("step(x, d, s, s)", &["x", "d", "s"]).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        CompileErrorKind::TooManyArguments { proc, expected: 3 } if proc == "step"
+    ));
+
+    // Missing optional arguments default to null before the speed
+    assert_eq!(
+        compile_instructions("step_to(x, d)", &["x", "d"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::PushVal(Value::Null.into()),
+            Instruction::StepTo,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("step_to(x, d, x, d)", &["x", "d"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::StepToSpeed,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+}
+
+#[test]
+fn sound_image_icon() {
+    let path = |path: &str| Instruction::PushVal(Value::Path(path.to_owned()).into());
+
+    assert_eq!(
+        compile_instructions("sound(s)", &["s"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            path("/sound"),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::New(1),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("image(s, x)", &["s", "x"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::NewImageArgs(2),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::NewList(3),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("icon(s)", &["s"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::IconNew(1),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    // Named arguments need new /icon(...)
+    let instructions = compile_instructions("icon(s, icon_state = x)", &["s", "x"]);
+    assert_eq!(instructions[1], path("/icon"));
+    assert!(instructions.contains(&Instruction::NewArgList));
+    assert!(!instructions.iter().any(|ins| matches!(ins, Instruction::IconNew(_))));
+}
+
+#[test]
+fn file_proc() {
+    assert_eq!(
+        compile_instructions("file(path)", &["path"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::PushVal(Value::File.into()),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::New(1),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    for code in &["file()", "file(path, path)"] {
+        let err = compile_expr(code, &["path"]).unwrap_err();
+        assert!(matches!(err.kind, CompileErrorKind::IncorrectArgCount(proc) if proc == "file"));
+    }
+}
+
+#[test]
+fn compile_files() {
+    let source = "/proc/add(n)\n\ttotal += n\n\treturn helper()\n\n/proc/helper()\n\treturn total\n\n/datum/thing\n\tvar/count = 0\n\tvar/obj/held\n\n/datum/thing/proc/tick()\n\tsrc.count++\n";
+    let file = compile_file(source).unwrap();
+
+    let paths: Vec<&str> = file.procs.keys().map(String::as_str).collect();
+    assert_eq!(paths, vec!["/datum/thing/proc/tick", "/proc/add", "/proc/helper"]);
+
+    let add = &file.procs["/proc/add"].metadata;
+    assert_eq!(add.globals_read, vec!["total".to_owned()]);
+    assert_eq!(add.globals_written, vec!["total".to_owned()]);
+    assert_eq!(add.procs_called, vec!["/proc/helper".to_owned()]);
+
+    let helper = &file.procs["/proc/helper"].metadata;
+    assert_eq!(helper.globals_read, vec!["total".to_owned()]);
+    assert!(helper.globals_written.is_empty());
+    assert!(helper.procs_called.is_empty());
+
+    assert!(file.procs["/datum/thing/proc/tick"].metadata.globals_written.is_empty());
+
+    let mut vars: Vec<(&str, &str)> = file.vars["/datum/thing"]
+        .iter()
+        .map(|var| (var.name.as_str(), var.type_path.as_str()))
+        .collect();
+    vars.sort();
+    assert_eq!(vars, vec![("count", ""), ("held", "/obj")]);
+    assert!(file.vars["/datum/thing"].iter().all(|var| !var.is_static));
+
+    // Every broken proc is reported
+    let errors = compile_file_collecting_errors(
+        "/proc/a()\n\treturn locate()\n\n/proc/b()\n\treturn locate()\n",
+        &CompilerOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn sized_lists() {
+    assert_eq!(
+        compile_instructions("new /list(n)", &["n"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::EmptyList,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("new /list(2, 3)", &[]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::PushInt(3),
+            Instruction::EmptyList,
+            Instruction::PushInt(3),
+            Instruction::EmptyList,
+            Instruction::NewList(2),
+            Instruction::NewList(1),
+            Instruction::Ret,
+        ]
+    );
+
+    // Sizes only known when it runs are up to /list
+    assert_eq!(
+        compile_instructions("new /list(n, 3)", &["n"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::PushVal(Value::Path("/list".to_owned()).into()),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::PushInt(3),
+            Instruction::New(2),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+}
+
+#[test]
+fn new_expr() {
+    // The type can come from anything, and goes on the stack before the args
+    assert_eq!(
+        compile_instructions("new (text2path(t))(x)", &["t", "x"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::Text2Path,
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::New(1),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::GetVar(Variable::Arg(1)),
+            Instruction::NewList(3),
+            Instruction::Ret,
+        ]
+    );
+
+    assert_eq!(
+        compile_instructions("new (t)()", &["t"]),
+        vec![
+            Instruction::DbgFile(DMString(b"<dmasm expression>".to_vec())),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::New(0),
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::NewList(2),
+            Instruction::Ret,
+        ]
+    );
+
+    // The same text in a string is just text
+    assert!(compile_instructions("\"new (t)(x)\"", &["t", "x"])
+        .contains(&Instruction::PushVal(Value::DMString(DMString(b"new (t)(x)".to_vec())).into())));
+
+    // Without the second set of brackets it's still an implicit new
+    let err = compile_expr("new (t)", &["t"]).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::UnsupportedImplicitNew));
+
+    // In a proc, behind the wrapping and any defines
+    let options = CompilerOptions::new().debug_info(false).define("TYPE", "/obj");
+    let nodes = compile_proc_with_options("var/t = TYPE\nreturn new (t)(1)", &[], &options)
+        .unwrap()
+        .nodes;
+    assert_eq!(
+        nodes,
+        vec![
+            Node::Instruction(Instruction::PushVal(Value::Path("/obj".to_owned()).into()), ()),
+            Node::Instruction(Instruction::SetVar(Variable::Local(0)), ()),
+            Node::Instruction(Instruction::GetVar(Variable::Local(0)), ()),
+            Node::Instruction(Instruction::PushInt(1), ()),
+            Node::Instruction(Instruction::New(1), ()),
+            Node::Instruction(Instruction::Ret, ()),
+        ]
+    );
+}
+
+#[test]
+fn astype() {
+    let ins = |ins: Instruction| Node::Instruction(ins, ());
+    let label = |name: &str| Label(name.to_owned());
+
+    // `x` is checked against the type, and null if it isn't one. The labels come after the one `emit_expr` takes
+    // for each expression the call is nested in.
+    let lowered = |type_path: &str, null: &str, end: &str| {
+        vec![
+            ins(Instruction::SetVar(Variable::Cache)),
+            ins(Instruction::PushCache),
+            ins(Instruction::GetVar(Variable::Cache)),
+            ins(Instruction::PushVal(Value::Path(type_path.to_owned()).into())),
+            ins(Instruction::IsType),
+            ins(Instruction::Test),
+            ins(Instruction::PopCache),
+            ins(Instruction::Jz(label(null))),
+            ins(Instruction::GetVar(Variable::Cache)),
+            ins(Instruction::Jmp(label(end))),
+            Node::Label(null.to_owned()),
+            ins(Instruction::PushVal(Value::Null.into())),
+            Node::Label(end.to_owned()),
+        ]
+    };
+
+    let options = CompilerOptions::new().debug_info(false);
+    let mut expected = vec![ins(Instruction::GetVar(Variable::Arg(0)))];
+    expected.extend(lowered("/obj", "LAB_0001", "LAB_0002"));
+    expected.extend(vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::NewList(2)),
+        ins(Instruction::Ret),
+    ]);
+    assert_eq!(compile_expr_with_options("astype(x, /obj)", &["x"], &options).unwrap(), expected);
+
+    // The type can be left for whatever the result is assigned to
+    let options = CompilerOptions::new().debug_info(false).implied_type(|var| match var {
+        "held" => Some("/obj/item".to_owned()),
+        _ => None,
+    });
+    let mut expected = vec![ins(Instruction::GetVar(Variable::Arg(0)))];
+    expected.extend(lowered("/obj/item", "LAB_0002", "LAB_0003"));
+    expected.extend(vec![
+        ins(Instruction::SetVarExpr(Variable::Global(DMString(b"held".to_vec())))),
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::NewList(2)),
+        ins(Instruction::Ret),
+    ]);
+    assert_eq!(compile_expr_with_options("held = astype(x)", &["x"], &options).unwrap(), expected);
+
+    let err = compile_expr("astype(x)", &["x"]).unwrap_err();
+    assert!(matches!(err.kind, CompileErrorKind::UnsupportedImplicitAsType));
+
+    // Even when targeting 516, which has an instruction for it
+    let options = CompilerOptions::new().debug_info(false).target_version(516);
+    let mut expected = vec![ins(Instruction::GetVar(Variable::Arg(0)))];
+    expected.extend(lowered("/obj", "LAB_0001", "LAB_0002"));
+    expected.extend(vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::NewList(2)),
+        ins(Instruction::Ret),
+    ]);
+    assert_eq!(compile_expr_with_options("astype(x, /obj)", &["x"], &options).unwrap(), expected);
 }
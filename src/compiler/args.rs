@@ -65,7 +65,7 @@ fn can_short_circuit(expr: &Expression) -> bool {
     }
 }
 
-fn has_assoc_argument(context: ArgsContext, args: &[Expression]) -> Result<bool, CompileError> {
+pub(super) fn has_assoc_argument(context: ArgsContext, args: &[Expression]) -> Result<bool, CompileError> {
     let mut found_associative = false;
 
     for arg in args {
@@ -81,7 +81,7 @@ fn has_assoc_argument(context: ArgsContext, args: &[Expression]) -> Result<bool,
                         // BYOND's behaviour for short-circuiting ops here is mad, so I'm going to error instead of matching it
                         // TODO: Move to emit code?
                         if can_short_circuit(unroll(lhs)) {
-                            return Err(CompileError::AmbiguousListConstructor);
+                            return Err(CompileErrorKind::AmbiguousListConstructor.into());
                         }
 
                         match lhs.as_ref() {
@@ -114,7 +114,7 @@ fn has_assoc_argument(context: ArgsContext, args: &[Expression]) -> Result<bool,
                         // BYOND's behaviour for short-circuiting ops here is mad, so I'm going to error instead of matching it
                         // TODO: Move to emit code?
                         if can_short_circuit(unroll(lhs)) {
-                            return Err(CompileError::AmbiguousListConstructor);
+                            return Err(CompileErrorKind::AmbiguousListConstructor.into());
                         }
 
                         // Finding an assign op means these args are associative
@@ -136,6 +136,9 @@ pub(super) enum ArgsContext {
     List,
 }
 
+// The argument count a call is given when its arguments are in a list on the stack, rather than pushed one by one
+pub(super) const ARG_LIST: u32 = 0xFFFF;
+
 pub(super) enum ArgsResult {
     // Each argument has been pushed on to the stack
     Normal,
@@ -176,8 +179,8 @@ pub(super) fn emit_normal(
 
     match result {
         ArgsResult::Normal => Ok(()),
-        ArgsResult::Assoc => Err(CompileError::UnexpectedNamedArguments),
-        ArgsResult::ArgList => Err(CompileError::UnexpectedArgList),
+        ArgsResult::Assoc => Err(CompileErrorKind::UnexpectedNamedArguments.into()),
+        ArgsResult::ArgList => Err(CompileErrorKind::UnexpectedArgList.into()),
     }
 }
 
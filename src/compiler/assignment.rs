@@ -64,7 +64,7 @@ fn emit_conditional(
         }
 
         // Could be a conditional call
-        _ => return Err(CompileError::ExpectedLValue),
+        _ => return Err(CompileErrorKind::ExpectedLValue.into()),
     };
 
     match op {
@@ -172,6 +172,29 @@ fn emit_conditional(
     Ok(EvalKind::Stack)
 }
 
+// Evaluates an expression into a Variable that can be written to.
+// Anything already on the stack stays there.
+pub(super) fn emit_lvalue(
+    compiler: &mut Compiler<'_>,
+    expr: Expression,
+) -> Result<Variable, CompileError> {
+    let var = match compiler.emit_expr(expr)? {
+        EvalKind::Var(var) if is_writable(&var) => var,
+
+        EvalKind::Field(builder, field) => builder.get_field(DMString(field.into())),
+
+        EvalKind::ListRef => {
+            compiler.emit_ins(Instruction::SetVar(Variable::CacheKey));
+            compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+            Variable::CacheIndex
+        }
+
+        _ => return Err(CompileErrorKind::ExpectedLValue.into()),
+    };
+
+    Ok(var)
+}
+
 pub(super) fn emit(
     compiler: &mut Compiler<'_>,
     op: AssignOp,
@@ -196,24 +219,26 @@ pub(super) fn emit(
         | AssignOp::BitXorAssign
         | AssignOp::LShiftAssign
         | AssignOp::RShiftAssign => {
+            // `x = new` creates whatever type `x` is
+            if let Expression::Base {
+                unary,
+                term,
+                follow,
+            } = &lhs
+            {
+                if let dreammaker::ast::Term::Ident(ident) = &term.elem {
+                    if op == AssignOp::Assign && unary.is_empty() && follow.is_empty() {
+                        compiler.implied_type = compiler.lookup_implied_type(ident);
+                    }
+                }
+            }
+
             // RHS evalutes before LHS for these assignments
             let rhs = compiler.emit_expr(rhs)?;
             compiler.emit_move_to_stack(rhs)?;
 
             // These ops require an l-value
-            let var = match compiler.emit_expr(lhs)? {
-                EvalKind::Var(var) if is_writable(&var) => var,
-
-                EvalKind::Field(builder, field) => builder.get_field(DMString(field.into())),
-
-                EvalKind::ListRef => {
-                    compiler.emit_ins(Instruction::SetVar(Variable::CacheKey));
-                    compiler.emit_ins(Instruction::SetVar(Variable::Cache));
-                    Variable::CacheIndex
-                }
-
-                _ => return Err(CompileError::ExpectedLValue),
-            };
+            let var = emit_lvalue(compiler, lhs)?;
 
             match op {
                 AssignOp::Assign => compiler.emit_ins(Instruction::SetVarExpr(var)),
@@ -311,7 +336,7 @@ pub(super) fn emit(
                     CacheKind::ListRef
                 }
 
-                _ => return Err(CompileError::ExpectedLValue),
+                _ => return Err(CompileErrorKind::ExpectedLValue.into()),
             };
 
             let rhs = compiler.emit_expr(rhs)?;
@@ -3,6 +3,18 @@ use dreammaker::ast::Expression;
 use crate::compiler::*;
 use crate::Instruction;
 
+// A bare `null` literal
+fn is_null(expr: &Expression) -> bool {
+    match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } => unary.is_empty() && follow.is_empty() && matches!(term.elem, dreammaker::ast::Term::Null),
+        _ => false,
+    }
+}
+
 pub(super) fn emit(
     compiler: &mut Compiler<'_>,
     op: BinaryOp,
@@ -36,6 +48,8 @@ pub(super) fn emit(
         }
 
         // Simple stack operations
+        // Comparisons don't chain: `a < b < c` is parsed as `(a < b) < c`, so the result of the first test is
+        // what gets compared against `c`. That's also what BYOND does, so we don't try to be clever here.
         BinaryOp::Add
         | BinaryOp::Sub
         | BinaryOp::Mul
@@ -55,6 +69,10 @@ pub(super) fn emit(
         | BinaryOp::BitOr
         | BinaryOp::LShift
         | BinaryOp::RShift => {
+            if (op == BinaryOp::Eq || op == BinaryOp::NotEq) && (is_null(&lhs) || is_null(&rhs)) {
+                compiler.warn(CompileWarningKind::NullComparison);
+            }
+
             // Bring LHS to stack
             let lhs = compiler.emit_expr(lhs)?;
             compiler.emit_move_to_stack(lhs)?;
@@ -133,3 +151,38 @@ pub(super) fn emit(
 
     Ok(kind)
 }
+
+// `a << b` and `a >> b` are output and input operations when used as a statement. Nothing is left behind by these.
+pub(super) fn emit_io(
+    compiler: &mut Compiler<'_>,
+    op: BinaryOp,
+    lhs: Expression,
+    rhs: Expression,
+) -> Result<EvalKind, CompileError> {
+    match op {
+        // target << message
+        BinaryOp::LShift => {
+            let lhs = compiler.emit_expr(lhs)?;
+            compiler.emit_move_to_stack(lhs)?;
+
+            let rhs = compiler.emit_expr(rhs)?;
+            compiler.emit_move_to_stack(rhs)?;
+
+            compiler.emit_ins(Instruction::Output);
+        }
+
+        // source >> var
+        BinaryOp::RShift => {
+            let lhs = compiler.emit_expr(lhs)?;
+            compiler.emit_move_to_stack(lhs)?;
+            compiler.emit_ins(Instruction::Read);
+
+            let var = assignment::emit_lvalue(compiler, rhs)?;
+            compiler.emit_ins(Instruction::SetVar(var));
+        }
+
+        _ => unreachable!(),
+    }
+
+    Ok(EvalKind::Nothing)
+}
@@ -35,6 +35,7 @@ impl From<f32> for DefaultValue {
 // These are built-in procs where a fixed amount of values are pushed to the stack
 // followed by a bare instruction (with no operands)
 // The parameters can have default values. If any params are missing, it'll error.
+// Procs like step() also take a trailing speed argument, which BYOND gives a separate instruction to.
 // Note: Many of the defaults are null because that's how BYOND's compiler does it.
 macro_rules! simple_stack_procs {
     (
@@ -48,6 +49,7 @@ macro_rules! simple_stack_procs {
                     )?
                 ),* $(,)?
             ) => $instruction:expr
+            $(; speed => $speed_instruction:expr)?
         ),* $(,)?
     ) => {
         fn eval_simple_stack_procs(
@@ -83,20 +85,34 @@ macro_rules! simple_stack_procs {
                                     }
 
                                     None => {
-                                        return Err(CompileError::MissingArgument {
+                                        return Err(CompileErrorKind::MissingArgument {
                                             proc: stringify!($proc_name).to_owned(),
                                             index: arg_idx as u32,
-                                        });
+                                        }
+                                        .into());
                                     }
                                 }
                             }
                         )*
 
-                        if arg_count > arg_idx {
-                            return Err(CompileError::TooManyArguments {
+                        #[allow(unused_mut)]
+                        let mut max_args = arg_idx;
+
+                        $(
+                            max_args += 1;
+
+                            if arg_count == max_args {
+                                compiler.emit_ins($speed_instruction);
+                                return Ok(Some(EvalKind::Stack));
+                            }
+                        )?
+
+                        if arg_count > max_args {
+                            return Err(CompileErrorKind::TooManyArguments {
                                 proc: stringify!($proc_name).to_owned(),
-                                expected: arg_idx as u32,
-                            });
+                                expected: max_args as u32,
+                            }
+                            .into());
                         }
 
                         compiler.emit_ins($instruction);
@@ -107,6 +123,35 @@ macro_rules! simple_stack_procs {
                 _ => Ok(None),
             }
         }
+
+        // How many values the instruction takes off the stack, if it belongs to a simple-stack proc
+        pub(super) fn simple_stack_proc_arity(ins: &Instruction) -> Option<u32> {
+            $(
+                let params: &[&str] = &[$(stringify!($param_name)),*];
+
+                if *ins == $instruction {
+                    return Some(params.len() as u32);
+                }
+
+                $(
+                    if *ins == $speed_instruction {
+                        return Some(params.len() as u32 + 1);
+                    }
+                )?
+            )*
+
+            None
+        }
+
+        fn simple_stack_proc_name(ins: &Instruction) -> Option<&'static str> {
+            $(
+                if *ins == $instruction $( || *ins == $speed_instruction )? {
+                    return Some(stringify!($proc_name));
+                }
+            )*
+
+            None
+        }
     }
 }
 
@@ -157,7 +202,6 @@ simple_stack_procs! {
     /proc/istype(val, path) => Instruction::IsType,
     /proc/jointext(list, glue, start = 1, end = null) => Instruction::JoinText,
     /proc/json_decode(json) => Instruction::JsonDecode,
-    /proc/json_encode(val) => Instruction::JsonEncode,
     /proc/length(val) => Instruction::Length,
     /proc/length_char(val) => Instruction::LengthChar,
     /proc/lentext(text) => Instruction::Length,
@@ -188,6 +232,11 @@ simple_stack_procs! {
     /proc/splittext(text, delimiter, start = 1, end = null, include_delimiters = null) => Instruction::SplitText,
     /proc/splittext_char(text, delimiter, start = 1, end = null, include_delimiters = null) => Instruction::SplitTextChar,
     /proc/sqrt(val) => Instruction::Sqrt,
+    /proc/step(ref, dir) => Instruction::Step; speed => Instruction::StepSpeed,
+    /proc/step_away(ref, target, max = null) => Instruction::StepAway; speed => Instruction::StepAwaySpeed,
+    /proc/step_rand(ref) => Instruction::StepRand; speed => Instruction::StepRandSpeed,
+    /proc/step_to(ref, target, min = null) => Instruction::StepTo; speed => Instruction::StepToSpeed,
+    /proc/step_towards(ref, target) => Instruction::StepTowards; speed => Instruction::StepTowardsSpeed,
     /proc/tan(val) => Instruction::Tan,
     /proc/text2ascii(text, pos = null) => Instruction::Text2Ascii,
     /proc/text2ascii_char(text, pos = null) => Instruction::Text2AsciiChar,
@@ -236,13 +285,13 @@ macro_rules! simple_vararg_procs {
                     stringify!($proc_name) => {
                         $(
                             if args_len < $min_args {
-                                return Err(CompileError::IncorrectArgCount(name.to_owned()));
+                                return Err(CompileErrorKind::IncorrectArgCount(name.to_owned()).into());
                             }
                         )?
 
                         $(
                             if args_len > $max_args {
-                                return Err(CompileError::IncorrectArgCount(name.to_owned()));
+                                return Err(CompileErrorKind::IncorrectArgCount(name.to_owned()).into());
                             }
                         )?
 
@@ -255,6 +304,16 @@ macro_rules! simple_vararg_procs {
                 _ => Ok(None),
             }
         }
+
+        fn simple_vararg_proc_name(ins: &Instruction) -> Option<&'static str> {
+            $(
+                if std::mem::discriminant(ins) == std::mem::discriminant(&$instruction(0)) {
+                    return Some(stringify!($proc_name));
+                }
+            )*
+
+            None
+        }
     }
 }
 
@@ -273,6 +332,11 @@ simple_vararg_procs! {
     /proc/typesof(1) => Instruction::TypesOf,
 }
 
+/// The built-in proc an instruction implements, for the ones that are called like a normal proc
+pub(crate) fn builtin_proc_name(ins: &Instruction) -> Option<&'static str> {
+    simple_stack_proc_name(ins).or_else(|| simple_vararg_proc_name(ins))
+}
+
 // # Unsupported Procs
 // Get to these later.
 macro_rules! unsupported_procs {
@@ -319,11 +383,8 @@ unsupported_procs! {
     /proc/animate,
     /proc/cmptext,
     /proc/cmptextEx,
-    /proc/file,
     /proc/filter,
     /proc/gradient,
-    /proc/icon,
-    /proc/image,
     /proc/isarea,
     /proc/isloc,
     /proc/ismob,
@@ -333,12 +394,6 @@ unsupported_procs! {
     /proc/isturf,
     /proc/newlist,
     /proc/rgb,
-    /proc/sound,
-    /proc/step,
-    /proc/step_away,
-    /proc/step_rand,
-    /proc/step_to,
-    /proc/step_towards,
     /proc/text,
 
     // Not actually procs
@@ -371,9 +426,10 @@ pub(super) fn emit(
     args: &Vec<Expression>,
 ) -> Result<Option<EvalKind>, CompileError> {
     if is_unsupported_proc(name) {
-        return Err(CompileError::UnsupportedBuiltin {
+        return Err(CompileErrorKind::UnsupportedBuiltin {
             proc: name.to_owned(),
-        });
+        }
+        .into());
     }
 
     if let Some(res) = eval_simple_stack_procs(compiler, name, args)? {
@@ -389,16 +445,187 @@ pub(super) fn emit(
     match name {
         "arglist" => {
             if arg_count != 1 {
-                return Err(CompileError::IncorrectArgCount(name.to_owned()));
+                return Err(CompileErrorKind::IncorrectArgCount(name.to_owned()).into());
             }
 
             args::emit_single_normal(compiler, args::ArgsContext::Proc, args[0].clone())?;
             Ok(Some(EvalKind::ArgList))
         }
 
+        // file() creates a new instance of the special /file type
+        "file" => {
+            if arg_count != 1 {
+                return Err(CompileErrorKind::IncorrectArgCount(name.to_owned()).into());
+            }
+
+            compiler.emit_ins(Instruction::PushVal(operands::Value::File.into()));
+            args::emit_single_normal(compiler, args::ArgsContext::Proc, args[0].clone())?;
+            compiler.emit_ins(Instruction::New(1));
+            Ok(Some(EvalKind::Stack))
+        }
+
+        // sound() is the same as new /sound(...)
+        "sound" => {
+            compiler.emit_ins(Instruction::PushVal(
+                operands::Value::Path("/sound".to_owned()).into(),
+            ));
+            term::emit_new(compiler, Some(args.clone())).map(Some)
+        }
+
+        "image" => {
+            match args::emit(compiler, args::ArgsContext::Proc, args.clone())? {
+                args::ArgsResult::Normal => {
+                    compiler.emit_ins(Instruction::NewImageArgs(arg_count));
+                }
+
+                args::ArgsResult::Assoc => {
+                    compiler.emit_ins(Instruction::NewAssocList(arg_count));
+                    compiler.emit_ins(Instruction::NewImageArgList);
+                }
+
+                args::ArgsResult::ArgList => {
+                    compiler.emit_ins(Instruction::NewImageArgList);
+                }
+            }
+
+            Ok(Some(EvalKind::Stack))
+        }
+
+        // IconNew has no arglist form, so named arguments are compiled as new /icon(...)
+        "icon" => {
+            let icon_path = || Instruction::PushVal(operands::Value::Path("/icon".to_owned()).into());
+
+            if args::has_assoc_argument(args::ArgsContext::Proc, args)? {
+                compiler.emit_ins(icon_path());
+                return term::emit_new(compiler, Some(args.clone())).map(Some);
+            }
+
+            match args::emit(compiler, args::ArgsContext::Proc, args.clone())? {
+                args::ArgsResult::Normal => {
+                    compiler.emit_ins(Instruction::IconNew(arg_count));
+                }
+
+                args::ArgsResult::Assoc => unreachable!(),
+
+                // The list is already on the stack, but the type needs to be beneath it
+                args::ArgsResult::ArgList => {
+                    compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+                    compiler.emit_ins(icon_path());
+                    compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+                    compiler.emit_ins(Instruction::NewArgList);
+                }
+            }
+
+            Ok(Some(EvalKind::Stack))
+        }
+
+        // 516 has an instruction for astype(x, T), but its opcode isn't confirmed yet, so it's always lowered to
+        // `istype(x, T) ? x : null`. The value is kept in the cache register so `x` is only evaluated once.
+        "astype" => {
+            let implied_type = compiler.implied_type.take();
+
+            match arg_count {
+                0 => {
+                    return Err(CompileErrorKind::MissingArgument {
+                        proc: name.to_owned(),
+                        index: 1,
+                    }
+                    .into())
+                }
+
+                // The type is implied by whatever the result is assigned to
+                1 if implied_type.is_none() => {
+                    return Err(CompileErrorKind::UnsupportedImplicitAsType.into())
+                }
+
+                1 | 2 => {}
+
+                _ => {
+                    return Err(CompileErrorKind::TooManyArguments {
+                        proc: name.to_owned(),
+                        expected: 2,
+                    }
+                    .into())
+                }
+            }
+
+            let label_null = format!("LAB_{:0>4X}", compiler.label_count);
+            let label_end = format!("LAB_{:0>4X}", compiler.label_count + 1);
+            compiler.label_count += 2;
+
+            let value = compiler.emit_expr(args[0].clone())?;
+            compiler.emit_move_to_stack(value)?;
+            compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+
+            // The type expression might clobber the cache
+            compiler.emit_ins(Instruction::PushCache);
+            compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+
+            match implied_type {
+                Some(path) if arg_count == 1 => {
+                    compiler.emit_ins(Instruction::PushVal(operands::Value::Path(path).into()));
+                }
+
+                _ => {
+                    let type_ = compiler.emit_expr(args[1].clone())?;
+                    compiler.emit_move_to_stack(type_)?;
+                }
+            }
+
+            compiler.emit_ins(Instruction::IsType);
+            compiler.emit_ins(Instruction::Test);
+            compiler.emit_ins(Instruction::PopCache);
+            compiler.emit_ins(Instruction::Jz(Label(label_null.clone())));
+
+            compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+            compiler.emit_ins(Instruction::Jmp(Label(label_end.clone())));
+
+            compiler.emit_label(label_null);
+            compiler.emit_ins(Instruction::PushVal(operands::Value::Null.into()));
+
+            compiler.emit_label(label_end);
+            Ok(Some(EvalKind::Stack))
+        }
+
+        // 515 added a flags argument, which has an instruction of its own. Its opcode isn't confirmed yet, so only the
+        // plain form compiles.
+        "json_encode" => {
+            match arg_count {
+                0 => {
+                    return Err(CompileErrorKind::MissingArgument {
+                        proc: name.to_owned(),
+                        index: 1,
+                    }
+                    .into())
+                }
+
+                1 => {
+                    args::emit_normal(compiler, args::ArgsContext::Proc, args.clone())?;
+                    compiler.emit_ins(Instruction::JsonEncode);
+                }
+
+                2 => {
+                    return Err(CompileErrorKind::UnsupportedBuiltin {
+                        proc: "json_encode() with flags".to_owned(),
+                    }
+                    .into())
+                }
+
+                _ => {
+                    return Err(CompileErrorKind::TooManyArguments {
+                        proc: name.to_owned(),
+                        expected: 2,
+                    }
+                    .into())
+                }
+            }
+
+            Ok(Some(EvalKind::Stack))
+        }
+
         "initial" => {
             if arg_count != 1 {
-                return Err(CompileError::IncorrectArgCount(name.to_owned()));
+                return Err(CompileErrorKind::IncorrectArgCount(name.to_owned()).into());
             }
 
             let var = match compiler.emit_expr(args[0].clone())? {
@@ -406,7 +633,7 @@ pub(super) fn emit(
                     builder.get_initial_field(DMString(field.into()))
                 }
 
-                _ => return Err(CompileError::ExpectedFieldReference),
+                _ => return Err(CompileErrorKind::ExpectedFieldReference.into()),
             };
 
             // The chain builder can't handle the kind of var we have, so move the reuslt to the stack
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::compiler::*;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    code: String,
+    params: Vec<String>,
+
+    // Not everything in the options can be compared directly, but the debug output covers all of it except which
+    // object tree is used
+    options: String,
+    object_tree: Option<TreeKey>,
+}
+
+// Object trees go by identity. Holding on to the tree means another one can't turn up at the same address.
+#[derive(Clone)]
+struct TreeKey(Arc<ObjectTree>);
+
+impl PartialEq for TreeKey {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TreeKey {}
+
+impl Hash for TreeKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state);
+    }
+}
+
+struct CacheEntry {
+    nodes: Vec<Node>,
+    last_used: u64,
+}
+
+/// Remembers the output of `compile_expr_with_options` for the most recently used expressions,
+/// so the same expression evaluated over and over only gets compiled once. Errors aren't cached.
+pub struct Cache {
+    capacity: usize,
+    entries: HashMap<CacheKey, CacheEntry>,
+    clock: u64,
+}
+
+impl Cache {
+    /// A cache that holds up to `capacity` expressions before it starts dropping the least recently used
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn compile_expr(
+        &mut self,
+        code: &str,
+        params: &[&str],
+        options: &CompilerOptions,
+    ) -> Result<Vec<Node>, CompileError> {
+        let key = CacheKey {
+            code: code.to_owned(),
+            params: params.iter().map(|param| (*param).to_owned()).collect(),
+            options: format!("{:?}", options),
+            object_tree: options.object_tree.clone().map(TreeKey),
+        };
+
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.clock;
+            return Ok(entry.nodes.clone());
+        }
+
+        let nodes = compile_expr_with_options(code, params, options)?;
+
+        if self.capacity == 0 {
+            return Ok(nodes);
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.evict();
+        }
+
+        self.entries.insert(
+            key,
+            CacheEntry {
+                nodes: nodes.clone(),
+                last_used: self.clock,
+            },
+        );
+
+        Ok(nodes)
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[test]
+fn least_recently_used() {
+    let options = CompilerOptions::default();
+    let mut cache = Cache::new(2);
+
+    let nodes = cache.compile_expr("x + 1", &["x"], &options).unwrap();
+    assert_eq!(nodes, compile_expr("x + 1", &["x"]).unwrap());
+
+    cache.compile_expr("x + 2", &["x"], &options).unwrap();
+    cache.compile_expr("x + 1", &["x"], &options).unwrap();
+    assert_eq!(cache.len(), 2);
+
+    // `x + 2` is the oldest now
+    cache.compile_expr("x + 3", &["x"], &options).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(cache.entries.keys().all(|key| key.code != "x + 2"));
+
+    // Different params or options are different entries
+    cache.compile_expr("x + 3", &["x", "y"], &options).unwrap();
+    cache.compile_expr("x + 3", &["x"], &CompilerOptions::new().debug_info(false)).unwrap();
+    assert!(cache.entries.keys().all(|key| key.code == "x + 3"));
+
+    assert!(cache.compile_expr("x +", &["x"], &options).is_err());
+}
+
+#[test]
+fn object_trees() {
+    let tree = Arc::new(parse_object_tree("/mob/living\n", &[]).unwrap());
+    let other = Arc::new(parse_object_tree("/mob/living\n", &[]).unwrap());
+    let mut cache = Cache::new(4);
+
+    // Clones of the same `Arc` are the same tree
+    cache.compile_expr("x", &["x"], &CompilerOptions::new().object_tree(tree.clone())).unwrap();
+    cache.compile_expr("x", &["x"], &CompilerOptions::new().object_tree(Arc::clone(&tree))).unwrap();
+    assert_eq!(cache.len(), 1);
+
+    // An identical tree isn't
+    cache.compile_expr("x", &["x"], &CompilerOptions::new().object_tree(other)).unwrap();
+    assert_eq!(cache.len(), 2);
+}
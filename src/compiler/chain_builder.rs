@@ -114,12 +114,13 @@ impl ChainBuilder {
         Variable::Initial(Box::new(Variable::Field(field)))
     }
 
-    pub fn get_dynamic_proc(mut self, proc: DMString) -> Variable {
+    // `proc` is a DynamicProc, StaticProc or StaticVerb
+    pub fn get_proc(mut self, proc: Variable) -> Variable {
         if let Some(rhs) = self.last_setcache_rhs() {
-            **rhs = Variable::DynamicProc(proc);
+            **rhs = proc;
             return self.var;
         }
 
-        Variable::DynamicProc(proc)
+        proc
     }
 }
@@ -0,0 +1,243 @@
+use dreammaker::ast::{BinaryOp, Expression, Follow, Spanned, Term, UnaryOp};
+use dreammaker::Location;
+
+use crate::operands::{DMString, Value};
+
+// The literals we know how to compute with at compile time
+enum Constant {
+    Null,
+    Number(f32),
+    String(String),
+}
+
+impl Constant {
+    fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            Term::Null => Some(Self::Null),
+            Term::Int(i) => Some(Self::Number(*i as f32)),
+            Term::Float(f) => Some(Self::Number(*f)),
+            Term::String(str) => Some(Self::String(str.clone())),
+            _ => None,
+        }
+    }
+
+    fn into_term(self) -> Term {
+        match self {
+            Self::Null => Term::Null,
+
+            // Whole numbers that an f32 can represent exactly can use PushInt
+            Self::Number(f) if f.fract() == 0.0 && f.abs() <= 16777216.0 => Term::Int(f as i32),
+            Self::Number(f) => Term::Float(f),
+            Self::String(str) => Term::String(str),
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Null => false,
+            Self::Number(f) => *f != 0.0,
+            Self::String(str) => !str.is_empty(),
+        }
+    }
+}
+
+impl From<Constant> for Value {
+    fn from(constant: Constant) -> Self {
+        match constant {
+            Constant::Null => Value::Null,
+            Constant::Number(f) => Value::Number(f),
+            Constant::String(str) => Value::DMString(DMString(str.into_bytes())),
+        }
+    }
+}
+
+fn constant(expr: &Expression) -> Option<Constant> {
+    match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } if unary.is_empty() && follow.is_empty() => Constant::from_term(&term.elem),
+        _ => None,
+    }
+}
+
+fn eval(expr: &Expression) -> Option<Constant> {
+    match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } => {
+            if !follow.is_empty() {
+                return None;
+            }
+
+            let value = match &term.elem {
+                Term::Expr(expr) => eval(expr)?,
+                term => Constant::from_term(term)?,
+            };
+
+            if unary.is_empty() {
+                return Some(value);
+            }
+
+            // Negations cancel out in pairs, anything else is left to the runtime
+            match value {
+                Constant::Number(f) if unary.iter().all(|op| *op == UnaryOp::Neg) => {
+                    Some(Constant::Number(if unary.len() % 2 == 0 { f } else { -f }))
+                }
+                _ => None,
+            }
+        }
+
+        Expression::BinaryOp { op, lhs, rhs } => fold_binary(*op, eval(lhs)?, eval(rhs)?),
+
+        Expression::TernaryOp { cond, if_, else_ } => {
+            if eval(cond)?.is_truthy() {
+                eval(if_)
+            } else {
+                eval(else_)
+            }
+        }
+
+        Expression::AssignOp { .. } => None,
+    }
+}
+
+/// Works out the value of an expression made only of literals, arithmetic, string concatenation and ternaries.
+/// This is what constant folding uses, so anything it gives up on (see `fold`) is `None` here too.
+pub fn const_eval(expr: &Expression) -> Option<Value> {
+    eval(expr).map(Value::from)
+}
+
+fn constant_expr(location: Location, constant: Constant) -> Expression {
+    Expression::Base {
+        unary: vec![],
+        term: Box::new(Spanned::new(location, constant.into_term())),
+        follow: vec![],
+    }
+}
+
+/// Replaces constant arithmetic, concatenation of string literals and ternaries with constant conditions with their result.
+/// Anything that could fail or behave differently at runtime (division by zero, non-finite results) is left alone.
+pub(super) fn fold(expr: Expression) -> Expression {
+    if let Some(value) = eval(&expr) {
+        return constant_expr(super::expr_location(&expr), value);
+    }
+
+    match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } => fold_base(unary, *term, follow),
+
+        Expression::BinaryOp { op, lhs, rhs } => {
+            Expression::BinaryOp {
+                op,
+                lhs: Box::new(fold(*lhs)),
+                rhs: Box::new(fold(*rhs)),
+            }
+        }
+
+        // The LHS is left alone: it has to stay something we can assign to
+        Expression::AssignOp { op, lhs, rhs } => Expression::AssignOp {
+            op,
+            lhs,
+            rhs: Box::new(fold(*rhs)),
+        },
+
+        Expression::TernaryOp { cond, if_, else_ } => {
+            let cond = fold(*cond);
+
+            match constant(&cond) {
+                Some(value) if value.is_truthy() => fold(*if_),
+                Some(_) => fold(*else_),
+                None => Expression::TernaryOp {
+                    cond: Box::new(cond),
+                    if_: Box::new(fold(*if_)),
+                    else_: Box::new(fold(*else_)),
+                },
+            }
+        }
+    }
+}
+
+fn fold_base(unary: Vec<UnaryOp>, term: Spanned<Term>, follow: Vec<Spanned<Follow>>) -> Expression {
+    let location = term.location;
+    let mut term = fold_term(term.elem);
+
+    let follow: Vec<Spanned<Follow>> = follow
+        .into_iter()
+        .map(|follow| Spanned::new(follow.location, fold_follow(follow.elem)))
+        .collect();
+
+    // Parentheses around a constant don't mean anything
+    if follow.is_empty() {
+        if let Term::Expr(expr) = &term {
+            if let Some(value) = constant(expr) {
+                term = value.into_term();
+            }
+        }
+    }
+
+    Expression::Base {
+        unary,
+        term: Box::new(Spanned::new(location, term)),
+        follow,
+    }
+}
+
+fn fold_args(args: Vec<Expression>) -> Vec<Expression> {
+    args.into_iter().map(fold).collect()
+}
+
+fn fold_term(term: Term) -> Term {
+    match term {
+        Term::Expr(expr) => Term::Expr(Box::new(fold(*expr))),
+        Term::Call(name, args) => Term::Call(name, fold_args(args)),
+        Term::List(args) => Term::List(fold_args(args)),
+        Term::New { type_, args } => Term::New {
+            type_,
+            args: args.map(fold_args),
+        },
+        term => term,
+    }
+}
+
+fn fold_follow(follow: Follow) -> Follow {
+    match follow {
+        Follow::Index(kind, expr) => Follow::Index(kind, Box::new(fold(*expr))),
+        Follow::Call(kind, name, args) => Follow::Call(kind, name, fold_args(args)),
+        follow => follow,
+    }
+}
+
+fn fold_binary(op: BinaryOp, lhs: Constant, rhs: Constant) -> Option<Constant> {
+    let result = match (op, lhs, rhs) {
+        (BinaryOp::Add, Constant::String(l), Constant::String(r)) => {
+            return Some(Constant::String(l + &r))
+        }
+        (BinaryOp::Add, Constant::Number(l), Constant::Number(r)) => l + r,
+        (BinaryOp::Sub, Constant::Number(l), Constant::Number(r)) => l - r,
+        (BinaryOp::Mul, Constant::Number(l), Constant::Number(r)) => l * r,
+        (BinaryOp::Div, Constant::Number(l), Constant::Number(r)) if r != 0.0 => l / r,
+        (BinaryOp::Pow, Constant::Number(l), Constant::Number(r)) => l.powf(r),
+
+        // BYOND's modulo works on integers
+        (BinaryOp::Mod, Constant::Number(l), Constant::Number(r))
+            if l.fract() == 0.0 && r.fract() == 0.0 && r != 0.0 =>
+        {
+            (l as i32 % r as i32) as f32
+        }
+
+        _ => return None,
+    };
+
+    if !result.is_finite() {
+        return None;
+    }
+
+    Some(Constant::Number(result))
+}
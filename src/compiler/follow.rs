@@ -6,6 +6,7 @@ use crate::Instruction;
 pub(super) fn emit(
     compiler: &mut Compiler,
     follow: Vec<Follow>,
+    procs: Vec<Option<Variable>>,
     kind: EvalKind,
 ) -> Result<EvalKind, CompileError> {
     let mut kind = kind;
@@ -14,7 +15,8 @@ pub(super) fn emit(
     // TODO: Move this state and the commit function into a struct!
     let mut field_buffer = vec![];
 
-    for sub_expr in follow {
+    // `procs` has what each call in `follow` resolved to, if anything (see `type_check::check_follows`)
+    for (sub_expr, resolved) in follow.into_iter().zip(procs) {
         match sub_expr {
             Follow::Field(access_kind, ident) => {
                 // We currently treat `.` and `:` as the same
@@ -37,6 +39,22 @@ pub(super) fn emit(
             }
 
             Follow::Index(access_kind, expr) => {
+                // `x.vars["name"]` is just `x.name`, which doesn't have to create the vars list
+                if let (ListAccessKind::Normal, Some(name)) = (access_kind, constant_var_name(&expr)) {
+                    if let Some(field) = field_buffer.last_mut().filter(|field| *field == "vars") {
+                        *field = name;
+                        continue;
+                    }
+
+                    // `x?.vars["name"]`
+                    if let (true, EvalKind::Field(_, field)) = (field_buffer.is_empty(), &mut kind) {
+                        if field == "vars" {
+                            *field = name;
+                            continue;
+                        }
+                    }
+                }
+
                 kind = commit_field_buffer(compiler, kind, &mut field_buffer)?;
 
                 match access_kind {
@@ -71,6 +89,8 @@ pub(super) fn emit(
             }
 
             Follow::Call(index_kind, ident, args) => {
+                compiler.check_call_allowed(&ident)?;
+
                 let arg_count = args.len() as u32;
 
                 match index_kind {
@@ -78,26 +98,44 @@ pub(super) fn emit(
                     PropertyAccessKind::Dot | PropertyAccessKind::Colon
                         if matches!(kind, EvalKind::Global) && field_buffer.is_empty() =>
                     {
+                        let path = type_check::check_global_proc(compiler, &ident)?;
+
                         match args::emit(compiler, args::ArgsContext::Proc, args)? {
                             args::ArgsResult::Normal => {
                                 // We're treating all Term::Call expressions as global calls
-                                compiler.emit_ins(Instruction::CallGlob(
-                                    arg_count,
-                                    operands::Proc::from_path(format!("/proc/{}", ident)),
-                                ));
+                                compiler.emit_ins(Instruction::CallGlob(arg_count, operands::Proc::from_path(path)));
+                            }
+
+                            args::ArgsResult::Assoc => {
+                                compiler.emit_ins(Instruction::NewAssocList(arg_count));
+                                compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(path)));
+                            }
+
+                            args::ArgsResult::ArgList => {
+                                compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(path)));
+                            }
+                        }
+                    }
+
+                    // `world` can be named in the call itself, so it doesn't need to go through the cache
+                    PropertyAccessKind::Dot | PropertyAccessKind::Colon
+                        if matches!(kind, EvalKind::Var(Variable::World)) && field_buffer.is_empty() =>
+                    {
+                        let proc = type_check::check_world_proc(compiler, &ident)?;
+                        let proc = ChainBuilder::begin(Variable::World).get_proc(proc);
+
+                        match args::emit(compiler, args::ArgsContext::Proc, args)? {
+                            args::ArgsResult::Normal => {
+                                compiler.emit_ins(Instruction::Call(proc, arg_count));
                             }
 
                             args::ArgsResult::Assoc => {
                                 compiler.emit_ins(Instruction::NewAssocList(arg_count));
-                                compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(
-                                    format!("/proc/{}", ident),
-                                )));
+                                compiler.emit_ins(Instruction::Call(proc, args::ARG_LIST));
                             }
 
                             args::ArgsResult::ArgList => {
-                                compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(
-                                    format!("/proc/{}", ident),
-                                )));
+                                compiler.emit_ins(Instruction::Call(proc, args::ARG_LIST));
                             }
                         }
                     }
@@ -106,6 +144,7 @@ pub(super) fn emit(
                     // TODO: Should we type check?
                     PropertyAccessKind::Dot | PropertyAccessKind::Colon => {
                         // TODO: Can emit much cleaner code when no params
+                        let proc = resolved.unwrap_or_else(|| Variable::DynamicProc(DMString(ident.into())));
                         kind = commit_field_buffer(compiler, kind, &mut field_buffer)?;
                         compiler.emit_move_to_stack(kind)?;
 
@@ -118,7 +157,7 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
+                                    proc,
                                     arg_count,
                                 ));
                             }
@@ -129,8 +168,8 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
-                                    65535, // TODO: remove hardcoded value
+                                    proc,
+                                    args::ARG_LIST,
                                 ));
                             }
 
@@ -138,8 +177,8 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
-                                    65535, // TODO: remove hardcoded value
+                                    proc,
+                                    args::ARG_LIST,
                                 ));
                             }
                         }
@@ -147,6 +186,7 @@ pub(super) fn emit(
 
                     PropertyAccessKind::SafeDot | PropertyAccessKind::SafeColon => {
                         // TODO: Can emit much cleaner code when no params
+                        let proc = resolved.unwrap_or_else(|| Variable::DynamicProc(DMString(ident.into())));
                         kind = commit_field_buffer(compiler, kind, &mut field_buffer)?;
                         compiler.emit_move_to_stack(kind)?;
 
@@ -161,7 +201,7 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
+                                    proc,
                                     arg_count,
                                 ));
                             }
@@ -172,8 +212,8 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
-                                    65535, // TODO: remove hardcoded value
+                                    proc,
+                                    args::ARG_LIST,
                                 ));
                             }
 
@@ -181,8 +221,8 @@ pub(super) fn emit(
                                 compiler.emit_ins(Instruction::PopCache);
 
                                 compiler.emit_ins(Instruction::Call(
-                                    Variable::DynamicProc(DMString(ident.into())),
-                                    65535, // TODO: remove hardcoded value
+                                    proc,
+                                    args::ARG_LIST,
                                 ));
                             }
                         }
@@ -198,6 +238,34 @@ pub(super) fn emit(
     Ok(kind)
 }
 
+// A string literal that's a valid var name
+fn constant_var_name(expr: &Expression) -> Option<String> {
+    let name = match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } if unary.is_empty() && follow.is_empty() => match &term.elem {
+            dreammaker::ast::Term::String(name) => name,
+            _ => return None,
+        },
+
+        _ => return None,
+    };
+
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return None,
+    }
+
+    if !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    Some(name.clone())
+}
+
 fn commit_field_buffer(
     compiler: &mut Compiler,
     kind: EvalKind,
@@ -221,10 +289,11 @@ fn commit_field_buffer(
             ChainBuilder::begin(Variable::Cache)
         }
 
-        EvalKind::Range => return Err(CompileError::UnexpectedRange),
-        EvalKind::ArgList => return Err(CompileError::UnexpectedArgList),
+        EvalKind::Range => return Err(CompileErrorKind::UnexpectedRange.into()),
+        EvalKind::ArgList => return Err(CompileErrorKind::UnexpectedArgList.into()),
+        EvalKind::Nothing => return Err(CompileErrorKind::UnexpectedNothing.into()),
 
-        // Bit hacky.
+        // Bit hacky. The first field is the name of the global, including `global.vars` (the list of all of them).
         EvalKind::Global => {
             let name = field_chain.remove(0);
             let var = Variable::Global(DMString(name.into()));
@@ -0,0 +1,159 @@
+use crate::operands::{DMString, Variable};
+use crate::Instruction;
+use crate::Node;
+
+/// What compiled code touches outside of itself, for checking it against a running world before it gets injected.
+/// Each list is in the order things are first seen, without duplicates.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    pub globals_read: Vec<String>,
+    pub globals_written: Vec<String>,
+
+    /// Global procs by path (`/proc/foo`), procs called on objects by name
+    pub procs_called: Vec<String>,
+}
+
+fn push_unique(list: &mut Vec<String>, name: String) {
+    if !list.contains(&name) {
+        list.push(name);
+    }
+}
+
+fn name(str: &DMString) -> String {
+    String::from_utf8_lossy(&str.0).into_owned()
+}
+
+// The variable operand of an instruction, followed by whether the instruction reads and writes it
+fn variable_access(ins: &Instruction) -> Option<(&Variable, bool, bool)> {
+    let access = match ins {
+        Instruction::GetVar(var) | Instruction::Call(var, _) => (var, true, false),
+
+        Instruction::SetVar(var) | Instruction::SetVarExpr(var) | Instruction::AssignInto(var) => {
+            (var, false, true)
+        }
+
+        Instruction::AugAdd(var)
+        | Instruction::AugSub(var)
+        | Instruction::AugMul(var)
+        | Instruction::AugDiv(var)
+        | Instruction::AugMod(var)
+        | Instruction::AugBand(var)
+        | Instruction::AugBor(var)
+        | Instruction::AugXor(var)
+        | Instruction::AugLShift(var)
+        | Instruction::AugRShift(var)
+        | Instruction::PreInc(var)
+        | Instruction::PostInc(var)
+        | Instruction::PreDec(var)
+        | Instruction::PostDec(var) => (var, true, true),
+
+        _ => return None,
+    };
+
+    Some(access)
+}
+
+impl Metadata {
+    // Everything before the end of a chain only gets read, the access only applies to the end
+    fn visit(&mut self, var: &Variable, read: bool, write: bool) {
+        match var {
+            Variable::Global(global) => {
+                if read {
+                    push_unique(&mut self.globals_read, name(global));
+                }
+
+                if write {
+                    push_unique(&mut self.globals_written, name(global));
+                }
+            }
+
+            Variable::SetCache(lhs, rhs) => {
+                self.visit(lhs, true, false);
+                self.visit(rhs, read, write);
+            }
+
+            Variable::Initial(inner) | Variable::IsSaved(inner) => self.visit(inner, true, false),
+
+            Variable::StaticProc(proc) | Variable::StaticVerb(proc) => {
+                push_unique(&mut self.procs_called, proc.path.clone())
+            }
+
+            Variable::DynamicProc(proc) | Variable::DynamicVerb(proc) => {
+                push_unique(&mut self.procs_called, name(proc))
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Lists the globals and procs used by compiled code
+pub fn collect_metadata<D>(nodes: &[Node<D>]) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    for node in nodes {
+        let ins = match node {
+            Node::Instruction(ins, _) => ins,
+            _ => continue,
+        };
+
+        match ins {
+            Instruction::CallGlob(_, proc) | Instruction::CallGlobalArgList(proc) => {
+                push_unique(&mut metadata.procs_called, proc.path.clone())
+            }
+
+            ins => {
+                if let Some((var, read, write)) = variable_access(ins) {
+                    metadata.visit(var, read, write);
+                }
+            }
+        }
+    }
+
+    metadata
+}
+
+#[test]
+fn globals_and_procs() {
+    use crate::operands::Proc;
+
+    let global = |name: &str| Variable::Global(DMString(name.as_bytes().to_vec()));
+
+    // a.b = c; e += 1; d.heal(); foo(a)
+    let nodes: Vec<Node> = vec![
+        Node::Instruction(Instruction::GetVar(global("c")), ()),
+        Node::Instruction(
+            Instruction::SetVar(Variable::SetCache(
+                Box::new(global("a")),
+                Box::new(Variable::Field(DMString(b"b".to_vec()))),
+            )),
+            (),
+        ),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::AugAdd(global("e")), ()),
+        Node::Instruction(
+            Instruction::Call(
+                Variable::SetCache(
+                    Box::new(global("d")),
+                    Box::new(Variable::DynamicProc(DMString(b"heal".to_vec()))),
+                ),
+                0,
+            ),
+            (),
+        ),
+        Node::Instruction(Instruction::GetVar(global("a")), ()),
+        Node::Instruction(
+            Instruction::CallGlob(1, Proc::from_path("/proc/foo".to_owned())),
+            (),
+        ),
+    ];
+
+    assert_eq!(
+        collect_metadata(&nodes),
+        Metadata {
+            globals_read: vec!["c".to_owned(), "a".to_owned(), "e".to_owned(), "d".to_owned()],
+            globals_written: vec!["e".to_owned()],
+            procs_called: vec!["heal".to_owned(), "/proc/foo".to_owned()],
+        }
+    );
+}
@@ -0,0 +1,184 @@
+//! `new (expr)(args)` creates whatever type `expr` evaluates to. dreammaker doesn't parse that form, so before parsing
+//! it's rewritten in place to `new (expr, args)`. That's the same length, so nothing else in the code moves, and where
+//! each one was is kept so the compiler can tell it apart from an implicit `new` given those args.
+
+use dreammaker::Location;
+
+/// Where each rewritten `new` is, by line and column as dreammaker counts them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct NewExprs(Vec<(u32, u16)>);
+
+impl NewExprs {
+    pub fn contains(&self, location: Location) -> bool {
+        self.0.contains(&(location.line, location.column))
+    }
+}
+
+pub(crate) fn rewrite(code: &str) -> (String, NewExprs) {
+    let mut bytes = code.as_bytes().to_vec();
+    let mut found = vec![];
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        if let Some(end) = skip_literal(&bytes, idx) {
+            idx = end;
+            continue;
+        }
+
+        if is_new_keyword(&bytes, idx) && rewrite_at(&mut bytes, idx) {
+            found.push(position(&bytes, idx));
+        }
+
+        idx += 1;
+    }
+
+    // Only ASCII punctuation and spaces were swapped for each other
+    (String::from_utf8(bytes).unwrap(), NewExprs(found))
+}
+
+// Turns the `)(` after `new (expr)` into `, ` (or just spaces when there are no args)
+fn rewrite_at(bytes: &mut [u8], idx: usize) -> bool {
+    let type_open = skip_spaces(bytes, idx + 3);
+    if bytes.get(type_open) != Some(&b'(') {
+        return false;
+    }
+
+    let type_close = match find_close(bytes, type_open) {
+        Some(close) => close,
+        None => return false,
+    };
+
+    let args_open = skip_spaces(bytes, type_close + 1);
+    if bytes.get(args_open) != Some(&b'(') {
+        return false;
+    }
+
+    let args_close = match find_close(bytes, args_open) {
+        Some(close) => close,
+        None => return false,
+    };
+
+    if is_blank(&bytes[type_open + 1..type_close]) {
+        return false;
+    }
+
+    let has_args = !is_blank(&bytes[args_open + 1..args_close]);
+    for byte in &mut bytes[type_close..=args_open] {
+        *byte = b' ';
+    }
+
+    if has_args {
+        bytes[type_close] = b',';
+    }
+
+    true
+}
+
+fn is_new_keyword(bytes: &[u8], idx: usize) -> bool {
+    let is_ident = |byte: Option<&u8>| matches!(byte, Some(byte) if byte.is_ascii_alphanumeric() || *byte == b'_');
+
+    bytes[idx..].starts_with(b"new")
+        && (idx == 0 || !is_ident(bytes.get(idx - 1)) && bytes[idx - 1] != b'.' && bytes[idx - 1] != b':')
+        && !is_ident(bytes.get(idx + 3))
+}
+
+fn skip_spaces(bytes: &[u8], mut idx: usize) -> usize {
+    while matches!(bytes.get(idx), Some(b' ') | Some(b'\t')) {
+        idx += 1;
+    }
+
+    idx
+}
+
+fn is_blank(bytes: &[u8]) -> bool {
+    bytes.iter().all(u8::is_ascii_whitespace)
+}
+
+// The `)` matching the `(` at `open`
+fn find_close(bytes: &[u8], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut idx = open;
+
+    while idx < bytes.len() {
+        if let Some(end) = skip_literal(bytes, idx) {
+            idx = end;
+            continue;
+        }
+
+        match bytes[idx] {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => {}
+        }
+
+        idx += 1;
+    }
+
+    None
+}
+
+// Where the string, file or comment starting at `idx` ends, if there is one. Code in them is left alone.
+fn skip_literal(bytes: &[u8], idx: usize) -> Option<usize> {
+    let rest = &bytes[idx..];
+
+    let end = if rest.starts_with(b"{\"") {
+        find(rest, b"\"}").map(|end| end + 2)
+    } else if rest.starts_with(b"//") {
+        find(rest, b"\n")
+    } else if rest.starts_with(b"/*") {
+        find(&rest[2..], b"*/").map(|end| end + 4)
+    } else if rest[0] == b'"' || rest[0] == b'\'' {
+        let mut end = 1;
+        while end < rest.len() && rest[end] != rest[0] && rest[end] != b'\n' {
+            end += if rest[end] == b'\\' { 2 } else { 1 };
+        }
+        Some(end + 1)
+    } else {
+        return None;
+    };
+
+    Some(end.map_or(bytes.len(), |end| (idx + end).min(bytes.len())))
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes.windows(needle.len()).position(|window| window == needle)
+}
+
+fn position(bytes: &[u8], idx: usize) -> (u32, u16) {
+    let before = &bytes[..idx];
+    let line = before.iter().filter(|byte| **byte == b'\n').count() as u32 + 1;
+    let line_start = before.iter().rposition(|byte| *byte == b'\n').map_or(0, |newline| newline + 1);
+    (line, (idx - line_start) as u16 + 1)
+}
+
+#[test]
+fn rewrites() {
+    let (code, found) = rewrite("x = new (types[1])(a, b)\n\ty = new (t) ()");
+    assert_eq!(code, "x = new (types[1], a, b)\n\ty = new (t   )");
+    assert_eq!(found, NewExprs(vec![(1, 5), (2, 6)]));
+
+    // Nested in each other
+    let (code, found) = rewrite("new (new (a)())(new (b)(c))");
+    assert_eq!(code, "new (new (a  ), new (b, c))");
+    assert_eq!(found, NewExprs(vec![(1, 1), (1, 6), (1, 17)]));
+
+    // Anything else is left alone
+    for code in &[
+        "new(loc)",
+        "new /obj(loc)",
+        "renew (a)(b)",
+        "x.new (a)(b)",
+        "\"new (a)(b)\"",
+        "{\"\nnew (a)(b)\"}",
+        "// new (a)(b)",
+        "/* new (a)(b) */",
+        "new ()(b)",
+    ] {
+        assert_eq!(rewrite(code), (code.to_string(), NewExprs::default()), "{}", code);
+    }
+}
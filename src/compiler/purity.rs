@@ -0,0 +1,364 @@
+use crate::operands::Variable;
+use crate::Instruction;
+use crate::Node;
+
+/// How much compiled code can affect (or be affected by) the world around it, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Purity {
+    /// Only uses its arguments and constants, so it can be run any number of times with the same result
+    Pure,
+
+    /// Reads globals, fields, the map or randomness, but doesn't change anything
+    ReadOnly,
+
+    /// Writes to something outside of the proc, calls procs, or uses a builtin that might do either
+    SideEffecting,
+}
+
+// Variables that only exist for the duration of the proc. `cache[cache_key]` isn't one of them, as the list in
+// the cache can be a global, a field or anything else handed to the proc.
+fn is_proc_state(var: &Variable) -> bool {
+    matches!(
+        var,
+        Variable::Null
+            | Variable::Args
+            | Variable::Dot
+            | Variable::Cache
+            | Variable::CacheKey
+            | Variable::Arg(_)
+            | Variable::Local(_)
+            | Variable::NamedLocal(..)
+    )
+}
+
+fn read_purity(var: &Variable) -> Purity {
+    if is_proc_state(var) {
+        Purity::Pure
+    } else {
+        Purity::ReadOnly
+    }
+}
+
+// Writing to an argument only changes what the proc sees, but writing to a field of one (or to a list it holds)
+// doesn't. Setting the cache itself only changes what the proc sees too, writes through it are fields or indexes.
+fn write_purity(var: &Variable) -> Purity {
+    if is_proc_state(var) {
+        Purity::Pure
+    } else {
+        Purity::SideEffecting
+    }
+}
+
+fn instruction_purity(ins: &Instruction) -> Purity {
+    match ins {
+        Instruction::GetVar(var) => read_purity(var),
+
+        Instruction::SetVar(var)
+        | Instruction::SetVarExpr(var)
+        | Instruction::AssignInto(var)
+        | Instruction::AugAdd(var)
+        | Instruction::AugSub(var)
+        | Instruction::AugMul(var)
+        | Instruction::AugDiv(var)
+        | Instruction::AugMod(var)
+        | Instruction::AugBand(var)
+        | Instruction::AugBor(var)
+        | Instruction::AugXor(var)
+        | Instruction::AugLShift(var)
+        | Instruction::AugRShift(var)
+        | Instruction::PreInc(var)
+        | Instruction::PostInc(var)
+        | Instruction::PreDec(var)
+        | Instruction::PostDec(var)
+        | Instruction::Inc(var)
+        | Instruction::Dec(var) => write_purity(var),
+
+        // Control flow, the stack and the cache
+        Instruction::End
+        | Instruction::Ret
+        | Instruction::Test
+        | Instruction::Jmp(_)
+        | Instruction::Jz(_)
+        | Instruction::Jnz(_)
+        | Instruction::JmpOr(_)
+        | Instruction::JmpAnd(_)
+        | Instruction::JmpLoop(_)
+        | Instruction::JzLoop(_)
+        | Instruction::JnzLoop(_)
+        | Instruction::SetCacheJmpIfNull(_)
+        | Instruction::SetCachePopJmpIfNull(_)
+        | Instruction::Pop
+        | Instruction::PopN(_)
+        | Instruction::PushTop
+        | Instruction::PushInt(_)
+        | Instruction::PushVal(_)
+        | Instruction::PushEval
+        | Instruction::PushCache
+        | Instruction::PopCache
+        | Instruction::PushCacheKey
+        | Instruction::PopCacheKey
+        | Instruction::GetFlag
+        | Instruction::DbgFile(_)
+        | Instruction::DbgLine(_) => Purity::Pure,
+
+        // Operators, and builtins that only look at their arguments
+        Instruction::Not
+        | Instruction::UnaryNeg
+        | Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::Pow
+        | Instruction::Band
+        | Instruction::Bor
+        | Instruction::Bxor
+        | Instruction::Bnot
+        | Instruction::LShift
+        | Instruction::RShift
+        | Instruction::Teq
+        | Instruction::Tne
+        | Instruction::Tl
+        | Instruction::Tg
+        | Instruction::Tle
+        | Instruction::Tge
+        | Instruction::TestEquiv
+        | Instruction::TestNotEquiv
+        | Instruction::EmptyList
+        | Instruction::NewList(_)
+        | Instruction::NewAssocList(_)
+        | Instruction::Abs
+        | Instruction::Sqrt
+        | Instruction::Sin
+        | Instruction::Cos
+        | Instruction::Tan
+        | Instruction::ArcSin
+        | Instruction::ArcCos
+        | Instruction::ArcTan
+        | Instruction::ArcTan2
+        | Instruction::Log
+        | Instruction::Log10
+        | Instruction::Round
+        | Instruction::RoundN
+        | Instruction::Clamp
+        | Instruction::Min(_)
+        | Instruction::Max(_)
+        | Instruction::Turn
+        | Instruction::IsNull
+        | Instruction::IsNum
+        | Instruction::IsText
+        | Instruction::IsList
+        | Instruction::IsType
+        | Instruction::IsPath
+        | Instruction::IsSubPath
+        | Instruction::IsIcon
+        | Instruction::IsFile
+        | Instruction::IsLoc
+        | Instruction::IsMob
+        | Instruction::IsObj
+        | Instruction::IsArea
+        | Instruction::IsTurf
+        | Instruction::IsMovable
+        | Instruction::AddText(_)
+        | Instruction::Length
+        | Instruction::LengthChar
+        | Instruction::CopyText
+        | Instruction::CopyTextChar
+        | Instruction::FindText
+        | Instruction::FindTextChar
+        | Instruction::FindTextEx
+        | Instruction::FindTextExChar
+        | Instruction::FindLastText
+        | Instruction::FindLastTextChar
+        | Instruction::FindLastTextEx
+        | Instruction::FindLastTextExChar
+        | Instruction::ReplaceText
+        | Instruction::ReplaceTextChar
+        | Instruction::ReplaceTextEx
+        | Instruction::ReplaceTextExChar
+        | Instruction::SpanText
+        | Instruction::SpanTextChar
+        | Instruction::NonSpanText
+        | Instruction::NonSpanTextChar
+        | Instruction::SplitText
+        | Instruction::SplitTextChar
+        | Instruction::SpliceText
+        | Instruction::SpliceTextChar
+        | Instruction::JoinText
+        | Instruction::CmpText
+        | Instruction::SortText(_)
+        | Instruction::SortTextEx(_)
+        | Instruction::UpperText
+        | Instruction::LowerText
+        | Instruction::Text2Num
+        | Instruction::Text2NumRadix
+        | Instruction::Num2Text
+        | Instruction::Num2TextSigFigs
+        | Instruction::Num2TextRadix
+        | Instruction::Text2Ascii
+        | Instruction::Text2AsciiChar
+        | Instruction::Ascii2Text
+        | Instruction::Text2Path
+        | Instruction::CKey
+        | Instruction::CKeyEx
+        | Instruction::HtmlEncode
+        | Instruction::HtmlDecode
+        | Instruction::UrlEncode
+        | Instruction::UrlDecode
+        | Instruction::Md5
+        | Instruction::Sha1
+        | Instruction::List2Params
+        | Instruction::Params2List
+        | Instruction::JsonEncode
+        | Instruction::JsonDecode
+        | Instruction::Rgb
+        | Instruction::Rgba
+        | Instruction::RgbEx
+        | Instruction::Rgb2Num => Purity::Pure,
+
+        // Lists can be shared with the world, and the rest look at the map, the filesystem, the clock or the RNG
+        Instruction::ListGet
+        | Instruction::IsIn(_)
+        | Instruction::Ref
+        | Instruction::HasCall
+        | Instruction::TypesOf(_)
+        | Instruction::View
+        | Instruction::OView
+        | Instruction::Viewers
+        | Instruction::OViewers
+        | Instruction::Hearers
+        | Instruction::OHearers
+        | Instruction::Range(_)
+        | Instruction::ORange(_)
+        | Instruction::Block
+        | Instruction::Bounds(_)
+        | Instruction::OBounds(_)
+        | Instruction::BoundsDist
+        | Instruction::LocatePos
+        | Instruction::LocateRef
+        | Instruction::LocateType
+        | Instruction::GetStep
+        | Instruction::GetStepTo
+        | Instruction::GetStepAway
+        | Instruction::GetStepTowards
+        | Instruction::GetStepRand
+        | Instruction::GetDist
+        | Instruction::GetDir
+        | Instruction::FExists
+        | Instruction::FList
+        | Instruction::File2Text
+        | Instruction::Time2Text
+        | Instruction::Time2TextTZ(_)
+        | Instruction::Prob
+        | Instruction::Rand
+        | Instruction::RandRange
+        | Instruction::Roll
+        | Instruction::RollStr
+        | Instruction::Pick
+        | Instruction::PickProb(_) => Purity::ReadOnly,
+
+        // Proc calls, `new`, output, movement, sleeping and anything not listed above
+        _ => Purity::SideEffecting,
+    }
+}
+
+/// Works out whether it's safe to run compiled code without asking, e.g. re-evaluating a watch expression every tick.
+/// Anything it doesn't recognise counts as side-effecting.
+pub fn purity<D>(nodes: &[Node<D>]) -> Purity {
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Instruction(ins, _) => Some(instruction_purity(ins)),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(Purity::Pure)
+}
+
+#[cfg(test)]
+fn ins(instruction: Instruction) -> Node {
+    Node::Instruction(instruction, ())
+}
+
+#[test]
+fn classification() {
+    use crate::operands::DMString;
+
+    // args[1] + 1, returned through .
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::PushInt(1)),
+        ins(Instruction::Add),
+        ins(Instruction::SetVar(Variable::Dot)),
+        ins(Instruction::End),
+    ];
+    assert_eq!(purity(&nodes), Purity::Pure);
+
+    // src.health
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::SetCache(
+            Box::new(Variable::Src),
+            Box::new(Variable::Field(DMString(b"health".to_vec()))),
+        ))),
+        ins(Instruction::Ret),
+    ];
+    assert_eq!(purity(&nodes), Purity::ReadOnly);
+
+    // args[1].health = 0
+    let nodes = vec![
+        ins(Instruction::PushInt(0)),
+        ins(Instruction::SetVar(Variable::SetCache(
+            Box::new(Variable::Arg(0)),
+            Box::new(Variable::Field(DMString(b"health".to_vec()))),
+        ))),
+        ins(Instruction::End),
+    ];
+    assert_eq!(purity(&nodes), Purity::SideEffecting);
+
+    // src.heal()
+    let nodes = vec![
+        ins(Instruction::Call(
+            Variable::SetCache(
+                Box::new(Variable::Src),
+                Box::new(Variable::DynamicProc(DMString(b"heal".to_vec()))),
+            ),
+            0,
+        )),
+        ins(Instruction::Ret),
+    ];
+    assert_eq!(purity(&nodes), Purity::SideEffecting);
+}
+
+#[test]
+fn list_writes() {
+    use crate::operands::DMString;
+
+    // `list[1] = 2`, for a list from anywhere
+    let assign_index = |list: Variable| {
+        vec![
+            ins(Instruction::GetVar(list)),
+            ins(Instruction::PushInt(1)),
+            ins(Instruction::SetVar(Variable::CacheKey)),
+            ins(Instruction::SetVar(Variable::Cache)),
+            ins(Instruction::PushInt(2)),
+            ins(Instruction::SetVarExpr(Variable::CacheIndex)),
+            ins(Instruction::Pop),
+            ins(Instruction::End),
+        ]
+    };
+
+    let global_list = Variable::Global(DMString(b"global_list".to_vec()));
+    assert_eq!(purity(&assign_index(global_list)), Purity::SideEffecting);
+    assert_eq!(purity(&assign_index(Variable::Arg(0))), Purity::SideEffecting);
+
+    // Reading one can't change anything, but the list might not be the proc's own
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::PushInt(1)),
+        ins(Instruction::SetVar(Variable::CacheKey)),
+        ins(Instruction::SetVar(Variable::Cache)),
+        ins(Instruction::GetVar(Variable::CacheIndex)),
+        ins(Instruction::Ret),
+    ];
+    assert_eq!(purity(&nodes), Purity::ReadOnly);
+}
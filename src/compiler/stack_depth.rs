@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cfg::falls_through;
+use crate::compiler::{args, builtin_procs};
+use crate::operands::{IsInParams, Label};
+use crate::optimizer::jump_destinations;
+use crate::Instruction;
+use crate::Node;
+
+#[derive(Debug, PartialEq)]
+pub enum StackDepthError {
+    // We don't know how this instruction affects the stack
+    UnknownStackEffect(Instruction),
+    StackUnderflow(Instruction),
+    UnknownLabel(String),
+
+    // Two paths reach the same label with a different amount on the stack
+    InconsistentDepth(String),
+}
+
+impl fmt::Display for StackDepthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownStackEffect(ins) => write!(f, "unknown stack effect for {:?}", ins),
+            Self::StackUnderflow(ins) => write!(f, "stack underflow at {:?}", ins),
+            Self::UnknownLabel(label) => write!(f, "jump to unknown label {}", label),
+            Self::InconsistentDepth(label) => {
+                write!(f, "label {} is reached with different stack depths", label)
+            }
+        }
+    }
+}
+
+// The number of values an instruction takes off the stack, followed by the number it pushes.
+// This only needs to cover what the compiler emits.
+pub(crate) fn stack_effect(ins: &Instruction) -> Option<(u32, u32)> {
+    if let Some(arity) = builtin_procs::simple_stack_proc_arity(ins) {
+        return Some((arity, 1));
+    }
+
+    let effect = match ins {
+        Instruction::DbgFile(_)
+        | Instruction::DbgLine(_)
+        | Instruction::Jmp(_)
+        | Instruction::JmpLoop(_)
+        | Instruction::Jz(_)
+        | Instruction::Jnz(_)
+        | Instruction::PushCache
+        | Instruction::PopCache
+        | Instruction::PushCacheKey
+        | Instruction::PopCacheKey
+        | Instruction::End => (0, 0),
+
+        Instruction::GetVar(_)
+        | Instruction::PushInt(_)
+        | Instruction::PushVal(_)
+        | Instruction::PushEval
+        | Instruction::GetFlag
+        | Instruction::PreInc(_)
+        | Instruction::PostInc(_)
+        | Instruction::PreDec(_)
+        | Instruction::PostDec(_) => (0, 1),
+
+        Instruction::SetVar(_)
+        | Instruction::Pop
+        | Instruction::Test
+        | Instruction::Ret
+        | Instruction::Throw
+        | Instruction::AugAdd(_)
+        | Instruction::AugSub(_)
+        | Instruction::AugMul(_)
+        | Instruction::AugDiv(_)
+        | Instruction::AugMod(_)
+        | Instruction::AugBand(_)
+        | Instruction::AugBor(_)
+        | Instruction::AugXor(_)
+        | Instruction::AugLShift(_)
+        | Instruction::AugRShift(_)
+        | Instruction::AssignInto(_) => (1, 0),
+
+        // The value stays on the stack when jumping, see `branch_keeps_value`
+        Instruction::JmpOr(_) | Instruction::JmpAnd(_) | Instruction::SetCacheJmpIfNull(_) => (1, 0),
+
+        Instruction::SetVarExpr(_)
+        | Instruction::Not
+        | Instruction::UnaryNeg
+        | Instruction::Bnot
+        | Instruction::Read
+        | Instruction::EmptyList
+        | Instruction::Pick
+        | Instruction::LocateRef
+        | Instruction::JsonEncode
+        | Instruction::NewImageArgList
+        | Instruction::CallGlobalArgList(_) => (1, 1),
+
+        Instruction::Output => (2, 0),
+
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::Pow
+        | Instruction::Band
+        | Instruction::Bor
+        | Instruction::Bxor
+        | Instruction::LShift
+        | Instruction::RShift
+        | Instruction::Teq
+        | Instruction::Tne
+        | Instruction::Tl
+        | Instruction::Tg
+        | Instruction::Tle
+        | Instruction::Tge
+        | Instruction::TestEquiv
+        | Instruction::TestNotEquiv
+        | Instruction::ListGet
+        | Instruction::LocateType
+        | Instruction::NewArgList
+        | Instruction::CallPathArgList
+        | Instruction::IsIn(IsInParams::Value) => (2, 1),
+
+        Instruction::LocatePos | Instruction::CallNameArgList | Instruction::IsIn(IsInParams::Range) => (3, 1),
+
+        // The arguments are in a list
+        Instruction::Call(_, args::ARG_LIST) => (1, 1),
+        Instruction::Call(_, arg_count) => (*arg_count, 1),
+
+        Instruction::CallGlob(arg_count, _)
+        | Instruction::NewList(arg_count)
+        | Instruction::NewImageArgs(arg_count)
+        | Instruction::IconNew(arg_count)
+        | Instruction::AddText(arg_count)
+        | Instruction::Bounds(arg_count)
+        | Instruction::OBounds(arg_count)
+        | Instruction::MatrixNew(arg_count)
+        | Instruction::Max(arg_count)
+        | Instruction::Min(arg_count)
+        | Instruction::RegexNew(arg_count)
+        | Instruction::SortText(arg_count)
+        | Instruction::SortTextEx(arg_count)
+        | Instruction::Startup(arg_count)
+        | Instruction::TypesOf(arg_count) => (*arg_count, 1),
+
+        Instruction::NewAssocList(arg_count) => (arg_count * 2, 1),
+
+        // The type (or proc path) is beneath the arguments
+        Instruction::New(arg_count) | Instruction::CallPath(arg_count) => (arg_count + 1, 1),
+
+        // As is the object the proc is called on
+        Instruction::CallName(arg_count) => (arg_count + 2, 1),
+
+        Instruction::PickProb(params) => (params.cases.len() as u32, 0),
+
+        _ => return None,
+    };
+
+    Some(effect)
+}
+
+// Whether the value the instruction tests is left on the stack when it jumps
+fn branch_keeps_value(ins: &Instruction) -> bool {
+    matches!(
+        ins,
+        Instruction::JmpOr(_) | Instruction::JmpAnd(_) | Instruction::SetCacheJmpIfNull(_)
+    )
+}
+
+/// Works out the deepest the operand stack can get while running the code, following every jump.
+/// Proc headers need this when code gets written back into a .dmb (or patched in at runtime).
+pub fn max_stack_depth<D>(nodes: &[Node<D>]) -> Result<u32, StackDepthError> {
+    walk(nodes).map(|(_, max)| max)
+}
+
+/// How much is on the operand stack on the way into each node, or `None` for nodes that can't be reached.
+/// Handy for checking hand-written or patched code, which is where mismatched depths tend to come from.
+pub fn stack_depths<D>(nodes: &[Node<D>]) -> Result<Vec<Option<u32>>, StackDepthError> {
+    walk(nodes).map(|(depths, _)| depths)
+}
+
+// The depth on the way into each node, along with the deepest it gets
+fn walk<D>(nodes: &[Node<D>]) -> Result<(Vec<Option<u32>>, u32), StackDepthError> {
+    let labels: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| match node {
+            Node::Label(name) => Some((name.as_str(), idx)),
+            _ => None,
+        })
+        .collect();
+
+    let mut depths: Vec<Option<u32>> = vec![None; nodes.len()];
+    let mut pending = vec![(0, 0)];
+    let mut max = 0;
+
+    while let Some((mut idx, mut depth)) = pending.pop() {
+        while idx < nodes.len() {
+            match depths[idx] {
+                Some(seen) if seen == depth => break,
+                Some(_) => {
+                    let label = match &nodes[idx] {
+                        Node::Label(name) => name.clone(),
+                        _ => format!("#{}", idx),
+                    };
+
+                    return Err(StackDepthError::InconsistentDepth(label));
+                }
+                None => {}
+            }
+
+            depths[idx] = Some(depth);
+
+            let ins = match &nodes[idx] {
+                Node::Instruction(ins, _) => ins,
+                _ => {
+                    idx += 1;
+                    continue;
+                }
+            };
+
+            let (pops, pushes) =
+                stack_effect(ins).ok_or_else(|| StackDepthError::UnknownStackEffect(ins.clone()))?;
+
+            if pops > depth {
+                return Err(StackDepthError::StackUnderflow(ins.clone()));
+            }
+
+            let next_depth = depth - pops + pushes;
+            max = max.max(next_depth);
+
+            let jump_depth = if branch_keeps_value(ins) { depth } else { next_depth };
+
+            for Label(destination) in jump_destinations(ins) {
+                match labels.get(destination.as_str()) {
+                    Some(target) => pending.push((*target, jump_depth)),
+                    None => return Err(StackDepthError::UnknownLabel(destination.clone())),
+                }
+            }
+
+            if !falls_through(ins) {
+                break;
+            }
+
+            idx += 1;
+            depth = next_depth;
+        }
+    }
+
+    Ok((depths, max))
+}
+
+#[cfg(test)]
+fn ins(instruction: Instruction) -> Node {
+    Node::Instruction(instruction, ())
+}
+
+#[test]
+fn straight_line() {
+    use crate::operands::Variable;
+
+    // 1 + 2 * 3
+    let nodes = vec![
+        ins(Instruction::PushInt(1)),
+        ins(Instruction::PushInt(2)),
+        ins(Instruction::PushInt(3)),
+        ins(Instruction::Mul),
+        ins(Instruction::Add),
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::NewList(2)),
+        ins(Instruction::Ret),
+    ];
+
+    assert_eq!(max_stack_depth(&nodes), Ok(3));
+}
+
+#[test]
+fn short_circuit() {
+    use crate::operands::Variable;
+
+    // a || b(1, 2)
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::JmpOr(Label("LAB_0000".to_owned()))),
+        ins(Instruction::PushInt(1)),
+        ins(Instruction::PushInt(2)),
+        ins(Instruction::CallGlob(2, crate::operands::Proc::from_path("/proc/b".to_owned()))),
+        Node::Label("LAB_0000".to_owned()),
+        ins(Instruction::Ret),
+    ];
+
+    assert_eq!(max_stack_depth(&nodes), Ok(2));
+
+    // Both paths have to agree on what's on the stack
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::Test),
+        ins(Instruction::Jz(Label("LAB_0000".to_owned()))),
+        ins(Instruction::PushInt(1)),
+        Node::Label("LAB_0000".to_owned()),
+        ins(Instruction::End),
+    ];
+
+    assert_eq!(
+        max_stack_depth(&nodes),
+        Err(StackDepthError::InconsistentDepth("LAB_0000".to_owned()))
+    );
+}
+
+#[test]
+fn depths() {
+    use crate::operands::Variable;
+
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::JmpAnd(Label("LAB_0000".to_owned()))),
+        ins(Instruction::GetVar(Variable::Arg(1))),
+        Node::Label("LAB_0000".to_owned()),
+        ins(Instruction::Ret),
+        ins(Instruction::PushInt(2)),
+    ];
+
+    assert_eq!(
+        stack_depths(&nodes),
+        Ok(vec![Some(0), Some(1), Some(0), Some(1), Some(1), None])
+    );
+}
+
+#[test]
+fn throw_ends_the_path() {
+    use crate::operands::Variable;
+
+    // Nothing jumps past the throw, so the code after it is never run
+    let nodes = vec![
+        ins(Instruction::GetVar(Variable::Arg(0))),
+        ins(Instruction::Throw),
+        ins(Instruction::Pop),
+    ];
+
+    assert_eq!(stack_depths(&nodes), Ok(vec![Some(0), Some(1), None]));
+}
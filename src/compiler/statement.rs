@@ -0,0 +1,239 @@
+use dreammaker::ast::{ForListStatement, Statement, VarStatement};
+
+use crate::compiler::*;
+use crate::Instruction;
+
+pub(super) fn emit(compiler: &mut Compiler<'_>, statement: Statement) -> Result<(), CompileError> {
+    match statement {
+        Statement::Expr(expr) => match compiler.emit_statement_expr(expr)? {
+            // Output and input don't leave anything to pop
+            EvalKind::Nothing => {}
+
+            kind => {
+                compiler.emit_move_to_stack(kind)?;
+                compiler.emit_ins(Instruction::Pop);
+            }
+        },
+
+        Statement::Return(Some(expr)) => {
+            let expr = compiler.optimize_expr(expr);
+            let kind = compiler.emit_expr(expr)?;
+            compiler.emit_move_to_stack(kind)?;
+            compiler.emit_ins(Instruction::Ret);
+        }
+
+        // Returns the value of `.`
+        Statement::Return(None) => {
+            compiler.emit_ins(Instruction::End);
+        }
+
+        Statement::Var(var) => emit_var(compiler, *var)?,
+
+        Statement::ForList(for_list) => emit_for_list(compiler, *for_list)?,
+
+        _ => return Err(CompileErrorKind::UnsupportedStatement.into()),
+    }
+
+    Ok(())
+}
+
+/// The name of the global that remembers whether the static `name` has been given its value yet. It's made of
+/// characters no DM identifier can have, so it can't be mistaken for a var the code declares.
+pub fn static_guard_name(name: &str) -> String {
+    format!("<dmasm initialized {}>", name)
+}
+
+// A static given a value only gets it the first time through, which is kept track of by another global that has to be
+// null (or 0) until then. That global is listed in `CompiledProc::globals` next to the static, and it's up to the host
+// to create both of them and keep them for as long as the static should keep its value.
+fn emit_var(compiler: &mut Compiler<'_>, statement: VarStatement) -> Result<(), CompileError> {
+    // The initializer can't see the var it's declaring
+    let type_path = if statement.var_type.type_path.is_empty() {
+        None
+    } else {
+        Some(format!("{}", FormatTreePath(&statement.var_type.type_path)))
+    };
+
+    let is_static = statement.var_type.flags.is_static();
+
+    let initialized = static_guard_name(&statement.name);
+    let label_end = match &statement.value {
+        Some(_) if is_static => {
+            let label = format!("LAB_{:0>4X}", compiler.label_count);
+            compiler.label_count += 1;
+
+            compiler.emit_ins(Instruction::GetVar(Variable::Global(DMString(initialized.clone().into()))));
+            compiler.emit_ins(Instruction::Test);
+            compiler.emit_ins(Instruction::Jnz(Label(label.clone())));
+            Some(label)
+        }
+
+        _ => None,
+    };
+
+    let value = match statement.value {
+        Some(expr) => {
+            // `var/obj/O = new` creates an /obj
+            compiler.implied_type = match &type_path {
+                Some(path) => Some(path.clone()),
+                None => compiler.lookup_implied_type(&statement.name),
+            };
+
+            let expr = compiler.optimize_expr(expr);
+            let kind = compiler.emit_expr(expr)?;
+            compiler.emit_move_to_stack(kind)?;
+            true
+        }
+
+        None => false,
+    };
+
+    // `var/global/x` is the same as `var/static/x`. These live outside of the proc so the caller gets told about them.
+    let var = if is_static {
+        if !compiler.globals.contains(&statement.name) {
+            compiler.globals.push(statement.name.clone());
+        }
+
+        if label_end.is_some() && !compiler.globals.contains(&initialized) {
+            compiler.globals.push(initialized.clone());
+        }
+
+        Variable::Global(DMString(statement.name.into()))
+    } else {
+        compiler.locals.push(statement.name);
+        compiler.local_types.push(type_path);
+        Variable::Local(compiler.locals.len() as u32 - 1)
+    };
+
+    if value {
+        compiler.emit_ins(Instruction::SetVar(var));
+    }
+
+    if let Some(label) = label_end {
+        compiler.emit_ins(Instruction::PushInt(1));
+        compiler.emit_ins(Instruction::SetVar(Variable::Global(DMString(initialized.into()))));
+        compiler.emit_label(label);
+    }
+
+    Ok(())
+}
+
+// `for (var/T/x in L)` walks a copy of the list by index, skipping anything that isn't a T. Anything that isn't a list
+// has its contents walked instead, which covers atoms and `world` (what a loop with no list is over), and a null list
+// is the same as an empty one.
+// BYOND's own compiler uses IterLoad, IterNext and IterPop for this, but what IterLoad's first operand means isn't
+// known, so the loop sticks to instructions that are.
+fn emit_for_list(compiler: &mut Compiler<'_>, statement: ForListStatement) -> Result<(), CompileError> {
+    // The list can't see the var the loop declares
+    match statement.in_list {
+        Some(expr) => {
+            let expr = compiler.optimize_expr(expr);
+            let kind = compiler.emit_expr(expr)?;
+            compiler.emit_move_to_stack(kind)?;
+        }
+
+        None => compiler.emit_ins(Instruction::GetVar(Variable::World)),
+    }
+
+    let (var, type_path) = match &statement.var_type {
+        Some(var_type) => {
+            let type_path = if var_type.type_path.is_empty() {
+                None
+            } else {
+                Some(format!("{}", FormatTreePath(&var_type.type_path)))
+            };
+
+            compiler.locals.push(statement.name);
+            compiler.local_types.push(type_path.clone());
+            (Variable::Local(compiler.locals.len() as u32 - 1), type_path)
+        }
+
+        None => match compiler.emit_find_var(statement.name)? {
+            EvalKind::Var(Variable::Local(idx)) => {
+                (Variable::Local(idx), compiler.local_types[idx as usize].clone())
+            }
+
+            EvalKind::Var(var) if is_writable(&var) => (var, None),
+            _ => return Err(CompileErrorKind::ExpectedLValue.into()),
+        },
+    };
+
+    // The copy and where the loop is in it live in locals of their own, named so code can't refer to them
+    let list = hidden_local(compiler, "<dmasm for list>");
+    let index = hidden_local(compiler, "<dmasm for index>");
+
+    let label_list = format!("LAB_{:0>4X}", compiler.label_count);
+    let label_next = format!("LAB_{:0>4X}", compiler.label_count + 1);
+    let label_end = format!("LAB_{:0>4X}", compiler.label_count + 2);
+    compiler.label_count += 3;
+
+    compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+    compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+    compiler.emit_ins(Instruction::Test);
+    compiler.emit_ins(Instruction::Jz(Label(label_end.clone())));
+
+    compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+    compiler.emit_ins(Instruction::IsList);
+    compiler.emit_ins(Instruction::Test);
+    compiler.emit_ins(Instruction::Jnz(Label(label_list.clone())));
+    compiler.emit_ins(Instruction::GetVar(Variable::Field(DMString(b"contents".to_vec()))));
+    compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+
+    compiler.emit_label(label_list);
+    compiler.emit_ins(Instruction::Call(Variable::DynamicProc(DMString(b"Copy".to_vec())), 0));
+    compiler.emit_ins(Instruction::SetVar(list.clone()));
+    compiler.emit_ins(Instruction::PushInt(0));
+    compiler.emit_ins(Instruction::SetVar(index.clone()));
+
+    compiler.emit_label(label_next.clone());
+    compiler.emit_ins(Instruction::GetVar(index.clone()));
+    compiler.emit_ins(Instruction::PushInt(1));
+    compiler.emit_ins(Instruction::Add);
+    compiler.emit_ins(Instruction::SetVar(index.clone()));
+
+    compiler.emit_ins(Instruction::GetVar(index.clone()));
+    compiler.emit_ins(Instruction::GetVar(list.clone()));
+    compiler.emit_ins(Instruction::Length);
+    compiler.emit_ins(Instruction::Tle);
+    compiler.emit_ins(Instruction::Test);
+    compiler.emit_ins(Instruction::Jz(Label(label_end.clone())));
+
+    compiler.emit_ins(Instruction::GetVar(list));
+    compiler.emit_ins(Instruction::GetVar(index));
+    compiler.emit_ins(Instruction::ListGet);
+    compiler.emit_ins(Instruction::SetVar(var.clone()));
+
+    if let Some(type_path) = type_path {
+        compiler.emit_ins(Instruction::GetVar(var));
+        compiler.emit_ins(Instruction::PushVal(Value::Path(type_path).into()));
+        compiler.emit_ins(Instruction::IsType);
+        compiler.emit_ins(Instruction::Test);
+        compiler.emit_ins(Instruction::Jz(Label(label_next.clone())));
+    }
+
+    // Like the statements around the loop, an error in one of these doesn't stop the rest being compiled
+    for statement in statement.block.into_vec() {
+        compiler.location = Some(statement.location);
+        compiler.emit_line(statement.location);
+
+        if let Err(err) = emit(compiler, statement.elem) {
+            let err = err.or_location(statement.location);
+            if !compiler.collect_errors {
+                return Err(err);
+            }
+
+            compiler.errors.push(err);
+        }
+    }
+
+    compiler.emit_ins(Instruction::Jmp(Label(label_next)));
+    compiler.emit_label(label_end);
+
+    Ok(())
+}
+
+fn hidden_local(compiler: &mut Compiler<'_>, name: &str) -> Variable {
+    compiler.locals.push(name.to_owned());
+    compiler.local_types.push(None);
+    Variable::Local(compiler.locals.len() as u32 - 1)
+}
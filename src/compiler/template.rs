@@ -0,0 +1,287 @@
+use std::fmt;
+
+use crate::compiler::*;
+use crate::operands::ValueOp;
+
+// Placeholders are swapped for identifiers before parsing and compile to globals with these names
+const PLACEHOLDER_PREFIX: &str = "__dmasm_placeholder_";
+
+pub(super) fn is_placeholder(ident: &str) -> bool {
+    placeholder_index(ident).is_some()
+}
+
+fn placeholder_index(ident: &str) -> Option<u32> {
+    ident.strip_prefix(PLACEHOLDER_PREFIX)?.parse().ok()
+}
+
+fn placeholder_var(index: u32) -> Variable {
+    Variable::Global(DMString(format!("{}{}", PLACEHOLDER_PREFIX, index).into()))
+}
+
+// Replaces every `$N` outside of string literals with its identifier, returning the new code and the highest N
+fn replace_placeholders(code: &str) -> Result<(String, u32), CompileError> {
+    let mut out = String::with_capacity(code.len());
+    let mut count = 0;
+    let mut quote = None;
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                out.push(c);
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+                continue;
+            }
+
+            (Some(q), _) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+
+            (None, '$') => {
+                let mut digits = String::new();
+                while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(*digit);
+                    chars.next();
+                }
+
+                let index: u32 = match digits.parse() {
+                    Ok(index) if index > 0 => index,
+                    _ => return Err(CompileErrorKind::InvalidPlaceholder(format!("${}", digits)).into()),
+                };
+
+                count = count.max(index);
+                out.push_str(PLACEHOLDER_PREFIX);
+                out.push_str(&digits);
+                continue;
+            }
+
+            _ => {}
+        }
+
+        out.push(c);
+    }
+
+    Ok((out, count))
+}
+
+/// What a placeholder in a `Template` gets replaced with
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateArg {
+    /// A constant. These can only be read, not assigned to or have fields accessed.
+    Value(Value),
+
+    /// Any variable, which can be used in every way a var in the expression could
+    Variable(Variable),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    WrongArgCount { expected: u32, found: u32 },
+
+    // The placeholder was used in a way that needs a variable, but it was given a value
+    ExpectedVariable(u32),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongArgCount { expected, found } => {
+                write!(f, "template expects {} argument(s), got {}", expected, found)
+            }
+            Self::ExpectedVariable(index) => {
+                write!(f, "placeholder ${} is used as a variable but was given a value", index)
+            }
+        }
+    }
+}
+
+/// An expression compiled with `$1`, `$2`... placeholders that can be filled in later without compiling it again.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+    placeholder_count: u32,
+}
+
+// The variable operand of the instructions the compiler emits
+fn variable_mut(ins: &mut Instruction) -> Option<&mut Variable> {
+    match ins {
+        Instruction::GetVar(var)
+        | Instruction::SetVar(var)
+        | Instruction::SetVarExpr(var)
+        | Instruction::AugAdd(var)
+        | Instruction::AugSub(var)
+        | Instruction::AugMul(var)
+        | Instruction::AugDiv(var)
+        | Instruction::AugMod(var)
+        | Instruction::AugBand(var)
+        | Instruction::AugBor(var)
+        | Instruction::AugXor(var)
+        | Instruction::AugLShift(var)
+        | Instruction::AugRShift(var)
+        | Instruction::AssignInto(var)
+        | Instruction::PreInc(var)
+        | Instruction::PostInc(var)
+        | Instruction::PreDec(var)
+        | Instruction::PostDec(var)
+        | Instruction::Call(var, _) => Some(var),
+        _ => None,
+    }
+}
+
+// Swaps placeholders anywhere in a variable (including inside field chains) for their variables.
+// Values can't go in a variable, so the placeholder they were meant for comes back as an error.
+fn substitute(var: &mut Variable, args: &[TemplateArg]) -> Result<(), TemplateError> {
+    match var {
+        Variable::Global(DMString(name)) => {
+            let index = match std::str::from_utf8(name).ok().and_then(placeholder_index) {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+
+            match &args[index as usize - 1] {
+                TemplateArg::Variable(replacement) => *var = replacement.clone(),
+                TemplateArg::Value(_) => return Err(TemplateError::ExpectedVariable(index)),
+            }
+        }
+
+        Variable::SetCache(lhs, rhs) => {
+            substitute(lhs, args)?;
+            substitute(rhs, args)?;
+        }
+
+        Variable::Initial(inner) | Variable::IsSaved(inner) => substitute(inner, args)?,
+
+        _ => {}
+    }
+
+    Ok(())
+}
+
+impl Template {
+    /// The highest placeholder used in the expression. `instantiate` needs exactly this many args.
+    pub fn placeholder_count(&self) -> u32 {
+        self.placeholder_count
+    }
+
+    /// Fills in the placeholders, `$1` being `args[0]`
+    pub fn instantiate(&self, args: &[TemplateArg]) -> Result<Vec<Node>, TemplateError> {
+        if args.len() != self.placeholder_count as usize {
+            return Err(TemplateError::WrongArgCount {
+                expected: self.placeholder_count,
+                found: args.len() as u32,
+            });
+        }
+
+        let mut nodes = self.nodes.clone();
+
+        for node in &mut nodes {
+            let ins = match node {
+                Node::Instruction(ins, _) => ins,
+                _ => continue,
+            };
+
+            // Reading a placeholder given a value is just pushing the value
+            if let Instruction::GetVar(Variable::Global(DMString(name))) = ins {
+                if let Some(index) = std::str::from_utf8(name).ok().and_then(placeholder_index) {
+                    if let TemplateArg::Value(value) = &args[index as usize - 1] {
+                        *ins = Instruction::PushVal(ValueOp::from(value.clone()));
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(var) = variable_mut(ins) {
+                substitute(var, args)?;
+            }
+        }
+
+        Ok(nodes)
+    }
+}
+
+/// Compiles an expression where `$1`, `$2`... stand for values or variables given later, see `Template::instantiate`.
+/// Column numbers in errors are off for anything after a placeholder.
+pub fn compile_template(
+    code: &str,
+    params: &[&str],
+    options: &CompilerOptions,
+) -> Result<Template, CompileError> {
+    let (code, placeholder_count) = replace_placeholders(code)?;
+
+    Ok(Template {
+        nodes: compile_expr_with_options(&code, params, options)?,
+        placeholder_count,
+    })
+}
+
+#[test]
+fn placeholders() {
+    assert_eq!(
+        replace_placeholders("$1 + length(\"$2\") + $12").unwrap(),
+        (
+            "__dmasm_placeholder_1 + length(\"$2\") + __dmasm_placeholder_12".to_owned(),
+            12
+        )
+    );
+
+    assert!(replace_placeholders("$ + 1").is_err());
+    assert!(replace_placeholders("$0").is_err());
+}
+
+#[test]
+fn instantiate() {
+    let template = Template {
+        nodes: vec![
+            Node::Instruction(Instruction::GetVar(placeholder_var(1)), ()),
+            Node::Instruction(
+                Instruction::GetVar(Variable::SetCache(
+                    Box::new(placeholder_var(2)),
+                    Box::new(Variable::Field(DMString(b"x".to_vec()))),
+                )),
+                (),
+            ),
+            Node::Instruction(Instruction::Add, ()),
+        ],
+        placeholder_count: 2,
+    };
+
+    let nodes = template
+        .instantiate(&[
+            TemplateArg::Value(Value::Number(5.0)),
+            TemplateArg::Variable(Variable::Src),
+        ])
+        .unwrap();
+
+    assert_eq!(
+        nodes,
+        vec![
+            Node::Instruction(Instruction::PushVal(Value::Number(5.0).into()), ()),
+            Node::Instruction(
+                Instruction::GetVar(Variable::SetCache(
+                    Box::new(Variable::Src),
+                    Box::new(Variable::Field(DMString(b"x".to_vec()))),
+                )),
+                (),
+            ),
+            Node::Instruction(Instruction::Add, ()),
+        ]
+    );
+
+    // `$2.x` needs $2 to be something with fields
+    assert_eq!(
+        template.instantiate(&[
+            TemplateArg::Value(Value::Null),
+            TemplateArg::Value(Value::Null)
+        ]),
+        Err(TemplateError::ExpectedVariable(2))
+    );
+
+    assert_eq!(
+        template.instantiate(&[]),
+        Err(TemplateError::WrongArgCount {
+            expected: 2,
+            found: 0
+        })
+    );
+}
@@ -28,7 +28,7 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
         }
 
         // Identifiers. These could be params or globals.
-        Term::Ident(ident) => Ok(compiler.emit_find_var(ident)),
+        Term::Ident(ident) => compiler.emit_find_var(ident),
 
         // Resources
         Term::Resource(resource) => {
@@ -45,7 +45,7 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
         // Type paths: We don't support the anonymous kind with variable declarations.
         Term::Prefab(prefab) => {
             if !prefab.vars.is_empty() {
-                return Err(CompileError::UnsupportedPrefabWithVars);
+                return Err(CompileErrorKind::UnsupportedPrefabWithVars.into());
             }
 
             let mut path = String::new();
@@ -61,34 +61,39 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
         }
 
         Term::Call(ident, args) => {
+            compiler.check_call_allowed(&ident)?;
+
             match builtin_procs::emit(compiler, &ident, &args)? {
                 // Handled by builtin_procs
                 Some(kind) => Ok(kind),
 
                 // We've got to call a proc
                 None => {
+                    let path = match type_check::resolve_call(compiler, &ident)? {
+                        type_check::CallTarget::Global(path) => path,
+
+                        // `foo()` inside a type with a `foo` proc is `src.foo()`
+                        type_check::CallTarget::Src(proc) => {
+                            let call = Follow::Call(PropertyAccessKind::Dot, ident, args);
+                            return follow::emit(compiler, vec![call], vec![Some(proc)], EvalKind::Var(Variable::Src));
+                        }
+                    };
+
                     let arg_count = args.len() as u32;
 
                     match args::emit(compiler, args::ArgsContext::Proc, args)? {
                         args::ArgsResult::Normal => {
                             // We're treating all Term::Call expressions as global calls
-                            compiler.emit_ins(Instruction::CallGlob(
-                                arg_count,
-                                operands::Proc::from_path(format!("/proc/{}", ident)),
-                            ));
+                            compiler.emit_ins(Instruction::CallGlob(arg_count, operands::Proc::from_path(path)));
                         }
 
                         args::ArgsResult::Assoc => {
                             compiler.emit_ins(Instruction::NewAssocList(arg_count));
-                            compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(
-                                format!("/proc/{}", ident),
-                            )));
+                            compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(path)));
                         }
 
                         args::ArgsResult::ArgList => {
-                            compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(
-                                format!("/proc/{}", ident),
-                            )));
+                            compiler.emit_ins(Instruction::CallGlobalArgList(operands::Proc::from_path(path)));
                         }
                     }
 
@@ -97,22 +102,27 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
             }
         }
 
+        // The proc isn't known until runtime, so the sandbox can only allow or deny `call()` as a whole
         Term::DynamicCall(lhs, rhs) => {
+            compiler.check_call_allowed("call")?;
+
             let lhs_len = lhs.len();
             let rhs_len = rhs.len();
 
             if lhs.is_empty() {
-                return Err(CompileError::MissingArgument {
+                return Err(CompileErrorKind::MissingArgument {
                     proc: "call".to_owned(),
                     index: 1,
-                });
+                }
+                .into());
             }
 
             if lhs_len > 2 {
-                return Err(CompileError::TooManyArguments {
+                return Err(CompileErrorKind::TooManyArguments {
                     proc: "call".to_owned(),
                     expected: 2,
-                });
+                }
+                .into());
             }
 
             // Push LHS
@@ -155,16 +165,34 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
         Term::SelfCall { .. } | Term::ParentCall { .. } => {
             // Can't implement these until we compile full procs
             // Well, maybe we could
-            return Err(CompileError::UnsupportedRelativeCall);
+            return Err(CompileErrorKind::UnsupportedRelativeCall.into());
         }
 
         Term::New { type_, args } => match type_ {
             NewType::Prefab(prefab) => {
                 if !prefab.vars.is_empty() {
-                    return Err(CompileError::UnsupportedPrefabWithVars);
+                    return Err(CompileErrorKind::UnsupportedPrefabWithVars.into());
                 }
 
                 let path = format!("{}", FormatTypePath(&prefab.path));
+
+                // new /list(N) has its own instruction, and so do the lists inside new /list(X, Y)
+                if path == "/list" {
+                    if let Some(args) = &args {
+                        if !args.is_empty() && !args::has_assoc_argument(args::ArgsContext::Proc, args)? {
+                            let (inner, outer) = args.split_last().unwrap();
+
+                            if outer.is_empty() {
+                                return emit_sized_list(compiler, inner.clone());
+                            }
+
+                            if let Some(outer) = list_dimensions(outer, inner) {
+                                return emit_nested_list(compiler, &outer, inner);
+                            }
+                        }
+                    }
+                }
+
                 let typeval = operands::Value::Path(path);
                 compiler.emit_ins(Instruction::PushVal(typeval.into()));
 
@@ -172,26 +200,56 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
             }
 
             NewType::MiniExpr { ident, fields } => {
-                let var = compiler.emit_find_var(ident);
+                let var = compiler.emit_find_var(ident)?;
                 let follows: Vec<Follow> = fields.into_iter().map(|f| f.into()).collect();
 
-                let kind = follow::emit(compiler, follows, var)?;
+                let procs = vec![None; follows.len()];
+                let kind = follow::emit(compiler, follows, procs, var)?;
                 compiler.emit_move_to_stack(kind)?;
 
                 emit_new(compiler, args)
             }
 
-            NewType::Implicit => Err(CompileError::UnsupportedImplicitNew),
+            // `var/obj/O = new` creates whatever type the var is
+            NewType::Implicit => match compiler.implied_type.take() {
+                Some(path) => {
+                    let typeval = operands::Value::Path(path);
+                    compiler.emit_ins(Instruction::PushVal(typeval.into()));
+
+                    emit_new(compiler, args)
+                }
+
+                None => Err(CompileErrorKind::UnsupportedImplicitNew.into()),
+            },
         },
 
         Term::Locate { args, in_list } => {
             let args_len = args.len();
+            let implied_type = compiler.implied_type.take();
 
             args::emit_normal(compiler, args::ArgsContext::Proc, args)?;
 
             match args_len {
-                // locate()
-                0 => return Err(CompileError::UnsupportedImplicitLocate),
+                // locate(), which looks for whatever type the var it's assigned to is
+                0 => {
+                    let path = match implied_type {
+                        Some(path) => path,
+                        None => return Err(CompileErrorKind::UnsupportedImplicitLocate.into()),
+                    };
+
+                    compiler.emit_ins(Instruction::PushVal(operands::Value::Path(path).into()));
+
+                    match in_list {
+                        Some(in_list) => {
+                            let kind = compiler.emit_expr(*in_list)?;
+                            compiler.emit_move_to_stack(kind)?;
+
+                            compiler.emit_ins(Instruction::LocateType);
+                        }
+
+                        None => compiler.emit_ins(Instruction::LocateRef),
+                    }
+                }
 
                 // locate(ref|type)
                 1 if in_list.is_none() => {
@@ -211,7 +269,7 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
                     compiler.emit_ins(Instruction::LocatePos);
                 }
 
-                _ => return Err(CompileError::InvalidLocateArgs),
+                _ => return Err(CompileErrorKind::InvalidLocateArgs.into()),
             }
 
             Ok(EvalKind::Stack)
@@ -221,10 +279,11 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
             match args.len() {
                 // prob()
                 0 => {
-                    return Err(CompileError::MissingArgument {
+                    return Err(CompileErrorKind::MissingArgument {
                         proc: "pick".to_owned(),
                         index: 1,
-                    })
+                    }
+                    .into())
                 }
 
                 // prob(L)
@@ -232,7 +291,7 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
                     let (lhs, rhs) = args.pop().unwrap();
 
                     if let Some(_) = lhs {
-                        return Err(CompileError::UnexpectedProbability);
+                        return Err(CompileErrorKind::UnexpectedProbability.into());
                     }
 
                     let kind = compiler.emit_expr(rhs)?;
@@ -296,23 +355,38 @@ pub(super) fn emit(compiler: &mut Compiler<'_>, term: Term) -> Result<EvalKind,
                     compiler.emit_ins(Instruction::NewAssocList(arg_count as u32));
                 }
 
-                args::ArgsResult::ArgList => return Err(CompileError::UnexpectedArgList),
+                args::ArgsResult::ArgList => return Err(CompileErrorKind::UnexpectedArgList.into()),
             }
 
             Ok(EvalKind::Stack)
         }
 
-        Term::InterpString(_, _) => return Err(CompileError::UnsupportedStringInterpolation),
+        Term::InterpString(_, _) => return Err(CompileErrorKind::UnsupportedStringInterpolation.into()),
         Term::Input {
             args: _,
             input_type: _,
             in_list: _,
-        } => return Err(CompileError::UnsupportedInput),
+        } => return Err(CompileErrorKind::UnsupportedInput.into()),
     }
 }
 
 // Assuming the type to create will always be on the stack
-fn emit_new(
+// `new (expr)(args)`, where the type comes from any expression. It reaches us as `new (expr, args)`, see `new_expr`.
+pub(super) fn emit_new_expr(
+    compiler: &mut Compiler<'_>,
+    mut args: Vec<Expression>,
+) -> Result<EvalKind, CompileError> {
+    // The type is given, so there's no use for the implied one
+    compiler.implied_type = None;
+
+    let type_ = args.remove(0);
+    let kind = compiler.emit_expr(type_)?;
+    compiler.emit_move_to_stack(kind)?;
+
+    emit_new(compiler, Some(args))
+}
+
+pub(super) fn emit_new(
     compiler: &mut Compiler<'_>,
     args: Option<Vec<Expression>>,
 ) -> Result<EvalKind, CompileError> {
@@ -336,3 +410,69 @@ fn emit_new(
 
     Ok(EvalKind::Stack)
 }
+
+// Any more lists than this and new /list(X, Y) is left to /list's own New
+const MAX_NESTED_LISTS: u32 = 256;
+
+// The sizes of the outer dimensions of new /list(X, Y), when each list can be built right here. The inner size
+// is pushed once for every innermost list, so it has to be a constant as well.
+fn list_dimensions(outer: &[Expression], inner: &Expression) -> Option<Vec<u32>> {
+    const_eval(inner)?;
+
+    let mut dimensions = vec![];
+    let mut lists: u32 = 1;
+
+    for dimension in outer {
+        let size = match const_eval(dimension)? {
+            operands::Value::Number(size) if size >= 0.0 && size.fract() == 0.0 => size as u32,
+            _ => return None,
+        };
+
+        lists = lists.checked_mul(size).filter(|lists| *lists <= MAX_NESTED_LISTS)?;
+        dimensions.push(size);
+    }
+
+    Some(dimensions)
+}
+
+// new /list(X, Y) is X lists made by new /list(Y), in a list
+fn emit_nested_list(
+    compiler: &mut Compiler<'_>,
+    outer: &[u32],
+    inner: &Expression,
+) -> Result<EvalKind, CompileError> {
+    let (size, rest) = match outer.split_first() {
+        Some(split) => split,
+        None => return emit_sized_list(compiler, inner.clone()),
+    };
+
+    for _ in 0..*size {
+        emit_nested_list(compiler, rest, inner)?;
+    }
+
+    compiler.emit_ins(Instruction::NewList(*size));
+    Ok(EvalKind::Stack)
+}
+
+// new /list(N)
+fn emit_sized_list(compiler: &mut Compiler<'_>, size: Expression) -> Result<EvalKind, CompileError> {
+    match args::emit(compiler, args::ArgsContext::Proc, vec![size])? {
+        args::ArgsResult::Normal => {
+            compiler.emit_ins(Instruction::EmptyList);
+        }
+
+        args::ArgsResult::Assoc => unreachable!(),
+
+        // The arg list is already on the stack, but the type needs to be beneath it
+        args::ArgsResult::ArgList => {
+            compiler.emit_ins(Instruction::SetVar(Variable::Cache));
+            compiler.emit_ins(Instruction::PushVal(
+                operands::Value::Path("/list".to_owned()).into(),
+            ));
+            compiler.emit_ins(Instruction::GetVar(Variable::Cache));
+            compiler.emit_ins(Instruction::NewArgList);
+        }
+    }
+
+    Ok(EvalKind::Stack)
+}
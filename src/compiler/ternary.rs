@@ -7,6 +7,21 @@ pub(super) fn emit(
     lhs: Expression,
     rhs: Expression,
 ) -> Result<EvalKind, CompileError> {
+    // An assignment needs parentheses to be a ternary's condition at all, so it takes a second pair to say it's intended
+    match &condition {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } if unary.is_empty() && follow.is_empty() => {
+            if let dreammaker::ast::Term::Expr(inner) = &term.elem {
+                compiler.check_condition(inner);
+            }
+        }
+
+        _ => compiler.check_condition(&condition),
+    }
+
     // Bring condition to stack
     let condition = compiler.emit_expr(condition)?;
     compiler.emit_move_to_stack(condition)?;
@@ -0,0 +1,403 @@
+use dreammaker::ast::{
+    AssignOp, BinaryOp, Follow, FormatTreePath, FormatTypePath, NewType, ProcDeclKind, PropertyAccessKind,
+    Spanned, Term,
+};
+use dreammaker::objtree::TypeRef;
+
+use crate::compiler::*;
+
+// The type path a term is known to have, if any. Params, anything from the resolver and anything more
+// complicated than a var are unknown.
+fn term_type(compiler: &Compiler<'_>, term: &Term) -> Option<String> {
+    let ident = match term {
+        Term::Ident(ident) => ident,
+        _ => return None,
+    };
+
+    if let Some(index) = compiler.locals.iter().rposition(|x| x == ident) {
+        return compiler.local_types[index].clone();
+    }
+
+    if compiler.resolve_ident(ident).is_some() || compiler.params.contains(&ident.as_str()) {
+        return None;
+    }
+
+    match ident.as_str() {
+        "src" => compiler.options.src_type.clone(),
+        "usr" => Some("/mob".to_owned()),
+        "world" => Some("/world".to_owned()),
+        _ => None,
+    }
+}
+
+/// Checks that every `.` field access (or proc call) on a value of a known type names a var (or proc) that type has.
+/// `:` is DM's way of saying "don't check this", so the chain is unknown after one.
+/// Gives back the proc each of those calls resolved to (see `static_proc`), or `None` for follows that aren't one.
+pub(super) fn check_follows(
+    compiler: &Compiler<'_>,
+    term: &Term,
+    follow: &[Spanned<Follow>],
+) -> Result<Vec<Option<Variable>>, CompileError> {
+    let mut procs = vec![None; follow.len()];
+
+    let tree = match &compiler.options.object_tree {
+        Some(tree) => tree,
+        None => return Ok(procs),
+    };
+
+    let mut type_path = term_type(compiler, term);
+
+    for (idx, follow) in follow.iter().enumerate() {
+        let field = match &follow.elem {
+            Follow::Field(PropertyAccessKind::Dot, field)
+            | Follow::Field(PropertyAccessKind::SafeDot, field) => field,
+
+            Follow::Call(PropertyAccessKind::Dot, proc, _)
+            | Follow::Call(PropertyAccessKind::SafeDot, proc, _) => {
+                if let Some(path) = &type_path {
+                    if let Some(ty) = tree.find(path) {
+                        procs[idx] = static_proc(ty, proc);
+
+                        if procs[idx].is_none() {
+                            return Err(CompileError::from(CompileErrorKind::UnknownProc(format!(
+                                "{}/proc/{}",
+                                path, proc
+                            )))
+                            .or_location(follow.location));
+                        }
+                    }
+                }
+
+                // We don't know what procs return
+                type_path = None;
+                continue;
+            }
+
+            // `:` accesses, indexing and calls could give back anything
+            _ => {
+                type_path = None;
+                continue;
+            }
+        };
+
+        // Types missing from the tree aren't worth an error, the var might still be there at runtime
+        let ty = match type_path.as_deref().and_then(|path| tree.find(path)) {
+            Some(ty) => ty,
+            None => {
+                type_path = None;
+                continue;
+            }
+        };
+
+        let declaration = match ty.get_var_declaration(field) {
+            Some(declaration) => declaration,
+            None => {
+                return Err(CompileError::from(CompileErrorKind::UnknownField {
+                    type_path: type_path.unwrap(),
+                    field: field.clone(),
+                })
+                .or_location(follow.location))
+            }
+        };
+
+        type_path = declared_type(&declaration.var_type.type_path);
+    }
+
+    Ok(procs)
+}
+
+/// How to call the proc `name` on a value of type `ty`, if it has one. The operand names the proc by where it was
+/// first declared, like `/datum/proc/foo` even for an override on a subtype, as BYOND picks the override at runtime.
+fn static_proc(ty: TypeRef<'_>, name: &str) -> Option<Variable> {
+    // Without a declaration anywhere above it, the proc goes by the type that defines it
+    let mut declared = (ty.get_proc(name)?.ty(), ProcDeclKind::Proc);
+
+    let mut current = Some(ty);
+    while let Some(ty) = current {
+        if let Some(declaration) = ty.get().procs.get(name).and_then(|proc| proc.declaration.as_ref()) {
+            declared = (ty, declaration.kind);
+            break;
+        }
+
+        current = ty.parent_type();
+    }
+
+    let (ty, kind) = declared;
+    Some(match kind {
+        ProcDeclKind::Verb => {
+            Variable::StaticVerb(operands::Proc::from_path(format!("{}/verb/{}", ty.get().path, name)))
+        }
+
+        _ => Variable::StaticProc(operands::Proc::from_path(format!("{}/proc/{}", ty.get().path, name))),
+    })
+}
+
+fn declared_type(type_path: &[String]) -> Option<String> {
+    if type_path.is_empty() {
+        None
+    } else {
+        Some(format!("{}", FormatTreePath(type_path)))
+    }
+}
+
+/// What an expression evaluates to, as far as can be told without running it
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaticType {
+    Unknown,
+    Null,
+    Number,
+    Text,
+    List,
+
+    /// A type path itself, like `/mob`
+    TypePath(String),
+
+    /// An instance of the type (or one of its subtypes)
+    Object(String),
+}
+
+impl StaticType {
+    fn from_type_path(path: Option<String>) -> Self {
+        match path {
+            Some(path) if path == "/list" => Self::List,
+            Some(path) => Self::Object(path),
+            None => Self::Unknown,
+        }
+    }
+
+    // When the result could be either side
+    fn either(self, other: Self) -> Self {
+        if self == other {
+            self
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+// Builtins that always give back the same kind of value
+fn builtin_result(name: &str) -> StaticType {
+    match name {
+        "abs" | "arccos" | "arcsin" | "arctan" | "cos" | "sin" | "tan" | "sqrt" | "log" | "round"
+        | "floor" | "ceil" | "clamp" | "rand" | "prob" | "length" | "length_char" | "text2num"
+        | "get_dist" | "get_dir" | "findtext" | "findtextEx" | "findlasttext" | "findlasttextEx"
+        | "text2ascii" | "isnull" | "isnum" | "istext" | "islist" | "istype" | "ispath"
+        | "isicon" | "isfile" | "isloc" | "ismob" | "isobj" | "isarea" | "isturf" | "ismovable"
+        | "fexists" | "hascall" | "cmptext" => StaticType::Number,
+
+        "text" | "num2text" | "copytext" | "copytext_char" | "uppertext" | "lowertext"
+        | "replacetext" | "replacetextEx" | "jointext" | "html_encode" | "html_decode"
+        | "url_encode" | "url_decode" | "json_encode" | "ckey" | "ckeyEx" | "ascii2text" | "md5"
+        | "sha1" | "ref" | "time2text" | "list2params" | "rgb" => StaticType::Text,
+
+        "list" | "splittext" | "splittext_char" | "params2list" | "view" | "oview" | "range"
+        | "orange" | "viewers" | "oviewers" | "hearers" | "ohearers" | "block" | "flist"
+        | "typesof" | "bounds" | "obounds" | "rgb2num" => StaticType::List,
+
+        _ => StaticType::Unknown,
+    }
+}
+
+fn term_result(compiler: &Compiler<'_>, term: &Term) -> StaticType {
+    match term {
+        Term::Null => StaticType::Null,
+        Term::Int(_) | Term::Float(_) => StaticType::Number,
+        Term::String(_) | Term::InterpString(..) => StaticType::Text,
+        Term::List(_) => StaticType::List,
+        Term::Expr(expr) => infer_type(compiler, expr),
+        Term::Call(name, _) => builtin_result(name),
+        Term::Ident(_) => StaticType::from_type_path(term_type(compiler, term)),
+
+        Term::Prefab(prefab) if prefab.vars.is_empty() => {
+            StaticType::TypePath(format!("{}", FormatTypePath(&prefab.path)))
+        }
+
+        Term::New {
+            type_: NewType::Prefab(prefab),
+            ..
+        } => StaticType::from_type_path(Some(format!("{}", FormatTypePath(&prefab.path)))),
+
+        _ => StaticType::Unknown,
+    }
+}
+
+/// Works out the type of an expression's result from literals, builtins and the declared types of vars in the object tree.
+/// Anything that depends on a proc's return value is unknown.
+pub(super) fn infer_type(compiler: &Compiler<'_>, expr: &Expression) -> StaticType {
+    match expr {
+        Expression::Base {
+            unary,
+            term,
+            follow,
+        } => {
+            // Every unary operator gives back a number
+            if !unary.is_empty() {
+                return StaticType::Number;
+            }
+
+            let mut result = term_result(compiler, &term.elem);
+
+            for follow in follow {
+                let type_path = match &result {
+                    StaticType::Object(path) => path,
+                    _ => return StaticType::Unknown,
+                };
+
+                let field = match &follow.elem {
+                    Follow::Field(_, field) => field,
+                    _ => return StaticType::Unknown,
+                };
+
+                let declaration = compiler
+                    .options
+                    .object_tree
+                    .as_ref()
+                    .and_then(|tree| tree.find(type_path))
+                    .and_then(|ty| ty.get_var_declaration(field));
+
+                result = match declaration {
+                    Some(declaration) => {
+                        StaticType::from_type_path(declared_type(&declaration.var_type.type_path))
+                    }
+                    None => return StaticType::Unknown,
+                };
+            }
+
+            result
+        }
+
+        Expression::BinaryOp { op, lhs, rhs } => match op {
+            BinaryOp::Add => match (infer_type(compiler, lhs), infer_type(compiler, rhs)) {
+                (StaticType::Text, _) => StaticType::Text,
+                (StaticType::List, _) => StaticType::List,
+                (StaticType::Number, StaticType::Number) => StaticType::Number,
+                _ => StaticType::Unknown,
+            },
+
+            // These could also be overloaded operators on a list
+            BinaryOp::Sub | BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+                match infer_type(compiler, lhs) {
+                    StaticType::List => StaticType::List,
+                    _ => StaticType::Number,
+                }
+            }
+
+            BinaryOp::And | BinaryOp::Or => {
+                infer_type(compiler, lhs).either(infer_type(compiler, rhs))
+            }
+
+            BinaryOp::To => StaticType::Unknown,
+
+            _ => StaticType::Number,
+        },
+
+        Expression::AssignOp {
+            op: AssignOp::Assign,
+            rhs,
+            ..
+        } => infer_type(compiler, rhs),
+
+        Expression::AssignOp { .. } => StaticType::Unknown,
+
+        Expression::TernaryOp { if_, else_, .. } => {
+            infer_type(compiler, if_).either(infer_type(compiler, else_))
+        }
+    }
+}
+
+pub(super) enum CallTarget {
+    // A global proc, called by the path given
+    Global(String),
+
+    // A proc on the type of `src`, called through the operand given (see `static_proc`)
+    Src(Variable),
+}
+
+/// Errors if there's a list of global procs to check against and `name` isn't one of them.
+/// Gives back the path to call it by, which is `/proc/name` unless the object tree says it's a verb.
+pub(super) fn check_global_proc(compiler: &Compiler<'_>, name: &str) -> Result<String, CompileError> {
+    let tree = compiler.options.object_tree.as_ref();
+    let path = format!("/proc/{}", name);
+
+    if tree.is_none() && compiler.options.global_procs.is_empty() {
+        return Ok(path);
+    }
+
+    if let Some(tree) = tree {
+        match static_proc(tree.root(), name) {
+            Some(Variable::StaticProc(proc)) | Some(Variable::StaticVerb(proc)) => return Ok(proc.path),
+            _ => {}
+        }
+    }
+
+    if compiler.options.global_procs.iter().any(|proc| proc == name) {
+        return Ok(path);
+    }
+
+    Err(CompileErrorKind::UnknownProc(path).into())
+}
+
+// The procs every `/world` has
+const WORLD_PROCS: &[&str] = &[
+    "AddCredits",
+    "ClearMedal",
+    "Del",
+    "Error",
+    "Export",
+    "GetConfig",
+    "GetCredits",
+    "GetMedal",
+    "GetScores",
+    "Import",
+    "IsBanned",
+    "IsSubscribed",
+    "New",
+    "OpenPort",
+    "PayCredits",
+    "Profile",
+    "Reboot",
+    "Repop",
+    "SetConfig",
+    "SetMedal",
+    "SetScores",
+    "Tick",
+    "Topic",
+];
+
+/// Errors if `world` has no proc called `name`, and gives back how to call it on `world`.
+/// With an object tree `check_follows` has already looked, and knows about any procs the code base adds to `/world`.
+pub(super) fn check_world_proc(compiler: &Compiler<'_>, name: &str) -> Result<Variable, CompileError> {
+    if let Some(tree) = &compiler.options.object_tree {
+        if let Some(proc) = tree.find("/world").and_then(|ty| static_proc(ty, name)) {
+            return Ok(proc);
+        }
+    }
+
+    if compiler.options.object_tree.is_some() || WORLD_PROCS.contains(&name) {
+        return Ok(Variable::DynamicProc(DMString(name.into())));
+    }
+
+    Err(CompileErrorKind::UnknownProc(format!("/world/proc/{}", name)).into())
+}
+
+/// Works out what an unqualified call like `foo()` refers to. Procs on `src`'s type win over global procs, like in BYOND.
+pub(super) fn resolve_call(compiler: &Compiler<'_>, name: &str) -> Result<CallTarget, CompileError> {
+    if let Some(tree) = &compiler.options.object_tree {
+        let src_type = compiler
+            .options
+            .src_type
+            .as_deref()
+            .and_then(|path| tree.find(path));
+
+        // Global procs live on the root type, which every type inherits from
+        if let Some(ty) = src_type {
+            if let Some(proc) = ty.get_proc(name) {
+                if !proc.ty().is_root() {
+                    return Ok(CallTarget::Src(static_proc(ty, name).unwrap()));
+                }
+            }
+        }
+    }
+
+    Ok(CallTarget::Global(check_global_proc(compiler, name)?))
+}
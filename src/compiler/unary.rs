@@ -50,7 +50,7 @@ fn emit_single(
                     Variable::CacheIndex
                 }
 
-                _ => return Err(CompileError::ExpectedLValue),
+                _ => return Err(CompileErrorKind::ExpectedLValue.into()),
             };
 
             match op {
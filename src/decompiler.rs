@@ -0,0 +1,1253 @@
+//! Turns a proc's nodes back into something that reads like the DM it was compiled from.
+//! The output is meant for people, there's no guarantee it compiles.
+
+mod ast;
+mod ir;
+mod text;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg::{Cfg, CfgError};
+use crate::compiler::{builtin_proc_name, stack_effect};
+use crate::operands::{DMString, IsInParams, Label, Value, Variable};
+use crate::optimizer::jump_destinations;
+use crate::{Instruction, Node};
+use ir::{AssignOp, BinaryOp, Callee, Case, Expr, Stmt, UnaryOp};
+
+// Where `break` and `continue` go in the loop being decompiled
+struct LoopContext {
+    headers: Vec<String>,
+    exits: Vec<String>,
+}
+
+struct Decompiler<'a, D> {
+    nodes: &'a [Node<D>],
+    labels: HashMap<&'a str, usize>,
+
+    // The first node of each loop, along with the last jump back to it
+    loops: HashMap<usize, usize>,
+
+    // A proc's last `End` is implied, so it doesn't need a `return`
+    last_instruction: Option<usize>,
+
+    stack: Vec<Expr>,
+    flag: Option<Expr>,
+    cache: Option<Expr>,
+    cache_key: Option<Expr>,
+    saved_caches: Vec<Option<Expr>>,
+    saved_cache_keys: Vec<Option<Expr>>,
+
+    // What an `Aug*` or `AssignInto` did, in case a `PushEval` wants it
+    eval: Option<Expr>,
+
+    // `JmpAnd`/`JmpOr` waiting for their label, with the left hand side
+    short_circuits: Vec<(String, BinaryOp, Expr)>,
+
+    loop_contexts: Vec<LoopContext>,
+    gotos: HashSet<String>,
+}
+
+fn name(string: &DMString) -> String {
+    String::from_utf8_lossy(&string.0).into_owned()
+}
+
+fn short_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_owned()
+}
+
+fn negate(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary(UnaryOp::Not, inner) => *inner,
+        other => Expr::Unary(UnaryOp::Not, Box::new(other)),
+    }
+}
+
+fn value(value: &Value) -> Expr {
+    match value {
+        Value::Null => Expr::Null,
+        Value::Number(number) => Expr::Float(*number),
+        Value::DMString(string) => Expr::String(string.clone()),
+        Value::Path(path) => Expr::Path(path.clone()),
+        Value::Resource(path) => Expr::Resource(path.clone()),
+        Value::File => Expr::Path("/file".to_owned()),
+        Value::Raw { tag, data } => Expr::Ident(format!("ref({:X}{:08X})", tag, data)),
+    }
+}
+
+fn binary_op(ins: &Instruction) -> Option<BinaryOp> {
+    let op = match ins {
+        Instruction::Add => BinaryOp::Add,
+        Instruction::Sub => BinaryOp::Sub,
+        Instruction::Mul => BinaryOp::Mul,
+        Instruction::Div => BinaryOp::Div,
+        Instruction::Mod => BinaryOp::Mod,
+        Instruction::Pow => BinaryOp::Pow,
+        Instruction::Band => BinaryOp::BitAnd,
+        Instruction::Bor => BinaryOp::BitOr,
+        Instruction::Bxor => BinaryOp::BitXor,
+        Instruction::LShift => BinaryOp::LShift,
+        Instruction::RShift => BinaryOp::RShift,
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+// Comparisons set the flag as well as pushing their result
+fn comparison_op(ins: &Instruction) -> Option<BinaryOp> {
+    let op = match ins {
+        Instruction::Teq => BinaryOp::Eq,
+        Instruction::Tne => BinaryOp::NotEq,
+        Instruction::Tl => BinaryOp::Less,
+        Instruction::Tg => BinaryOp::Greater,
+        Instruction::Tle => BinaryOp::LessEq,
+        Instruction::Tge => BinaryOp::GreaterEq,
+        Instruction::TestEquiv => BinaryOp::Equiv,
+        Instruction::TestNotEquiv => BinaryOp::NotEquiv,
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+fn augmented_op(ins: &Instruction) -> Option<(AssignOp, &Variable)> {
+    let op = match ins {
+        Instruction::AugAdd(var) => (AssignOp::Add, var),
+        Instruction::AugSub(var) => (AssignOp::Sub, var),
+        Instruction::AugMul(var) => (AssignOp::Mul, var),
+        Instruction::AugDiv(var) => (AssignOp::Div, var),
+        Instruction::AugMod(var) => (AssignOp::Mod, var),
+        Instruction::AugBand(var) => (AssignOp::BitAnd, var),
+        Instruction::AugBor(var) => (AssignOp::BitOr, var),
+        Instruction::AugXor(var) => (AssignOp::BitXor, var),
+        Instruction::AugLShift(var) => (AssignOp::LShift, var),
+        Instruction::AugRShift(var) => (AssignOp::RShift, var),
+        Instruction::AssignInto(var) => (AssignOp::Into, var),
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+// Labels only matter when a `goto` still needs them
+fn remove_unused_labels(stmts: &mut Vec<Stmt>, gotos: &HashSet<String>) {
+    stmts.retain(|stmt| !matches!(stmt, Stmt::Label(name) if !gotos.contains(name)));
+
+    for stmt in stmts {
+        match stmt {
+            Stmt::If(_, then, otherwise) => {
+                remove_unused_labels(then, gotos);
+                remove_unused_labels(otherwise, gotos);
+            }
+            Stmt::While(_, body) | Stmt::Spawn(_, body) => remove_unused_labels(body, gotos),
+            Stmt::Switch(_, arms, otherwise) => {
+                for (_, body) in arms {
+                    remove_unused_labels(body, gotos);
+                }
+                remove_unused_labels(otherwise, gotos);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, D> Decompiler<'a, D> {
+    fn pop(&mut self) -> Expr {
+        self.stack.pop().unwrap_or(Expr::Unknown)
+    }
+
+    fn pop_n(&mut self, count: u32) -> Vec<Expr> {
+        let mut exprs: Vec<Expr> = (0..count).map(|_| self.pop()).collect();
+        exprs.reverse();
+        exprs
+    }
+
+    // An argument count of 0xFFFF means the arguments are in a list
+    fn pop_args(&mut self, count: u32) -> Vec<Expr> {
+        if count == 0xFFFF {
+            return self.pop_arg_list();
+        }
+
+        self.pop_n(count)
+    }
+
+    fn pop_arg_list(&mut self) -> Vec<Expr> {
+        let list = self.pop();
+        vec![Expr::Call(Callee::Global("arglist".to_owned()), vec![list])]
+    }
+
+    // Fields without anything in the cache belong to `src`
+    fn field(&self, field: &DMString) -> Expr {
+        match &self.cache {
+            Some(base) => Expr::Field(Box::new(base.clone()), name(field)),
+            None => Expr::Ident(name(field)),
+        }
+    }
+
+    fn cache(&self) -> Expr {
+        self.cache.clone().unwrap_or(Expr::Unknown)
+    }
+
+    fn variable(&mut self, var: &Variable) -> Expr {
+        match var {
+            Variable::Null => Expr::Null,
+            Variable::World => Expr::Ident("world".to_owned()),
+            Variable::Usr => Expr::Ident("usr".to_owned()),
+            Variable::Src => Expr::Ident("src".to_owned()),
+            Variable::Args => Expr::Ident("args".to_owned()),
+            Variable::Dot => Expr::Ident(".".to_owned()),
+            Variable::Cache => self.cache(),
+            Variable::CacheKey => self.cache_key.clone().unwrap_or(Expr::Unknown),
+            Variable::CacheIndex => Expr::Index(
+                Box::new(self.cache()),
+                Box::new(self.cache_key.clone().unwrap_or(Expr::Unknown)),
+            ),
+            Variable::Arg(idx) => Expr::Ident(format!("arg{}", idx)),
+            Variable::Local(idx) => Expr::Ident(format!("local{}", idx)),
+            Variable::NamedLocal(_, local) => Expr::Ident(name(local)),
+            Variable::Global(var) => {
+                Expr::Field(Box::new(Expr::Ident("global".to_owned())), name(var))
+            }
+            Variable::SetCache(lhs, rhs) => {
+                self.cache = Some(self.variable(lhs));
+                self.variable(rhs)
+            }
+            Variable::Initial(var) => Expr::Call(
+                Callee::Global("initial".to_owned()),
+                vec![self.variable(var)],
+            ),
+            Variable::IsSaved(var) => Expr::Call(
+                Callee::Global("issaved".to_owned()),
+                vec![self.variable(var)],
+            ),
+            Variable::Field(field)
+            | Variable::DynamicVerb(field)
+            | Variable::DynamicProc(field) => self.field(field),
+            Variable::StaticVerb(proc) | Variable::StaticProc(proc) => {
+                Expr::Path(proc.path.clone())
+            }
+        }
+    }
+
+    fn callee(&mut self, var: &Variable) -> Callee {
+        let proc_name = match var {
+            Variable::SetCache(lhs, rhs) => {
+                self.cache = Some(self.variable(lhs));
+                return self.callee(rhs);
+            }
+
+            Variable::DynamicProc(proc) | Variable::DynamicVerb(proc) => name(proc),
+            Variable::StaticProc(proc) | Variable::StaticVerb(proc) => short_name(&proc.path),
+            other => return Callee::Path(Box::new(self.variable(other))),
+        };
+
+        match &self.cache {
+            Some(base) => Callee::Method(Box::new(base.clone()), proc_name),
+            None => Callee::Global(proc_name),
+        }
+    }
+
+    fn jump(&mut self, label: &str) -> Stmt {
+        if let Some(context) = self.loop_contexts.last() {
+            if context.exits.iter().any(|exit| exit == label) {
+                return Stmt::Break;
+            }
+
+            if context.headers.iter().any(|header| header == label) {
+                return Stmt::Continue;
+            }
+        }
+
+        self.gotos.insert(label.to_owned());
+        Stmt::Goto(label.to_owned())
+    }
+
+    fn label(&mut self, label: &str, stmts: &mut Vec<Stmt>) {
+        while matches!(self.short_circuits.last(), Some((target, ..)) if target == label) {
+            let (_, op, lhs) = self.short_circuits.pop().unwrap();
+            let rhs = self.pop();
+            self.stack
+                .push(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+        }
+
+        stmts.push(Stmt::Label(label.to_owned()));
+    }
+
+    fn flush_eval(&mut self, stmts: &mut Vec<Stmt>) {
+        if let Some(expr) = self.eval.take() {
+            stmts.push(Stmt::Expr(expr));
+        }
+    }
+
+    // Decompiles the nodes in `start..end`
+    fn sweep(&mut self, start: usize, end: usize) -> Vec<Stmt> {
+        let mut stmts = vec![];
+        let mut idx = start;
+
+        while idx < end {
+            if let Some(back) = self.loops.get(&idx).copied() {
+                if back < end {
+                    idx = self.structure_loop(idx, back, &mut stmts);
+                    continue;
+                }
+            }
+
+            match &self.nodes[idx] {
+                Node::Comment(_) => {}
+                Node::Label(name) => self.label(name, &mut stmts),
+                Node::RawData(words, _) => {
+                    stmts.push(Stmt::Comment(format!("{} words of raw data", words.len())))
+                }
+                Node::Instruction(ins, _) => {
+                    idx = self.instruction(idx, ins, end, &mut stmts);
+                    continue;
+                }
+            }
+
+            idx += 1;
+        }
+
+        self.flush_eval(&mut stmts);
+        stmts
+    }
+
+    // Loops run from their first label up to the last jump back to it. Returns where to carry on from.
+    fn structure_loop(&mut self, start: usize, back: usize, stmts: &mut Vec<Stmt>) -> usize {
+        let mut headers = vec![];
+        let mut body_start = start;
+
+        while body_start < back {
+            match &self.nodes[body_start] {
+                Node::Label(name) => headers.push(name.clone()),
+                Node::Comment(_) => {}
+                _ => break,
+            }
+
+            body_start += 1;
+        }
+
+        let exits = self.nodes[back + 1..]
+            .iter()
+            .take_while(|node| matches!(node, Node::Label(_) | Node::Comment(_)))
+            .filter_map(|node| match node {
+                Node::Label(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        for header in &headers {
+            stmts.push(Stmt::Label(header.clone()));
+        }
+
+        self.loop_contexts.push(LoopContext { headers, exits });
+        let mut body = self.sweep(body_start, back);
+
+        // Loops that test at the end jump back while the condition holds
+        match &self.nodes[back] {
+            Node::Instruction(Instruction::Jz(_), _)
+            | Node::Instruction(Instruction::JzLoop(_), _) => {
+                let cond = self.flag.take().unwrap_or(Expr::Unknown);
+                body.push(Stmt::If(cond, vec![Stmt::Break], vec![]));
+            }
+
+            Node::Instruction(Instruction::Jnz(_), _)
+            | Node::Instruction(Instruction::JnzLoop(_), _) => {
+                let cond = self.flag.take().unwrap_or(Expr::Unknown);
+                body.push(Stmt::If(negate(cond), vec![Stmt::Break], vec![]));
+            }
+
+            _ => {}
+        }
+
+        self.loop_contexts.pop();
+
+        // A test that leaves the loop straight away is the loop's condition
+        let cond = match body.first() {
+            Some(Stmt::If(cond, then, otherwise))
+                if then.as_slice() == [Stmt::Break] && otherwise.is_empty() =>
+            {
+                let cond = negate(cond.clone());
+                body.remove(0);
+                cond
+            }
+
+            _ => Expr::Ident("TRUE".to_owned()),
+        };
+
+        stmts.push(Stmt::While(cond, body));
+        back + 1
+    }
+
+    // The `Jmp` that the code in `start..end` finishes with, if there is one. Returns it along with where it goes.
+    fn trailing_jump(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let jump = (start..end)
+            .rev()
+            .find(|idx| !matches!(self.nodes[*idx], Node::Comment(_)))?;
+
+        match &self.nodes[jump] {
+            Node::Instruction(Instruction::Jmp(Label(label)), _) => {
+                Some((jump, *self.labels.get(label.as_str())?))
+            }
+
+            _ => None,
+        }
+    }
+
+    // The `Jmp` over the else branch, if the code before `target` ends with one. Returns it along with where it goes.
+    fn else_jump(&self, start: usize, target: usize, end: usize) -> Option<(usize, usize)> {
+        self.trailing_jump(start, target)
+            .filter(|(_, destination)| *destination > target && *destination <= end)
+    }
+
+    // `Jz` and `Jnz`. Returns where to carry on from.
+    fn conditional(
+        &mut self,
+        idx: usize,
+        label: &str,
+        jumps_if: bool,
+        end: usize,
+        stmts: &mut Vec<Stmt>,
+    ) -> usize {
+        let flag = self.flag.take().unwrap_or(Expr::Unknown);
+
+        // What has to be true to get to the code after the jump
+        let cond = if jumps_if { negate(flag) } else { flag };
+
+        let target = match self.labels.get(label) {
+            Some(target) if *target > idx && *target <= end => *target,
+
+            // Backwards or out of the code being looked at, so it can't contain anything
+            _ => {
+                let jump = self.jump(label);
+                stmts.push(Stmt::If(negate(cond), vec![jump], vec![]));
+                return idx + 1;
+            }
+        };
+
+        let depth = self.stack.len();
+
+        let (then_end, next) = match self.else_jump(idx + 1, target, end) {
+            Some((jump, destination)) => (jump, destination),
+            None => (target, target),
+        };
+
+        let then = self.sweep(idx + 1, then_end);
+        if next == target {
+            stmts.push(Stmt::If(cond, then, vec![]));
+            return target;
+        }
+
+        let then_value = if self.stack.len() > depth {
+            self.stack.pop()
+        } else {
+            None
+        };
+
+        let otherwise = self.sweep(target, next);
+
+        let else_value = if self.stack.len() > depth {
+            self.stack.pop()
+        } else {
+            None
+        };
+
+        let only_labels = |stmts: &[Stmt]| stmts.iter().all(|stmt| matches!(stmt, Stmt::Label(_)));
+
+        match (then_value, else_value) {
+            // Both branches just leave a value behind
+            (Some(then_value), Some(else_value))
+                if only_labels(&then) && only_labels(&otherwise) =>
+            {
+                stmts.extend(then);
+                stmts.extend(otherwise);
+                self.stack.push(Expr::Ternary(
+                    Box::new(cond),
+                    Box::new(then_value),
+                    Box::new(else_value),
+                ));
+            }
+
+            (then_value, else_value) => {
+                stmts.push(Stmt::If(cond, then, otherwise));
+                self.stack.extend(then_value);
+                self.stack.extend(else_value);
+            }
+        }
+
+        next
+    }
+
+    // `Switch` and `SwitchRange`. DM puts each arm straight after the jump table, one after another, each jumping to
+    // the end of the switch when it's done, with the `else` arm last. Returns where to carry on from, or `None` if the
+    // arms aren't laid out like that.
+    fn switch(
+        &mut self,
+        idx: usize,
+        cases: Vec<(Case, &Label)>,
+        default: &Label,
+        end: usize,
+        stmts: &mut Vec<Stmt>,
+    ) -> Option<usize> {
+        let position = |Label(label): &Label| match self.labels.get(label.as_str()) {
+            Some(target) if *target > idx && *target <= end => Some(*target),
+            _ => None,
+        };
+
+        // Cases that go to the same place are one arm
+        let mut arms: Vec<(usize, Vec<Case>)> = vec![];
+        for (case, label) in cases {
+            let start = position(label)?;
+
+            match arms.iter_mut().find(|(arm_start, _)| *arm_start == start) {
+                Some((_, arm_cases)) => arm_cases.push(case),
+                None => arms.push((start, vec![case])),
+            }
+        }
+
+        arms.sort_by_key(|(start, _)| *start);
+
+        let default = position(default)?;
+        let first = arms.first().map_or(default, |(start, _)| *start);
+
+        if arms.iter().any(|(start, _)| *start > default)
+            || self.nodes[idx + 1..first]
+                .iter()
+                .any(|node| !matches!(node, Node::Label(_) | Node::Comment(_)))
+        {
+            return None;
+        }
+
+        // Each arm runs up to where the next one starts
+        let arm_ends: Vec<usize> = arms
+            .iter()
+            .skip(1)
+            .map(|(start, _)| *start)
+            .chain(std::iter::once(default))
+            .collect();
+
+        // Without any jumps to the end, there's nothing to say the `else` arm isn't just the code after the switch
+        let exit = arms
+            .iter()
+            .zip(&arm_ends)
+            .filter_map(|((start, _), arm_end)| self.trailing_jump(*start, *arm_end))
+            .map(|(_, destination)| destination)
+            .find(|destination| *destination >= default && *destination <= end)
+            .unwrap_or(default);
+
+        let input = self.pop();
+
+        // The jump to the end is implied
+        let body = |decompiler: &mut Self, start: usize, arm_end: usize| {
+            let body_end = match decompiler.trailing_jump(start, arm_end) {
+                Some((jump, destination)) if destination == exit => jump,
+                _ => arm_end,
+            };
+
+            decompiler.sweep(start, body_end)
+        };
+
+        let mut switch_arms = vec![];
+        for ((start, arm_cases), arm_end) in arms.into_iter().zip(arm_ends) {
+            switch_arms.push((arm_cases, body(self, start, arm_end)));
+        }
+
+        let otherwise = body(self, default, exit);
+
+        stmts.push(Stmt::Switch(input, switch_arms, otherwise));
+        Some(exit)
+    }
+
+    // Returns where to carry on from
+    fn instruction(
+        &mut self,
+        idx: usize,
+        ins: &'a Instruction,
+        end: usize,
+        stmts: &mut Vec<Stmt>,
+    ) -> usize {
+        if *ins != Instruction::PushEval {
+            self.flush_eval(stmts);
+        }
+
+        if let Some(op) = binary_op(ins) {
+            let rhs = self.pop();
+            let lhs = self.pop();
+            self.stack
+                .push(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+            return idx + 1;
+        }
+
+        if let Some(op) = comparison_op(ins) {
+            let rhs = self.pop();
+            let lhs = self.pop();
+            let expr = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            self.flag = Some(expr.clone());
+            self.stack.push(expr);
+            return idx + 1;
+        }
+
+        if let Some((op, var)) = augmented_op(ins) {
+            let rhs = self.pop();
+            let lhs = self.variable(var);
+            self.eval = Some(Expr::Assign(op, Box::new(lhs), Box::new(rhs)));
+            return idx + 1;
+        }
+
+        match ins {
+            Instruction::DbgFile(_) | Instruction::DbgLine(_) => {}
+
+            Instruction::PushInt(value) => self.stack.push(Expr::Int(*value)),
+            Instruction::PushVal(operand) => self.stack.push(value(&operand.value)),
+
+            Instruction::GetVar(var) => {
+                let expr = self.variable(var);
+                self.stack.push(expr);
+            }
+
+            Instruction::SetVar(Variable::Cache) => self.cache = Some(self.pop()),
+            Instruction::SetVar(Variable::CacheKey) => self.cache_key = Some(self.pop()),
+
+            Instruction::SetVar(var) => {
+                let rhs = self.pop();
+                let lhs = self.variable(var);
+                stmts.push(Stmt::Expr(Expr::Assign(
+                    AssignOp::Assign,
+                    Box::new(lhs),
+                    Box::new(rhs),
+                )));
+            }
+
+            Instruction::SetVarExpr(var) => {
+                let rhs = self.pop();
+                let lhs = self.variable(var);
+                self.stack
+                    .push(Expr::Assign(AssignOp::Assign, Box::new(lhs), Box::new(rhs)));
+            }
+
+            Instruction::PushEval => {
+                let expr = self.eval.take().unwrap_or(Expr::Unknown);
+                self.stack.push(expr);
+            }
+
+            Instruction::PreInc(var)
+            | Instruction::PostInc(var)
+            | Instruction::PreDec(var)
+            | Instruction::PostDec(var) => {
+                let op = match ins {
+                    Instruction::PreInc(_) => UnaryOp::PreInc,
+                    Instruction::PostInc(_) => UnaryOp::PostInc,
+                    Instruction::PreDec(_) => UnaryOp::PreDec,
+                    _ => UnaryOp::PostDec,
+                };
+
+                let expr = self.variable(var);
+                self.stack.push(Expr::Unary(op, Box::new(expr)));
+            }
+
+            Instruction::Inc(var) | Instruction::Dec(var) => {
+                let op = match ins {
+                    Instruction::Inc(_) => UnaryOp::PostInc,
+                    _ => UnaryOp::PostDec,
+                };
+
+                let expr = self.variable(var);
+                stmts.push(Stmt::Expr(Expr::Unary(op, Box::new(expr))));
+            }
+
+            Instruction::Pop => {
+                let expr = self.pop();
+
+                // Comparisons get popped so the flag can be pushed instead
+                if expr.has_side_effects() && self.flag.as_ref() != Some(&expr) {
+                    stmts.push(Stmt::Expr(expr));
+                }
+            }
+
+            Instruction::PopN(count) => {
+                for expr in self.pop_n(*count) {
+                    if expr.has_side_effects() {
+                        stmts.push(Stmt::Expr(expr));
+                    }
+                }
+            }
+
+            Instruction::PushTop => {
+                let top = self.stack.last().cloned().unwrap_or(Expr::Unknown);
+                self.stack.push(top);
+            }
+
+            Instruction::Test => self.flag = Some(self.pop()),
+            Instruction::GetFlag => {
+                let flag = self.flag.clone().unwrap_or(Expr::Unknown);
+                self.stack.push(flag);
+            }
+
+            Instruction::Not | Instruction::UnaryNeg | Instruction::Bnot => {
+                let op = match ins {
+                    Instruction::Not => UnaryOp::Not,
+                    Instruction::UnaryNeg => UnaryOp::Neg,
+                    _ => UnaryOp::BitNot,
+                };
+
+                let expr = self.pop();
+                self.stack.push(Expr::Unary(op, Box::new(expr)));
+            }
+
+            // The list is pushed before the value being looked for, a range's bounds go first too
+            Instruction::IsIn(params) => {
+                let lhs = self.pop();
+                let rhs = match params {
+                    IsInParams::Value => self.pop(),
+                    IsInParams::Range => {
+                        let max = self.pop();
+                        let min = self.pop();
+                        Expr::Binary(BinaryOp::To, Box::new(min), Box::new(max))
+                    }
+                };
+
+                let expr = Expr::Binary(BinaryOp::In, Box::new(lhs), Box::new(rhs));
+                self.flag = Some(expr.clone());
+                self.stack.push(expr);
+            }
+
+            Instruction::ListGet => {
+                let index = self.pop();
+                let list = self.pop();
+                self.stack
+                    .push(Expr::Index(Box::new(list), Box::new(index)));
+            }
+
+            Instruction::Jmp(Label(label)) | Instruction::JmpLoop(Label(label)) => {
+                let jump = self.jump(label);
+                stmts.push(jump);
+            }
+
+            Instruction::Jz(Label(label)) | Instruction::JzLoop(Label(label)) => {
+                return self.conditional(idx, label, false, end, stmts);
+            }
+
+            Instruction::Jnz(Label(label)) | Instruction::JnzLoop(Label(label)) => {
+                return self.conditional(idx, label, true, end, stmts);
+            }
+
+            Instruction::JmpAnd(Label(label)) | Instruction::JmpOr(Label(label)) => {
+                let op = match ins {
+                    Instruction::JmpAnd(_) => BinaryOp::And,
+                    _ => BinaryOp::Or,
+                };
+
+                let lhs = self.pop();
+                self.short_circuits.push((label.clone(), op, lhs));
+            }
+
+            // These are `?.`, the null check doesn't change much when reading the code
+            Instruction::SetCacheJmpIfNull(_) | Instruction::SetCachePopJmpIfNull(_) => {
+                self.cache = Some(self.pop());
+            }
+
+            Instruction::Spawn(Label(label)) => {
+                let delay = self.pop();
+
+                match self.labels.get(label.as_str()) {
+                    Some(target) if *target > idx && *target <= end => {
+                        let target = *target;
+                        let mut body = self.sweep(idx + 1, target);
+
+                        if body.last() == Some(&Stmt::Return(None)) {
+                            body.pop();
+                        }
+
+                        stmts.push(Stmt::Spawn(delay, body));
+                        return target;
+                    }
+
+                    _ => {
+                        self.gotos.insert(label.clone());
+                        stmts.push(Stmt::Comment(ins.to_string()));
+                    }
+                }
+            }
+
+            Instruction::Switch(params) => {
+                let cases = params
+                    .cases
+                    .iter()
+                    .map(|(case, label)| (Case::Exact(value(case)), label))
+                    .collect();
+
+                if let Some(next) = self.switch(idx, cases, &params.default, end, stmts) {
+                    return next;
+                }
+
+                self.generic(ins, stmts);
+            }
+
+            Instruction::SwitchRange(params) => {
+                let cases = params
+                    .cases
+                    .iter()
+                    .map(|(case, label)| (Case::Exact(value(case)), label))
+                    .chain(
+                        params
+                            .range_cases
+                            .iter()
+                            .map(|(min, max, label)| (Case::Range(value(min), value(max)), label)),
+                    )
+                    .collect();
+
+                if let Some(next) = self.switch(idx, cases, &params.default, end, stmts) {
+                    return next;
+                }
+
+                self.generic(ins, stmts);
+            }
+
+            Instruction::Ret => {
+                let expr = self.pop();
+                stmts.push(Stmt::Return(Some(expr)));
+            }
+
+            Instruction::End => {
+                if self.last_instruction != Some(idx) {
+                    stmts.push(Stmt::Return(None));
+                }
+            }
+
+            Instruction::Throw => {
+                let expr = self.pop();
+                stmts.push(Stmt::Throw(expr));
+            }
+
+            Instruction::Del => {
+                let expr = self.pop();
+                stmts.push(Stmt::Del(expr));
+            }
+
+            Instruction::Crash | Instruction::Sleep => {
+                let proc_name = match ins {
+                    Instruction::Crash => "CRASH",
+                    _ => "sleep",
+                };
+
+                let arg = self.pop();
+                stmts.push(Stmt::Expr(Expr::Call(
+                    Callee::Global(proc_name.to_owned()),
+                    vec![arg],
+                )));
+            }
+
+            Instruction::Output => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                stmts.push(Stmt::Expr(Expr::Binary(
+                    BinaryOp::LShift,
+                    Box::new(lhs),
+                    Box::new(rhs),
+                )));
+            }
+
+            Instruction::OutputFormat(pattern, arg_count) => {
+                let args = self.pop_n(*arg_count);
+                let target = self.pop();
+                stmts.push(Stmt::Expr(Expr::Binary(
+                    BinaryOp::LShift,
+                    Box::new(target),
+                    Box::new(Expr::Format(pattern.clone(), args)),
+                )));
+            }
+
+            Instruction::Format(pattern, arg_count) => {
+                let args = self.pop_n(*arg_count);
+                self.stack.push(Expr::Format(pattern.clone(), args));
+            }
+
+            Instruction::Call(var, arg_count) | Instruction::CallStatement(var, arg_count) => {
+                let args = self.pop_args(*arg_count);
+                let callee = self.callee(var);
+                self.stack.push(Expr::Call(callee, args));
+            }
+
+            Instruction::CallGlob(arg_count, proc) => {
+                let args = self.pop_args(*arg_count);
+                self.stack
+                    .push(Expr::Call(Callee::Global(short_name(&proc.path)), args));
+            }
+
+            Instruction::CallGlobalArgList(proc) => {
+                let args = self.pop_arg_list();
+                self.stack
+                    .push(Expr::Call(Callee::Global(short_name(&proc.path)), args));
+            }
+
+            Instruction::CallParent => self.stack.push(Expr::Call(Callee::Parent, vec![])),
+            Instruction::CallSelf => self.stack.push(Expr::Call(Callee::This, vec![])),
+
+            Instruction::CallParentArgs(arg_count) | Instruction::CallSelfArgs(arg_count) => {
+                let callee = match ins {
+                    Instruction::CallParentArgs(_) => Callee::Parent,
+                    _ => Callee::This,
+                };
+
+                let args = self.pop_args(*arg_count);
+                self.stack.push(Expr::Call(callee, args));
+            }
+
+            Instruction::CallParentArgList | Instruction::CallSelfArgList => {
+                let callee = match ins {
+                    Instruction::CallParentArgList => Callee::Parent,
+                    _ => Callee::This,
+                };
+
+                let args = self.pop_arg_list();
+                self.stack.push(Expr::Call(callee, args));
+            }
+
+            Instruction::CallPath(arg_count) => {
+                let args = self.pop_args(*arg_count);
+                let path = self.pop();
+                self.stack
+                    .push(Expr::Call(Callee::Path(Box::new(path)), args));
+            }
+
+            Instruction::CallPathArgList => {
+                let args = self.pop_arg_list();
+                let path = self.pop();
+                self.stack
+                    .push(Expr::Call(Callee::Path(Box::new(path)), args));
+            }
+
+            Instruction::CallName(_) | Instruction::CallNameArgList => {
+                let args = match ins {
+                    Instruction::CallName(arg_count) => self.pop_args(*arg_count),
+                    _ => self.pop_arg_list(),
+                };
+
+                let proc_name = self.pop();
+                let object = self.pop();
+                self.stack.push(Expr::Call(
+                    Callee::Name(Box::new(object), Box::new(proc_name)),
+                    args,
+                ));
+            }
+
+            Instruction::New(arg_count) => {
+                let args = self.pop_args(*arg_count);
+                let type_ = self.pop();
+                self.stack.push(Expr::New(Box::new(type_), args));
+            }
+
+            Instruction::NewArgList => {
+                let args = self.pop_arg_list();
+                let type_ = self.pop();
+                self.stack.push(Expr::New(Box::new(type_), args));
+            }
+
+            Instruction::NewList(arg_count) => {
+                let items = self.pop_n(*arg_count);
+                self.stack.push(Expr::List(items));
+            }
+
+            Instruction::NewAssocList(arg_count) => {
+                let mut items = self.pop_n(arg_count * 2).into_iter();
+                let mut pairs = vec![];
+
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    pairs.push((key, value));
+                }
+
+                self.stack.push(Expr::AssocList(pairs));
+            }
+
+            Instruction::PushCache => self.saved_caches.push(self.cache.clone()),
+            Instruction::PopCache => self.cache = self.saved_caches.pop().flatten(),
+            Instruction::PushCacheKey => self.saved_cache_keys.push(self.cache_key.clone()),
+            Instruction::PopCacheKey => self.cache_key = self.saved_cache_keys.pop().flatten(),
+
+            _ => self.generic(ins, stmts),
+        }
+
+        idx + 1
+    }
+
+    // Anything else is written as a call, either to the built-in proc it implements or to the instruction itself
+    fn generic(&mut self, ins: &Instruction, stmts: &mut Vec<Stmt>) {
+        let destinations = jump_destinations(ins);
+
+        let (pops, pushes) = match stack_effect(ins) {
+            Some(effect) if destinations.is_empty() => effect,
+
+            // Nothing after this can be trusted to be right, but it's better than stopping
+            _ => {
+                for Label(label) in destinations {
+                    self.gotos.insert(label.clone());
+                }
+
+                stmts.push(Stmt::Comment(ins.to_string()));
+                return;
+            }
+        };
+
+        let callee = match builtin_proc_name(ins) {
+            Some(proc_name) => Callee::Global(proc_name.to_owned()),
+            None => Callee::Instruction(format!("__{}", ins.op_name())),
+        };
+
+        let call = Expr::Call(callee, self.pop_n(pops));
+
+        match pushes {
+            0 => stmts.push(Stmt::Expr(call)),
+            _ => {
+                self.stack.push(call);
+
+                for _ in 1..pushes {
+                    self.stack.push(Expr::Unknown);
+                }
+            }
+        }
+    }
+}
+
+fn structure<D>(nodes: &[Node<D>]) -> Result<Vec<Stmt>, CfgError> {
+    let cfg = Cfg::new(nodes)?;
+
+    let mut loops: HashMap<usize, usize> = HashMap::new();
+    for found in cfg.loops() {
+        for latch in found.latches {
+            let block = &cfg.blocks[latch];
+            let back = match nodes[block.nodes.clone()]
+                .iter()
+                .rposition(|node| matches!(node, Node::Instruction(..)))
+            {
+                Some(idx) => block.nodes.start + idx,
+                None => continue,
+            };
+
+            let start = cfg.blocks[found.header].nodes.start;
+            let entry = loops.entry(start).or_insert(back);
+            *entry = (*entry).max(back);
+        }
+    }
+
+    let labels = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| match node {
+            Node::Label(name) => Some((name.as_str(), idx)),
+            _ => None,
+        })
+        .collect();
+
+    let mut decompiler = Decompiler {
+        nodes,
+        labels,
+        loops,
+        last_instruction: nodes
+            .iter()
+            .rposition(|node| matches!(node, Node::Instruction(..))),
+        stack: vec![],
+        flag: None,
+        cache: None,
+        cache_key: None,
+        saved_caches: vec![],
+        saved_cache_keys: vec![],
+        eval: None,
+        short_circuits: vec![],
+        loop_contexts: vec![],
+        gotos: HashSet::new(),
+    };
+
+    let mut stmts = decompiler.sweep(0, nodes.len());
+    remove_unused_labels(&mut stmts, &decompiler.gotos);
+
+    Ok(stmts)
+}
+
+/// Decompiles a proc into DM-like source. Control flow that doesn't fit `if`, `while`, `switch` or `spawn` comes out as `goto`,
+/// and instructions the decompiler doesn't understand are left as comments.
+pub fn decompile<D>(nodes: &[Node<D>]) -> Result<String, CfgError> {
+    let stmts = structure(nodes)?;
+
+    let mut out = String::new();
+    text::write_stmts(&mut out, &stmts, 0);
+    Ok(out)
+}
+
+/// `decompile`, but as a dreammaker syntax tree for tools that would rather not parse the text again.
+/// Instructions the decompiler doesn't understand become calls to `__asm("...")`, and nothing has a location.
+pub fn decompile_ast<D>(nodes: &[Node<D>]) -> Result<dreammaker::ast::Block, CfgError> {
+    let stmts = structure(nodes)?;
+    Ok(ast::block(&stmts))
+}
+
+#[test]
+fn pseudo_source() {
+    let nodes = crate::parser::parse(
+        r#"
+DbgLine 1
+GetVar arg(0)
+Test
+Jz LAB_0000
+PushInt 1
+Ret
+LAB_0000:
+PushInt 0
+SetVar local(0)
+LAB_0001:
+GetVar local(0)
+PushInt 10
+Tl
+Jz LAB_0002
+GetVar local(0)
+JmpAnd LAB_0003
+GetVar cache = src; cache["ready"]
+LAB_0003:
+Test
+Jz LAB_0004
+GetVar local(0)
+PushVal "x"
+Call cache = src; dynamic_proc("poke") 2
+Pop
+Jmp LAB_0005
+LAB_0004:
+PushVal "waiting"
+GetVar local(0)
+CallGlob 2 /proc/wait
+Pop
+LAB_0005:
+Inc local(0)
+Jmp LAB_0001
+LAB_0002:
+GetVar arg(0)
+Test
+Jz LAB_0006
+PushInt 2
+Jmp LAB_0007
+LAB_0006:
+PushInt 3
+LAB_0007:
+Ret
+End
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        decompile(&nodes).unwrap(),
+        r#"if (arg0)
+	return 1
+local0 = 0
+while (local0 < 10)
+	if (local0 && src.ready)
+		src.poke(local0, "x")
+	else
+		wait("waiting", local0)
+	local0++
+return arg0 ? 2 : 3
+"#
+    );
+}
+
+#[test]
+fn named_locals() {
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+SetVar local(0, "amount")
+GetVar local(0, "amount")
+GetVar local(1)
+Add
+Ret
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        decompile(&nodes).unwrap(),
+        "amount = arg0\nreturn amount + local1\n"
+    );
+}
+
+#[test]
+fn syntax_tree() {
+    use dreammaker::ast::{Expression, Follow, Statement, Term};
+
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Test
+Jz LAB_0000
+PushVal "boom"
+Crash
+LAB_0000:
+PushInt 1
+GetVar cache = src; cache["count"]
+Add
+Ret
+"#,
+    )
+    .unwrap();
+
+    let block = decompile_ast(&nodes).unwrap();
+    assert_eq!(block.len(), 2);
+
+    match &block[0].elem {
+        Statement::If { arms, else_arm } => {
+            assert_eq!(arms.len(), 1);
+            assert!(else_arm.is_none());
+            assert!(matches!(arms[0].1[0].elem, Statement::Crash(Some(_))));
+        }
+        other => panic!("{:?}", other),
+    }
+
+    match &block[1].elem {
+        Statement::Return(Some(Expression::BinaryOp { lhs, rhs, .. })) => {
+            assert_eq!(**lhs, Expression::from(Term::Int(1)));
+
+            match &**rhs {
+                Expression::Base { term, follow, .. } => {
+                    assert_eq!(term.elem, Term::Ident("src".to_owned()));
+                    assert!(matches!(&follow[0].elem, Follow::Field(_, name) if name == "count"));
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn switch_statement() {
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+SwitchRange default => LAB_0002, 1 => LAB_0000, 2 => LAB_0000, (3 to 5) => LAB_0001, 
+LAB_0000:
+PushInt 1
+Ret
+LAB_0001:
+Call cache = src; dynamic_proc("poke") 0
+Pop
+Jmp LAB_0003
+LAB_0002:
+CallGlob 0 /proc/wait
+Pop
+LAB_0003:
+PushInt 0
+Ret
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        decompile(&nodes).unwrap(),
+        r#"switch (arg0)
+	if (1, 2)
+		return 1
+	if (3 to 5)
+		src.poke()
+	else
+		wait()
+return 0
+"#
+    );
+}
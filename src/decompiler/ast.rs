@@ -0,0 +1,375 @@
+use dreammaker::ast::{
+    self, Expression, Follow, ListAccessKind, NewType, PathOp, Prefab, PropertyAccessKind, Spanned,
+    Statement, Term,
+};
+use dreammaker::Location;
+
+use super::ir::{AssignOp, BinaryOp, Callee, Case, Expr, Stmt, UnaryOp};
+use crate::operands::DMString;
+
+// Nothing the decompiler makes has a place in a source file
+fn spanned<T>(elem: T) -> Spanned<T> {
+    Spanned::new(Location::default(), elem)
+}
+
+fn term(term: Term) -> Expression {
+    Expression::from(term)
+}
+
+fn string(string: &DMString) -> String {
+    String::from_utf8_lossy(&string.0).into_owned()
+}
+
+fn prefab(path: &str) -> Prefab {
+    Prefab {
+        path: path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| (PathOp::Slash, part.to_owned()))
+            .collect(),
+        vars: Default::default(),
+    }
+}
+
+// Follows and unary operators can only be added to a bare term, anything else needs wrapping first
+fn with_follow(expr: Expression, follow: Follow) -> Expression {
+    match expr {
+        Expression::Base {
+            unary,
+            term: base,
+            follow: mut follows,
+        } if unary.is_empty() => {
+            follows.push(spanned(follow));
+            Expression::Base {
+                unary,
+                term: base,
+                follow: follows,
+            }
+        }
+
+        other => Expression::Base {
+            unary: vec![],
+            term: Box::new(spanned(Term::Expr(Box::new(other)))),
+            follow: vec![spanned(follow)],
+        },
+    }
+}
+
+// dreammaker applies the last unary operator first, so the outermost goes at the front
+fn with_unary(expr: Expression, op: ast::UnaryOp) -> Expression {
+    match expr {
+        Expression::Base {
+            mut unary,
+            term,
+            follow,
+        } => {
+            unary.insert(0, op);
+            Expression::Base {
+                unary,
+                term,
+                follow,
+            }
+        }
+
+        other => Expression::Base {
+            unary: vec![op],
+            term: Box::new(spanned(Term::Expr(Box::new(other)))),
+            follow: vec![],
+        },
+    }
+}
+
+fn unary_op(op: UnaryOp) -> ast::UnaryOp {
+    match op {
+        UnaryOp::Neg => ast::UnaryOp::Neg,
+        UnaryOp::Not => ast::UnaryOp::Not,
+        UnaryOp::BitNot => ast::UnaryOp::BitNot,
+        UnaryOp::PreInc => ast::UnaryOp::PreIncr,
+        UnaryOp::PostInc => ast::UnaryOp::PostIncr,
+        UnaryOp::PreDec => ast::UnaryOp::PreDecr,
+        UnaryOp::PostDec => ast::UnaryOp::PostDecr,
+    }
+}
+
+fn binary_op(op: BinaryOp) -> ast::BinaryOp {
+    match op {
+        BinaryOp::Add => ast::BinaryOp::Add,
+        BinaryOp::Sub => ast::BinaryOp::Sub,
+        BinaryOp::Mul => ast::BinaryOp::Mul,
+        BinaryOp::Div => ast::BinaryOp::Div,
+        BinaryOp::Mod => ast::BinaryOp::Mod,
+        BinaryOp::Pow => ast::BinaryOp::Pow,
+        BinaryOp::Eq => ast::BinaryOp::Eq,
+        BinaryOp::NotEq => ast::BinaryOp::NotEq,
+        BinaryOp::Less => ast::BinaryOp::Less,
+        BinaryOp::Greater => ast::BinaryOp::Greater,
+        BinaryOp::LessEq => ast::BinaryOp::LessEq,
+        BinaryOp::GreaterEq => ast::BinaryOp::GreaterEq,
+        BinaryOp::Equiv => ast::BinaryOp::Equiv,
+        BinaryOp::NotEquiv => ast::BinaryOp::NotEquiv,
+        BinaryOp::BitAnd => ast::BinaryOp::BitAnd,
+        BinaryOp::BitOr => ast::BinaryOp::BitOr,
+        BinaryOp::BitXor => ast::BinaryOp::BitXor,
+        BinaryOp::LShift => ast::BinaryOp::LShift,
+        BinaryOp::RShift => ast::BinaryOp::RShift,
+        BinaryOp::And => ast::BinaryOp::And,
+        BinaryOp::Or => ast::BinaryOp::Or,
+        BinaryOp::In => ast::BinaryOp::In,
+        BinaryOp::To => ast::BinaryOp::To,
+    }
+}
+
+fn assign_op(op: AssignOp) -> ast::AssignOp {
+    match op {
+        AssignOp::Assign => ast::AssignOp::Assign,
+        AssignOp::Into => ast::AssignOp::AssignInto,
+        AssignOp::Add => ast::AssignOp::AddAssign,
+        AssignOp::Sub => ast::AssignOp::SubAssign,
+        AssignOp::Mul => ast::AssignOp::MulAssign,
+        AssignOp::Div => ast::AssignOp::DivAssign,
+        AssignOp::Mod => ast::AssignOp::ModAssign,
+        AssignOp::BitAnd => ast::AssignOp::BitAndAssign,
+        AssignOp::BitOr => ast::AssignOp::BitOrAssign,
+        AssignOp::BitXor => ast::AssignOp::BitXorAssign,
+        AssignOp::LShift => ast::AssignOp::LShiftAssign,
+        AssignOp::RShift => ast::AssignOp::RShiftAssign,
+        AssignOp::And => ast::AssignOp::AndAssign,
+        AssignOp::Or => ast::AssignOp::OrAssign,
+    }
+}
+
+fn exprs(exprs: &[Expr]) -> Vec<Expression> {
+    exprs.iter().map(expression).collect()
+}
+
+// Splits the string at each place an embedded expression goes. Other formatting markers (`\the`, `\s` and so on)
+// have nowhere to go in the tree, so they're dropped.
+fn interp_string(pattern: &DMString, args: &[Expr]) -> Term {
+    let mut first = vec![];
+    let mut parts: Vec<(Option<Expression>, Vec<u8>)> = vec![];
+    let mut args = args.iter();
+    let mut bytes = pattern.0.iter();
+
+    while let Some(byte) = bytes.next() {
+        if *byte != 0xFF {
+            match parts.last_mut() {
+                Some((_, text)) => text.push(*byte),
+                None => first.push(*byte),
+            }
+            continue;
+        }
+
+        if let Some(1..=5) | Some(42..=45) = bytes.next() {
+            parts.push((args.next().map(expression), vec![]));
+        }
+    }
+
+    Term::InterpString(
+        String::from_utf8_lossy(&first).into_owned(),
+        parts
+            .into_iter()
+            .map(|(expr, text)| (expr, String::from_utf8_lossy(&text).into_owned()))
+            .collect(),
+    )
+}
+
+fn call(callee: &Callee, args: Vec<Expression>) -> Expression {
+    match callee {
+        Callee::Global(name) | Callee::Instruction(name) => term(Term::Call(name.clone(), args)),
+        Callee::Method(base, name) => with_follow(
+            expression(base),
+            Follow::Call(PropertyAccessKind::Dot, name.clone(), args),
+        ),
+        Callee::Path(path) => term(Term::DynamicCall(vec![expression(path)], args)),
+        Callee::Name(object, name) => term(Term::DynamicCall(
+            vec![expression(object), expression(name)],
+            args,
+        )),
+        Callee::Parent => term(Term::ParentCall(args)),
+        Callee::This => term(Term::SelfCall(args)),
+    }
+}
+
+// `new` only takes a type path or a chain of fields
+fn new_type(type_: &Expr) -> Option<NewType> {
+    match type_ {
+        Expr::Path(path) => Some(NewType::Prefab(prefab(path))),
+        Expr::Ident(ident) => Some(NewType::MiniExpr {
+            ident: ident.clone(),
+            fields: vec![],
+        }),
+        Expr::Field(base, name) => match new_type(base)? {
+            NewType::MiniExpr { ident, mut fields } => {
+                fields.push(ast::Field {
+                    kind: PropertyAccessKind::Dot,
+                    ident: name.clone(),
+                });
+                Some(NewType::MiniExpr { ident, fields })
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub(super) fn expression(expr: &Expr) -> Expression {
+    match expr {
+        Expr::Null => term(Term::Null),
+        Expr::Int(value) => term(Term::Int(*value)),
+        Expr::Float(value) => term(Term::Float(*value)),
+        Expr::String(value) => term(Term::String(string(value))),
+        Expr::Format(pattern, args) => term(interp_string(pattern, args)),
+        Expr::Path(path) => term(Term::Prefab(prefab(path))),
+        Expr::Resource(path) => term(Term::Resource(path.clone())),
+        Expr::Ident(name) => term(Term::Ident(name.clone())),
+
+        Expr::Field(base, name) => with_follow(
+            expression(base),
+            Follow::Field(PropertyAccessKind::Dot, name.clone()),
+        ),
+
+        Expr::Index(base, index) => with_follow(
+            expression(base),
+            Follow::Index(ListAccessKind::Normal, Box::new(expression(index))),
+        ),
+
+        Expr::Call(callee, args) => call(callee, exprs(args)),
+
+        Expr::New(type_, args) => match new_type(type_) {
+            Some(type_) => term(Term::New {
+                type_,
+                args: Some(exprs(args)),
+            }),
+
+            // Anything else is written like an unknown instruction
+            None => {
+                let mut args = exprs(args);
+                args.insert(0, expression(type_));
+                term(Term::Call("__New".to_owned(), args))
+            }
+        },
+
+        Expr::List(items) => term(Term::List(exprs(items))),
+
+        // This is how dreammaker parses `list(a = b)`
+        Expr::AssocList(pairs) => term(Term::List(
+            pairs
+                .iter()
+                .map(|(key, value)| Expression::AssignOp {
+                    op: ast::AssignOp::Assign,
+                    lhs: Box::new(expression(key)),
+                    rhs: Box::new(expression(value)),
+                })
+                .collect(),
+        )),
+
+        Expr::Unary(op, operand) => with_unary(expression(operand), unary_op(*op)),
+
+        Expr::Binary(op, lhs, rhs) => Expression::BinaryOp {
+            op: binary_op(*op),
+            lhs: Box::new(expression(lhs)),
+            rhs: Box::new(expression(rhs)),
+        },
+
+        Expr::Ternary(cond, lhs, rhs) => Expression::TernaryOp {
+            cond: Box::new(expression(cond)),
+            if_: Box::new(expression(lhs)),
+            else_: Box::new(expression(rhs)),
+        },
+
+        Expr::Assign(op, lhs, rhs) => Expression::AssignOp {
+            op: assign_op(*op),
+            lhs: Box::new(expression(lhs)),
+            rhs: Box::new(expression(rhs)),
+        },
+
+        Expr::Unknown => term(Term::Ident("__unknown".to_owned())),
+    }
+}
+
+fn case(case: &Case) -> ast::Case {
+    match case {
+        Case::Exact(expr) => ast::Case::Exact(expression(expr)),
+        Case::Range(min, max) => ast::Case::Range(expression(min), expression(max)),
+    }
+}
+
+fn statement(stmt: &Stmt) -> Statement {
+    match stmt {
+        // CRASH() is a statement of its own in the tree
+        Stmt::Expr(Expr::Call(Callee::Global(name), args)) if name == "CRASH" => {
+            Statement::Crash(args.first().map(expression))
+        }
+
+        Stmt::Expr(expr) => Statement::Expr(expression(expr)),
+        Stmt::Return(expr) => Statement::Return(expr.as_ref().map(expression)),
+
+        Stmt::If(cond, then, otherwise) => {
+            let mut arms = vec![(spanned(expression(cond)), block(then))];
+            let mut otherwise = otherwise;
+
+            // `else if` chains are one statement
+            while let [Stmt::If(cond, then, next)] = otherwise.as_slice() {
+                arms.push((spanned(expression(cond)), block(then)));
+                otherwise = next;
+            }
+
+            Statement::If {
+                arms,
+                else_arm: if otherwise.is_empty() {
+                    None
+                } else {
+                    Some(block(otherwise))
+                },
+            }
+        }
+
+        Stmt::While(cond, body) => Statement::While {
+            condition: expression(cond),
+            block: block(body),
+        },
+
+        Stmt::Spawn(delay, body) => Statement::Spawn {
+            delay: Some(expression(delay)),
+            block: block(body),
+        },
+
+        Stmt::Switch(input, arms, otherwise) => Statement::Switch {
+            input: Box::new(expression(input)),
+            cases: arms
+                .iter()
+                .map(|(cases, body)| (spanned(cases.iter().map(case).collect()), block(body)))
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+            default: if otherwise.is_empty() {
+                None
+            } else {
+                Some(block(otherwise))
+            },
+        },
+
+        Stmt::Break => Statement::Break(None),
+        Stmt::Continue => Statement::Continue(None),
+        Stmt::Goto(label) => Statement::Goto(label.clone()),
+        Stmt::Label(name) => Statement::Label {
+            name: name.clone(),
+            block: block(&[]),
+        },
+        Stmt::Del(expr) => Statement::Del(expression(expr)),
+        Stmt::Throw(expr) => Statement::Throw(expression(expr)),
+
+        // The tree has no comments, so these become calls like unknown instructions do
+        Stmt::Comment(text) => Statement::Expr(term(Term::Call(
+            "__asm".to_owned(),
+            vec![term(Term::String(text.clone()))],
+        ))),
+    }
+}
+
+pub(super) fn block(stmts: &[Stmt]) -> ast::Block {
+    stmts
+        .iter()
+        .map(|stmt| spanned(statement(stmt)))
+        .collect::<Vec<_>>()
+        .into_boxed_slice()
+}
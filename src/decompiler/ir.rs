@@ -0,0 +1,182 @@
+use crate::operands::DMString;
+
+// What the decompiler turns bytecode into, before it's written out as text
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    PreInc,
+    PostInc,
+    PreDec,
+    PostDec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    NotEq,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Equiv,
+    NotEquiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LShift,
+    RShift,
+    And,
+    Or,
+    In,
+    To,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum AssignOp {
+    Assign,
+    Into,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LShift,
+    RShift,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Callee {
+    /// A global proc or a built-in one
+    Global(String),
+
+    /// A proc on an object, `a.foo()`
+    Method(Box<Expr>, String),
+
+    /// `call(a)()`
+    Path(Box<Expr>),
+
+    /// `call(a, "name")()`
+    Name(Box<Expr>, Box<Expr>),
+
+    /// `..()`
+    Parent,
+
+    /// `.()`
+    This,
+
+    /// An instruction the decompiler doesn't have a better name for
+    Instruction(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    Null,
+    Int(i32),
+    Float(f32),
+    String(DMString),
+
+    /// An embedded expression goes wherever the string has a formatting marker
+    Format(DMString, Vec<Expr>),
+
+    Path(String),
+    Resource(String),
+
+    /// `src`, `usr`, `.`, locals and so on
+    Ident(String),
+
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Callee, Vec<Expr>),
+    New(Box<Expr>, Vec<Expr>),
+    List(Vec<Expr>),
+    AssocList(Vec<(Expr, Expr)>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(AssignOp, Box<Expr>, Box<Expr>),
+
+    /// A value the decompiler lost track of
+    Unknown,
+}
+
+impl Expr {
+    pub(super) fn has_side_effects(&self) -> bool {
+        match self {
+            Self::Null
+            | Self::Int(_)
+            | Self::Float(_)
+            | Self::String(_)
+            | Self::Path(_)
+            | Self::Resource(_)
+            | Self::Ident(_) => false,
+
+            Self::Call(..) | Self::New(..) | Self::Assign(..) | Self::Unknown => true,
+
+            Self::Unary(op, expr) => {
+                !matches!(op, UnaryOp::Neg | UnaryOp::Not | UnaryOp::BitNot)
+                    || expr.has_side_effects()
+            }
+
+            Self::Format(_, exprs) | Self::List(exprs) => {
+                exprs.iter().any(|expr| expr.has_side_effects())
+            }
+
+            Self::AssocList(pairs) => pairs
+                .iter()
+                .any(|(key, value)| key.has_side_effects() || value.has_side_effects()),
+
+            Self::Field(expr, _) => expr.has_side_effects(),
+
+            Self::Index(lhs, rhs) | Self::Binary(_, lhs, rhs) => {
+                lhs.has_side_effects() || rhs.has_side_effects()
+            }
+
+            Self::Ternary(cond, lhs, rhs) => {
+                cond.has_side_effects() || lhs.has_side_effects() || rhs.has_side_effects()
+            }
+        }
+    }
+}
+
+/// What a `switch` arm is compared against
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Case {
+    Exact(Expr),
+    Range(Expr, Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Stmt {
+    Expr(Expr),
+    Return(Option<Expr>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Spawn(Expr, Vec<Stmt>),
+
+    /// The input, each arm's cases and body, then the `else` body
+    Switch(Expr, Vec<(Vec<Case>, Vec<Stmt>)>, Vec<Stmt>),
+
+    Break,
+    Continue,
+    Goto(String),
+    Label(String),
+    Del(Expr),
+    Throw(Expr),
+
+    /// Anything that couldn't be decompiled, such as an instruction with an unknown effect on the stack
+    Comment(String),
+}
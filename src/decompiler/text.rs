@@ -0,0 +1,403 @@
+use std::fmt::{self, Write};
+
+use super::ir::{AssignOp, BinaryOp, Callee, Case, Expr, Stmt, UnaryOp};
+use crate::operands::{DMString, Operand};
+
+// Lets DMString's serializer be used with `write!`
+struct Quoted<'a>(&'a DMString);
+
+impl<'a> fmt::Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.serialize(f)
+    }
+}
+
+// How tightly an expression binds, following DM's operator table. Higher binds tighter.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign(..) => 0,
+        Expr::Ternary(..) => 1,
+        Expr::Binary(op, ..) => match op {
+            BinaryOp::In | BinaryOp::To => 2,
+            BinaryOp::Or => 3,
+            BinaryOp::And => 4,
+            BinaryOp::BitOr => 5,
+            BinaryOp::BitXor => 6,
+            BinaryOp::BitAnd => 7,
+            BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Equiv | BinaryOp::NotEquiv => 8,
+            BinaryOp::LShift | BinaryOp::RShift => 9,
+            BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEq | BinaryOp::GreaterEq => 10,
+            BinaryOp::Add | BinaryOp::Sub => 11,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 12,
+            BinaryOp::Pow => 13,
+        },
+        Expr::Unary(..) => 14,
+        _ => 15,
+    }
+}
+
+fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Pow => "**",
+        BinaryOp::Eq => "==",
+        BinaryOp::NotEq => "!=",
+        BinaryOp::Less => "<",
+        BinaryOp::Greater => ">",
+        BinaryOp::LessEq => "<=",
+        BinaryOp::GreaterEq => ">=",
+        BinaryOp::Equiv => "~=",
+        BinaryOp::NotEquiv => "~!",
+        BinaryOp::BitAnd => "&",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "^",
+        BinaryOp::LShift => "<<",
+        BinaryOp::RShift => ">>",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+        BinaryOp::In => "in",
+        BinaryOp::To => "to",
+    }
+}
+
+fn assign_op(op: AssignOp) -> &'static str {
+    match op {
+        AssignOp::Assign => "=",
+        AssignOp::Into => ":=",
+        AssignOp::Add => "+=",
+        AssignOp::Sub => "-=",
+        AssignOp::Mul => "*=",
+        AssignOp::Div => "/=",
+        AssignOp::Mod => "%=",
+        AssignOp::BitAnd => "&=",
+        AssignOp::BitOr => "|=",
+        AssignOp::BitXor => "^=",
+        AssignOp::LShift => "<<=",
+        AssignOp::RShift => ">>=",
+        AssignOp::And => "&&=",
+        AssignOp::Or => "||=",
+    }
+}
+
+// Writes the expression, wrapping it in parentheses if it binds looser than `min`
+fn write_operand(out: &mut String, expr: &Expr, min: u8) {
+    if precedence(expr) < min {
+        out.push('(');
+        write_expr(out, expr);
+        out.push(')');
+    } else {
+        write_expr(out, expr);
+    }
+}
+
+fn write_list(out: &mut String, exprs: &[Expr]) {
+    for (idx, expr) in exprs.iter().enumerate() {
+        if idx > 0 {
+            out.push_str(", ");
+        }
+
+        // Commas are lower than anything, so only assignments need wrapping to read clearly
+        write_operand(out, expr, 1);
+    }
+}
+
+fn write_format(out: &mut String, pattern: &DMString, args: &[Expr]) {
+    let text = Quoted(pattern).to_string();
+    let mut args = args.iter();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            }
+
+            '[' if chars.peek() == Some(&']') => {
+                chars.next();
+                out.push('[');
+                match args.next() {
+                    Some(arg) => write_expr(out, arg),
+                    None => out.push('?'),
+                }
+                out.push(']');
+            }
+
+            _ => out.push(c),
+        }
+    }
+}
+
+pub(super) fn write_expr(out: &mut String, expr: &Expr) {
+    let prec = precedence(expr);
+
+    match expr {
+        Expr::Null => out.push_str("null"),
+        Expr::Int(value) => write!(out, "{}", value).unwrap(),
+        Expr::Float(value) => write!(out, "{}", value).unwrap(),
+        Expr::String(value) => write!(out, "{}", Quoted(value)).unwrap(),
+        Expr::Format(pattern, args) => write_format(out, pattern, args),
+        Expr::Path(path) => out.push_str(path),
+        Expr::Resource(path) => write!(out, "'{}'", path).unwrap(),
+        Expr::Ident(name) => out.push_str(name),
+
+        Expr::Field(base, name) => {
+            write_operand(out, base, prec);
+            write!(out, ".{}", name).unwrap();
+        }
+
+        Expr::Index(base, index) => {
+            write_operand(out, base, prec);
+            out.push('[');
+            write_expr(out, index);
+            out.push(']');
+        }
+
+        Expr::Call(callee, args) => {
+            match callee {
+                Callee::Global(name) | Callee::Instruction(name) => out.push_str(name),
+                Callee::Method(base, name) => {
+                    write_operand(out, base, prec);
+                    write!(out, ".{}", name).unwrap();
+                }
+                Callee::Path(path) => {
+                    out.push_str("call(");
+                    write_expr(out, path);
+                    out.push(')');
+                }
+                Callee::Name(obj, name) => {
+                    out.push_str("call(");
+                    write_expr(out, obj);
+                    out.push_str(", ");
+                    write_expr(out, name);
+                    out.push(')');
+                }
+                Callee::Parent => out.push_str(".."),
+                Callee::This => out.push('.'),
+            }
+
+            out.push('(');
+            write_list(out, args);
+            out.push(')');
+        }
+
+        Expr::New(type_, args) => {
+            out.push_str("new ");
+            write_operand(out, type_, prec);
+            out.push('(');
+            write_list(out, args);
+            out.push(')');
+        }
+
+        Expr::List(items) => {
+            out.push_str("list(");
+            write_list(out, items);
+            out.push(')');
+        }
+
+        Expr::AssocList(pairs) => {
+            out.push_str("list(");
+            for (idx, (key, value)) in pairs.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                write_operand(out, key, 1);
+                out.push_str(" = ");
+                write_operand(out, value, 1);
+            }
+            out.push(')');
+        }
+
+        Expr::Unary(op, operand) => {
+            let (prefix, postfix) = match op {
+                UnaryOp::Neg => ("-", ""),
+                UnaryOp::Not => ("!", ""),
+                UnaryOp::BitNot => ("~", ""),
+                UnaryOp::PreInc => ("++", ""),
+                UnaryOp::PreDec => ("--", ""),
+                UnaryOp::PostInc => ("", "++"),
+                UnaryOp::PostDec => ("", "--"),
+            };
+
+            out.push_str(prefix);
+            write_operand(out, operand, prec);
+            out.push_str(postfix);
+        }
+
+        // Everything is left associative apart from `**`
+        Expr::Binary(op, lhs, rhs) => {
+            let (lhs_min, rhs_min) = match op {
+                BinaryOp::Pow => (prec + 1, prec),
+                _ => (prec, prec + 1),
+            };
+
+            write_operand(out, lhs, lhs_min);
+            write!(out, " {} ", binary_op(*op)).unwrap();
+            write_operand(out, rhs, rhs_min);
+        }
+
+        Expr::Ternary(cond, lhs, rhs) => {
+            write_operand(out, cond, prec + 1);
+            out.push_str(" ? ");
+            write_operand(out, lhs, prec + 1);
+            out.push_str(" : ");
+            write_operand(out, rhs, prec);
+        }
+
+        Expr::Assign(op, lhs, rhs) => {
+            write_operand(out, lhs, prec + 1);
+            write!(out, " {} ", assign_op(*op)).unwrap();
+            write_operand(out, rhs, prec);
+        }
+
+        Expr::Unknown => out.push_str("__unknown"),
+    }
+}
+
+fn write_indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push('\t');
+    }
+}
+
+fn write_block(out: &mut String, stmts: &[Stmt], depth: usize) {
+    if stmts.is_empty() {
+        out.push_str(" {}\n");
+        return;
+    }
+
+    out.push('\n');
+    write_stmts(out, stmts, depth + 1);
+}
+
+pub(super) fn write_stmts(out: &mut String, stmts: &[Stmt], depth: usize) {
+    for stmt in stmts {
+        // Labels sit one level out so they stand out from the code around them
+        match stmt {
+            Stmt::Label(name) => {
+                write_indent(out, depth.saturating_sub(1));
+                writeln!(out, "{}:", name).unwrap();
+                continue;
+            }
+
+            _ => write_indent(out, depth),
+        }
+
+        match stmt {
+            Stmt::Expr(expr) => {
+                write_expr(out, expr);
+                out.push('\n');
+            }
+
+            Stmt::Return(None) => out.push_str("return\n"),
+            Stmt::Return(Some(expr)) => {
+                out.push_str("return ");
+                write_expr(out, expr);
+                out.push('\n');
+            }
+
+            Stmt::If(cond, then, otherwise) => {
+                out.push_str("if (");
+                write_expr(out, cond);
+                out.push(')');
+                write_block(out, then, depth);
+
+                // `else if` chains stay flat
+                let mut otherwise = otherwise;
+                while !otherwise.is_empty() {
+                    write_indent(out, depth);
+                    out.push_str("else");
+
+                    match otherwise.as_slice() {
+                        [Stmt::If(cond, then, next)] => {
+                            out.push_str(" if (");
+                            write_expr(out, cond);
+                            out.push(')');
+                            write_block(out, then, depth);
+                            otherwise = next;
+                        }
+
+                        _ => {
+                            write_block(out, otherwise, depth);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            Stmt::While(cond, body) => {
+                out.push_str("while (");
+                write_expr(out, cond);
+                out.push(')');
+                write_block(out, body, depth);
+            }
+
+            Stmt::Spawn(delay, body) => {
+                out.push_str("spawn (");
+                write_expr(out, delay);
+                out.push(')');
+                write_block(out, body, depth);
+            }
+
+            Stmt::Switch(input, arms, otherwise) => {
+                out.push_str("switch (");
+                write_expr(out, input);
+                out.push_str(")\n");
+
+                for (cases, body) in arms {
+                    write_indent(out, depth + 1);
+                    out.push_str("if (");
+
+                    for (idx, case) in cases.iter().enumerate() {
+                        if idx > 0 {
+                            out.push_str(", ");
+                        }
+
+                        match case {
+                            Case::Exact(expr) => write_operand(out, expr, 1),
+                            Case::Range(min, max) => {
+                                write_operand(out, min, 3);
+                                out.push_str(" to ");
+                                write_operand(out, max, 3);
+                            }
+                        }
+                    }
+
+                    out.push(')');
+                    write_block(out, body, depth + 1);
+                }
+
+                if !otherwise.is_empty() {
+                    write_indent(out, depth + 1);
+                    out.push_str("else");
+                    write_block(out, otherwise, depth + 1);
+                }
+            }
+
+            Stmt::Break => out.push_str("break\n"),
+            Stmt::Continue => out.push_str("continue\n"),
+            Stmt::Goto(label) => writeln!(out, "goto {}", label).unwrap(),
+            Stmt::Label(_) => unreachable!(),
+
+            Stmt::Del(expr) => {
+                out.push_str("del ");
+                write_expr(out, expr);
+                out.push('\n');
+            }
+
+            Stmt::Throw(expr) => {
+                out.push_str("throw ");
+                write_expr(out, expr);
+                out.push('\n');
+            }
+
+            Stmt::Comment(text) => writeln!(out, "// {}", text).unwrap(),
+        }
+    }
+}
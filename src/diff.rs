@@ -0,0 +1,356 @@
+//! Comparing two versions of a proc, such as before and after a hot-patch. Jumps are compared by where they land
+//! rather than by label name, so renamed labels and shifted offsets don't show up as changes.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::disassembler::{disassemble, DisassembleEnv, DisassembleError};
+use crate::operands::Label;
+use crate::optimizer::{jump_destinations, jump_destinations_mut};
+use crate::Node;
+
+/// One instruction (or run of raw data) in the diff. The numbers are node indices into the old and new procs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Same {
+        old: usize,
+        new: usize,
+    },
+
+    Removed {
+        old: usize,
+        text: String,
+    },
+
+    Added {
+        new: usize,
+        text: String,
+    },
+
+    /// The same instruction with different operands, or jumping somewhere that doesn't line up
+    Changed {
+        old: usize,
+        new: usize,
+        old_text: String,
+        new_text: String,
+    },
+}
+
+/// Labels and comments aren't part of the diff, only what they mean for the jumps
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+}
+
+impl Diff {
+    /// Whether the procs do the same thing
+    pub fn is_empty(&self) -> bool {
+        self.changes
+            .iter()
+            .all(|change| matches!(change, Change::Same { .. }))
+    }
+}
+
+/// Only what changed, a line each for the old and new sides
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            match change {
+                Change::Same { .. } => {}
+                Change::Removed { old, text } => writeln!(f, "- {}: {}", old, text)?,
+                Change::Added { new, text } => writeln!(f, "+ {}: {}", new, text)?,
+                Change::Changed {
+                    old,
+                    new,
+                    old_text,
+                    new_text,
+                } => {
+                    writeln!(f, "- {}: {}", old, old_text)?;
+                    writeln!(f, "+ {}: {}", new, new_text)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Item {
+    node: usize,
+
+    // What gets compared: the node with its jumps' labels blanked out
+    key: String,
+    text: String,
+
+    // Which item each jump lands on, with the end of the proc being one past the last
+    targets: Vec<Option<usize>>,
+}
+
+fn items<D>(nodes: &[Node<D>]) -> Vec<Item> {
+    let mut items = vec![];
+    let mut labels = HashMap::new();
+
+    for (idx, node) in nodes.iter().enumerate() {
+        let key = match node {
+            Node::Label(name) => {
+                labels.insert(name.as_str(), items.len());
+                continue;
+            }
+
+            Node::Comment(_) => continue,
+
+            Node::Instruction(ins, _) => {
+                let mut masked = ins.clone();
+                for label in jump_destinations_mut(&mut masked) {
+                    *label = Label(String::new());
+                }
+
+                masked.to_string()
+            }
+
+            Node::RawData(..) => node.to_string().trim_end().to_owned(),
+        };
+
+        items.push(Item {
+            node: idx,
+            key,
+            text: node.to_string().trim_end().to_owned(),
+            targets: vec![],
+        });
+    }
+
+    for item in &mut items {
+        if let Node::Instruction(ins, _) = &nodes[item.node] {
+            item.targets = jump_destinations(ins)
+                .into_iter()
+                .map(|Label(name)| labels.get(name.as_str()).copied())
+                .collect();
+        }
+    }
+
+    items
+}
+
+// Pairs of indices of the longest run of matching keys the two lists have in common
+fn common_subsequence(old: &[Item], new: &[Item]) -> Vec<(usize, usize)> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old.key == new.key)
+        .count();
+
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old.key == new.key)
+        .count();
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    // lengths[i][j] is the length of the longest common subsequence of old_middle[i..] and new_middle[j..]
+    let width = new_middle.len() + 1;
+    let mut lengths = vec![0u32; (old_middle.len() + 1) * width];
+
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            lengths[i * width + j] = if old_middle[i].key == new_middle[j].key {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|idx| (idx, idx)).collect();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_middle.len() && j < new_middle.len() {
+        if old_middle[i].key == new_middle[j].key {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    pairs.extend((0..suffix).map(|idx| (old.len() - suffix + idx, new.len() - suffix + idx)));
+    pairs
+}
+
+/// Compares two procs
+pub fn diff<D, E>(old: &[Node<D>], new: &[Node<E>]) -> Diff {
+    let old = items(old);
+    let new = items(new);
+    let pairs = common_subsequence(&old, &new);
+
+    // Jumps line up if the first unchanged instruction from where they land is the same one on both sides
+    let mut old_landing = vec![new.len(); old.len() + 1];
+    let mut new_landing = vec![new.len(); new.len() + 1];
+    let matched_old: HashMap<usize, usize> = pairs.iter().copied().collect();
+    let matched_new: HashSet<usize> = pairs.iter().map(|(_, new)| *new).collect();
+
+    for idx in (0..old.len()).rev() {
+        old_landing[idx] = match matched_old.get(&idx) {
+            Some(new_idx) => *new_idx,
+            None => old_landing[idx + 1],
+        };
+    }
+
+    for idx in (0..new.len()).rev() {
+        new_landing[idx] = if matched_new.contains(&idx) {
+            idx
+        } else {
+            new_landing[idx + 1]
+        };
+    }
+
+    let mut changes = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    for (old_idx, new_idx) in pairs
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        changes.extend(old[i..old_idx].iter().map(|item| Change::Removed {
+            old: item.node,
+            text: item.text.clone(),
+        }));
+
+        changes.extend(new[j..new_idx].iter().map(|item| Change::Added {
+            new: item.node,
+            text: item.text.clone(),
+        }));
+
+        if old_idx == old.len() {
+            break;
+        }
+
+        let (old_item, new_item) = (&old[old_idx], &new[new_idx]);
+
+        let same_targets =
+            old_item
+                .targets
+                .iter()
+                .zip(&new_item.targets)
+                .all(|(old_target, new_target)| match (old_target, new_target) {
+                    (Some(old_target), Some(new_target)) => {
+                        old_landing[*old_target] == new_landing[*new_target]
+                    }
+                    (None, None) => true,
+                    _ => false,
+                });
+
+        changes.push(if same_targets {
+            Change::Same {
+                old: old_item.node,
+                new: new_item.node,
+            }
+        } else {
+            Change::Changed {
+                old: old_item.node,
+                new: new_item.node,
+                old_text: old_item.text.clone(),
+                new_text: new_item.text.clone(),
+            }
+        });
+
+        i = old_idx + 1;
+        j = new_idx + 1;
+    }
+
+    Diff { changes }
+}
+
+/// Disassembles and compares two procs' bytecode
+pub fn diff_bytecode<E: DisassembleEnv>(
+    old: &[u32],
+    new: &[u32],
+    env: &mut E,
+) -> Result<Diff, DisassembleError> {
+    let (old, err) = disassemble(old, &mut *env);
+    if let Some(err) = err {
+        return Err(err);
+    }
+    let old: Vec<Node> = old.into_iter().map(Node::strip_debug_data).collect();
+
+    let (new, err) = disassemble(new, &mut *env);
+    if let Some(err) = err {
+        return Err(err);
+    }
+
+    Ok(diff(&old, &new))
+}
+
+#[test]
+fn differences() {
+    let old = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Jz LAB_0000
+PushInt 1
+Ret
+LAB_0000:
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    // Labels renamed, a check added at the start, and a different value returned
+    let new = crate::parser::parse(
+        r#"
+GetVar arg(1)
+Pop
+GetVar arg(0)
+Jz skip
+PushInt 1
+Ret
+skip:
+PushInt 3
+Ret
+"#,
+    )
+    .unwrap();
+
+    let changes = diff(&old, &new);
+    assert!(!changes.is_empty());
+    assert_eq!(
+        changes.to_string(),
+        "+ 0: GetVar arg(1)\n+ 1: Pop\n- 5: PushInt 2\n+ 7: PushInt 3\n"
+    );
+
+    // A jump that lands somewhere else
+    let moved = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Jz LAB_0000
+PushInt 1
+LAB_0000:
+Ret
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        diff(&old, &moved).changes[1],
+        Change::Changed {
+            old: 1,
+            new: 1,
+            old_text: "Jz LAB_0000".to_owned(),
+            new_text: "Jz LAB_0000".to_owned(),
+        }
+    );
+
+    let bytecode = crate::assembler::assemble(&old, &mut crate::TestAssembleEnv).unwrap();
+    let mut env = crate::TestDisassembleEnv;
+    assert!(diff_bytecode(&bytecode, &bytecode, &mut env)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,358 @@
+// `.equ` and `.macro` support for hand-written assembly. These are expanded away before the text reaches the parser.
+//
+//  .equ MAX_HEALTH 100
+//
+//  .macro clamp_health target
+//      GetVar target
+//      PushInt MAX_HEALTH
+//      Tg
+//      Jz skip_\@
+//      PushInt MAX_HEALTH
+//      SetVar target
+//  skip_\@:
+//  .endm
+//
+//  clamp_health local(0)
+//
+// Macro parameters and constants are replaced wherever they appear as a whole word outside of a string.
+// `\@` is replaced with a number unique to each expansion, so labels in a macro don't clash.
+
+use std::collections::HashMap;
+
+// Deep enough for any sane use, shallow enough to catch a macro that expands to itself
+const MAX_EXPANSION_DEPTH: u32 = 64;
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+struct Expander {
+    constants: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    expansion_count: u32,
+    out: String,
+    lines: Vec<SourceLine>,
+}
+
+// Where a line of the expanded text came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SourceLine {
+    line: usize,
+    in_macro: bool,
+}
+
+/// The text with every directive expanded, along with where each of its lines was in the original
+pub(crate) struct Expanded {
+    pub text: String,
+    lines: Vec<SourceLine>,
+}
+
+impl Expanded {
+    /// Points the line numbers in an error from parsing the expanded text back at the original
+    pub fn map_error(&self, err: &str) -> String {
+        const PREFIX: &str = "at line ";
+
+        let mut out = String::with_capacity(err.len());
+        let mut rest = err;
+
+        while let Some(idx) = rest.find(PREFIX) {
+            let (before, after) = rest.split_at(idx + PREFIX.len());
+            out.push_str(before);
+
+            let digits = after.find(|c: char| !c.is_ascii_digit()).unwrap_or(after.len());
+            let source = after[..digits]
+                .parse::<usize>()
+                .ok()
+                .and_then(|line| self.lines.get(line.checked_sub(1)?));
+
+            match source {
+                Some(source) if source.in_macro => {
+                    out.push_str(&format!("{} (in a macro expanded there)", source.line))
+                }
+                Some(source) => out.push_str(&source.line.to_string()),
+                None => out.push_str(&after[..digits]),
+            }
+
+            rest = &after[digits..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Splits off a leading identifier
+fn split_identifier(text: &str) -> Option<(&str, &str)> {
+    if !text.starts_with(is_identifier_start) {
+        return None;
+    }
+
+    let end = text.find(|c| !is_identifier_char(c)).unwrap_or(text.len());
+    Some(text.split_at(end))
+}
+
+// Replaces whole words outside of strings (and comments) with whatever `lookup` gives back
+fn replace_words<'a>(line: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    let mut in_string = false;
+
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            match c {
+                '\\' => {
+                    let len = rest.chars().take(2).map(char::len_utf8).sum();
+                    out.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ';' {
+            out.push_str(rest);
+            break;
+        } else if let Some((word, after)) = split_identifier(rest) {
+            out.push_str(lookup(word).unwrap_or(word));
+            rest = after;
+            continue;
+        } else if is_identifier_char(c) {
+            // The middle of something like `0x10`, which isn't a word of its own
+            let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+// Splits macro arguments on commas that aren't in a string
+fn split_args(text: &str) -> Vec<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return vec![];
+    }
+
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                args.push(current.trim().to_owned());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    args.push(current.trim().to_owned());
+    args
+}
+
+impl Expander {
+    fn define_constant(&mut self, line_number: usize, definition: &str) -> Result<(), String> {
+        let (name, value) = split_identifier(definition.trim())
+            .ok_or_else(|| format!("line {}: expected a name after .equ", line_number))?;
+
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("line {}: .equ {} has no value", line_number, name));
+        }
+
+        let value = replace_words(value, |word| self.constants.get(word).map(String::as_str));
+        self.constants.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    // A line that starts with the name of a macro (and isn't a label with the same name)
+    fn invocation<'b>(&self, line: &'b str) -> Option<(Macro, &'b str)> {
+        let (name, rest) = split_identifier(line.trim_start())?;
+
+        if rest.starts_with(':') {
+            return None;
+        }
+
+        self.macros.get(name).map(|mac| (mac.clone(), rest))
+    }
+
+    fn emit_line(&mut self, line_number: usize, line: &str, depth: u32) -> Result<(), String> {
+        let (mac, args) = match self.invocation(line) {
+            Some(invocation) => invocation,
+            None => {
+                let line = replace_words(line, |word| self.constants.get(word).map(String::as_str));
+                self.out.push_str(&line);
+                self.out.push('\n');
+                self.lines.push(SourceLine {
+                    line: line_number,
+                    in_macro: depth > 0,
+                });
+                return Ok(());
+            }
+        };
+
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(format!("line {}: macros nested too deeply", line_number));
+        }
+
+        let args = split_args(args);
+        if args.len() != mac.params.len() {
+            return Err(format!(
+                "line {}: macro expects {} argument(s), got {}",
+                line_number,
+                mac.params.len(),
+                args.len()
+            ));
+        }
+
+        self.expansion_count += 1;
+        let unique = self.expansion_count.to_string();
+
+        for body_line in &mac.body {
+            let body_line = body_line.replace("\\@", &unique);
+            let body_line = replace_words(&body_line, |word| {
+                mac.params
+                    .iter()
+                    .position(|param| param == word)
+                    .map(|idx| args[idx].as_str())
+            });
+
+            self.emit_line(line_number, &body_line, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Expands every `.equ` and `.macro` in the text
+pub(crate) fn expand(text: &str) -> Result<Expanded, String> {
+    let mut expander = Expander {
+        constants: HashMap::new(),
+        macros: HashMap::new(),
+        expansion_count: 0,
+        out: String::with_capacity(text.len()),
+        lines: vec![],
+    };
+
+    let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+
+    while let Some((line_number, line)) = lines.next() {
+        let trimmed = line.trim();
+
+        if let Some(definition) = trimmed.strip_prefix(".equ ") {
+            expander.define_constant(line_number, definition)?;
+            continue;
+        }
+
+        if let Some(header) = trimmed.strip_prefix(".macro ") {
+            let (name, params) = split_identifier(header.trim())
+                .ok_or_else(|| format!("line {}: expected a name after .macro", line_number))?;
+
+            let mut body = vec![];
+
+            loop {
+                let line = match lines.next() {
+                    Some((_, line)) => line,
+                    None => return Err(format!("line {}: .macro {} has no .endm", line_number, name)),
+                };
+
+                match line.trim() {
+                    ".endm" => break,
+                    inner if inner.starts_with(".macro ") => {
+                        return Err(format!("line {}: macros can't be defined inside a macro", line_number))
+                    }
+                    _ => body.push(line.to_owned()),
+                }
+            }
+
+            let mac = Macro {
+                params: split_args(params),
+                body,
+            };
+
+            expander.macros.insert(name.to_owned(), mac);
+            continue;
+        }
+
+        if trimmed == ".endm" {
+            return Err(format!("line {}: .endm without .macro", line_number));
+        }
+
+        expander.emit_line(line_number, line, 0)?;
+    }
+
+    Ok(Expanded {
+        text: expander.out,
+        lines: expander.lines,
+    })
+}
+
+#[test]
+fn constants() {
+    assert_eq!(
+        expand(".equ MAX 100\n.equ DOUBLE_MAX MAX\nPushInt MAX\nPushInt DOUBLE_MAX\nPushVal \"MAX\" ; MAX\n").unwrap().text,
+        "PushInt 100\nPushInt 100\nPushVal \"MAX\" ; MAX\n"
+    );
+}
+
+#[test]
+fn macros() {
+    let text = r#"
+.macro guard var, value
+GetVar var
+Jz skip_\@
+PushVal value
+skip_\@:
+.endm
+guard arg(0), "a, b"
+guard local(1), 5
+"#;
+
+    assert_eq!(
+        expand(text).unwrap().text,
+        "\nGetVar arg(0)\nJz skip_1\nPushVal \"a, b\"\nskip_1:\nGetVar local(1)\nJz skip_2\nPushVal 5\nskip_2:\n"
+    );
+
+    assert!(expand(".macro oops\nPushInt 1\n").is_err());
+    assert!(expand(".macro one a\nPushInt a\n.endm\none\n").is_err());
+    assert!(expand(".macro forever\nforever\n.endm\nforever\n").is_err());
+}
+
+#[test]
+fn error_lines() {
+    // The broken line is the fourth in the expanded text, but the eighth in what was written
+    let text = ".equ MAX 100\n.macro twice value\nPushInt value\nPushInt value\n.endm\ntwice MAX\nPushInt 3\nNotAnInstruction MAX\n";
+    let err = crate::assembler::parse(text).unwrap_err();
+    assert!(err.contains("at line 8"), "{}", err);
+
+    let expanded = expand(".macro twice value\nPushInt value\nPushInt value\n.endm\nPushInt 1\ntwice 2\n").unwrap();
+    assert_eq!(expanded.text, "PushInt 1\nPushInt 2\nPushInt 2\n");
+    assert_eq!(expanded.map_error("0: at line 1:\n"), "0: at line 5:\n");
+    assert_eq!(expanded.map_error("0: at line 3:\n"), "0: at line 6 (in a macro expanded there):\n");
+    assert_eq!(expanded.map_error("0: at line 7:\n"), "0: at line 7:\n");
+}
@@ -1,13 +1,47 @@
 use crate::Instruction;
 use crate::Node;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[cfg(feature = "json")]
+mod json;
+
+#[cfg(feature = "json")]
+pub use json::to_json;
 
 pub trait DisassembleEnv {
     fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>>;
     fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>>;
     fn get_proc_name(&mut self, index: u32) -> Option<String>;
     fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>>;
+
+    /// The name the local in this slot had in the source, such as from the proc's debug info. Locals with one
+    /// disassemble to `Variable::NamedLocal`.
+    fn get_local_name(&mut self, _slot: u32) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl<E: DisassembleEnv + ?Sized> DisassembleEnv for &mut E {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        (**self).get_string_data(index)
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        (**self).get_variable_name(index)
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        (**self).get_proc_name(index)
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        (**self).value_to_string_data(tag, data)
+    }
+
+    fn get_local_name(&mut self, slot: u32) -> Option<Vec<u8>> {
+        (**self).get_local_name(slot)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -24,20 +58,126 @@ pub enum DisassembleError {
     UnknownIsInOperand { offset: u32, value: u32 },
     UnknownValue { offset: u32, tag: u32 },
     UnknownTypeFilter { offset: u32, value: u32 },
+    // Jumped into the middle of an instruction that was already disassembled
+    OverlappingInstruction { offset: u32 },
     Todo,
 }
 
+impl DisassembleError {
+    // For errors from disassembling part of the bytecode on its own
+    fn shifted(self, by: u32) -> Self {
+        match self {
+            Self::UnknownOpcode { offset, opcode } => Self::UnknownOpcode {
+                offset: offset + by,
+                opcode,
+            },
+            Self::InvalidOffset { offset, dst } => Self::InvalidOffset {
+                offset: offset + by,
+                dst,
+            },
+            Self::InvalidString { offset, id } => Self::InvalidString {
+                offset: offset + by,
+                id,
+            },
+            Self::InvalidVariableName { offset, id } => Self::InvalidVariableName {
+                offset: offset + by,
+                id,
+            },
+            Self::InvalidProc { offset, id } => Self::InvalidProc {
+                offset: offset + by,
+                id,
+            },
+            Self::UnknownAccessModifier { offset, value } => Self::UnknownAccessModifier {
+                offset: offset + by,
+                value,
+            },
+            Self::UnknownFieldAccessModifier { offset, value } => {
+                Self::UnknownFieldAccessModifier {
+                    offset: offset + by,
+                    value,
+                }
+            }
+            Self::UnknownRangeParams { offset, value } => Self::UnknownRangeParams {
+                offset: offset + by,
+                value,
+            },
+            Self::UnknownIsInOperand { offset, value } => Self::UnknownIsInOperand {
+                offset: offset + by,
+                value,
+            },
+            Self::UnknownValue { offset, tag } => Self::UnknownValue {
+                offset: offset + by,
+                tag,
+            },
+            Self::UnknownTypeFilter { offset, value } => Self::UnknownTypeFilter {
+                offset: offset + by,
+                value,
+            },
+            Self::OverlappingInstruction { offset } => Self::OverlappingInstruction {
+                offset: offset + by,
+            },
+            Self::UnexpectedEnd | Self::Todo => self,
+        }
+    }
+}
+
+// The bytecode is borrowed, so this can be written out but not read back in
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct DebugData<'a> {
     pub offset: u32,
     pub bytecode: &'a [u32],
 }
 
+/// Everything that changes how bytecode gets disassembled. Start from `DisassembleOptions::new()` and chain the setters.
+#[derive(Clone, Debug, Default)]
+pub struct DisassembleOptions {
+    /// The BYOND version (such as 514) the bytecode is from. Opcodes for instructions added after it are unknown,
+    /// so a dump from an older server can't be mistaken for newer instructions. `None` allows everything.
+    pub version: Option<u32>,
+
+    /// Names labels by what they look like they're for rather than by offset, see `labels::name_labels`
+    pub name_labels: bool,
+
+    /// How many words of operands follow each opcode that should become an `Instruction::Unknown` rather than
+    /// stopping disassembly. Only used for opcodes that aren't instructions, or are too new for `version`.
+    pub unknown_operands: HashMap<u32, u32>,
+}
+
+impl DisassembleOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn name_labels(mut self, name_labels: bool) -> Self {
+        self.name_labels = name_labels;
+        self
+    }
+
+    pub fn unknown_opcode(mut self, opcode: u32, operand_count: u32) -> Self {
+        self.unknown_operands.insert(opcode, operand_count);
+        self
+    }
+}
+
 pub fn disassemble<'a, E: DisassembleEnv>(
     bytecode: &'a [u32],
     env: &'a mut E,
 ) -> (Vec<Node<DebugData<'a>>>, Option<DisassembleError>) {
-    let mut state = Disassembler::new(bytecode, env);
+    disassemble_with_options(bytecode, env, &DisassembleOptions::new())
+}
+
+pub fn disassemble_with_options<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    options: &DisassembleOptions,
+) -> (Vec<Node<DebugData<'a>>>, Option<DisassembleError>) {
+    let mut state = Disassembler::new(bytecode, env, options);
     let mut instructions = vec![];
     let mut err = None;
 
@@ -59,33 +199,521 @@ pub fn disassemble<'a, E: DisassembleEnv>(
         }
     }
 
+    let destinations: HashSet<u32> = state.indirection_destinations.into_iter().collect();
+    let mut nodes = vec![];
+
+    for (ins, dbg) in instructions {
+        if destinations.contains(&dbg.offset) {
+            nodes.push(Node::Label(format!("LAB_{:0>4X}", dbg.offset)));
+        }
+
+        nodes.push(Node::Instruction(ins, dbg));
+    }
+
+    (finish(nodes, options), err)
+}
+
+/// Disassembles one instruction at a time as the iterator is advanced, for going through a lot of bytecode without
+/// keeping all of it around. Nothing is known about jumps that haven't been reached yet, so there are no
+/// `Node::Label`s: jumps are to `LAB_` followed by the offset in hex, which can be matched against `DebugData`.
+///
+/// The iterator ends after the first error.
+pub fn disassemble_iter<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+) -> Disassembler<'a, E> {
+    disassemble_iter_with_options(bytecode, env, &DisassembleOptions::new())
+}
+
+pub fn disassemble_iter_with_options<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    options: &DisassembleOptions,
+) -> Disassembler<'a, E> {
+    Disassembler::new(bytecode, env, options)
+}
+
+/// Disassembles only the code around `pc`, such as where a runtime error happened, rather than the whole proc.
+/// `pc` has to be the start of an instruction. Disassembly starts as far back as it can, up to `before` words, from
+/// somewhere that lines up with `pc`, and goes on to at least `after` words past it.
+///
+/// Jumps to outside the window have no labels to go with them.
+pub fn disassemble_around<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    pc: u32,
+    before: u32,
+    after: u32,
+) -> (Vec<Node<DebugData<'a>>>, Option<DisassembleError>) {
+    disassemble_around_with_options(bytecode, env, pc, before, after, &DisassembleOptions::new())
+}
+
+pub fn disassemble_around_with_options<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    pc: u32,
+    before: u32,
+    after: u32,
+    options: &DisassembleOptions,
+) -> (Vec<Node<DebugData<'a>>>, Option<DisassembleError>) {
+    let mut state = Disassembler::new(bytecode, env, options);
+    let end = pc.saturating_add(after).min(bytecode.len() as u32);
+    let mut instructions = vec![];
+    let mut err = None;
+
+    // The earliest start that runs into `pc` rather than over it. Nothing can go wrong starting at `pc` itself.
+    let start = (pc.saturating_sub(before)..pc)
+        .find(|start| {
+            state.current_offset = *start;
+
+            let mut ok = true;
+            while ok && state.current_offset < pc {
+                ok = Instruction::disassemble(&mut state).is_ok();
+            }
+
+            ok && state.current_offset == pc
+        })
+        .unwrap_or(pc);
+
+    // Only jumps from the instructions that are kept count
+    state.indirection_destinations.clear();
+    state.current_offset = start;
+
+    while state.current_offset < end {
+        match Instruction::disassemble(&mut state) {
+            Ok(disassembled) => instructions.push(disassembled),
+            Err(e) => {
+                err = Some(e);
+                break;
+            }
+        }
+    }
+
+    let destinations: HashSet<u32> = state.indirection_destinations.into_iter().collect();
     let mut nodes = vec![];
 
     for (ins, dbg) in instructions {
-        if state.indirection_destinations.contains(&dbg.offset) {
+        if destinations.contains(&dbg.offset) {
             nodes.push(Node::Label(format!("LAB_{:0>4X}", dbg.offset)));
         }
 
         nodes.push(Node::Instruction(ins, dbg));
     }
 
-    (nodes, err)
+    (finish(nodes, options), err)
+}
+
+/// Like `disassemble`, but keeps going when something can't be disassembled, such as an opcode from a newer
+/// version of BYOND. Whatever can't be understood becomes `Node::RawData`.
+///
+/// After an error, disassembly carries on from the next offset something is known to jump to, or the next word if
+/// there isn't one. Anything jumped to from later on that ended up in `RawData` gets another go at the end.
+/// Each error is the first one of its `RawData`.
+pub fn disassemble_tolerant<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+) -> (Vec<Node<DebugData<'a>>>, Vec<DisassembleError>) {
+    disassemble_tolerant_with_options(bytecode, env, &DisassembleOptions::new())
+}
+
+pub fn disassemble_tolerant_with_options<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    options: &DisassembleOptions,
+) -> (Vec<Node<DebugData<'a>>>, Vec<DisassembleError>) {
+    let mut state = Disassembler::new(bytecode, env, options);
+    let mut disassembled = vec![];
+    let mut errors = vec![];
+
+    sweep(
+        &mut state,
+        0,
+        bytecode.len() as u32,
+        &mut disassembled,
+        &mut errors,
+    );
+
+    let mut retried = HashSet::new();
+
+    loop {
+        let found = state
+            .indirection_destinations
+            .iter()
+            .filter(|destination| !retried.contains(*destination))
+            .find_map(|destination| {
+                disassembled
+                    .iter()
+                    .position(|(start, node)| match node {
+                        Node::RawData(words, _) => {
+                            *start < *destination && *destination < start + words.len() as u32
+                        }
+                        _ => false,
+                    })
+                    .map(|idx| (idx, *destination))
+            });
+
+        let (idx, destination) = match found {
+            Some(found) => found,
+            None => break,
+        };
+
+        retried.insert(destination);
+
+        let (start, end) = match &disassembled[idx] {
+            (start, Node::RawData(words, _)) => (*start, start + words.len() as u32),
+            _ => unreachable!(),
+        };
+
+        let mut replacement = vec![(start, raw_data(bytecode, start, destination))];
+        sweep(&mut state, destination, end, &mut replacement, &mut errors);
+        disassembled.splice(idx..=idx, replacement);
+    }
+
+    let destinations: HashSet<u32> = state.indirection_destinations.into_iter().collect();
+    let mut nodes = vec![];
+
+    for (offset, node) in disassembled {
+        if destinations.contains(&offset) {
+            nodes.push(Node::Label(format!("LAB_{:0>4X}", offset)));
+        }
+
+        nodes.push(node);
+    }
+
+    (finish(nodes, options), errors)
+}
+
+/// Disassembles by following control flow from the start of the proc rather than going through it word by word,
+/// so data or padding between pieces of code can't knock the rest out of alignment. Anything execution never
+/// reaches becomes `Node::RawData`.
+///
+/// Each path stops at the first thing it can't disassemble, and the errors are in the order they were found.
+pub fn disassemble_recursive<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+) -> (Vec<Node<DebugData<'a>>>, Vec<DisassembleError>) {
+    disassemble_recursive_with_options(bytecode, env, &DisassembleOptions::new())
+}
+
+pub fn disassemble_recursive_with_options<'a, E: DisassembleEnv>(
+    bytecode: &'a [u32],
+    env: &'a mut E,
+    options: &DisassembleOptions,
+) -> (Vec<Node<DebugData<'a>>>, Vec<DisassembleError>) {
+    let mut state = Disassembler::new(bytecode, env, options);
+    let mut instructions = BTreeMap::new();
+    let mut errors = vec![];
+    let mut visited = HashSet::new();
+    let mut pending = vec![0];
+
+    while let Some(offset) = pending.pop() {
+        if offset as usize >= bytecode.len() || !visited.insert(offset) {
+            continue;
+        }
+
+        state.current_offset = offset;
+
+        // Jumps in a broken instruction can't be trusted
+        let destinations = state.indirection_destinations.len();
+
+        match Instruction::disassemble(&mut state) {
+            Ok((ins, dbg)) => {
+                // Pushed last so the straight-line code is followed first
+                pending.extend(state.indirection_destinations[destinations..].iter().rev());
+
+                if crate::cfg::falls_through(&ins) {
+                    pending.push(state.current_offset);
+                }
+
+                instructions.insert(offset, (ins, dbg));
+            }
+
+            Err(err) => {
+                state.indirection_destinations.truncate(destinations);
+                errors.push(err);
+            }
+        }
+    }
+
+    let destinations: HashSet<u32> = state.indirection_destinations.into_iter().collect();
+    let mut disassembled = vec![];
+    let mut end = 0;
+
+    for (offset, (ins, dbg)) in instructions {
+        if offset < end {
+            errors.push(DisassembleError::OverlappingInstruction { offset });
+            continue;
+        }
+
+        unreached(bytecode, end, offset, &destinations, &mut disassembled);
+        end = offset + dbg.bytecode.len() as u32;
+        disassembled.push((offset, Node::Instruction(ins, dbg)));
+    }
+
+    unreached(
+        bytecode,
+        end,
+        bytecode.len() as u32,
+        &destinations,
+        &mut disassembled,
+    );
+
+    let mut nodes = vec![];
+
+    for (offset, node) in disassembled {
+        if destinations.contains(&offset) {
+            nodes.push(Node::Label(format!("LAB_{:0>4X}", offset)));
+        }
+
+        nodes.push(node);
+    }
+
+    (finish(nodes, options), errors)
+}
+
+// Raw data for the words from `start` to `end`, split wherever something jumps so each destination gets its label
+fn unreached<'a>(
+    bytecode: &'a [u32],
+    start: u32,
+    end: u32,
+    destinations: &HashSet<u32>,
+    disassembled: &mut Vec<(u32, Node<DebugData<'a>>)>,
+) {
+    let mut splits: Vec<u32> = destinations
+        .iter()
+        .copied()
+        .filter(|destination| *destination > start && *destination < end)
+        .collect();
+    splits.sort_unstable();
+
+    let mut start = start;
+    for split in splits.into_iter().chain(std::iter::once(end)) {
+        if split > start {
+            disassembled.push((start, raw_data(bytecode, start, split)));
+            start = split;
+        }
+    }
+}
+
+fn finish<'a>(
+    nodes: Vec<Node<DebugData<'a>>>,
+    options: &DisassembleOptions,
+) -> Vec<Node<DebugData<'a>>> {
+    if options.name_labels {
+        crate::labels::name_labels(nodes)
+    } else {
+        nodes
+    }
+}
+
+fn raw_data(bytecode: &[u32], start: u32, end: u32) -> Node<DebugData<'_>> {
+    let words = &bytecode[start as usize..end as usize];
+    Node::RawData(
+        words.to_vec(),
+        DebugData {
+            offset: start,
+            bytecode: words,
+        },
+    )
+}
+
+// Disassembles from `start` up to `end`, where something else already begins
+fn sweep<'a, E: DisassembleEnv>(
+    state: &mut Disassembler<'a, E>,
+    start: u32,
+    end: u32,
+    disassembled: &mut Vec<(u32, Node<DebugData<'a>>)>,
+    errors: &mut Vec<DisassembleError>,
+) {
+    let bytecode = state.bytecode;
+    state.current_offset = start;
+
+    // Where the words that haven't disassembled yet start
+    let mut raw_start = None;
+
+    while state.current_offset < end {
+        let offset = state.current_offset;
+
+        // Jumps in a broken instruction can't be trusted
+        let destinations = state.indirection_destinations.len();
+
+        match Instruction::disassemble(state) {
+            // Running into whatever comes next means this wasn't an instruction after all
+            Ok((ins, dbg)) if state.current_offset <= end => {
+                if let Some(start) = raw_start.take() {
+                    disassembled.push((start, raw_data(bytecode, start, offset)));
+                }
+
+                disassembled.push((offset, Node::Instruction(ins, dbg)));
+                continue;
+            }
+
+            result => {
+                if raw_start.is_none() {
+                    raw_start = Some(offset);
+                    errors.push(result.err().unwrap_or(DisassembleError::UnexpectedEnd));
+                }
+            }
+        }
+
+        state.indirection_destinations.truncate(destinations);
+        state.current_offset = state
+            .indirection_destinations
+            .iter()
+            .copied()
+            .filter(|destination| *destination > offset && *destination < end)
+            .min()
+            .unwrap_or(offset + 1);
+    }
+
+    if let Some(start) = raw_start {
+        disassembled.push((start, raw_data(bytecode, start, end)));
+    }
+}
+
+/// An env that doesn't look anything up, for when only the shape of the instructions matters. Every string, name,
+/// proc and type comes out as a placeholder holding its id, which `resolve` later swaps for the real thing:
+///
+/// - strings and variable names are `#id`
+/// - procs are `/#id`
+/// - values such as types are `/#tag:data`
+///
+/// Nothing is ever missing, so disassembling with this only fails on bytecode that's actually broken.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unresolved;
+
+impl DisassembleEnv for Unresolved {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        Some(format!("#{}", index).into_bytes())
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        Some(format!("#{}", index).into_bytes())
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        Some(format!("/#{}", index))
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        Some(format!("/#{}:{}", tag, data).into_bytes())
+    }
+}
+
+/// Looks up everything in instructions disassembled with `Unresolved`, by disassembling each of them again with a
+/// real env. Only the nodes passed in are looked at, so a tool can pick out what it needs first.
+pub fn resolve<'a, E: DisassembleEnv>(
+    nodes: Vec<Node<DebugData<'a>>>,
+    env: &mut E,
+) -> Result<Vec<Node<DebugData<'a>>>, DisassembleError> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Instruction(_, debug) => {
+                let mut state =
+                    Disassembler::new(debug.bytecode, &mut *env, &DisassembleOptions::new());
+                let (ins, _) = Instruction::disassemble(&mut state)
+                    .map_err(|err| err.shifted(debug.offset))?;
+                Ok(Node::Instruction(ins, debug))
+            }
+
+            other => Ok(other),
+        })
+        .collect()
+}
+
+// Remembers what each lookup made through it turned up
+struct Recorder<'e, E> {
+    env: &'e mut E,
+    lookups: Vec<String>,
+}
+
+impl<'e, E> Recorder<'e, E> {
+    fn record<T>(
+        &mut self,
+        id: String,
+        found: Option<T>,
+        show: impl Fn(&T) -> String,
+    ) -> Option<T> {
+        let text = format!(
+            "{} = {}",
+            id,
+            found.as_ref().map_or_else(|| "?".to_owned(), show)
+        );
+
+        if !self.lookups.contains(&text) {
+            self.lookups.push(text);
+        }
+
+        found
+    }
+}
+
+impl<'e, E: DisassembleEnv> DisassembleEnv for Recorder<'e, E> {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        let found = self.env.get_string_data(index);
+        self.record(index.to_string(), found, |data| {
+            crate::operands::Serialized(&crate::operands::DMString(data.clone())).to_string()
+        })
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        let found = self.env.get_variable_name(index);
+        self.record(index.to_string(), found, |name| {
+            String::from_utf8_lossy(name).into_owned()
+        })
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        let found = self.env.get_proc_name(index);
+        self.record(index.to_string(), found, String::clone)
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        let found = self.env.value_to_string_data(tag, data);
+        self.record(format!("{:X}:{:X}", tag, data), found, |name| {
+            String::from_utf8_lossy(name).into_owned()
+        })
+    }
+
+    // Slots aren't ids into a table, so there's nothing to record
+    fn get_local_name(&mut self, slot: u32) -> Option<Vec<u8>> {
+        self.env.get_local_name(slot)
+    }
+}
+
+/// What each id in an instruction's bytecode turns out to be in the env, such as `12 = "hello"` or
+/// `3 = /proc/f`, with `?` for ones it doesn't have. Found by disassembling the instruction again.
+pub(crate) fn resolutions<E: DisassembleEnv>(bytecode: &[u32], env: &mut E) -> Vec<String> {
+    let mut recorder = Recorder {
+        env,
+        lookups: vec![],
+    };
+
+    let mut state = Disassembler::new(bytecode, &mut recorder, &DisassembleOptions::new());
+    let _ = Instruction::disassemble(&mut state);
+    recorder.lookups
 }
 
 pub struct Disassembler<'a, E: DisassembleEnv> {
     pub bytecode: &'a [u32],
     pub current_offset: u32,
-    indirection_destinations: HashSet<u32>,
+    // In the order they were found, with duplicates
+    indirection_destinations: Vec<u32>,
     pub env: &'a mut E,
+    pub(crate) version: Option<u32>,
+    pub(crate) unknown_operands: HashMap<u32, u32>,
 }
 
 impl<'a, E: DisassembleEnv> Disassembler<'a, E> {
-    fn new(bytecode: &'a [u32], env: &'a mut E) -> Self {
+    fn new(bytecode: &'a [u32], env: &'a mut E, options: &DisassembleOptions) -> Self {
         Self {
             bytecode,
             current_offset: 0,
-            indirection_destinations: HashSet::new(),
+            indirection_destinations: vec![],
             env,
+            version: options.version,
+            unknown_operands: options.unknown_operands.clone(),
         }
     }
 
@@ -112,6 +740,482 @@ impl<'a, E: DisassembleEnv> Disassembler<'a, E> {
     }
 
     pub fn reserve_destination(&mut self, offset: u32) {
-        self.indirection_destinations.insert(offset);
+        self.indirection_destinations.push(offset);
+    }
+}
+
+impl<'a, E: DisassembleEnv> Iterator for Disassembler<'a, E> {
+    type Item = Result<Node<DebugData<'a>>, DisassembleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_offset as usize >= self.bytecode.len() {
+            return None;
+        }
+
+        // Jumps aren't given labels one instruction at a time, so there's no need to keep where they go
+        let result = Instruction::disassemble(self);
+        self.indirection_destinations.clear();
+
+        match result {
+            Ok((ins, dbg)) => Some(Ok(Node::Instruction(ins, dbg))),
+
+            Err(err) => {
+                self.current_offset = self.bytecode.len() as u32;
+                Some(Err(err))
+            }
+        }
     }
 }
+
+#[test]
+fn tolerant() {
+    use crate::operands::Label;
+
+    let nodes = vec![
+        Node::Instruction(Instruction::Jmp(Label("end".to_owned())), ()),
+        Node::RawData(vec![0xABCDEF, 0xABCDEE], ()),
+        Node::Label("end".to_owned()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, errors) = disassemble_tolerant(&bytecode, &mut env);
+
+    assert_eq!(
+        errors,
+        vec![DisassembleError::UnknownOpcode {
+            offset: 2,
+            opcode: 0xABCDEF
+        }]
+    );
+
+    let mut expected = nodes;
+    expected[0] = Node::Instruction(Instruction::Jmp(Label("LAB_0004".to_owned())), ());
+    expected[2] = Node::Label("LAB_0004".to_owned());
+
+    assert_eq!(
+        disassembled
+            .into_iter()
+            .map(Node::strip_debug_data)
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    // Stops at the first error without tolerance
+    let (disassembled, err) = disassemble(&bytecode, &mut env);
+    assert_eq!(disassembled.len(), 1);
+    assert!(err.is_some());
+}
+
+#[test]
+fn tolerant_resume() {
+    use crate::operands::Label;
+
+    let push_int = crate::assembler::assemble(
+        &[Node::Instruction(Instruction::PushInt(0), ())],
+        &mut crate::TestAssembleEnv,
+    )
+    .unwrap()[0];
+
+    // Going word by word, the garbage's last word would swallow the Pop as a PushInt
+    let nodes = vec![
+        Node::Instruction(Instruction::Jz(Label("after".to_owned())), ()),
+        Node::RawData(vec![0xABCDEF, push_int], ()),
+        Node::Label("middle".to_owned()),
+        Node::Instruction(Instruction::Pop, ()),
+        Node::Label("after".to_owned()),
+        Node::Instruction(Instruction::Jmp(Label("middle".to_owned())), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, errors) = disassemble_tolerant(&bytecode, &mut env);
+
+    assert_eq!(errors.len(), 1);
+
+    let rename = |label: &str| match label {
+        "middle" => "LAB_0004".to_owned(),
+        "after" => "LAB_0005".to_owned(),
+        other => other.to_owned(),
+    };
+
+    let expected: Vec<Node> = nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Label(name) => Node::Label(rename(&name)),
+            Node::Instruction(Instruction::Jz(Label(name)), ()) => {
+                Node::Instruction(Instruction::Jz(Label(rename(&name))), ())
+            }
+            Node::Instruction(Instruction::Jmp(Label(name)), ()) => {
+                Node::Instruction(Instruction::Jmp(Label(rename(&name))), ())
+            }
+            other => other,
+        })
+        .collect();
+
+    assert_eq!(
+        disassembled
+            .into_iter()
+            .map(Node::strip_debug_data)
+            .collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[test]
+fn switch_tables() {
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Switch default => LAB_0002, 1 => LAB_0000, null => LAB_0001, 
+LAB_0000:
+SwitchRange default => LAB_0002, 1 => LAB_0000, (2 to 5) => LAB_0001, 
+LAB_0001:
+PickSwitch default => LAB_0002, 25 => LAB_0000, 
+LAB_0002:
+PickProb LAB_0000, LAB_0001, 
+End
+"#,
+    )
+    .unwrap();
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, err) = disassemble(&bytecode, &mut env);
+    assert!(err.is_none());
+
+    let disassembled: Vec<Node> = disassembled
+        .into_iter()
+        .map(Node::strip_debug_data)
+        .collect();
+
+    // Only the label names differ
+    let renamed = crate::format(&nodes)
+        .replace("LAB_0000", "LAB_000D")
+        .replace("LAB_0001", "LAB_001C")
+        .replace("LAB_0002", "LAB_0021");
+    assert_eq!(crate::format(&disassembled), renamed);
+
+    assert_eq!(
+        crate::assembler::assemble(&disassembled, &mut crate::TestAssembleEnv),
+        Ok(bytecode)
+    );
+}
+
+#[test]
+fn version() {
+    // Opcodes past Rgb2Num aren't confirmed for any version, so they're unknown even to 516
+    let bytecode = [0x163, 0x164, 0x12];
+
+    let mut env = crate::TestDisassembleEnv;
+    let options = DisassembleOptions::new().version(516);
+    let (_, err) = disassemble_with_options(&bytecode, &mut env, &options);
+    assert_eq!(
+        err,
+        Some(DisassembleError::UnknownOpcode {
+            offset: 0,
+            opcode: 0x163
+        })
+    );
+
+    // Told how big they are, they're left alone
+    let options = DisassembleOptions::new()
+        .version(516)
+        .unknown_opcode(0x163, 0)
+        .unknown_opcode(0x164, 0);
+    let (disassembled, err) = disassemble_with_options(&bytecode, &mut env, &options);
+    assert!(err.is_none());
+    assert_eq!(
+        crate::format(&disassembled),
+        "Unknown 00000163\nUnknown 00000164\nRet\n"
+    );
+
+    let (disassembled, errors) =
+        disassemble_tolerant_with_options(&bytecode, &mut env, &DisassembleOptions::new().version(516));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        disassembled
+            .into_iter()
+            .map(Node::strip_debug_data)
+            .collect::<Vec<_>>(),
+        vec![
+            Node::RawData(vec![0x163, 0x164], ()),
+            Node::Instruction(Instruction::Ret, ())
+        ]
+    );
+}
+
+#[test]
+fn recursive() {
+    use crate::operands::Label;
+
+    let push_int = crate::assembler::assemble(
+        &[Node::Instruction(Instruction::PushInt(0), ())],
+        &mut crate::TestAssembleEnv,
+    )
+    .unwrap()[0];
+
+    // Going word by word, the padding would swallow the PushInt's opcode as its operand
+    let nodes = vec![
+        Node::Instruction(Instruction::GetVar(crate::operands::Variable::Arg(0)), ()),
+        Node::Instruction(Instruction::Jz(Label("LAB_0007".to_owned())), ()),
+        Node::Instruction(Instruction::Jmp(Label("LAB_0009".to_owned())), ()),
+        Node::Label("LAB_0007".to_owned()),
+        Node::Instruction(Instruction::Ret, ()),
+        Node::RawData(vec![push_int], ()),
+        Node::Label("LAB_0009".to_owned()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+        Node::RawData(vec![0xABCDEF], ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, errors) = disassemble_recursive(&bytecode, &mut env);
+
+    assert!(errors.is_empty());
+    assert_eq!(
+        disassembled
+            .into_iter()
+            .map(Node::strip_debug_data)
+            .collect::<Vec<_>>(),
+        nodes
+    );
+}
+
+#[test]
+fn named_labels() {
+    use crate::operands::Label;
+
+    let nodes = vec![
+        Node::Label("top".to_owned()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Jnz(Label("top".to_owned())), ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let mut env = crate::TestDisassembleEnv;
+    let options = DisassembleOptions::new().name_labels(true);
+    let (disassembled, err) = disassemble_with_options(&bytecode, &mut env, &options);
+
+    assert!(err.is_none());
+    assert_eq!(disassembled[0], Node::Label("LOOP_HEAD_0".to_owned()));
+}
+
+#[test]
+fn unresolved() {
+    use crate::operands::{DMString, Value, Variable};
+
+    let nodes = vec![
+        Node::Instruction(
+            Instruction::PushVal(Value::DMString(DMString(b"hello".to_vec())).into()),
+            (),
+        ),
+        Node::Instruction(
+            Instruction::SetVar(Variable::Global(DMString(b"x".to_vec()))),
+            (),
+        ),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let mut unresolved = Unresolved;
+    let (lazy, err) = disassemble(&bytecode, &mut unresolved);
+    assert!(err.is_none());
+    assert_eq!(lazy[0].to_string(), "PushVal \"#1337\"\n");
+    assert_eq!(lazy[1].to_string(), "SetVar global(\"#1338\")\n");
+
+    let mut env = crate::TestDisassembleEnv;
+    let (eager, _) = disassemble(&bytecode, &mut env);
+    assert_eq!(resolve(lazy, &mut crate::TestDisassembleEnv), Ok(eager));
+}
+
+#[test]
+fn streaming() {
+    use crate::operands::Label;
+
+    let nodes = vec![
+        Node::Label("top".to_owned()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Jnz(Label("top".to_owned())), ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    let mut bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let streamed: Vec<Node> = disassemble_iter(&bytecode, &mut env)
+        .map(|node| node.unwrap().strip_debug_data())
+        .collect();
+
+    let mut expected = nodes;
+    expected.remove(0);
+    expected[1] = Node::Instruction(Instruction::Jnz(Label("LAB_0000".to_owned())), ());
+    assert_eq!(streamed, expected);
+
+    bytecode.push(0xABCDEF);
+    bytecode.push(0);
+
+    let mut env = crate::TestDisassembleEnv;
+    let mut iter = disassemble_iter(&bytecode, &mut env).skip(3);
+    assert_eq!(
+        iter.next(),
+        Some(Err(DisassembleError::UnknownOpcode {
+            offset: 5,
+            opcode: 0xABCDEF
+        }))
+    );
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn around() {
+    let nodes = crate::parser::parse(
+        r#"
+PushInt 1
+PushInt 2
+LAB_0000:
+Add
+GetVar arg(0)
+Jnz LAB_0000
+PushInt 3
+Ret
+"#,
+    )
+    .unwrap();
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    // Starting a word earlier would read the first PushInt's value as an opcode and run over the Add
+    let mut env = crate::TestDisassembleEnv;
+    let (window, err) = disassemble_around(&bytecode, &mut env, 4, 3, 5);
+    assert!(err.is_none());
+    assert_eq!(
+        window
+            .into_iter()
+            .map(|node| node.to_string())
+            .collect::<String>(),
+        "PushInt 2\nLAB_0004:\nAdd\nGetVar arg(0)\nJnz LAB_0004\n"
+    );
+}
+
+#[test]
+fn unknown_opcodes() {
+    // PushInt 1, two words of an opcode that isn't used, an opcode past the ones we know of, Ret
+    let bytecode = vec![0x50, 1, 0x0A, 7, 8, 0x182, 0x12];
+    let mut env = crate::TestDisassembleEnv;
+
+    let (_, err) = disassemble(&bytecode, &mut env);
+    assert_eq!(
+        err,
+        Some(DisassembleError::UnknownOpcode {
+            offset: 2,
+            opcode: 0x0A
+        })
+    );
+
+    let options = DisassembleOptions::new()
+        .unknown_opcode(0x0A, 2)
+        .unknown_opcode(0x182, 0);
+    let (nodes, err) = disassemble_with_options(&bytecode, &mut env, &options);
+    assert!(err.is_none());
+
+    let text = crate::format(&nodes);
+    assert_eq!(
+        text,
+        "PushInt 1\nUnknown 0000000A 00000007 00000008\nUnknown 00000182\nRet\n"
+    );
+
+    let parsed = crate::parser::parse(&text).unwrap();
+    assert_eq!(
+        crate::assembler::assemble(&parsed, &mut crate::TestAssembleEnv),
+        Ok(bytecode)
+    );
+}
+
+#[test]
+fn iter_forgets_destinations() {
+    let nodes = crate::parser::parse(
+        r#"
+LAB_0000:
+Jmp LAB_0000
+Jz LAB_0000
+"#,
+    )
+    .unwrap();
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+
+    let mut env = crate::TestDisassembleEnv;
+    let mut iter = disassemble_iter(&bytecode, &mut env);
+
+    while let Some(node) = iter.next() {
+        assert!(node.is_ok());
+        assert!(iter.indirection_destinations.is_empty());
+    }
+}
+
+#[test]
+fn local_names() {
+    use crate::operands::{DMString, Variable};
+
+    struct Locals;
+
+    impl DisassembleEnv for Locals {
+        fn get_string_data(&mut self, _index: u32) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn get_variable_name(&mut self, _index: u32) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn get_proc_name(&mut self, _index: u32) -> Option<String> {
+            None
+        }
+
+        fn value_to_string_data(&mut self, _tag: u32, _data: u32) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn get_local_name(&mut self, slot: u32) -> Option<Vec<u8>> {
+            match slot {
+                0 => Some(b"amount".to_vec()),
+                _ => None,
+            }
+        }
+    }
+
+    // GetVar local(0), SetVar local(1), Ret
+    let bytecode = vec![0x33, 0xFFDA, 0, 0x34, 0xFFDA, 1, 0x12];
+    let mut env = Locals;
+    let (nodes, err) = disassemble(&bytecode, &mut env);
+    assert!(err.is_none());
+
+    let nodes: Vec<Node> = nodes.into_iter().map(Node::strip_debug_data).collect();
+    assert_eq!(
+        nodes[0],
+        Node::Instruction(
+            Instruction::GetVar(Variable::NamedLocal(0, DMString(b"amount".to_vec()))),
+            ()
+        )
+    );
+    assert_eq!(
+        nodes[1],
+        Node::Instruction(Instruction::SetVar(Variable::Local(1)), ())
+    );
+
+    // The name is only for reading, it assembles the same as the slot
+    let assembled = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    assert_eq!(assembled, bytecode);
+    let text = crate::format(&nodes);
+    assert!(text.starts_with("GetVar local(0, \"amount\")\nSetVar local(1)\n"));
+    assert_eq!(crate::parser::parse(&text).unwrap(), nodes);
+}
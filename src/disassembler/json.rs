@@ -0,0 +1,154 @@
+use serde::Serialize;
+
+use super::DebugData;
+use crate::Node;
+
+// Bump this whenever the layout below changes in a way readers would notice
+const FORMAT: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    format: u32,
+    nodes: Vec<JsonNode<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonOperand {
+    name: &'static str,
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JsonNode<'a> {
+    Instruction {
+        offset: u32,
+        words: &'a [u32],
+        mnemonic: String,
+        operands: Vec<JsonOperand>,
+        text: String,
+    },
+
+    Label {
+        name: &'a str,
+    },
+
+    Comment {
+        text: &'a str,
+    },
+
+    RawData {
+        offset: u32,
+        words: &'a [u32],
+    },
+}
+
+/// Disassembled nodes as JSON, for viewers and tools that aren't written in Rust.
+/// The layout doesn't depend on how the crate's types happen to serialize, so it only changes along with `format`.
+///
+/// ```text
+/// {
+///     "format": 1,
+///     "nodes": [
+///         { "kind": "label", "name": "LAB_0004" },
+///         {
+///             "kind": "instruction",
+///             "offset": 4,                     // in words from the start of the proc
+///             "words": [80, 5],                // the instruction's bytecode
+///             "mnemonic": "PushInt",
+///             "operands": [{ "name": "value", "text": "5" }],
+///             "text": "PushInt 5"              // the line the formatter would write
+///         },
+///         { "kind": "comment", "text": "..." },
+///         { "kind": "raw_data", "offset": 6, "words": [11259375] }
+///     ]
+/// }
+/// ```
+///
+/// Operands are written the same way as in the text format, which the parser reads back in.
+pub fn to_json(nodes: &[Node<DebugData<'_>>]) -> String {
+    let nodes = nodes
+        .iter()
+        .map(|node| match node {
+            Node::Instruction(ins, debug) => JsonNode::Instruction {
+                offset: debug.offset,
+                words: debug.bytecode,
+                mnemonic: ins.op_name(),
+                operands: ins
+                    .operand_names()
+                    .iter()
+                    .zip(ins.operands())
+                    .map(|(name, text)| JsonOperand { name, text })
+                    .collect(),
+                text: ins.to_string(),
+            },
+
+            Node::Label(name) => JsonNode::Label { name },
+            Node::Comment(text) => JsonNode::Comment { text },
+
+            Node::RawData(_, debug) => JsonNode::RawData {
+                offset: debug.offset,
+                words: debug.bytecode,
+            },
+        })
+        .collect();
+
+    // Nothing in the document can fail to serialize
+    serde_json::to_string(&Document {
+        format: FORMAT,
+        nodes,
+    })
+    .unwrap()
+}
+
+#[test]
+fn json() {
+    use crate::operands::Label;
+    use crate::Instruction;
+
+    let nodes = vec![
+        Node::Label("top".to_owned()),
+        Node::Instruction(Instruction::PushInt(5), ()),
+        Node::Instruction(Instruction::Jz(Label("top".to_owned())), ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, _) = super::disassemble(&bytecode, &mut env);
+
+    let json: serde_json::Value = serde_json::from_str(&to_json(&disassembled)).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "format": 1,
+            "nodes": [
+                { "kind": "label", "name": "LAB_0000" },
+                {
+                    "kind": "instruction",
+                    "offset": 0,
+                    "words": [0x50, 5],
+                    "mnemonic": "PushInt",
+                    "operands": [{ "name": "value", "text": "5" }],
+                    "text": "PushInt 5",
+                },
+                {
+                    "kind": "instruction",
+                    "offset": 2,
+                    "words": [0x11, 0],
+                    "mnemonic": "Jz",
+                    "operands": [{ "name": "destination", "text": "LAB_0000" }],
+                    "text": "Jz LAB_0000",
+                },
+                {
+                    "kind": "instruction",
+                    "offset": 4,
+                    "words": [0],
+                    "mnemonic": "End",
+                    "operands": [],
+                    "text": "End",
+                },
+            ],
+        })
+    );
+}
@@ -0,0 +1,113 @@
+//! Splitting the text of a node into the pieces that get highlighted differently, for the colored and HTML output.
+
+use std::collections::HashSet;
+
+use crate::operands::Label;
+use crate::optimizer::jump_destinations;
+use crate::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Class {
+    Plain,
+    Mnemonic,
+
+    /// Where a label is, rather than a jump to it
+    Label,
+    Jump,
+    String,
+    Number,
+    Comment,
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Splits an instruction's operands up, with any of `labels` that show up as words being jumps
+fn operands<'a>(text: &'a str, labels: &HashSet<&str>, out: &mut Vec<(Class, &'a str)>) {
+    let mut pos = 0;
+
+    while let Some(c) = text[pos..].chars().next() {
+        let rest = &text[pos..];
+
+        let (class, len) = if c == '"' {
+            // Up to the closing quote, skipping escaped ones
+            let mut escaped = false;
+            let end = rest[1..]
+                .char_indices()
+                .find(|(_, c)| {
+                    let end = !escaped && *c == '"';
+                    escaped = !escaped && *c == '\\';
+                    end
+                })
+                .map_or(rest.len(), |(idx, _)| idx + 2);
+
+            (Class::String, end)
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            // Hex words and decimals alike
+            let end = rest[1..]
+                .find(|c: char| !is_identifier_char(c) && c != '.')
+                .map_or(rest.len(), |idx| idx + 1);
+
+            (Class::Number, end)
+        } else if is_identifier_char(c) {
+            let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+
+            if labels.contains(&rest[..end]) {
+                (Class::Jump, end)
+            } else {
+                (Class::Plain, end)
+            }
+        } else {
+            (Class::Plain, c.len_utf8())
+        };
+
+        // Runs of plain text are kept together
+        match out.last_mut() {
+            Some((Class::Plain, last)) if class == Class::Plain => {
+                let start = pos - last.len();
+                *last = &text[start..pos + len];
+            }
+            _ => out.push((class, &rest[..len])),
+        }
+
+        pos += len;
+    }
+}
+
+/// The text `format` gives for the node, without the newline, split up by how each part is highlighted
+pub(crate) fn highlight<D>(node: &Node<D>) -> Vec<(Class, String)> {
+    match node {
+        Node::Comment(text) => vec![(Class::Comment, format!(";{}", text))],
+
+        Node::Label(name) => vec![(Class::Label, name.clone()), (Class::Plain, ":".to_owned())],
+
+        Node::Instruction(ins, _) => {
+            let text = ins.to_string();
+            let (mnemonic, rest) = text.split_at(text.find(' ').unwrap_or(text.len()));
+            let labels = jump_destinations(ins)
+                .into_iter()
+                .map(|Label(name)| name.as_str())
+                .collect();
+
+            let mut parts = vec![(Class::Mnemonic, mnemonic)];
+            operands(rest, &labels, &mut parts);
+
+            parts
+                .into_iter()
+                .map(|(class, text)| (class, text.to_owned()))
+                .collect()
+        }
+
+        Node::RawData(words, _) => std::iter::once((Class::Mnemonic, "RawData".to_owned()))
+            .chain(words.iter().flat_map(|word| {
+                vec![
+                    (Class::Plain, " ".to_owned()),
+                    (Class::Number, format!("{:0>8X}", word)),
+                ]
+            }))
+            .collect(),
+    }
+}
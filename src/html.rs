@@ -0,0 +1,91 @@
+//! The same text as `format` as highlighted HTML, with jumps linking to where they go.
+
+use std::fmt::Write;
+
+use crate::highlight::{highlight, Class};
+use crate::Node;
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn class_name(class: Class) -> Option<&'static str> {
+    match class {
+        Class::Plain => None,
+        Class::Mnemonic => Some("mnemonic"),
+        Class::Label | Class::Jump => Some("label"),
+        Class::String => Some("string"),
+        Class::Number => Some("number"),
+        Class::Comment => Some("comment"),
+    }
+}
+
+/// A `<pre class="dmasm">` of the nodes, with each part in a `<span>` classed `mnemonic`, `label`, `string`,
+/// `number` or `comment` for a stylesheet to pick out. Labels have an `id` of `anchor_prefix` followed by their
+/// name, and jumps are links to them, so more than one proc can go on a page as long as each has its own prefix.
+pub fn format_html<D>(nodes: &[Node<D>], anchor_prefix: &str) -> String {
+    let mut out = String::from("<pre class=\"dmasm\">");
+
+    for node in nodes {
+        for (class, text) in highlight(node) {
+            let anchor = escape(&format!("{}{}", anchor_prefix, text));
+            let text = escape(&text);
+
+            match (class, class_name(class)) {
+                (Class::Label, Some(name)) => write!(
+                    &mut out,
+                    "<span class=\"{}\" id=\"{}\">{}</span>",
+                    name, anchor, text
+                ),
+                (Class::Jump, Some(name)) => write!(
+                    &mut out,
+                    "<a class=\"{}\" href=\"#{}\">{}</a>",
+                    name, anchor, text
+                ),
+                (_, Some(name)) => write!(&mut out, "<span class=\"{}\">{}</span>", name, text),
+                (_, None) => write!(&mut out, "{}", text),
+            }
+            .unwrap();
+        }
+
+        out.push('\n');
+    }
+
+    out.push_str("</pre>\n");
+    out
+}
+
+#[test]
+fn listing() {
+    let nodes = crate::parser::parse(
+        r#"
+LAB_0000:
+PushVal "<b>"
+Jz LAB_0000
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        format_html(&nodes, "f-"),
+        concat!(
+            "<pre class=\"dmasm\">",
+            "<span class=\"label\" id=\"f-LAB_0000\">LAB_0000</span>:\n",
+            "<span class=\"mnemonic\">PushVal</span> <span class=\"string\">&quot;&lt;b&gt;&quot;</span>\n",
+            "<span class=\"mnemonic\">Jz</span> <a class=\"label\" href=\"#f-LAB_0000\">LAB_0000</a>\n",
+            "</pre>\n",
+        )
+    );
+}
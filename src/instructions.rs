@@ -17,10 +17,15 @@ macro_rules! instructions {
         ),* $(,)? ) )?
     ),* $(,)? ) => {
         #[derive(PartialEq, Clone, Debug)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum Instruction {
             $(
                 $name$( ( $( $operand_type, )* ) )?,
             )*
+
+            /// An opcode the disassembler doesn't know, with however many words after it it was told to take.
+            /// These are emitted back as they were, so a jump among the operands won't follow the code moving.
+            Unknown { opcode: u32, operands: Vec<u32> },
         }
 
         impl Instruction {
@@ -29,9 +34,20 @@ macro_rules! instructions {
                     $(
                         Self::$name$( ( $( $operand_name, )* ) )? => {
                             asm.emit($opcode);
-                            $( $( $operand_name.assemble(asm)?; )* )?
+                            $( $(
+                                $operand_name
+                                    .assemble(asm)
+                                    .map_err(|err| err.in_operand(stringify!($operand_name)))?;
+                            )* )?
                         }
                     )*
+
+                    Self::Unknown { opcode, operands } => {
+                        asm.emit(*opcode);
+                        for word in operands {
+                            asm.emit(*word);
+                        }
+                    }
                 }
 
                 Ok(())
@@ -41,15 +57,28 @@ macro_rules! instructions {
                 dism: &mut Disassembler<'a, E>,
             ) -> Result<(Self, DebugData<'a>), DisassembleError> {
                 let offset = dism.current_offset;
+                let opcode = dism.read_u32()?;
+
+                // Opcodes from after the version being disassembled could mean anything there
+                let too_new = match (Self::opcode_min_version(opcode), dism.version) {
+                    (Some(min_version), Some(version)) => version < min_version,
+                    _ => false,
+                };
 
-                let ins = match dism.read_u32()? {
+                let ins = match opcode {
                     $(
-                        $opcode => {
+                        $opcode if !too_new => {
                             Self::$name$( ( $( $operand_type::disassemble(dism)?, )* ) )?
                         }
                     )*
 
-                    opcode => return Err(DisassembleError::UnknownOpcode { offset, opcode }),
+                    opcode => match dism.unknown_operands.get(&opcode).copied() {
+                        Some(count) => Self::Unknown {
+                            opcode,
+                            operands: (0..count).map(|_| dism.read_u32()).collect::<Result<_, _>>()?,
+                        },
+                        None => return Err(DisassembleError::UnknownOpcode { offset, opcode }),
+                    },
                 };
 
                 let range_start = offset as usize;
@@ -75,16 +104,80 @@ macro_rules! instructions {
                             )* )?
                         }
                     )*
+
+                    Self::Unknown { opcode, operands } => {
+                        write!(f, "Unknown {:0>8X}", opcode)?;
+                        for word in operands {
+                            write!(f, " {:0>8X}", word)?;
+                        }
+                    }
                 }
 
                 Ok(())
             }
 
+            /// Each operand the way `serialize` writes it
+            pub fn operands(&self) -> Vec<String> {
+                match self {
+                    $(
+                        Self::$name$( ( $( $operand_name, )* ) )? => {
+                            vec![$( $(
+                                Serialized($operand_name).to_string(),
+                            )* )?]
+                        }
+                    )*
+
+                    Self::Unknown { opcode, operands } => vec![
+                        format!("{:0>8X}", opcode),
+                        operands.iter().map(|word| format!("{:0>8X}", word)).collect::<Vec<_>>().join(" "),
+                    ],
+                }
+            }
+
+            /// The operands that are variables
+            pub fn variables(&self) -> Vec<&Variable> {
+                match self {
+                    $(
+                        Self::$name$( ( $( $operand_name, )* ) )? => {
+                            let variables: Vec<Option<&Variable>> = vec![$( $(
+                                $operand_name.variable(),
+                            )* )?];
+                            variables.into_iter().flatten().collect()
+                        }
+                    )*
+
+                    Self::Unknown { .. } => vec![],
+                }
+            }
+
+            /// The names of the operands, in the same order as `operands`
+            pub fn operand_names(&self) -> &'static [&'static str] {
+                match self {
+                    $(
+                        Self::$name { .. } => &[$( $( stringify!($operand_name), )* )?],
+                    )*
+
+                    Self::Unknown { .. } => &["opcode", "operands"],
+                }
+            }
+
+            pub fn opcode(&self) -> u32 {
+                match self {
+                    $(
+                        Self::$name { .. } => $opcode,
+                    )*
+
+                    Self::Unknown { opcode, .. } => *opcode,
+                }
+            }
+
             pub fn op_name(&self) -> String {
                 match self {
                     $(
                         Self::$name$( ( $( $operand_name, )* ) )? => { return stringify!($name).to_string() }
                     )*
+
+                    Self::Unknown { .. } => "Unknown".to_string(),
                 }
             }
 
@@ -93,6 +186,7 @@ macro_rules! instructions {
                 E: nom::error::ParseError<&'b str>
                     + nom::error::FromExternalError<&'b str, std::num::ParseIntError>,
             {
+                let start = i;
                 let (i, name) = parser::whitespace(parser::parse_identifier)(i)?;
 
                 let (i, instruction) = match name {
@@ -107,8 +201,18 @@ macro_rules! instructions {
                         },
                     )*
 
-                    // TODO: Real error
-                    other => panic!("unknown instruction {}", other),
+                    "Unknown" => {
+                        let (i, opcode) = parser::parse_hex_word(i)?;
+                        let (i, operands) = nom::multi::many0(nom::sequence::preceded(
+                            nom::character::complete::space1,
+                            parser::parse_hex_word,
+                        ))(i)?;
+                        (i, Self::Unknown { opcode, operands })
+                    }
+
+                    _ => {
+                        return Err(nom::Err::Error(E::from_error_kind(start, nom::error::ErrorKind::Tag)));
+                    }
                 };
 
                 Ok((i, instruction))
@@ -498,10 +602,26 @@ instructions! {
     0x161 = RgbEx, // Used when the color space for rgb() cannot be found to be COLORSPACE_RGB at compile-time
     0x162 = Rgb2Num, // This is technically a replacement for the original Rgb2Num which is somewhere else
 
+    // 515 and 516 added more (json_encode() flags, floor(), pointers, astype(), alists...). They go here once their
+    // opcodes and operands are confirmed against builds of those versions.
+
     0x1337 = AuxtoolsDebugBreak,
     0x1338 = AuxtoolsDebugBreakNop,
 }
 
+impl Instruction {
+    /// The first BYOND version with this instruction, for the ones that haven't been around since before 514.
+    /// Everything here has been so far, as nothing from 515 or 516 is confirmed yet.
+    pub fn min_version(&self) -> Option<u32> {
+        Self::opcode_min_version(self.opcode())
+    }
+
+    /// `min_version`, for an opcode that might not be an instruction yet
+    pub fn opcode_min_version(_opcode: u32) -> Option<u32> {
+        None
+    }
+}
+
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.serialize(f)
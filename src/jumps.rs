@@ -0,0 +1,239 @@
+//! Listing where each jump in a proc goes, for when that's all that's needed and a `Cfg` would be overkill.
+
+use std::collections::HashMap;
+
+use crate::disassembler::{disassemble, DebugData, DisassembleError, Unresolved};
+use crate::operands::Label;
+use crate::{Instruction, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JumpKind {
+    /// Only taken depending on a value, such as `Jz` or the end of a `ForRange`
+    Conditional,
+
+    /// Always taken, such as `Jmp`
+    Unconditional,
+
+    /// One of the cases of a `switch`, or a branch of `pick`
+    SwitchCase,
+
+    /// Where a `switch` goes when no case matches
+    SwitchDefault,
+
+    /// Past the code a `spawn` runs later
+    Spawn,
+
+    /// Set up by `try` and `catch` for when there's an error
+    Exception,
+}
+
+/// A jump from the instruction at `source` to `target`. For nodes these are the index of the instruction and the
+/// label, for bytecode they're both offsets in words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jump<S, T> {
+    pub source: S,
+    pub target: T,
+    pub kind: JumpKind,
+}
+
+// Every label an instruction can jump to, with how it gets there
+fn instruction_jumps(ins: &Instruction) -> Vec<(&Label, JumpKind)> {
+    match ins {
+        Instruction::Jmp(label) | Instruction::JmpLoop(label) => {
+            vec![(label, JumpKind::Unconditional)]
+        }
+
+        Instruction::Jnz(label)
+        | Instruction::Jz(label)
+        | Instruction::JmpOr(label)
+        | Instruction::JmpAnd(label)
+        | Instruction::JnzLoop(label)
+        | Instruction::JzLoop(label)
+        | Instruction::ForRange(label, _)
+        | Instruction::ForRangeStep(label, _)
+        | Instruction::SetCacheJmpIfNull(label)
+        | Instruction::SetCachePopJmpIfNull(label) => vec![(label, JumpKind::Conditional)],
+
+        Instruction::Spawn(label) => vec![(label, JumpKind::Spawn)],
+
+        Instruction::Try(label) | Instruction::Catch(label) | Instruction::TryJmp(label) => {
+            vec![(label, JumpKind::Exception)]
+        }
+
+        Instruction::Switch(params) => std::iter::once((&params.default, JumpKind::SwitchDefault))
+            .chain(
+                params
+                    .cases
+                    .iter()
+                    .map(|(_, label)| (label, JumpKind::SwitchCase)),
+            )
+            .collect(),
+
+        Instruction::PickSwitch(params) => {
+            std::iter::once((&params.default, JumpKind::SwitchDefault))
+                .chain(
+                    params
+                        .cases
+                        .iter()
+                        .map(|(_, label)| (label, JumpKind::SwitchCase)),
+                )
+                .collect()
+        }
+
+        Instruction::SwitchRange(params) => {
+            std::iter::once((&params.default, JumpKind::SwitchDefault))
+                .chain(
+                    params
+                        .cases
+                        .iter()
+                        .map(|(_, label)| (label, JumpKind::SwitchCase)),
+                )
+                .chain(
+                    params
+                        .range_cases
+                        .iter()
+                        .map(|(_, _, label)| (label, JumpKind::SwitchCase)),
+                )
+                .collect()
+        }
+
+        Instruction::PickProb(params) => params
+            .cases
+            .iter()
+            .map(|label| (label, JumpKind::SwitchCase))
+            .collect(),
+
+        _ => vec![],
+    }
+}
+
+/// Every jump in the nodes, in order. Switches give one for each case, even when cases share a label.
+pub fn jumps<D>(nodes: &[Node<D>]) -> Vec<Jump<usize, &str>> {
+    nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| match node {
+            Node::Instruction(ins, _) => Some((idx, ins)),
+            _ => None,
+        })
+        .flat_map(|(idx, ins)| {
+            instruction_jumps(ins)
+                .into_iter()
+                .map(move |(Label(target), kind)| Jump {
+                    source: idx,
+                    target: target.as_str(),
+                    kind,
+                })
+        })
+        .collect()
+}
+
+/// `jumps`, straight from bytecode. Nothing needs looking up to find the jumps, so no env is needed.
+pub fn bytecode_jumps(bytecode: &[u32]) -> Result<Vec<Jump<u32, u32>>, DisassembleError> {
+    let mut env = Unresolved;
+    let (nodes, err) = disassemble(bytecode, &mut env);
+
+    if let Some(err) = err {
+        return Err(err);
+    }
+
+    Ok(offset_jumps(&nodes, bytecode.len() as u32))
+}
+
+fn offset_jumps(nodes: &[Node<DebugData<'_>>], end: u32) -> Vec<Jump<u32, u32>> {
+    // A label is at the offset of whatever comes after it
+    let mut offsets = HashMap::new();
+    let mut pending = vec![];
+
+    for node in nodes {
+        match node {
+            Node::Label(name) => pending.push(name.as_str()),
+            Node::Instruction(_, debug) | Node::RawData(_, debug) => {
+                for name in pending.drain(..) {
+                    offsets.insert(name, debug.offset);
+                }
+            }
+            Node::Comment(_) => {}
+        }
+    }
+
+    for name in pending {
+        offsets.insert(name, end);
+    }
+
+    jumps(nodes)
+        .into_iter()
+        .filter_map(|jump| {
+            let source = match &nodes[jump.source] {
+                Node::Instruction(_, debug) => debug.offset,
+                _ => return None,
+            };
+
+            Some(Jump {
+                source,
+                target: *offsets.get(jump.target)?,
+                kind: jump.kind,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn listing() {
+    let nodes = crate::parser::parse(
+        r#"
+LAB_0000:
+GetVar arg(0)
+Jz LAB_0001
+GetVar arg(1)
+Switch default => LAB_0002, 1 => LAB_0000,
+LAB_0001:
+Jmp LAB_0000
+LAB_0002:
+End
+"#,
+    )
+    .unwrap();
+
+    let expected = vec![
+        Jump {
+            source: 2,
+            target: "LAB_0001",
+            kind: JumpKind::Conditional,
+        },
+        Jump {
+            source: 4,
+            target: "LAB_0002",
+            kind: JumpKind::SwitchDefault,
+        },
+        Jump {
+            source: 4,
+            target: "LAB_0000",
+            kind: JumpKind::SwitchCase,
+        },
+        Jump {
+            source: 6,
+            target: "LAB_0000",
+            kind: JumpKind::Unconditional,
+        },
+    ];
+    assert_eq!(jumps(&nodes), expected);
+
+    // GetVar is 3 words, Jz and Jmp are 2, and this Switch is 7
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let offsets: Vec<(u32, u32, JumpKind)> = bytecode_jumps(&bytecode)
+        .unwrap()
+        .into_iter()
+        .map(|jump| (jump.source, jump.target, jump.kind))
+        .collect();
+
+    assert_eq!(
+        offsets,
+        vec![
+            (3, 15, JumpKind::Conditional),
+            (8, 17, JumpKind::SwitchDefault),
+            (8, 0, JumpKind::SwitchCase),
+            (15, 0, JumpKind::Unconditional),
+        ]
+    );
+}
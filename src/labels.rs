@@ -0,0 +1,238 @@
+//! Giving labels names that say what they're for, such as `LOOP_HEAD_0` rather than `LAB_0012`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::operands::Label;
+use crate::optimizer::{jump_destinations, jump_destinations_mut};
+use crate::{Instruction, Node};
+
+// In order of preference when a label could be more than one of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Role {
+    LoopHead,
+    SwitchCase,
+    LoopExit,
+    ShortCircuit,
+}
+
+impl Role {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::LoopHead => "LOOP_HEAD",
+            Self::SwitchCase => "SWITCH_CASE",
+            Self::LoopExit => "LOOP_EXIT",
+            Self::ShortCircuit => "SHORT_CIRCUIT",
+        }
+    }
+}
+
+fn case_labels(ins: &Instruction) -> Vec<&Label> {
+    match ins {
+        Instruction::Switch(params) => params.cases.iter().map(|(_, label)| label).collect(),
+        Instruction::PickSwitch(params) => params.cases.iter().map(|(_, label)| label).collect(),
+        Instruction::SwitchRange(params) => params
+            .range_cases
+            .iter()
+            .map(|(_, _, label)| label)
+            .chain(params.cases.iter().map(|(_, label)| label))
+            .collect(),
+        Instruction::PickProb(params) => params.cases.iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn assign(roles: &mut HashMap<String, Role>, label: &str, role: Role) {
+    let current = roles.entry(label.to_owned()).or_insert(role);
+    *current = (*current).min(role);
+}
+
+/// Renames the labels something jumps to by what the jumps look like they're for:
+///
+/// - `LOOP_HEAD_n` for anything jumped back to
+/// - `LOOP_EXIT_n` for just after a jump back that something inside the loop jumps to, or past the end of a `for`
+/// - `SWITCH_CASE_n` for the cases of a `switch` or `pick`, but not the default
+/// - `SHORT_CIRCUIT_n` for the end of an `&&`, `||` or `?.`
+///
+/// Only the shape of the jumps is looked at, so these are guesses. Labels that don't fit any of them are left alone.
+pub fn name_labels<D>(mut nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let positions: HashMap<String, usize> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, node)| match node {
+            Node::Label(name) => Some((name.clone(), idx)),
+            _ => None,
+        })
+        .collect();
+
+    let mut roles = HashMap::new();
+
+    // Where each unconditional jump back goes from and to
+    let mut loops = vec![];
+
+    for (idx, node) in nodes.iter().enumerate() {
+        let ins = match node {
+            Node::Instruction(ins, _) => ins,
+            _ => continue,
+        };
+
+        for Label(name) in case_labels(ins) {
+            assign(&mut roles, name, Role::SwitchCase);
+        }
+
+        for Label(name) in jump_destinations(ins) {
+            let position = match positions.get(name) {
+                Some(position) => *position,
+                None => continue,
+            };
+
+            if position < idx {
+                assign(&mut roles, name, Role::LoopHead);
+
+                if matches!(ins, Instruction::Jmp(_) | Instruction::JmpLoop(_)) {
+                    loops.push((position, idx));
+                }
+                continue;
+            }
+
+            match ins {
+                Instruction::ForRange(..) | Instruction::ForRangeStep(..) => {
+                    assign(&mut roles, name, Role::LoopExit)
+                }
+
+                Instruction::JmpOr(_)
+                | Instruction::JmpAnd(_)
+                | Instruction::SetCacheJmpIfNull(_)
+                | Instruction::SetCachePopJmpIfNull(_) => {
+                    assign(&mut roles, name, Role::ShortCircuit)
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    for (head, tail) in loops {
+        let after: Vec<&String> = nodes[tail + 1..]
+            .iter()
+            .map_while(|node| match node {
+                Node::Label(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        let exits: Vec<&String> = nodes[head..tail]
+            .iter()
+            .filter_map(|node| match node {
+                Node::Instruction(ins, _) => Some(jump_destinations(ins)),
+                _ => None,
+            })
+            .flatten()
+            .map(|Label(name)| name)
+            .filter(|name| after.contains(name))
+            .collect();
+
+        for name in exits {
+            assign(&mut roles, name, Role::LoopExit);
+        }
+    }
+
+    // Numbered in the order they appear, skipping any names already taken by labels that are staying
+    let mut taken: HashSet<String> = positions
+        .keys()
+        .filter(|name| !roles.contains_key(*name))
+        .cloned()
+        .collect();
+    let mut counts: HashMap<Role, usize> = HashMap::new();
+    let mut renames = HashMap::new();
+
+    for node in &nodes {
+        let name = match node {
+            Node::Label(name) => name,
+            _ => continue,
+        };
+
+        let role = match roles.get(name) {
+            Some(role) => *role,
+            None => continue,
+        };
+
+        let count = counts.entry(role).or_insert(0);
+        let new_name = loop {
+            let candidate = format!("{}_{}", role.prefix(), count);
+            *count += 1;
+
+            if taken.insert(candidate.clone()) {
+                break candidate;
+            }
+        };
+
+        renames.insert(name.clone(), new_name);
+    }
+
+    for node in &mut nodes {
+        match node {
+            Node::Label(name) => {
+                if let Some(new_name) = renames.get(name) {
+                    *name = new_name.clone();
+                }
+            }
+
+            Node::Instruction(ins, _) => {
+                for Label(name) in jump_destinations_mut(ins) {
+                    if let Some(new_name) = renames.get(name) {
+                        *name = new_name.clone();
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    nodes
+}
+
+#[test]
+fn roles() {
+    let nodes = crate::parser::parse(
+        r#"
+LAB_0000:
+GetVar arg(0)
+JmpAnd LAB_0001
+GetVar arg(1)
+LAB_0001:
+Jz LAB_0003
+GetVar arg(2)
+Switch default => LAB_0003, 1 => LAB_0002, 2 => LAB_0002,
+LAB_0002:
+Jmp LAB_0003
+Jmp LAB_0000
+LAB_0003:
+LAB_0004:
+End
+"#,
+    )
+    .unwrap();
+
+    let expected = crate::parser::parse(
+        r#"
+LOOP_HEAD_0:
+GetVar arg(0)
+JmpAnd SHORT_CIRCUIT_0
+GetVar arg(1)
+SHORT_CIRCUIT_0:
+Jz LOOP_EXIT_0
+GetVar arg(2)
+Switch default => LOOP_EXIT_0, 1 => SWITCH_CASE_0, 2 => SWITCH_CASE_0,
+SWITCH_CASE_0:
+Jmp LOOP_EXIT_0
+Jmp LOOP_HEAD_0
+LOOP_EXIT_0:
+LAB_0004:
+End
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(name_labels(nodes), expected);
+}
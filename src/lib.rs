@@ -2,15 +2,33 @@
 
 mod access_modifiers;
 pub mod assembler;
+mod cached_env;
+pub mod cfg;
+#[cfg(feature = "color")]
+pub mod color;
 pub mod disassembler;
 // pub mod builder;
 pub mod compiler;
+pub mod decompiler;
+pub mod diff;
+mod directives;
+mod highlight;
+pub mod html;
+pub mod labels;
 mod instructions;
+pub mod jumps;
 pub mod list_operands;
+pub mod memory_dump;
 pub mod operands;
 mod operands_deserialize;
+pub mod optimizer;
 mod parser;
+pub mod pattern;
+pub mod rewrite;
+pub mod source_map;
+pub mod xref;
 
+pub use cached_env::CachedEnv;
 pub use disassembler::DebugData;
 pub use instructions::Instruction;
 
@@ -19,10 +37,14 @@ use std::fmt::Write;
 struct Proc {}
 
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node<D = ()> {
     Comment(String),
     Label(String),
     Instruction(Instruction, D),
+
+    /// Words the disassembler couldn't make sense of, see `disassemble_tolerant`. They're assembled as they are.
+    RawData(Vec<u32>, D),
 }
 
 impl<D> Node<D> {
@@ -31,10 +53,12 @@ impl<D> Node<D> {
             Self::Comment(str) => Node::Comment(str),
             Self::Label(str) => Node::Label(str),
             Self::Instruction(ins, _debug) => Node::Instruction(ins, ()),
+            Self::RawData(words, _debug) => Node::RawData(words, ()),
         }
     }
 }
 
+/// A line of `format`'s output, newline included. `Instruction`'s `Display` is the same without one.
 impl<D> std::fmt::Display for Node<D> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -44,17 +68,56 @@ impl<D> std::fmt::Display for Node<D> {
                 ins.serialize(f)?;
                 write!(f, "\n")
             }
+            Self::RawData(words, _) => {
+                write!(f, "RawData")?;
+                for word in words {
+                    write!(f, " {:0>8X}", word)?;
+                }
+                writeln!(f)
+            }
         }
     }
 }
 
 /// Formats the output of the disassembler into a human readable format including offsets, bytecode, and assembly.
 pub fn format_disassembly(nodes: &[Node<DebugData>], cursor: Option<u32>) -> String {
+    format_lines(nodes, cursor, |_| vec![])
+}
+
+/// Like `format_disassembly`, but each instruction ends with a comment saying what the ids in its bytecode are in
+/// `env`, such as `; 12 = "hello", 3 = /proc/f`. Handy when the nodes were disassembled with `Unresolved`.
+pub fn format_disassembly_with_env<E: disassembler::DisassembleEnv>(nodes: &[Node<DebugData>], cursor: Option<u32>, env: &mut E) -> String {
+    format_lines(nodes, cursor, |bytecode| disassembler::resolutions(bytecode, env))
+}
+
+fn format_lines<F>(nodes: &[Node<DebugData>], cursor: Option<u32>, mut resolutions: F) -> String
+where
+    F: FnMut(&[u32]) -> Vec<String>,
+{
     let mut buf = String::new();
 
     for node in nodes {
         match node {
-            Node::Instruction(ins, dbg) => {
+            Node::Instruction(_, dbg) | Node::RawData(_, dbg) => {
+                let text = match node {
+                    Node::Instruction(ins, _) => {
+                        let mut text = ins.to_string();
+
+                        // Chains through the cache are hard to follow, so they're spelled out
+                        for path in ins.variables().iter().filter_map(|var| var.dotted()) {
+                            write!(&mut text, " ; {}", path).unwrap();
+                        }
+
+                        let resolved = resolutions(dbg.bytecode);
+                        if !resolved.is_empty() {
+                            write!(&mut text, " ; {}", resolved.join(", ")).unwrap();
+                        }
+
+                        text
+                    }
+                    _ => "RawData".to_owned(),
+                };
+
                 let mut raw_lines = vec![];
 
                 for chunk in dbg.bytecode.chunks(3) {
@@ -78,7 +141,7 @@ pub fn format_disassembly(nodes: &[Node<DebugData>], cursor: Option<u32>) -> Str
                 writeln!(
                     &mut buf,
                     "{} {:0>4X}:{:28} {}",
-                    prefix, dbg.offset, raw_lines[0], ins
+                    prefix, dbg.offset, raw_lines[0], text
                 )
                 .unwrap();
 
@@ -164,3 +227,60 @@ End
 
     println!("{}", format_disassembly(&nodes, Some(4)));
 }
+
+#[test]
+fn dotted_chains() {
+    use operands::{DMString, Variable};
+
+    let field = |name: &str| Box::new(Variable::Field(DMString(name.as_bytes().to_vec())));
+    let chain = Variable::SetCache(
+        Box::new(Variable::Src),
+        Box::new(Variable::SetCache(field("a"), field("b"))),
+    );
+
+    assert_eq!(chain.dotted(), Some("src.a.b".to_owned()));
+    assert_eq!(Variable::Src.dotted(), None);
+
+    let nodes = vec![
+        Node::Instruction(Instruction::GetVar(chain), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let bytecode = assembler::assemble(&nodes, &mut TestAssembleEnv).unwrap();
+    let mut env = TestDisassembleEnv;
+    let (disassembled, _) = disassembler::disassemble(&bytecode, &mut env);
+
+    assert!(format_disassembly(&disassembled, None)
+        .lines()
+        .next()
+        .unwrap()
+        .ends_with("; src.(Test String for 1337).(Test String for 1337)"));
+}
+
+#[test]
+fn display() {
+    let nodes = parser::parse("; note\nLAB_0000:\nPushVal \"hi\"\nJmp LAB_0000\nRawData 00000012\n").unwrap();
+
+    assert_eq!(nodes.iter().map(|node| node.to_string()).collect::<String>(), format(&nodes));
+
+    match &nodes[2] {
+        Node::Instruction(ins, _) => assert_eq!(ins.to_string(), "PushVal \"hi\""),
+        _ => panic!("expected an instruction"),
+    }
+}
+
+#[test]
+fn resolution_comments() {
+    let nodes = parser::parse("PushVal \"hi\"\nCallGlob 1 /proc/f\nRet\n").unwrap();
+    let bytecode = assembler::assemble(&nodes, &mut TestAssembleEnv).unwrap();
+
+    let mut unresolved = disassembler::Unresolved;
+    let (disassembled, _) = disassembler::disassemble(&bytecode, &mut unresolved);
+
+    let text = format_disassembly_with_env(&disassembled, None, &mut TestDisassembleEnv);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert!(lines[0].ends_with("PushVal \"#1337\" ; 1337 = \"(Test String for 1337)\""));
+    assert!(lines[1].ends_with("CallGlob 1 /#1339 ; 1339 = /proc/func1339"));
+    assert!(lines[2].ends_with("Ret"));
+}
@@ -22,6 +22,7 @@ pub static BLOCK: u32 = 0x06;
 pub static CONTENTS: u32 = 0x05;
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct TypeFilter: u32 {
         const MOB = 0x01;
         const OBJ = 0x02;
@@ -45,8 +46,9 @@ bitflags! {
 }
 
 impl Operand for TypeFilter {
-    fn assemble<E: AssembleEnv>(&self, _asm: &mut Assembler<E>) -> Result<(), AssembleError> {
-        unimplemented!()
+    fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
+        asm.emit(self.bits());
+        Ok(())
     }
 
     fn disassemble<E: DisassembleEnv>(
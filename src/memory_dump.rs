@@ -0,0 +1,271 @@
+//! Looking things up in a snapshot of a game process's memory, so bytecode from a crash dump can be disassembled
+//! after the process is gone.
+//!
+//! `Layout::byond_514` knows how BYOND 514 lays out its string and proc tables. Anything else, such as types or
+//! other versions, has to come from the caller, such as from the symbols of the exact build the dump is from.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::disassembler::DisassembleEnv;
+
+/// How big pointers are in the process the dump came from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PointerWidth {
+    #[default]
+    Bits32,
+    Bits64,
+}
+
+/// Where one of the game's tables is and how its entries are laid out. These move around between BYOND versions and
+/// builds, so they come from whoever knows which one the dump is from.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    pub address: u64,
+    pub count: u32,
+
+    /// Bytes from one entry to the next
+    pub entry_size: u32,
+
+    /// Whether each entry is only a pointer to the real one, which is what `name_offset` is then from
+    pub indirect: bool,
+
+    /// Where the entry's name is. For strings that's a pointer to the text, for everything else a string id.
+    pub name_offset: u32,
+}
+
+/// Every table the env looks things up in. Ids are always read as 4 bytes and pointers as `pointer_width`, both
+/// little-endian.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Layout {
+    pub pointer_width: PointerWidth,
+    pub strings: Table,
+    pub procs: Table,
+
+    /// Tables of anything else a value can refer to, such as types, by the value's tag
+    pub values: HashMap<u32, Table>,
+}
+
+impl Layout {
+    /// BYOND 514's tables in a 32-bit process. The strings are an array of pointers to entries with the text first,
+    /// and the procs an array of 36 byte entries with the path's string id first.
+    ///
+    /// Each table is given as where its array starts and how many entries it has. Those differ between builds and
+    /// runs, so they still have to be found in the dump.
+    pub fn byond_514(strings: (u64, u32), procs: (u64, u32)) -> Self {
+        Self {
+            pointer_width: PointerWidth::Bits32,
+            strings: Table {
+                address: strings.0,
+                count: strings.1,
+                entry_size: 4,
+                indirect: true,
+                name_offset: 0,
+            },
+            procs: Table {
+                address: procs.0,
+                count: procs.1,
+                entry_size: 0x24,
+                indirect: false,
+                name_offset: 0,
+            },
+            values: HashMap::new(),
+        }
+    }
+}
+
+/// A run of a process's memory, starting from `base`. Anything outside of it can't be looked up.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MemoryDump {
+    pub base: u64,
+    pub bytes: Vec<u8>,
+    pub layout: Layout,
+}
+
+impl MemoryDump {
+    pub fn new(base: u64, bytes: Vec<u8>, layout: Layout) -> Self {
+        Self {
+            base,
+            bytes,
+            layout,
+        }
+    }
+
+    fn read(&self, address: u64, len: usize) -> Option<&[u8]> {
+        let start: usize = address.checked_sub(self.base)?.try_into().ok()?;
+        self.bytes.get(start..start.checked_add(len)?)
+    }
+
+    fn read_u32(&self, address: u64) -> Option<u32> {
+        let bytes = self.read(address, 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_pointer(&self, address: u64) -> Option<u64> {
+        match self.layout.pointer_width {
+            PointerWidth::Bits32 => self.read_u32(address).map(u64::from),
+            PointerWidth::Bits64 => {
+                let bytes = self.read(address, 8)?;
+                Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    }
+
+    // Up to the terminating NUL, which has to be in the dump too
+    fn read_c_string(&self, address: u64) -> Option<Vec<u8>> {
+        let start: usize = address.checked_sub(self.base)?.try_into().ok()?;
+        let bytes = self.bytes.get(start..)?;
+        let len = bytes.iter().position(|byte| *byte == 0)?;
+        Some(bytes[..len].to_vec())
+    }
+
+    fn entry(&self, table: &Table, index: u32) -> Option<u64> {
+        if index >= table.count {
+            return None;
+        }
+
+        let address = table
+            .address
+            .checked_add(u64::from(index) * u64::from(table.entry_size))?;
+
+        if table.indirect {
+            self.read_pointer(address)
+        } else {
+            Some(address)
+        }
+    }
+
+    fn string(&self, index: u32) -> Option<Vec<u8>> {
+        let entry = self.entry(&self.layout.strings, index)?;
+        let text = self.read_pointer(entry.checked_add(self.layout.strings.name_offset.into())?)?;
+        self.read_c_string(text)
+    }
+
+    fn name(&self, table: &Table, index: u32) -> Option<Vec<u8>> {
+        let entry = self.entry(table, index)?;
+        let id = self.read_u32(entry.checked_add(table.name_offset.into())?)?;
+        self.string(id)
+    }
+}
+
+impl DisassembleEnv for MemoryDump {
+    fn get_string_data(&mut self, index: u32) -> Option<Vec<u8>> {
+        self.string(index)
+    }
+
+    fn get_variable_name(&mut self, index: u32) -> Option<Vec<u8>> {
+        self.string(index)
+    }
+
+    fn get_proc_name(&mut self, index: u32) -> Option<String> {
+        let name = self.name(&self.layout.procs, index)?;
+        String::from_utf8(name).ok()
+    }
+
+    fn value_to_string_data(&mut self, tag: u32, data: u32) -> Option<Vec<u8>> {
+        let table = self.layout.values.get(&tag)?;
+        self.name(table, data)
+    }
+}
+
+#[cfg(test)]
+fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[test]
+fn lookups() {
+    let mut bytes = vec![];
+
+    // 0x1000: the string table, pointers to entries with the text second
+    bytes.extend(words(&[0x1008, 0x1010, 0, 0x1018, 0, 0x1020]));
+
+    // 0x1018: the text
+    bytes.extend_from_slice(b"hello\0\0\0/proc/f\0");
+
+    // 0x1028: the proc table, entries of 8 bytes with the path's string id first
+    bytes.extend(words(&[1, 0]));
+
+    let layout = Layout {
+        pointer_width: PointerWidth::Bits32,
+        strings: Table {
+            address: 0x1000,
+            count: 2,
+            entry_size: 4,
+            indirect: true,
+            name_offset: 4,
+        },
+        procs: Table {
+            address: 0x1028,
+            count: 1,
+            entry_size: 8,
+            indirect: false,
+            name_offset: 0,
+        },
+        values: HashMap::new(),
+    };
+
+    let mut dump = MemoryDump::new(0x1000, bytes, layout);
+    assert_eq!(dump.get_string_data(0), Some(b"hello".to_vec()));
+    assert_eq!(dump.get_proc_name(0), Some("/proc/f".to_owned()));
+    assert_eq!(dump.get_string_data(2), None);
+    assert_eq!(dump.get_proc_name(1), None);
+    assert_eq!(dump.value_to_string_data(9, 0), None);
+}
+
+#[test]
+fn byond_514() {
+    let mut bytes = vec![];
+
+    // 0x2000: the string table, pointers to the entries
+    bytes.extend(words(&[0x2008, 0x2014]));
+
+    // 0x2008: the string entries, text first and then fields that aren't read
+    bytes.extend(words(&[0x2020, 0, 0]));
+    bytes.extend(words(&[0x2024, 1, 0]));
+
+    // 0x2020: the text
+    bytes.extend_from_slice(b"src\0/proc/tick\0\0");
+
+    // 0x2030: two procs, the second being /proc/tick
+    bytes.extend(words(&[0; 9]));
+    bytes.extend(words(&[1, 0, 0, 0, 0, 0, 0, 0, 0]));
+
+    let layout = Layout::byond_514((0x2000, 2), (0x2030, 2));
+    let mut dump = MemoryDump::new(0x2000, bytes, layout);
+    assert_eq!(dump.get_variable_name(0), Some(b"src".to_vec()));
+    assert_eq!(dump.get_proc_name(0), Some("src".to_owned()));
+    assert_eq!(dump.get_proc_name(1), Some("/proc/tick".to_owned()));
+    assert_eq!(dump.get_proc_name(2), None);
+}
+
+#[test]
+fn pointer_width() {
+    let base: u64 = 0x7FFF_0000_0000;
+    let mut bytes = vec![];
+
+    // The string table is 8 byte pointers straight to the text, which can be past 4GB
+    bytes.extend((base + 16).to_le_bytes());
+    bytes.extend((base + 20).to_le_bytes());
+    bytes.extend_from_slice(b"one\0two\0");
+
+    let layout = Layout {
+        pointer_width: PointerWidth::Bits64,
+        strings: Table {
+            address: base,
+            count: 2,
+            entry_size: 8,
+            indirect: false,
+            name_offset: 0,
+        },
+        ..Layout::default()
+    };
+
+    let mut dump = MemoryDump::new(base, bytes, layout);
+    assert_eq!(dump.get_string_data(0), Some(b"one".to_vec()));
+    assert_eq!(dump.get_string_data(1), Some(b"two".to_vec()));
+
+    // Read as 32-bit, the same pointers don't point anywhere in the dump
+    dump.layout.pointer_width = PointerWidth::Bits32;
+    assert_eq!(dump.get_string_data(0), None);
+}
@@ -1,5 +1,5 @@
 use crate::{
-    assembler::{AssembleEnv, AssembleError, Assembler},
+    assembler::{AssembleEnv, AssembleError, AssembleErrorKind, Assembler, RelocationEncoding},
     disassembler::{DisassembleEnv, DisassembleError, Disassembler},
 };
 use std::fmt;
@@ -11,6 +11,20 @@ pub trait Operand: Sized {
         -> Result<Self, DisassembleError>;
 
     fn serialize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    /// The operand, if it's a variable
+    fn variable(&self) -> Option<&Variable> {
+        None
+    }
+}
+
+// Lets an operand be written with `format!` and friends
+pub(crate) struct Serialized<'a, T: Operand>(pub &'a T);
+
+impl<'a, T: Operand> fmt::Display for Serialized<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.serialize(f)
+    }
 }
 
 // This is a separate trait just so that the large amount of nom code can live in operands_deserialize
@@ -86,6 +100,7 @@ impl Operand for f32 {
 // Label
 //
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Label(pub String);
 
 impl Operand for Label {
@@ -115,6 +130,7 @@ impl Operand for Label {
 // Proc
 //
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Proc {
     pub path: String,
     pub id: Option<u32>
@@ -132,9 +148,8 @@ impl Proc {
 impl Operand for Proc {
     fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
         let idx = asm
-            .env
             .get_proc_index(&self.path)
-            .ok_or(AssembleError::ProcNotFound(self.path.to_owned()))?;
+            .ok_or_else(|| AssembleErrorKind::ProcNotFound(self.path.to_owned()))?;
         asm.emit(idx);
         Ok(())
     }
@@ -168,17 +183,23 @@ impl Operand for Proc {
 // DMString
 //
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DMString(pub Vec<u8>);
 
 impl DMString {
-    fn get_string_index<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> u32 {
-        asm.env.get_string_index(&self.0).unwrap()
+    fn get_string_index<E: AssembleEnv>(
+        &self,
+        asm: &mut Assembler<E>,
+        encoding: RelocationEncoding,
+    ) -> Result<u32, AssembleError> {
+        asm.get_string_index(&self.0, encoding)
+            .ok_or_else(|| AssembleErrorKind::StringNotFound(String::from_utf8_lossy(&self.0).into_owned()).into())
     }
 }
 
 impl Operand for DMString {
     fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
-        let idx = self.get_string_index(asm);
+        let idx = self.get_string_index(asm, RelocationEncoding::Word)?;
         asm.emit(idx);
         Ok(())
     }
@@ -289,6 +310,7 @@ impl Operand for DMString {
 // (TODO: Use the debugger to single-step over this and know for sure.)
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RangeParams;
 
 impl Operand for RangeParams {
@@ -322,6 +344,7 @@ impl Operand for RangeParams {
 // IsInParams
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IsInParams {
     Range,
     Value,
@@ -368,14 +391,22 @@ impl Operand for IsInParams {
 // SwitchParams
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwitchParams {
     pub default: Label,
     pub cases: Vec<(Value, Label)>,
 }
 
 impl Operand for SwitchParams {
-    fn assemble<E: AssembleEnv>(&self, _asm: &mut Assembler<E>) -> Result<(), AssembleError> {
-        unimplemented!();
+    fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
+        asm.emit(self.cases.len() as u32);
+
+        for (value, label) in &self.cases {
+            value.assemble(asm)?;
+            label.assemble(asm)?;
+        }
+
+        self.default.assemble(asm)
     }
 
     fn disassemble<E: DisassembleEnv>(
@@ -413,14 +444,22 @@ impl Operand for SwitchParams {
 // PickSwitchParams
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PickSwitchParams {
     pub default: Label,
     pub cases: Vec<(u32, Label)>,
 }
 
 impl Operand for PickSwitchParams {
-    fn assemble<E: AssembleEnv>(&self, _asm: &mut Assembler<E>) -> Result<(), AssembleError> {
-        unimplemented!();
+    fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
+        asm.emit(self.cases.len() as u32);
+
+        for (threshold, label) in &self.cases {
+            threshold.assemble(asm)?;
+            label.assemble(asm)?;
+        }
+
+        self.default.assemble(asm)
     }
 
     fn disassemble<E: DisassembleEnv>(
@@ -459,6 +498,7 @@ impl Operand for PickSwitchParams {
 // SwitchRangeParams
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwitchRangeParams {
     pub default: Label,
     pub cases: Vec<(Value, Label)>,
@@ -466,8 +506,24 @@ pub struct SwitchRangeParams {
 }
 
 impl Operand for SwitchRangeParams {
-    fn assemble<E: AssembleEnv>(&self, _asm: &mut Assembler<E>) -> Result<(), AssembleError> {
-        unimplemented!();
+    fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
+        // The ranges come first in the bytecode
+        asm.emit(self.range_cases.len() as u32);
+
+        for (min, max, label) in &self.range_cases {
+            min.assemble(asm)?;
+            max.assemble(asm)?;
+            label.assemble(asm)?;
+        }
+
+        asm.emit(self.cases.len() as u32);
+
+        for (value, label) in &self.cases {
+            value.assemble(asm)?;
+            label.assemble(asm)?;
+        }
+
+        self.default.assemble(asm)
     }
 
     fn disassemble<E: DisassembleEnv>(
@@ -525,6 +581,7 @@ impl Operand for SwitchRangeParams {
 // PickProbParams
 //
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PickProbParams {
     pub cases: Vec<Label>,
 }
@@ -566,6 +623,7 @@ impl Operand for PickProbParams {
 // Value
 //
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Null,
     Number(f32),
@@ -588,12 +646,14 @@ pub enum Value {
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValueOp {
     pub raw: Option<ValueOpRaw>,
     pub value: Value
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValueOpRaw {
     pub tag: u8,
     pub data: u32
@@ -602,6 +662,10 @@ pub struct ValueOpRaw {
 impl Operand for ValueOp {
     fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
         if let Some(raw) = &self.raw {
+            if raw.data > 0xFFFFFF {
+                return Err(AssembleErrorKind::OperandTooLarge(raw.data).into());
+            }
+
             asm.emit((raw.tag as u32) | ((raw.data & 0xFF0000) >> 8));
             asm.emit(raw.data & 0xFFFF);
             return Ok(())
@@ -656,8 +720,9 @@ impl Operand for Value {
     fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
         let (tag, data): (u8, u32) = match self {
             Self::Null => (0x00, 0x00),
+            Self::File => (0x27, 0x00),
             Self::Raw { tag, data } => (*tag, *data),
-            Self::DMString(value) => (0x06, value.get_string_index(asm)),
+            Self::DMString(value) => (0x06, value.get_string_index(asm, RelocationEncoding::Value)?),
 
             // Numbers are a special case. They use an extra operand.
             Self::Number(num) => {
@@ -668,15 +733,18 @@ impl Operand for Value {
                 return Ok(());
             }
 
-            Self::Path(path) => match asm.env.get_type(path) {
+            Self::Path(path) => match asm.get_type(path) {
                 Some(t) => t,
-                None => return Err(AssembleError::TypeNotFound(path.clone())),
+                None => return Err(AssembleErrorKind::TypeNotFound(path.clone()).into()),
             },
 
-            // TODO: This _will_ bite me in the ass, implement assemble errors asap
-            other => return Err(AssembleError::UnsupportedValue(other.clone())),
+            other => return Err(AssembleErrorKind::UnsupportedValue(other.clone()).into()),
         };
 
+        if data > 0xFFFFFF {
+            return Err(AssembleErrorKind::OperandTooLarge(data).into());
+        }
+
         // The top 8 bits of data live in tag
         asm.emit((tag as u32) | ((data & 0xFF0000) >> 8));
         asm.emit(data & 0xFFFF);
@@ -761,6 +829,7 @@ impl Operand for Value {
 // Variable
 //
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Variable {
     Null,
     World,
@@ -773,6 +842,10 @@ pub enum Variable {
     CacheIndex,
     Arg(u32),
     Local(u32),
+
+    /// A local along with the name it had in the source, for when the env knows it (see
+    /// `DisassembleEnv::get_local_name`). Only the slot makes it into the bytecode, the same as `Local`.
+    NamedLocal(u32, DMString),
     Global(DMString),
     SetCache(Box<Variable>, Box<Variable>),
     Initial(Box<Variable>),
@@ -786,6 +859,40 @@ pub enum Variable {
     //RuntimeProcField(Box<Variable>, Vec<DMString>, DMString),
 }
 
+impl Variable {
+    /// A chain of fields through the cache the way it'd be written in DM, such as `src.a.b` for
+    /// `cache = src; cache = cache["a"]; cache["b"]`. `None` for anything that isn't one.
+    pub fn dotted(&self) -> Option<String> {
+        let (base, mut rest) = match self {
+            Variable::SetCache(base, rest) => (base, rest),
+            _ => return None,
+        };
+
+        let mut path = Serialized(base.as_ref()).to_string();
+
+        loop {
+            match rest.as_ref() {
+                Variable::SetCache(field, next) => match field.as_ref() {
+                    Variable::Field(name) => {
+                        path.push('.');
+                        path.push_str(&String::from_utf8_lossy(&name.0));
+                        rest = next;
+                    }
+                    _ => return None,
+                },
+
+                Variable::Field(name) | Variable::DynamicProc(name) | Variable::DynamicVerb(name) => {
+                    path.push('.');
+                    path.push_str(&String::from_utf8_lossy(&name.0));
+                    return Some(path);
+                }
+
+                _ => return None,
+            }
+        }
+    }
+}
+
 impl Operand for Variable {
     fn assemble<E: AssembleEnv>(&self, asm: &mut Assembler<E>) -> Result<(), AssembleError> {
         use crate::access_modifiers;
@@ -795,9 +902,10 @@ impl Operand for Variable {
             name: &DMString,
         ) -> Result<(), AssembleError> {
             let id = asm
-                .env
                 .get_variable_name_index(&name.0)
-                .ok_or(AssembleError::InvalidVariableName)?;
+                .ok_or_else(|| {
+                    AssembleErrorKind::InvalidVariableName(String::from_utf8_lossy(&name.0).into_owned())
+                })?;
             asm.emit(id);
             Ok(())
         }
@@ -817,7 +925,7 @@ impl Operand for Variable {
                 asm.emit(access_modifiers::Arg);
                 asm.emit(*idx);
             }
-            Variable::Local(idx) => {
+            Variable::Local(idx) | Variable::NamedLocal(idx, _) => {
                 asm.emit(access_modifiers::Local);
                 asm.emit(*idx);
             }
@@ -915,7 +1023,14 @@ impl Operand for Variable {
             access_modifiers::CacheKey => Variable::CacheKey,
             access_modifiers::CacheIndex => Variable::CacheIndex,
             access_modifiers::Arg => Variable::Arg(dism.read_u32()?),
-            access_modifiers::Local => Variable::Local(dism.read_u32()?),
+            access_modifiers::Local => {
+                let slot = dism.read_u32()?;
+
+                match dism.env.get_local_name(slot) {
+                    Some(name) => Variable::NamedLocal(slot, DMString(name)),
+                    None => Variable::Local(slot),
+                }
+            }
             access_modifiers::Global => Variable::Global(read_variable_name(dism)?),
             access_modifiers::SetCache => Variable::SetCache(
                 Box::new(Variable::disassemble(dism)?),
@@ -961,6 +1076,13 @@ impl Operand for Variable {
                 x.serialize(f)?;
                 write!(f, ")")
             }
+            Variable::NamedLocal(x, name) => {
+                write!(f, "local(")?;
+                x.serialize(f)?;
+                write!(f, ", ")?;
+                name.serialize(f)?;
+                write!(f, ")")
+            }
             Variable::Global(name) => {
                 write!(f, "global(")?;
                 name.serialize(f)?;
@@ -1009,4 +1131,8 @@ impl Operand for Variable {
             }
         }
     }
+
+    fn variable(&self) -> Option<&Variable> {
+        Some(self)
+    }
 }
@@ -1,12 +1,115 @@
 use crate::list_operands::*;
 use crate::operands::{OperandDeserialize, *};
 use crate::parser;
+use nom::branch::*;
+use nom::bytes::complete::{tag, take_while, take_while1};
 use nom::combinator::*;
+use nom::error::ErrorKind;
 use nom::error::FromExternalError;
 use nom::error::ParseError;
+use nom::multi::*;
 use nom::sequence::*;
 use nom::{character::complete::*, *};
 
+// Everything is separated by ", " (with a trailing one) so a list can't run on to the next line
+fn list_separator<'a, E>(i: &'a str) -> IResult<&'a str, (), E>
+where
+    E: ParseError<&'a str>,
+{
+    map(pair(char(','), space0), |_| ())(i)
+}
+
+fn arrow<'a, E>(i: &'a str) -> IResult<&'a str, (), E>
+where
+    E: ParseError<&'a str>,
+{
+    map(tag(" => "), |_| ())(i)
+}
+
+fn default_case<'a, E>(i: &'a str) -> IResult<&'a str, Label, E>
+where
+    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+{
+    delimited(pair(tag("default"), arrow), Label::deserialize, list_separator)(i)
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '/'
+}
+
+fn parse_path<'a, E>(i: &'a str) -> IResult<&'a str, &'a str, E>
+where
+    E: ParseError<&'a str>,
+{
+    recognize(pair(char('/'), take_while(is_path_char)))(i)
+}
+
+fn parse_number<'a, E>(i: &'a str) -> IResult<&'a str, f32, E>
+where
+    E: ParseError<&'a str>,
+{
+    map_opt(
+        recognize(pair(
+            opt(char('-')),
+            alt((
+                tag("inf"),
+                tag("NaN"),
+                recognize(pair(digit1, opt(pair(char('.'), digit1)))),
+            )),
+        )),
+        |x: &str| x.parse::<f32>().ok(),
+    )(i)
+}
+
+// The text macros `DMString::serialize` writes out, besides the `[]` of embedded expressions.
+// Where two share the same text (and `\n`, which is read as a newline) the first one wins.
+const TEXT_MACROS: &[(u8, &str)] = &[
+    (6, "\\a"),
+    (7, "\\A"),
+    (8, "\\the"),
+    (9, "\\The"),
+    (10, "\\he"),
+    (11, "\\He"),
+    (12, "\\his"),
+    (13, "\\His"),
+    (14, "\\hers"),
+    (15, "\\Hers"),
+    (16, "\\him "),
+    (17, "\\himself"),
+    (18, "\\... "),
+    (20, "\\s "),
+    (21, "\\proper "),
+    (22, "\\improper "),
+    (23, "\\bold "),
+    (24, "\\italic "),
+    (25, "\\underline "),
+    (26, "\\strike "),
+    (27, "\\font"),
+    (28, "\\color"),
+    (31, "\\red "),
+    (32, "\\green "),
+    (33, "\\blue "),
+    (34, "\\black "),
+    (35, "\\white "),
+    (36, "\\yellow "),
+    (37, "\\cyan "),
+    (38, "\\magenta "),
+    (39, "\\beep "),
+    (40, "\\link"),
+    (42, "\\ref[]"),
+    (43, "\\icon[]"),
+    (44, "\\roman[]"),
+    (45, "\\Roman[]"),
+];
+
+fn parse_text_macro(i: &str) -> Option<(&str, u8)> {
+    TEXT_MACROS
+        .iter()
+        .filter(|(_, text)| i.starts_with(text))
+        .max_by_key(|(_, text)| text.len())
+        .map(|(code, text)| (&i[text.len()..], *code))
+}
+
 impl OperandDeserialize for u32 {
     fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
@@ -39,25 +142,72 @@ impl OperandDeserialize for Label {
 }
 
 impl OperandDeserialize for Proc {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        map(parse_path, |x: &str| Proc::from_path(x.to_owned()))(i)
     }
 }
 
 impl OperandDeserialize for DMString {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        // TODO: String formatting
-        // map(
-        //     delimited(char('"'), recognize(take_while(|x| x != '"')), char('"')),
-        //     |x: &str| DMString(x.into()),
-        // )(i)
-        unimplemented!()
+        let (mut i, _) = char('"')(i)?;
+        let mut data = vec![];
+
+        loop {
+            let c = match i.chars().next() {
+                Some(c) => c,
+                None => return Err(Err::Error(E::from_error_kind(i, ErrorKind::Char))),
+            };
+
+            match c {
+                '"' => return Ok((&i[1..], DMString(data))),
+
+                '\\' => {
+                    let escaped = match i[1..].chars().next() {
+                        Some('n') => Some(b'\n'),
+                        Some('r') => Some(b'\r'),
+                        Some(c @ '\\') | Some(c @ '[') | Some(c @ ']') | Some(c @ '"') => Some(c as u8),
+                        _ => None,
+                    };
+
+                    if let Some(byte) = escaped {
+                        data.push(byte);
+                        i = &i[2..];
+                        continue;
+                    }
+
+                    match parse_text_macro(i) {
+                        Some((rest, code)) => {
+                            data.extend_from_slice(&[0xFF, code]);
+                            i = rest;
+                        }
+                        None => return Err(Err::Error(E::from_error_kind(i, ErrorKind::Escaped))),
+                    }
+                }
+
+                // Embedded expressions
+                '[' if i.starts_with("[]\\th") => {
+                    data.extend_from_slice(&[0xFF, 5]);
+                    i = &i[5..];
+                }
+
+                '[' if i.starts_with("[]") => {
+                    data.extend_from_slice(&[0xFF, 1]);
+                    i = &i[2..];
+                }
+
+                c => {
+                    let mut buf = [0; 4];
+                    data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    i = &i[c.len_utf8()..];
+                }
+            }
+        }
     }
 }
 
@@ -72,82 +222,231 @@ impl OperandDeserialize for RangeParams {
 }
 
 impl OperandDeserialize for IsInParams {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        alt((
+            value(IsInParams::Range, tag("Range")),
+            value(IsInParams::Value, tag("Value")),
+        ))(i)
     }
 }
 
 impl OperandDeserialize for SwitchParams {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        map(
+            pair(
+                default_case,
+                many0(terminated(
+                    separated_pair(Value::deserialize, arrow, Label::deserialize),
+                    list_separator,
+                )),
+            ),
+            |(default, cases)| SwitchParams { default, cases },
+        )(i)
     }
 }
 
 impl OperandDeserialize for PickSwitchParams {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        map(
+            pair(
+                default_case,
+                many0(terminated(
+                    separated_pair(u32::deserialize, arrow, Label::deserialize),
+                    list_separator,
+                )),
+            ),
+            |(default, cases)| PickSwitchParams { default, cases },
+        )(i)
     }
 }
 
 impl OperandDeserialize for SwitchRangeParams {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        let range = delimited(
+            char('('),
+            separated_pair(Value::deserialize, tag(" to "), Value::deserialize),
+            char(')'),
+        );
+
+        map(
+            tuple((
+                default_case,
+                many0(terminated(
+                    separated_pair(Value::deserialize, arrow, Label::deserialize),
+                    list_separator,
+                )),
+                many0(terminated(
+                    separated_pair(range, arrow, Label::deserialize),
+                    list_separator,
+                )),
+            )),
+            |(default, cases, range_cases)| SwitchRangeParams {
+                default,
+                cases,
+                range_cases: range_cases
+                    .into_iter()
+                    .map(|((min, max), label)| (min, max, label))
+                    .collect(),
+            },
+        )(i)
     }
 }
 
 impl OperandDeserialize for PickProbParams {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        map(many0(terminated(Label::deserialize, list_separator)), |cases| {
+            PickProbParams { cases }
+        })(i)
     }
 }
 
 impl OperandDeserialize for Value {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        // `ref(...)` is the tag in hex followed by exactly 8 hex digits of data
+        let raw = map_opt(
+            delimited(tag("ref("), take_while1(|c: char| c.is_ascii_hexdigit()), char(')')),
+            |x: &str| {
+                if x.len() <= 8 {
+                    return None;
+                }
+
+                let (tag, data) = x.split_at(x.len() - 8);
+                Some(Value::Raw {
+                    tag: u8::from_str_radix(tag, 16).ok()?,
+                    data: u32::from_str_radix(data, 16).ok()?,
+                })
+            },
+        );
+
+        alt((
+            value(Value::Null, tag("null")),
+            map(DMString::deserialize, Value::DMString),
+            map(
+                delimited(char('\''), take_while(|c| c != '\''), char('\'')),
+                |x: &str| Value::Resource(x.to_owned()),
+            ),
+            raw,
+            map(parse_path, |x: &str| match x {
+                "/file" => Value::File,
+                path => Value::Path(path.to_owned()),
+            }),
+            map(parse_number, Value::Number),
+        ))(i)
     }
 }
 
 impl OperandDeserialize for ValueOp {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
-        where
-            E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
+    where
+        E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        map(Value::deserialize, ValueOp::from)(i)
     }
 }
 
 impl OperandDeserialize for Variable {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        fn call<'a, O, E, F>(name: &'static str, inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+        where
+            E: ParseError<&'a str>,
+            F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+        {
+            delimited(pair(tag(name), char('(')), inner, char(')'))
+        }
+
+        alt((
+            // `cache...` has to go from the longest to the shortest
+            map(
+                separated_pair(
+                    preceded(tag("cache = "), Variable::deserialize),
+                    tag("; "),
+                    Variable::deserialize,
+                ),
+                |(lhs, rhs)| Variable::SetCache(Box::new(lhs), Box::new(rhs)),
+            ),
+            value(Variable::CacheIndex, tag("cache[cache_key]")),
+            map(delimited(tag("cache["), DMString::deserialize, char(']')), Variable::Field),
+            value(Variable::CacheKey, tag("cache_key")),
+            value(Variable::Cache, tag("cache")),
+            value(Variable::Null, tag("null")),
+            value(Variable::World, tag("world")),
+            value(Variable::Usr, tag("usr")),
+            value(Variable::Src, tag("src")),
+            value(Variable::Args, tag("args")),
+            value(Variable::Dot, tag("dot")),
+            map(call("arg", u32::deserialize), Variable::Arg),
+            map(
+                call("local", pair(u32::deserialize, opt(preceded(tag(", "), DMString::deserialize)))),
+                |(slot, name)| match name {
+                    Some(name) => Variable::NamedLocal(slot, name),
+                    None => Variable::Local(slot),
+                },
+            ),
+            map(call("global", DMString::deserialize), Variable::Global),
+            map(call("initial", Variable::deserialize), |x| Variable::Initial(Box::new(x))),
+            map(call("issaved", Variable::deserialize), |x| Variable::IsSaved(Box::new(x))),
+            map(call("static_verb", Proc::deserialize), Variable::StaticVerb),
+            map(call("dynamic_verb", DMString::deserialize), Variable::DynamicVerb),
+            map(call("static_proc", Proc::deserialize), Variable::StaticProc),
+            map(call("dynamic_proc", DMString::deserialize), Variable::DynamicProc),
+        ))(i)
     }
 }
 
 impl OperandDeserialize for TypeFilter {
-    fn deserialize<'a, E>(_i: &'a str) -> IResult<&str, Self, E>
+    fn deserialize<'a, E>(i: &'a str) -> IResult<&str, Self, E>
     where
         E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
     {
-        panic!("TODO");
+        let flag = map_opt(parser::parse_identifier, |x: &str| {
+            let flag = match x {
+                "mob" => TypeFilter::MOB,
+                "obj" => TypeFilter::OBJ,
+                "text" => TypeFilter::TEXT,
+                "num" => TypeFilter::NUM,
+                "file" => TypeFilter::FILE,
+                "turf" => TypeFilter::TURF,
+                "key" => TypeFilter::KEY,
+                "null" => TypeFilter::NULL,
+                "area" => TypeFilter::AREA,
+                "icon" => TypeFilter::ICON,
+                "sound" => TypeFilter::SOUND,
+                "message" => TypeFilter::MESSAGE,
+                "anything" => TypeFilter::ANYTHING,
+                "datum_instances" => TypeFilter::DATUM_INSTANCES,
+                "password" => TypeFilter::PASSWORD,
+                "command_text" => TypeFilter::COMMAND_TEXT,
+                "color" => TypeFilter::COLOR,
+                _ => return None,
+            };
+
+            Some(flag)
+        });
+
+        map(
+            delimited(char('('), many0(terminated(flag, tag(" | "))), char(')')),
+            |flags| flags.into_iter().fold(TypeFilter::empty(), |acc, flag| acc | flag),
+        )(i)
     }
 }
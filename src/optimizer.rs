@@ -0,0 +1,489 @@
+//! Peephole optimizations over a stream of nodes, mostly cleaning up after the compiler.
+
+use std::collections::HashSet;
+
+use crate::cfg::falls_through;
+use crate::operands::{Label, Variable};
+use crate::Instruction;
+use crate::Node;
+
+/// Runs every pass until none of them find anything left to do.
+pub fn optimize<D>(mut nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    loop {
+        let before = nodes.len();
+
+        nodes = remove_discarded_pushes(nodes);
+        nodes = remove_cache_round_trips(nodes);
+        nodes = remove_empty_cache_saves(nodes);
+        nodes = remove_jumps_to_next(nodes);
+        nodes = remove_unreachable(nodes);
+        nodes = remove_unused_labels(nodes);
+
+        if nodes.len() == before {
+            return nodes;
+        }
+    }
+}
+
+/// Only the passes that clean up after code from different places has been stuck together:
+/// jumps to the next instruction, cache saves with nothing in between and labels that nothing jumps to.
+/// The assembler can run this right before encoding, see `AssembleOptions::peephole`.
+pub fn peephole<D>(mut nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    loop {
+        let before = nodes.len();
+
+        nodes = remove_empty_cache_saves(nodes);
+        nodes = remove_jumps_to_next(nodes);
+        nodes = remove_unused_labels(nodes);
+
+        if nodes.len() == before {
+            return nodes;
+        }
+    }
+}
+
+fn instruction<D>(node: Option<&Node<D>>) -> Option<&Instruction> {
+    match node {
+        Some(Node::Instruction(ins, _)) => Some(ins),
+        _ => None,
+    }
+}
+
+/// `PushInt`/`PushVal` immediately followed by `Pop`.
+pub fn remove_discarded_pushes<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let mut out: Vec<Node<D>> = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        if let Node::Instruction(Instruction::Pop, _) = node {
+            if let Some(Instruction::PushInt(_)) | Some(Instruction::PushVal(_)) = instruction(out.last()) {
+                out.pop();
+                continue;
+            }
+        }
+
+        out.push(node);
+    }
+
+    out
+}
+
+// Whether accessing the variable can read the cache
+fn uses_cache(var: &Variable) -> bool {
+    !matches!(
+        var,
+        Variable::Null
+            | Variable::World
+            | Variable::Usr
+            | Variable::Src
+            | Variable::Args
+            | Variable::Dot
+            | Variable::Arg(_)
+            | Variable::Local(_)
+            | Variable::NamedLocal(..)
+            | Variable::Global(_)
+    )
+}
+
+// Whether the value in the cache can never be read again on the way through these nodes.
+// This only follows straight-line code and gives up on anything it doesn't understand.
+fn is_cache_dead<D>(nodes: &[Node<D>]) -> bool {
+    for node in nodes {
+        let ins = match node {
+            Node::Instruction(ins, _) => ins,
+            _ => continue,
+        };
+
+        match ins {
+            Instruction::Ret | Instruction::End => return true,
+            Instruction::SetVar(Variable::Cache) => return true,
+
+            Instruction::GetVar(var) | Instruction::SetVar(var) if !uses_cache(var) => {}
+
+            Instruction::PushInt(_)
+            | Instruction::PushVal(_)
+            | Instruction::Pop
+            | Instruction::NewList(_)
+            | Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::Mod
+            | Instruction::Not
+            | Instruction::Test
+            | Instruction::Teq
+            | Instruction::Tne
+            | Instruction::Tl
+            | Instruction::Tg
+            | Instruction::Tle
+            | Instruction::Tge
+            | Instruction::DbgLine(_) => {}
+
+            _ => return false,
+        }
+    }
+
+    // Running off the end of the proc is the same as End
+    true
+}
+
+/// `SetVar cache` immediately followed by `GetVar cache`, when nothing reads the cache afterwards.
+pub fn remove_cache_round_trips<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let mut remove = vec![false; nodes.len()];
+
+    for idx in 0..nodes.len().saturating_sub(1) {
+        if remove[idx] {
+            continue;
+        }
+
+        let is_round_trip = matches!(
+            (instruction(nodes.get(idx)), instruction(nodes.get(idx + 1))),
+            (
+                Some(Instruction::SetVar(Variable::Cache)),
+                Some(Instruction::GetVar(Variable::Cache))
+            )
+        );
+
+        if is_round_trip && is_cache_dead(&nodes[idx + 2..]) {
+            remove[idx] = true;
+            remove[idx + 1] = true;
+        }
+    }
+
+    nodes
+        .into_iter()
+        .zip(remove)
+        .filter_map(|(node, remove)| if remove { None } else { Some(node) })
+        .collect()
+}
+
+/// `PushCache` immediately followed by `PopCache`, which saves the cache only to put it straight back.
+pub fn remove_empty_cache_saves<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let mut out: Vec<Node<D>> = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        if let Node::Instruction(Instruction::PopCache, _) = node {
+            if let Some(Instruction::PushCache) = instruction(out.last()) {
+                out.pop();
+                continue;
+            }
+        }
+
+        out.push(node);
+    }
+
+    out
+}
+
+/// `Jmp` to a label that comes before any other instruction.
+pub fn remove_jumps_to_next<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let mut remove = vec![false; nodes.len()];
+
+    for (idx, node) in nodes.iter().enumerate() {
+        let destination = match node {
+            Node::Instruction(Instruction::Jmp(Label(destination)), _) => destination,
+            _ => continue,
+        };
+
+        for next in &nodes[idx + 1..] {
+            match next {
+                Node::Label(name) if name == destination => {
+                    remove[idx] = true;
+                    break;
+                }
+
+                Node::Label(_) | Node::Comment(_) => {}
+                Node::Instruction(..) | Node::RawData(..) => break,
+            }
+        }
+    }
+
+    nodes
+        .into_iter()
+        .zip(remove)
+        .filter_map(|(node, remove)| if remove { None } else { Some(node) })
+        .collect()
+}
+
+/// Every label an instruction can jump to
+pub fn jump_destinations(ins: &Instruction) -> Vec<&Label> {
+    match ins {
+        Instruction::Jmp(label)
+        | Instruction::Jnz(label)
+        | Instruction::Jz(label)
+        | Instruction::Spawn(label)
+        | Instruction::JmpOr(label)
+        | Instruction::JmpAnd(label)
+        | Instruction::JmpLoop(label)
+        | Instruction::JnzLoop(label)
+        | Instruction::JzLoop(label)
+        | Instruction::ForRange(label, _)
+        | Instruction::ForRangeStep(label, _)
+        | Instruction::Try(label)
+        | Instruction::Catch(label)
+        | Instruction::TryJmp(label)
+        | Instruction::SetCacheJmpIfNull(label)
+        | Instruction::SetCachePopJmpIfNull(label) => vec![label],
+
+        Instruction::Switch(params) => std::iter::once(&params.default)
+            .chain(params.cases.iter().map(|(_, label)| label))
+            .collect(),
+
+        Instruction::PickSwitch(params) => std::iter::once(&params.default)
+            .chain(params.cases.iter().map(|(_, label)| label))
+            .collect(),
+
+        Instruction::SwitchRange(params) => std::iter::once(&params.default)
+            .chain(params.cases.iter().map(|(_, label)| label))
+            .chain(params.range_cases.iter().map(|(_, _, label)| label))
+            .collect(),
+
+        Instruction::PickProb(params) => params.cases.iter().collect(),
+
+        _ => vec![],
+    }
+}
+
+/// `jump_destinations`, but for renaming them
+pub fn jump_destinations_mut(ins: &mut Instruction) -> Vec<&mut Label> {
+    match ins {
+        Instruction::Jmp(label)
+        | Instruction::Jnz(label)
+        | Instruction::Jz(label)
+        | Instruction::Spawn(label)
+        | Instruction::JmpOr(label)
+        | Instruction::JmpAnd(label)
+        | Instruction::JmpLoop(label)
+        | Instruction::JnzLoop(label)
+        | Instruction::JzLoop(label)
+        | Instruction::ForRange(label, _)
+        | Instruction::ForRangeStep(label, _)
+        | Instruction::Try(label)
+        | Instruction::Catch(label)
+        | Instruction::TryJmp(label)
+        | Instruction::SetCacheJmpIfNull(label)
+        | Instruction::SetCachePopJmpIfNull(label) => vec![label],
+
+        Instruction::Switch(params) => std::iter::once(&mut params.default)
+            .chain(params.cases.iter_mut().map(|(_, label)| label))
+            .collect(),
+
+        Instruction::PickSwitch(params) => std::iter::once(&mut params.default)
+            .chain(params.cases.iter_mut().map(|(_, label)| label))
+            .collect(),
+
+        Instruction::SwitchRange(params) => std::iter::once(&mut params.default)
+            .chain(params.cases.iter_mut().map(|(_, label)| label))
+            .chain(params.range_cases.iter_mut().map(|(_, _, label)| label))
+            .collect(),
+
+        Instruction::PickProb(params) => params.cases.iter_mut().collect(),
+
+        _ => vec![],
+    }
+}
+
+fn referenced_labels<D>(nodes: &[Node<D>]) -> HashSet<String> {
+    let mut labels = HashSet::new();
+
+    for node in nodes {
+        if let Node::Instruction(ins, _) = node {
+            for Label(name) in jump_destinations(ins) {
+                labels.insert(name.clone());
+            }
+        }
+    }
+
+    labels
+}
+
+/// Anything after an unconditional jump, return or throw, up until a label that something jumps to.
+pub fn remove_unreachable<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let referenced = referenced_labels(&nodes);
+    let mut reachable = true;
+
+    nodes
+        .into_iter()
+        .filter(|node| match node {
+            Node::Label(name) if referenced.contains(name) => {
+                reachable = true;
+                true
+            }
+
+            Node::Instruction(ins, _) if reachable => {
+                reachable = falls_through(ins);
+                true
+            }
+
+            _ => reachable,
+        })
+        .collect()
+}
+
+/// Labels that nothing jumps to.
+pub fn remove_unused_labels<D>(nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    let referenced = referenced_labels(&nodes);
+
+    nodes
+        .into_iter()
+        .filter(|node| match node {
+            Node::Label(name) => referenced.contains(name),
+            _ => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn instructions(instructions: Vec<Instruction>) -> Vec<Node> {
+    instructions
+        .into_iter()
+        .map(|ins| Node::Instruction(ins, ()))
+        .collect()
+}
+
+#[test]
+fn discarded_pushes() {
+    let nodes = instructions(vec![
+        Instruction::PushInt(1),
+        Instruction::Pop,
+        Instruction::GetVar(Variable::Src),
+        Instruction::Pop,
+        Instruction::End,
+    ]);
+
+    assert_eq!(
+        optimize(nodes),
+        instructions(vec![
+            Instruction::GetVar(Variable::Src),
+            Instruction::Pop,
+            Instruction::End,
+        ])
+    );
+}
+
+#[test]
+fn cache_round_trips() {
+    let nodes = instructions(vec![
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::SetVar(Variable::Cache),
+        Instruction::GetVar(Variable::Cache),
+        Instruction::PushInt(1),
+        Instruction::Add,
+        Instruction::Ret,
+    ]);
+
+    assert_eq!(
+        optimize(nodes),
+        instructions(vec![
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::PushInt(1),
+            Instruction::Add,
+            Instruction::Ret,
+        ])
+    );
+
+    // The cache is read again later, so it has to be written
+    let nodes = instructions(vec![
+        Instruction::GetVar(Variable::Arg(0)),
+        Instruction::SetVar(Variable::Cache),
+        Instruction::GetVar(Variable::Cache),
+        Instruction::GetVar(Variable::Field(crate::operands::DMString(b"x".to_vec()))),
+        Instruction::Ret,
+    ]);
+
+    assert_eq!(optimize(nodes.clone()), nodes);
+}
+
+#[test]
+fn fragment_cleanup() {
+    // Two fragments that each save the cache and jump to their own end
+    let nodes = vec![
+        Node::Instruction(Instruction::PushCache, ()),
+        Node::Instruction(Instruction::GetVar(Variable::Arg(0)), ()),
+        Node::Instruction(Instruction::Jmp(Label("end_a".to_owned())), ()),
+        Node::Label("end_a".to_owned()),
+        Node::Instruction(Instruction::PopCache, ()),
+        Node::Instruction(Instruction::PushCache, ()),
+        Node::Instruction(Instruction::PopCache, ()),
+        Node::Instruction(Instruction::Pop, ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    // The push and pop left behind are the compiler optimizer's business
+    assert_eq!(
+        peephole(nodes),
+        instructions(vec![
+            Instruction::PushCache,
+            Instruction::GetVar(Variable::Arg(0)),
+            Instruction::PopCache,
+            Instruction::Pop,
+            Instruction::End,
+        ])
+    );
+}
+
+#[test]
+fn jumps_to_next() {
+    let nodes = vec![
+        Node::Instruction(Instruction::Jmp(Label("LAB_0000".to_owned())), ()),
+        Node::Label("LAB_0001".to_owned()),
+        Node::Label("LAB_0000".to_owned()),
+        Node::Instruction(Instruction::Jmp(Label("LAB_0001".to_owned())), ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    assert_eq!(
+        remove_jumps_to_next(nodes),
+        vec![
+            Node::Label("LAB_0001".to_owned()),
+            Node::Label("LAB_0000".to_owned()),
+            Node::Instruction(Instruction::Jmp(Label("LAB_0001".to_owned())), ()),
+            Node::Instruction(Instruction::End, ()),
+        ]
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    let nodes = vec![
+        Node::Instruction(Instruction::Jz(Label("LAB_0000".to_owned())), ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+        Node::Instruction(Instruction::PushInt(2), ()),
+        Node::Instruction(Instruction::Jmp(Label("LAB_0000".to_owned())), ()),
+        Node::Label("LAB_0001".to_owned()),
+        Node::Instruction(Instruction::PushInt(3), ()),
+        Node::Label("LAB_0000".to_owned()),
+        Node::Instruction(Instruction::End, ()),
+        Node::Instruction(Instruction::End, ()),
+    ];
+
+    // Nothing jumps to LAB_0001, so the code after it is just as dead
+    assert_eq!(
+        optimize(nodes),
+        vec![
+            Node::Instruction(Instruction::Jz(Label("LAB_0000".to_owned())), ()),
+            Node::Instruction(Instruction::PushInt(1), ()),
+            Node::Instruction(Instruction::Ret, ()),
+            Node::Label("LAB_0000".to_owned()),
+            Node::Instruction(Instruction::End, ()),
+        ]
+    );
+}
+
+#[test]
+fn unreachable_after_crash() {
+    let nodes = vec![
+        Node::Instruction(Instruction::PushInt(0), ()),
+        Node::Instruction(Instruction::Crash, ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    assert_eq!(
+        remove_unreachable(nodes),
+        vec![
+            Node::Instruction(Instruction::PushInt(0), ()),
+            Node::Instruction(Instruction::Crash, ()),
+        ]
+    );
+}
@@ -79,6 +79,27 @@ where
     )(i)
 }
 
+fn parse_raw_data<'a, E>(i: &'a str) -> IResult<&'a str, Node, E>
+where
+    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+{
+    map(
+        preceded(
+            bytes::complete::tag("RawData"),
+            many1(preceded(space1, parse_hex_word)),
+        ),
+        |x| Node::RawData(x, ()),
+    )(i)
+}
+
+// A word of bytecode, the way `RawData` writes them
+pub fn parse_hex_word<'a, E>(i: &'a str) -> IResult<&'a str, u32, E>
+where
+    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+{
+    map_res(hex_digit1, |x: &str| u32::from_str_radix(x, 16))(i)
+}
+
 fn parse_label_operand<'a, E>(i: &'a str) -> IResult<&str, operands::Label, E>
 where
     E: ParseError<&'a str>,
@@ -106,6 +127,7 @@ where
             alt((
                 parse_label,
                 parse_comment,
+                parse_raw_data,
                 map(Instruction::deserialize, |x| Node::Instruction(x, ())),
             )),
             multispace0,
@@ -195,6 +217,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_raw_data() {
+        assert_eq!(
+            parse_raw_data::<(_, ErrorKind)>("RawData 0000FFFF 12"),
+            Ok(("", Node::RawData(vec![0xFFFF, 0x12], ())))
+        );
+
+        let node = Node::RawData(vec![0xDEADBEEF, 0], ());
+        assert_eq!(parse(&node.to_string()), Ok(vec![node]));
+    }
+
     #[test]
     fn test_nodes() {
         assert_eq!(
@@ -219,4 +252,100 @@ End
             ))
         );
     }
+
+    #[test]
+    fn test_round_trip() {
+        use operands::*;
+
+        let label = |name: &str| Label(name.into());
+        let string = |text: &[u8]| DMString(text.to_vec());
+
+        let nodes: Vec<Node> = vec![
+            Node::Comment(" Hand-written".into()),
+            Node::Instruction(Instruction::DbgFile(string(b"code/\"main\".dm")), ()),
+            Node::Instruction(Instruction::DbgLine(7), ()),
+            Node::Instruction(Instruction::PushInt(-5), ()),
+            Node::Instruction(Instruction::PushVal(Value::Number(1.5).into()), ()),
+            Node::Instruction(Instruction::PushVal(Value::Null.into()), ()),
+            Node::Instruction(Instruction::PushVal(Value::Path("/mob/living".into()).into()), ()),
+            Node::Instruction(Instruction::PushVal(Value::Resource("icon.dmi".into()).into()), ()),
+            Node::Instruction(Instruction::PushVal(Value::Raw { tag: 0x29, data: 0x1234 }.into()), ()),
+            Node::Instruction(
+                Instruction::PushVal(Value::DMString(string(b"[\xFF\x01] says \xFF\x15hi\nthere")).into()),
+                (),
+            ),
+            Node::Instruction(
+                Instruction::GetVar(Variable::SetCache(
+                    Box::new(Variable::Src),
+                    Box::new(Variable::SetCache(
+                        Box::new(Variable::Field(string(b"loc"))),
+                        Box::new(Variable::Initial(Box::new(Variable::Field(string(b"name"))))),
+                    )),
+                )),
+                (),
+            ),
+            Node::Instruction(Instruction::SetVar(Variable::Global(string(b"config"))), ()),
+            Node::Instruction(Instruction::SetVar(Variable::NamedLocal(2, string(b"health"))), ()),
+            Node::Instruction(Instruction::AugAdd(Variable::CacheIndex), ()),
+            Node::Instruction(
+                Instruction::Call(
+                    Variable::SetCache(
+                        Box::new(Variable::Arg(0)),
+                        Box::new(Variable::DynamicProc(string(b"heal"))),
+                    ),
+                    2,
+                ),
+                (),
+            ),
+            Node::Instruction(Instruction::CallGlob(1, Proc::from_path("/proc/foo_bar".into())), ()),
+            Node::Label("LAB_0001".into()),
+            Node::Instruction(Instruction::IsIn(IsInParams::Range), ()),
+            Node::Instruction(Instruction::Range(RangeParams), ()),
+            Node::Instruction(
+                Instruction::IterLoad(
+                    5,
+                    crate::list_operands::TypeFilter::MOB | crate::list_operands::TypeFilter::OBJ,
+                ),
+                (),
+            ),
+            Node::Instruction(
+                Instruction::Switch(SwitchParams {
+                    default: label("LAB_0002"),
+                    cases: vec![(Value::Number(1.0), label("LAB_0001")), (Value::Null, label("LAB_0003"))],
+                }),
+                (),
+            ),
+            Node::Instruction(
+                Instruction::SwitchRange(SwitchRangeParams {
+                    default: label("LAB_0002"),
+                    cases: vec![(Value::Number(1.0), label("LAB_0001"))],
+                    range_cases: vec![(Value::Number(2.0), Value::Number(5.0), label("LAB_0003"))],
+                }),
+                (),
+            ),
+            Node::Instruction(
+                Instruction::PickSwitch(PickSwitchParams {
+                    default: label("LAB_0002"),
+                    cases: vec![(25, label("LAB_0001"))],
+                }),
+                (),
+            ),
+            Node::Instruction(
+                Instruction::PickProb(PickProbParams {
+                    cases: vec![label("LAB_0001"), label("LAB_0002")],
+                }),
+                (),
+            ),
+            Node::Label("LAB_0002".into()),
+            Node::Label("LAB_0003".into()),
+            Node::Instruction(Instruction::End, ()),
+        ];
+
+        assert_eq!(parse(&crate::format(&nodes)), Ok(nodes));
+    }
+
+    #[test]
+    fn test_unknown_instruction() {
+        assert!(parse("PushInt 1\nNotAnInstruction\n").is_err());
+    }
 }
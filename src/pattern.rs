@@ -0,0 +1,251 @@
+//! Searching procs for sequences of instructions, with wildcards for the parts that don't matter.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::Node;
+
+#[derive(Debug, PartialEq)]
+pub enum PatternError {
+    Empty,
+
+    // A string operand on this line is missing its closing quote
+    UnterminatedString(usize),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "pattern has no instructions"),
+            Self::UnterminatedString(line) => {
+                write!(f, "unterminated string on line {}", line)
+            }
+        }
+    }
+}
+
+/// Text that's either matched exactly or, ending in `*`, by prefix. `*` on its own matches anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob(pub String);
+
+impl Glob {
+    pub fn matches(&self, text: &str) -> bool {
+        match self.0.strip_suffix('*') {
+            Some(prefix) => text.starts_with(prefix),
+            None => self.0 == text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    /// An instruction with a matching name. Operands are compared in the form the disassembler writes them,
+    /// any operands past the end of the list match anything.
+    Instruction { name: Glob, operands: Vec<Glob> },
+
+    /// Any number of instructions, including none
+    Gap,
+}
+
+/// Labels and comments are skipped over when matching, only instructions count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub elements: Vec<Element>,
+}
+
+// Splits on whitespace, keeping quoted strings together
+fn split_operands(line: &str, line_number: usize) -> Result<Vec<String>, PatternError> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                current.push(c);
+
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            current.push('\\');
+                            current.extend(chars.next());
+                        }
+                        Some('"') => {
+                            current.push('"');
+                            break;
+                        }
+                        Some(c) => current.push(c),
+                        None => return Err(PatternError::UnterminatedString(line_number)),
+                    }
+                }
+            }
+
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+impl Pattern {
+    /// One element per line: `...` for a gap, otherwise an instruction name followed by its operands.
+    /// Any of them can be `*` or end in `*`, for example `CallGlob * /proc/shell` or `Aug*`.
+    pub fn parse(text: &str) -> Result<Self, PatternError> {
+        let mut elements = vec![];
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line == "..." {
+                elements.push(Element::Gap);
+                continue;
+            }
+
+            let mut tokens = split_operands(line, idx + 1)?.into_iter().map(Glob);
+            let name = tokens.next().unwrap();
+
+            elements.push(Element::Instruction {
+                name,
+                operands: tokens.collect(),
+            });
+        }
+
+        if !elements
+            .iter()
+            .any(|element| matches!(element, Element::Instruction { .. }))
+        {
+            return Err(PatternError::Empty);
+        }
+
+        Ok(Self { elements })
+    }
+
+    /// Every place the pattern matches, as ranges of node indices from the first instruction matched up to and
+    /// including the last. Matches don't overlap and gaps are as short as they can be.
+    pub fn find<D>(&self, nodes: &[Node<D>]) -> Vec<Range<usize>> {
+        let instructions: Vec<usize> = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, node)| match node {
+                Node::Instruction(..) => Some(idx),
+                _ => None,
+            })
+            .collect();
+
+        let mut matches = vec![];
+        let mut start = 0;
+
+        while start < instructions.len() {
+            match self.match_at(nodes, &instructions, start, 0) {
+                Some(end) if end > start => {
+                    matches.push(instructions[start]..instructions[end - 1] + 1);
+                    start = end;
+                }
+                _ => start += 1,
+            }
+        }
+
+        matches
+    }
+
+    /// Whether the pattern matches anywhere in the nodes
+    pub fn is_match<D>(&self, nodes: &[Node<D>]) -> bool {
+        !self.find(nodes).is_empty()
+    }
+
+    // Tries to match `elements[element..]` from the instruction at `position`, returning where the match ends
+    fn match_at<D>(
+        &self,
+        nodes: &[Node<D>],
+        instructions: &[usize],
+        position: usize,
+        element: usize,
+    ) -> Option<usize> {
+        let element_pattern = match self.elements.get(element) {
+            Some(element) => element,
+            None => return Some(position),
+        };
+
+        match element_pattern {
+            Element::Gap => (position..=instructions.len())
+                .find_map(|next| self.match_at(nodes, instructions, next, element + 1)),
+
+            Element::Instruction { name, operands } => {
+                let ins = match nodes.get(*instructions.get(position)?) {
+                    Some(Node::Instruction(ins, _)) => ins,
+                    _ => return None,
+                };
+
+                if !name.matches(&ins.op_name()) {
+                    return None;
+                }
+
+                let actual = ins.operands();
+                if operands.len() > actual.len()
+                    || !operands
+                        .iter()
+                        .zip(&actual)
+                        .all(|(glob, operand)| glob.matches(operand))
+                {
+                    return None;
+                }
+
+                self.match_at(nodes, instructions, position + 1, element + 1)
+            }
+        }
+    }
+}
+
+#[test]
+fn scanning() {
+    let nodes = crate::parser::parse(
+        r#"
+PushVal "rm -rf /"
+CallGlob 1 /proc/shell
+Pop
+LAB_0000:
+GetVar local(0)
+AugAdd local(1)
+PushVal "ls"
+CallGlob 1 /proc/shell
+Ret
+"#,
+    )
+    .unwrap();
+
+    let shell = Pattern::parse("CallGlob * /proc/shell").unwrap();
+    assert_eq!(shell.find(&nodes), vec![1..2, 7..8]);
+
+    let pattern = Pattern::parse(
+        r#"
+        PushVal "rm -rf /"
+        ...
+        Aug*
+        "#,
+    )
+    .unwrap();
+    assert_eq!(pattern.find(&nodes), vec![0..6]);
+
+    let pattern = Pattern::parse("PushVal \"ls\"\n*\nEnd").unwrap();
+    assert!(!pattern.is_match(&nodes));
+
+    assert_eq!(Pattern::parse("..."), Err(PatternError::Empty));
+    assert_eq!(
+        Pattern::parse("PushInt 1\nPushVal \"oops"),
+        Err(PatternError::UnterminatedString(2))
+    );
+}
@@ -0,0 +1,260 @@
+//! Inserting, deleting and replacing instructions in a proc without breaking its jumps.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::operands::Label;
+use crate::optimizer::jump_destinations_mut;
+use crate::Node;
+
+#[derive(Debug, PartialEq)]
+pub enum RewriteError {
+    // There's no node at this index to edit
+    OutOfRange(usize),
+
+    // The node at this index was deleted or replaced more than once
+    Conflict(usize),
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange(idx) => write!(f, "no node at index {}", idx),
+            Self::Conflict(idx) => write!(f, "node {} is replaced more than once", idx),
+        }
+    }
+}
+
+struct Edit<D> {
+    before: Vec<Node<D>>,
+    replacement: Option<Vec<Node<D>>>,
+    after: Vec<Node<D>>,
+}
+
+impl<D> Default for Edit<D> {
+    fn default() -> Self {
+        Self {
+            before: vec![],
+            replacement: None,
+            after: vec![],
+        }
+    }
+}
+
+/// A set of changes to make to a proc, all given by the index of the node in the proc as it was before any of them.
+/// Nothing changes until `apply`.
+///
+/// Labels are never removed, so jumps to a deleted or replaced instruction land on whatever ends up in its place.
+/// Labels in the new code that clash with ones already there are renamed, along with the jumps to them.
+pub struct Rewrite<D = ()> {
+    edits: BTreeMap<usize, Edit<D>>,
+    conflicts: Vec<usize>,
+}
+
+impl<D> Default for Rewrite<D> {
+    fn default() -> Self {
+        Self {
+            edits: BTreeMap::new(),
+            conflicts: vec![],
+        }
+    }
+}
+
+impl<D> Rewrite<D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts code right before a node. Inserting before an instruction puts the code after its labels, so it runs
+    /// whether the instruction is jumped to or not. Inserting before a label leaves it out for anything jumping there.
+    /// Inserting before the index one past the end adds code to the end.
+    pub fn insert_before(&mut self, node: usize, code: Vec<Node<D>>) -> &mut Self {
+        self.edits.entry(node).or_default().before.extend(code);
+        self
+    }
+
+    pub fn insert_after(&mut self, node: usize, code: Vec<Node<D>>) -> &mut Self {
+        self.edits.entry(node).or_default().after.extend(code);
+        self
+    }
+
+    pub fn replace(&mut self, node: usize, code: Vec<Node<D>>) -> &mut Self {
+        let edit = self.edits.entry(node).or_default();
+
+        if edit.replacement.is_some() {
+            self.conflicts.push(node);
+        }
+
+        edit.replacement = Some(code);
+        self
+    }
+
+    pub fn delete(&mut self, node: usize) -> &mut Self {
+        self.replace(node, vec![])
+    }
+
+    pub fn apply(mut self, nodes: Vec<Node<D>>) -> Result<Vec<Node<D>>, RewriteError> {
+        if let Some(idx) = self.conflicts.first() {
+            return Err(RewriteError::Conflict(*idx));
+        }
+
+        // One past the end can only have code inserted before it
+        if let Some((idx, _)) = self.edits.iter().find(|(idx, edit)| {
+            **idx > nodes.len()
+                || (**idx == nodes.len() && (edit.replacement.is_some() || !edit.after.is_empty()))
+        }) {
+            return Err(RewriteError::OutOfRange(*idx));
+        }
+
+        let mut taken: HashSet<String> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Label(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let mut rewritten = vec![];
+        let len = nodes.len();
+
+        for (idx, node) in nodes
+            .into_iter()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .enumerate()
+        {
+            let edit = match self.edits.remove(&idx) {
+                Some(edit) => edit,
+                None => {
+                    rewritten.extend(node);
+                    continue;
+                }
+            };
+
+            rewritten.extend(fresh_labels(edit.before, &mut taken));
+
+            match (node, edit.replacement) {
+                (Some(node), None) => rewritten.push(node),
+                (Some(node), Some(replacement)) => {
+                    if let Node::Label(_) = node {
+                        rewritten.push(node);
+                    }
+                    rewritten.extend(fresh_labels(replacement, &mut taken));
+                }
+                (None, _) => debug_assert_eq!(idx, len),
+            }
+
+            rewritten.extend(fresh_labels(edit.after, &mut taken));
+        }
+
+        Ok(rewritten)
+    }
+}
+
+// Renames any labels in the new code that are already taken, and takes the ones it ends up with
+fn fresh_labels<D>(mut code: Vec<Node<D>>, taken: &mut HashSet<String>) -> Vec<Node<D>> {
+    let mut renames = HashMap::new();
+
+    for node in &code {
+        if let Node::Label(name) = node {
+            if taken.insert(name.clone()) {
+                continue;
+            }
+
+            let new_name = (1..)
+                .map(|n| format!("{}_{}", name, n))
+                .find(|candidate| !taken.contains(candidate))
+                .unwrap();
+
+            taken.insert(new_name.clone());
+            renames.insert(name.clone(), new_name);
+        }
+    }
+
+    if renames.is_empty() {
+        return code;
+    }
+
+    for node in &mut code {
+        match node {
+            Node::Label(name) => {
+                if let Some(new_name) = renames.get(name) {
+                    *name = new_name.clone();
+                }
+            }
+
+            Node::Instruction(ins, _) => {
+                for Label(name) in jump_destinations_mut(ins) {
+                    if let Some(new_name) = renames.get(name) {
+                        *name = new_name.clone();
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    code
+}
+
+#[test]
+fn rewriting() {
+    let nodes = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Jz LAB_0000
+PushInt 1
+Ret
+LAB_0000:
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    let check = crate::parser::parse(
+        r#"
+GetVar arg(1)
+Jz LAB_0000
+Crash
+LAB_0000:
+"#,
+    )
+    .unwrap();
+
+    let mut rewrite = Rewrite::new();
+    rewrite
+        .insert_before(5, check)
+        .replace(2, crate::parser::parse("PushInt 3").unwrap())
+        .delete(4)
+        .insert_before(7, crate::parser::parse("End").unwrap());
+
+    let expected = crate::parser::parse(
+        r#"
+GetVar arg(0)
+Jz LAB_0000
+PushInt 3
+Ret
+LAB_0000:
+GetVar arg(1)
+Jz LAB_0000_1
+Crash
+LAB_0000_1:
+PushInt 2
+Ret
+End
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(rewrite.apply(nodes.clone()), Ok(expected));
+
+    let mut rewrite = Rewrite::new();
+    rewrite.delete(1).delete(1);
+    assert_eq!(rewrite.apply(nodes.clone()), Err(RewriteError::Conflict(1)));
+
+    let mut rewrite = Rewrite::new();
+    rewrite.delete(7);
+    assert_eq!(rewrite.apply(nodes), Err(RewriteError::OutOfRange(7)));
+}
@@ -0,0 +1,235 @@
+//! Mapping between bytecode offsets and the source lines recorded by `DbgFile` and `DbgLine`, for placing
+//! breakpoints and showing where a proc is up to. Also removing those instructions, or adding made up ones.
+
+use std::ops::Range;
+
+use crate::cfg::{Cfg, CfgError};
+use crate::disassembler::DebugData;
+use crate::operands::DMString;
+use crate::rewrite::Rewrite;
+use crate::{Instruction, Node};
+
+/// A run of bytecode compiled from one line
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineRange {
+    /// From the last `DbgFile`, if there was one
+    pub file: Option<String>,
+    pub line: u32,
+
+    /// In words from the start of the proc, starting at the `DbgLine`
+    pub offsets: Range<u32>,
+}
+
+/// Every line the proc has debug info for, in the order they appear in the bytecode. A line can show up more than
+/// once, such as a loop's condition being checked at the bottom.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    pub ranges: Vec<LineRange>,
+}
+
+impl SourceMap {
+    pub fn new(nodes: &[Node<DebugData<'_>>]) -> Self {
+        let mut ranges: Vec<LineRange> = vec![];
+        let mut file = None;
+
+        for node in nodes {
+            let debug = match node {
+                Node::Instruction(_, debug) | Node::RawData(_, debug) => debug,
+                _ => continue,
+            };
+
+            match node {
+                Node::Instruction(Instruction::DbgFile(name), _) => {
+                    file = Some(String::from_utf8_lossy(&name.0).into_owned());
+                    continue;
+                }
+
+                Node::Instruction(Instruction::DbgLine(line), _) => {
+                    ranges.push(LineRange {
+                        file: file.clone(),
+                        line: *line,
+                        offsets: debug.offset..debug.offset,
+                    });
+                }
+
+                _ => {}
+            }
+
+            // Anything before the first line isn't from any line, and a new file ends the line before it
+            if let Some(last) = ranges.last_mut() {
+                if last.file == file {
+                    last.offsets.end = debug.offset + debug.bytecode.len() as u32;
+                }
+            }
+        }
+
+        Self { ranges }
+    }
+
+    /// The line the instruction at the offset was compiled from
+    pub fn line_at(&self, offset: u32) -> Option<&LineRange> {
+        self.ranges
+            .iter()
+            .find(|range| range.offsets.contains(&offset))
+    }
+
+    /// Everywhere a line was compiled to, in any file
+    pub fn offsets_of(&self, line: u32) -> Vec<Range<u32>> {
+        self.ranges
+            .iter()
+            .filter(|range| range.line == line)
+            .map(|range| range.offsets.clone())
+            .collect()
+    }
+}
+
+fn is_debug_info<D>(node: &Node<D>) -> bool {
+    matches!(
+        node,
+        Node::Instruction(Instruction::DbgFile(_), _)
+            | Node::Instruction(Instruction::DbgLine(_), _)
+    )
+}
+
+/// Every `DbgFile` and `DbgLine`. Runtime errors in the code then won't say where they came from.
+pub fn strip_debug_info<D>(mut nodes: Vec<Node<D>>) -> Vec<Node<D>> {
+    nodes.retain(|node| !is_debug_info(node));
+    nodes
+}
+
+/// Replaces the proc's debug info with a `DbgFile` and a `DbgLine` at the start of every basic block, numbered
+/// from `first_line` in order. Stack traces through patched code then point at the block they came from.
+pub fn inject_debug_info(
+    nodes: Vec<Node>,
+    file: &str,
+    first_line: u32,
+) -> Result<Vec<Node>, CfgError> {
+    let nodes = strip_debug_info(nodes);
+    let cfg = Cfg::new(&nodes)?;
+    let mut rewrite = Rewrite::new();
+    let mut line = first_line;
+
+    for block in &cfg.blocks {
+        let first = match nodes[block.nodes.clone()]
+            .iter()
+            .position(|node| matches!(node, Node::Instruction(..)))
+        {
+            Some(idx) => block.nodes.start + idx,
+            None => continue,
+        };
+
+        let mut debug_info = vec![];
+        if line == first_line {
+            debug_info.push(Node::Instruction(
+                Instruction::DbgFile(DMString(file.as_bytes().to_vec())),
+                (),
+            ));
+        }
+        debug_info.push(Node::Instruction(Instruction::DbgLine(line), ()));
+
+        rewrite.insert_before(first, debug_info);
+        line += 1;
+    }
+
+    // Every edit is before a different instruction
+    Ok(rewrite.apply(nodes).unwrap())
+}
+
+#[test]
+fn lines() {
+    let nodes = crate::parser::parse(
+        r#"
+DbgFile "code/game.dm"
+DbgLine 10
+PushInt 1
+Pop
+DbgLine 11
+LAB_0000:
+PushInt 2
+Jz LAB_0001
+DbgLine 10
+Jmp LAB_0000
+LAB_0001:
+End
+"#,
+    )
+    .unwrap();
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, _) = crate::disassembler::disassemble(&bytecode, &mut env);
+    let map = SourceMap::new(&disassembled);
+
+    // The test environment doesn't keep strings
+    let file = map.ranges[0].file.clone();
+    assert!(file.is_some());
+
+    assert_eq!(
+        map.ranges,
+        vec![
+            LineRange {
+                file: file.clone(),
+                line: 10,
+                offsets: 2..7,
+            },
+            LineRange {
+                file: file.clone(),
+                line: 11,
+                offsets: 7..13,
+            },
+            LineRange {
+                file,
+                line: 10,
+                offsets: 13..18,
+            },
+        ]
+    );
+
+    assert_eq!(map.line_at(9).unwrap().line, 11);
+    assert!(map.line_at(0).is_none());
+    assert!(map.line_at(18).is_none());
+    assert_eq!(map.offsets_of(10), vec![2..7, 13..18]);
+}
+
+#[test]
+fn debug_info() {
+    let nodes = crate::parser::parse(
+        r#"
+DbgFile "code/game.dm"
+DbgLine 10
+GetVar arg(0)
+Jz LAB_0000
+DbgLine 11
+PushInt 1
+Ret
+LAB_0000:
+DbgLine 13
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    let expected = crate::parser::parse(
+        r#"
+DbgFile "patch.dm"
+DbgLine 1
+GetVar arg(0)
+Jz LAB_0000
+DbgLine 2
+PushInt 1
+Ret
+LAB_0000:
+DbgLine 3
+PushInt 2
+Ret
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        inject_debug_info(nodes.clone(), "patch.dm", 1),
+        Ok(expected)
+    );
+    assert_eq!(strip_debug_info(nodes).len(), 7);
+}
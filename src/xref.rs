@@ -0,0 +1,361 @@
+//! Cross-references between procs: who calls what, and which globals, fields, strings and types each one uses.
+
+use std::collections::BTreeMap;
+
+use crate::disassembler::DebugData;
+use crate::operands::{DMString, Value, Variable};
+use crate::{Instruction, Node};
+
+/// Everything a proc refers to, each in the order it first comes up
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct References {
+    /// Procs called by path
+    pub calls: Vec<String>,
+
+    /// Procs called by name on an object, which could end up at any proc with that name
+    pub dynamic_calls: Vec<String>,
+
+    pub globals: Vec<String>,
+
+    /// Variables read or written on an object, such as `name` in `src.name`
+    pub fields: Vec<String>,
+
+    pub strings: Vec<Vec<u8>>,
+
+    /// Type paths pushed as values, such as the types given to `new` and `istype`
+    pub types: Vec<String>,
+}
+
+fn add<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    if !list.contains(&item) {
+        list.push(item);
+    }
+}
+
+impl References {
+    fn add_variable(&mut self, var: &Variable) {
+        match var {
+            Variable::Global(name) => {
+                add(
+                    &mut self.globals,
+                    String::from_utf8_lossy(&name.0).into_owned(),
+                );
+            }
+
+            Variable::StaticProc(proc) | Variable::StaticVerb(proc) => {
+                add(&mut self.calls, proc.path.clone());
+            }
+
+            Variable::DynamicProc(name) | Variable::DynamicVerb(name) => {
+                add(
+                    &mut self.dynamic_calls,
+                    String::from_utf8_lossy(&name.0).into_owned(),
+                );
+            }
+
+            Variable::Field(name) => {
+                add(
+                    &mut self.fields,
+                    String::from_utf8_lossy(&name.0).into_owned(),
+                );
+            }
+
+            Variable::SetCache(lhs, rhs) => {
+                self.add_variable(lhs);
+                self.add_variable(rhs);
+            }
+
+            Variable::Initial(var) | Variable::IsSaved(var) => self.add_variable(var),
+
+            _ => {}
+        }
+    }
+
+    fn add_instruction(&mut self, ins: &Instruction) {
+        match ins {
+            Instruction::CallGlob(_, proc) | Instruction::CallGlobalArgList(proc) => {
+                add(&mut self.calls, proc.path.clone());
+            }
+
+            Instruction::PushVal(operand) => match &operand.value {
+                Value::DMString(string) => add(&mut self.strings, string.0.clone()),
+                Value::Path(path) => add(&mut self.types, path.clone()),
+                _ => {}
+            },
+
+            Instruction::Format(string, _) | Instruction::OutputFormat(string, _) => {
+                add(&mut self.strings, string.0.clone());
+            }
+
+            Instruction::Call(var, _)
+            | Instruction::CallStatement(var, _)
+            | Instruction::GetVar(var)
+            | Instruction::SetVar(var)
+            | Instruction::SetVarExpr(var)
+            | Instruction::AugAdd(var)
+            | Instruction::AugSub(var)
+            | Instruction::AugMul(var)
+            | Instruction::AugDiv(var)
+            | Instruction::AugMod(var)
+            | Instruction::AugBand(var)
+            | Instruction::AugBor(var)
+            | Instruction::AugXor(var)
+            | Instruction::AugLShift(var)
+            | Instruction::AugRShift(var)
+            | Instruction::AssignInto(var)
+            | Instruction::TurnOrFlipIcon(_, var)
+            | Instruction::IconIntensity(var)
+            | Instruction::IconSwapColor(var)
+            | Instruction::ShiftIcon(var)
+            | Instruction::IconDrawBox(var)
+            | Instruction::IconScale(var)
+            | Instruction::IconCrop(var)
+            | Instruction::IconBlend(var)
+            | Instruction::PreInc(var)
+            | Instruction::PostInc(var)
+            | Instruction::PreDec(var)
+            | Instruction::PostDec(var)
+            | Instruction::Inc(var)
+            | Instruction::Dec(var)
+            | Instruction::ForRange(_, var)
+            | Instruction::ForRangeStep(_, var) => self.add_variable(var),
+
+            _ => {}
+        }
+    }
+}
+
+/// Everything the proc's instructions refer to
+pub fn references<D>(nodes: &[Node<D>]) -> References {
+    let mut references = References::default();
+
+    for node in nodes {
+        if let Node::Instruction(ins, _) = node {
+            references.add_instruction(ins);
+        }
+    }
+
+    references
+}
+
+/// A string in the code, along with where it is
+#[derive(Debug, PartialEq)]
+pub struct StringLiteral<'a> {
+    pub string: &'a DMString,
+    pub instruction: &'a Instruction,
+
+    /// Of the instruction, in words from the start of the proc
+    pub offset: u32,
+}
+
+fn value_string(value: &Value) -> Option<&DMString> {
+    match value {
+        Value::DMString(string) => Some(string),
+        _ => None,
+    }
+}
+
+/// Every string pushed, formatted or switched on, in order. Repeats are all included, unlike `References::strings`.
+/// Names of variables and files aren't strings in this sense.
+pub fn string_literals<'a>(nodes: &'a [Node<DebugData<'_>>]) -> Vec<StringLiteral<'a>> {
+    let mut literals = vec![];
+
+    for node in nodes {
+        let (ins, debug) = match node {
+            Node::Instruction(ins, debug) => (ins, debug),
+            _ => continue,
+        };
+
+        let strings: Vec<&DMString> = match ins {
+            Instruction::PushVal(operand) => value_string(&operand.value).into_iter().collect(),
+            Instruction::Format(string, _) | Instruction::OutputFormat(string, _) => vec![string],
+            Instruction::Switch(params) => params
+                .cases
+                .iter()
+                .filter_map(|(value, _)| value_string(value))
+                .collect(),
+            Instruction::SwitchRange(params) => params
+                .range_cases
+                .iter()
+                .flat_map(|(min, max, _)| vec![min, max])
+                .chain(params.cases.iter().map(|(value, _)| value))
+                .filter_map(value_string)
+                .collect(),
+            _ => vec![],
+        };
+
+        literals.extend(strings.into_iter().map(|string| StringLiteral {
+            string,
+            instruction: ins,
+            offset: debug.offset,
+        }));
+    }
+
+    literals
+}
+
+// `/mob/proc/attack` is called by name as `attack`
+fn proc_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The references of many procs, indexed so they can be looked up in either direction
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CallGraph {
+    /// Each proc's references, by its path
+    pub procs: BTreeMap<String, References>,
+}
+
+impl CallGraph {
+    pub fn new<'a, D: 'a, I>(procs: I) -> Self
+    where
+        I: IntoIterator<Item = (String, &'a [Node<D>])>,
+    {
+        Self {
+            procs: procs
+                .into_iter()
+                .map(|(path, nodes)| (path, references(nodes)))
+                .collect(),
+        }
+    }
+
+    fn procs_where<F>(&self, mut predicate: F) -> Vec<&str>
+    where
+        F: FnMut(&References) -> bool,
+    {
+        self.procs
+            .iter()
+            .filter(|(_, references)| predicate(references))
+            .map(|(path, _)| path.as_str())
+            .collect()
+    }
+
+    /// The procs a proc calls by path
+    pub fn callees(&self, path: &str) -> &[String] {
+        self.procs
+            .get(path)
+            .map(|references| references.calls.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The procs that call a proc by path
+    pub fn callers(&self, path: &str) -> Vec<&str> {
+        self.procs_where(|references| references.calls.iter().any(|call| call == path))
+    }
+
+    /// `callers`, along with anything calling a proc of the same name on an object, which might end up there
+    pub fn possible_callers(&self, path: &str) -> Vec<&str> {
+        let name = proc_name(path);
+
+        self.procs_where(|references| {
+            references.calls.iter().any(|call| call == path)
+                || references.dynamic_calls.iter().any(|call| call == name)
+        })
+    }
+
+    pub fn users_of_global(&self, name: &str) -> Vec<&str> {
+        self.procs_where(|references| references.globals.iter().any(|global| global == name))
+    }
+
+    pub fn users_of_field(&self, name: &str) -> Vec<&str> {
+        self.procs_where(|references| references.fields.iter().any(|field| field == name))
+    }
+
+    pub fn users_of_string(&self, string: &[u8]) -> Vec<&str> {
+        self.procs_where(|references| references.strings.iter().any(|used| used == string))
+    }
+
+    pub fn users_of_type(&self, path: &str) -> Vec<&str> {
+        self.procs_where(|references| references.types.iter().any(|used| used == path))
+    }
+}
+
+#[test]
+fn call_graph() {
+    let attack = crate::parser::parse(
+        r#"
+GetVar global("round_started")
+PushVal "hit"
+PushVal /obj/effect
+CallGlob 2 /proc/spawn_effect
+Pop
+PushInt 1
+Call cache = src; dynamic_proc("take_damage") 1
+Pop
+GetVar cache = src; cache["health"]
+Pop
+End
+"#,
+    )
+    .unwrap();
+
+    let spawn_effect = crate::parser::parse(
+        r#"
+PushVal "hit"
+PushVal "hit"
+CallGlob 1 /proc/log
+Ret
+"#,
+    )
+    .unwrap();
+
+    let graph = CallGraph::new(vec![
+        ("/mob/proc/attack".to_owned(), attack.as_slice()),
+        ("/proc/spawn_effect".to_owned(), spawn_effect.as_slice()),
+    ]);
+
+    assert_eq!(
+        graph.procs["/mob/proc/attack"],
+        References {
+            calls: vec!["/proc/spawn_effect".to_owned()],
+            dynamic_calls: vec!["take_damage".to_owned()],
+            globals: vec!["round_started".to_owned()],
+            fields: vec!["health".to_owned()],
+            strings: vec![b"hit".to_vec()],
+            types: vec!["/obj/effect".to_owned()],
+        }
+    );
+
+    assert_eq!(
+        graph.callees("/proc/spawn_effect"),
+        ["/proc/log".to_owned()]
+    );
+    assert!(graph.callees("/proc/missing").is_empty());
+    assert_eq!(
+        graph.callers("/proc/spawn_effect"),
+        vec!["/mob/proc/attack"]
+    );
+    assert_eq!(
+        graph.possible_callers("/mob/living/proc/take_damage"),
+        vec!["/mob/proc/attack"]
+    );
+    assert_eq!(
+        graph.users_of_string(b"hit"),
+        vec!["/mob/proc/attack", "/proc/spawn_effect"]
+    );
+    assert_eq!(
+        graph.users_of_global("round_started"),
+        vec!["/mob/proc/attack"]
+    );
+    assert!(graph.users_of_type("/obj").is_empty());
+    assert_eq!(graph.users_of_field("health"), vec!["/mob/proc/attack"]);
+}
+
+#[test]
+fn strings() {
+    let nodes = vec![
+        Node::Instruction(Instruction::DbgFile(DMString(b"code.dm".to_vec())), ()),
+        Node::Instruction(Instruction::PushInt(1), ()),
+        Node::Instruction(Instruction::Format(DMString(b"[] apples".to_vec()), 1), ()),
+        Node::Instruction(Instruction::Ret, ()),
+    ];
+
+    let bytecode = crate::assembler::assemble(&nodes, &mut crate::TestAssembleEnv).unwrap();
+    let mut env = crate::TestDisassembleEnv;
+    let (disassembled, _) = crate::disassembler::disassemble(&bytecode, &mut env);
+
+    let literals = string_literals(&disassembled);
+    assert_eq!(literals.len(), 1);
+    assert_eq!(literals[0].offset, 4);
+    assert!(matches!(literals[0].instruction, Instruction::Format(_, 1)));
+}